@@ -1,4 +1,5 @@
 fn main() {
     // Ensure migrations are recompiled when changed
     println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=migrations_sqlite");
 }
@@ -0,0 +1,2 @@
+-- Per-upload secret required to delete a file via DELETE /drop/{id}
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS delete_token TEXT;
@@ -0,0 +1,5 @@
+-- Expired files keep their row as a tombstone (contents removed) so
+-- downloads can return 410 Gone; tombstones are deleted after a grace period
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS purged_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_file_mappings_purged_at ON file_mappings(purged_at);
@@ -0,0 +1,2 @@
+-- Optional cap on downloads; once access_count reaches it the file is consumed
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS max_downloads INTEGER;
@@ -0,0 +1,2 @@
+-- XXH3-128 of the file contents, served as a strong ETag
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS content_hash TEXT;
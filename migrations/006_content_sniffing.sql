@@ -0,0 +1,4 @@
+-- Content type sent by the client and the one sniffed from the file's magic bytes;
+-- content_type remains the type the file is served with
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS declared_content_type TEXT;
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS detected_content_type TEXT;
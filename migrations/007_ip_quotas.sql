@@ -0,0 +1,12 @@
+-- Bytes uploaded per client IP in the current daily quota window
+CREATE TABLE IF NOT EXISTS ip_quotas (
+    client_ip TEXT PRIMARY KEY,
+    window_start TIMESTAMPTZ NOT NULL,
+    bytes_used BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_ip_quotas_window_start ON ip_quotas(window_start);
+
+-- Who uploaded a file, so deleting it can credit their quota back
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS uploader_ip TEXT;
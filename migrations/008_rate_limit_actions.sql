@@ -0,0 +1,5 @@
+-- Uploads and downloads are rate limited separately
+ALTER TABLE rate_limits ADD COLUMN IF NOT EXISTS action TEXT NOT NULL DEFAULT 'upload';
+
+ALTER TABLE rate_limits DROP CONSTRAINT IF EXISTS rate_limits_pkey;
+ALTER TABLE rate_limits ADD PRIMARY KEY (client_ip, action);
@@ -0,0 +1,14 @@
+-- SHA-256 of the file contents, used to find identical uploads
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS sha256 TEXT;
+CREATE INDEX IF NOT EXISTS idx_file_mappings_sha256 ON file_mappings(sha256, file_size) WHERE purged_at IS NULL;
+
+-- Stored files shared by identical uploads, with how many mappings point at
+-- each. The bytes are removed when the last reference goes.
+CREATE TABLE IF NOT EXISTS file_blobs (
+    sha256 TEXT NOT NULL,
+    file_size BIGINT NOT NULL,
+    file_path TEXT NOT NULL UNIQUE,
+    ref_count INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (sha256, file_size)
+);
@@ -0,0 +1,7 @@
+-- Several files handed out together as one archive
+CREATE TABLE IF NOT EXISTS bundles (
+    id UUID PRIMARY KEY,
+    short_code TEXT NOT NULL UNIQUE,
+    file_ids TEXT NOT NULL, -- Member file IDs in archive order, comma separated
+    created_at TIMESTAMPTZ NOT NULL
+);
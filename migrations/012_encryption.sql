@@ -0,0 +1,3 @@
+-- Whether the stored contents are encrypted at rest. Files written before
+-- encryption was enabled stay readable as they are.
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,11 @@
+-- Uploads made under an Idempotency-Key, so a retried request gets the first response back
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    scope TEXT NOT NULL, -- key:<API key ID> for uploads made with an API key, else ip:<client IP>
+    idempotency_key TEXT NOT NULL,
+    response TEXT, -- The stored files as JSON; NULL while the upload is still running
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (scope, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
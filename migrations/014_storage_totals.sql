@@ -0,0 +1,60 @@
+-- Running totals over files that haven't been purged, kept up to date by a
+-- trigger so storage stats read one row instead of aggregating file_mappings
+CREATE TABLE IF NOT EXISTS storage_totals (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    total_files BIGINT NOT NULL,
+    total_size BIGINT NOT NULL,
+    memory_files BIGINT NOT NULL,
+    memory_size BIGINT NOT NULL
+);
+
+INSERT INTO storage_totals (id, total_files, total_size, memory_files, memory_size)
+SELECT
+    TRUE,
+    COUNT(*),
+    COALESCE(SUM(file_size), 0),
+    COUNT(*) FILTER (WHERE is_in_memory),
+    COALESCE(SUM(file_size) FILTER (WHERE is_in_memory), 0)
+FROM file_mappings
+WHERE purged_at IS NULL
+ON CONFLICT (id) DO NOTHING;
+
+-- Take the old row out of the totals and put the new one in; purged rows count as neither
+CREATE OR REPLACE FUNCTION update_storage_totals() RETURNS TRIGGER AS $$
+DECLARE
+    files BIGINT := 0;
+    size BIGINT := 0;
+    mem_files BIGINT := 0;
+    mem_size BIGINT := 0;
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.purged_at IS NULL THEN
+        files := files - 1;
+        size := size - OLD.file_size;
+        IF OLD.is_in_memory THEN
+            mem_files := mem_files - 1;
+            mem_size := mem_size - OLD.file_size;
+        END IF;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.purged_at IS NULL THEN
+        files := files + 1;
+        size := size + NEW.file_size;
+        IF NEW.is_in_memory THEN
+            mem_files := mem_files + 1;
+            mem_size := mem_size + NEW.file_size;
+        END IF;
+    END IF;
+    IF files <> 0 OR size <> 0 OR mem_files <> 0 OR mem_size <> 0 THEN
+        UPDATE storage_totals
+        SET total_files = total_files + files,
+            total_size = total_size + size,
+            memory_files = memory_files + mem_files,
+            memory_size = memory_size + mem_size;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS file_mappings_storage_totals ON file_mappings;
+CREATE TRIGGER file_mappings_storage_totals
+AFTER INSERT OR DELETE OR UPDATE OF file_size, is_in_memory, purged_at ON file_mappings
+FOR EACH ROW EXECUTE FUNCTION update_storage_totals();
@@ -0,0 +1,3 @@
+-- Pinned files are kept whatever their expiry or age, until they are unpinned
+-- or deleted.
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS pinned BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,17 @@
+-- API keys, each with its own files, storage quota and rate limit multiplier.
+-- Only a hash of each key is kept; the key itself is shown once, when created.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE, -- SHA-256 of the key, hex
+    quota_bytes BIGINT, -- NULL for no limit
+    quota_files BIGINT,
+    rate_limit_multiplier DOUBLE PRECISION NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ
+);
+
+-- The key a file was uploaded with; NULL for anonymous uploads
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS api_key_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_file_mappings_api_key_id ON file_mappings(api_key_id);
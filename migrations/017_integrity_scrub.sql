@@ -0,0 +1,9 @@
+-- Set by the integrity scrubber when a file's contents are missing or no
+-- longer match its hash; such files aren't served until they pass again.
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS corrupt BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- When the scrubber last checked the file; NULL until it first has
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS scrubbed_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_file_mappings_scrubbed_at ON file_mappings(scrubbed_at);
+CREATE INDEX IF NOT EXISTS idx_file_mappings_corrupt ON file_mappings(id) WHERE corrupt;
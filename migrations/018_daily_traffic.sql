@@ -0,0 +1,10 @@
+-- Downloads and bytes served per UTC day, for /admin/stats. Days are
+-- 'YYYY-MM-DD' text so both backends store and sort them the same way.
+CREATE TABLE IF NOT EXISTS daily_traffic (
+    day TEXT PRIMARY KEY,
+    downloads BIGINT NOT NULL DEFAULT 0,
+    bytes_served BIGINT NOT NULL DEFAULT 0
+);
+
+-- Top files by downloads
+CREATE INDEX IF NOT EXISTS idx_file_mappings_access_count ON file_mappings(access_count);
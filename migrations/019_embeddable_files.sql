@@ -0,0 +1,3 @@
+-- Files uploaded to be embedded elsewhere; their downloads skip the
+-- DROP_ALLOWED_REFERERS check
+ALTER TABLE file_mappings ADD COLUMN IF NOT EXISTS embeddable BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,44 @@
+-- SQLite schema, equivalent to the PostgreSQL migrations in ../migrations.
+-- UUIDs are stored as 16-byte blobs and timestamps as RFC 3339 text, always
+-- written by the application so that they compare correctly.
+
+CREATE TABLE IF NOT EXISTS file_mappings (
+    id BLOB PRIMARY KEY,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    file_path TEXT,
+    file_size INTEGER NOT NULL,
+    is_in_memory BOOLEAN DEFAULT FALSE,
+    created_at TEXT NOT NULL,
+    accessed_at TEXT NOT NULL,
+    access_count INTEGER DEFAULT 0,
+    expires_at TEXT,
+    delete_token TEXT,
+    purged_at TEXT,
+    max_downloads INTEGER,
+    content_hash TEXT,
+    declared_content_type TEXT,
+    detected_content_type TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_mappings_created_at ON file_mappings(created_at);
+CREATE INDEX IF NOT EXISTS idx_file_mappings_expires_at ON file_mappings(expires_at);
+CREATE INDEX IF NOT EXISTS idx_file_mappings_accessed_at ON file_mappings(accessed_at);
+CREATE INDEX IF NOT EXISTS idx_file_mappings_purged_at ON file_mappings(purged_at);
+
+CREATE TABLE IF NOT EXISTS short_urls (
+    short_code TEXT PRIMARY KEY,
+    file_id BLOB NOT NULL REFERENCES file_mappings(id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_short_urls_file_id ON short_urls(file_id);
+
+CREATE TABLE IF NOT EXISTS rate_limits (
+    client_ip TEXT PRIMARY KEY,
+    request_count INTEGER DEFAULT 0,
+    window_start TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limits_updated_at ON rate_limits(updated_at);
@@ -0,0 +1,14 @@
+-- Uploads and downloads are rate limited separately. SQLite can't change a
+-- primary key in place, and the rows only live for one window, so rebuild the table.
+DROP TABLE IF EXISTS rate_limits;
+
+CREATE TABLE IF NOT EXISTS rate_limits (
+    client_ip TEXT NOT NULL,
+    action TEXT NOT NULL DEFAULT 'upload',
+    request_count INTEGER DEFAULT 0,
+    window_start TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (client_ip, action)
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limits_updated_at ON rate_limits(updated_at);
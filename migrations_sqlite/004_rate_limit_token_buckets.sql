@@ -0,0 +1,4 @@
+-- Rate limits are token buckets: tokens left and when they were last refilled
+-- (milliseconds since the epoch). Existing rows start full.
+ALTER TABLE rate_limits ADD COLUMN tokens REAL NOT NULL DEFAULT 0;
+ALTER TABLE rate_limits ADD COLUMN refilled_at INTEGER NOT NULL DEFAULT 0;
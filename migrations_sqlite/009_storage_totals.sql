@@ -0,0 +1,60 @@
+-- Running totals over files that haven't been purged, kept up to date by
+-- triggers so storage stats read one row instead of aggregating file_mappings
+CREATE TABLE IF NOT EXISTS storage_totals (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    total_files INTEGER NOT NULL,
+    total_size INTEGER NOT NULL,
+    memory_files INTEGER NOT NULL,
+    memory_size INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO storage_totals (id, total_files, total_size, memory_files, memory_size)
+SELECT
+    1,
+    COUNT(*),
+    COALESCE(SUM(file_size), 0),
+    COUNT(CASE WHEN is_in_memory THEN 1 END),
+    COALESCE(SUM(CASE WHEN is_in_memory THEN file_size END), 0)
+FROM file_mappings
+WHERE purged_at IS NULL;
+
+CREATE TRIGGER IF NOT EXISTS file_mappings_storage_totals_insert
+AFTER INSERT ON file_mappings
+WHEN NEW.purged_at IS NULL
+BEGIN
+    UPDATE storage_totals
+    SET total_files = total_files + 1,
+        total_size = total_size + NEW.file_size,
+        memory_files = memory_files + (CASE WHEN NEW.is_in_memory THEN 1 ELSE 0 END),
+        memory_size = memory_size + (CASE WHEN NEW.is_in_memory THEN NEW.file_size ELSE 0 END);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_mappings_storage_totals_delete
+AFTER DELETE ON file_mappings
+WHEN OLD.purged_at IS NULL
+BEGIN
+    UPDATE storage_totals
+    SET total_files = total_files - 1,
+        total_size = total_size - OLD.file_size,
+        memory_files = memory_files - (CASE WHEN OLD.is_in_memory THEN 1 ELSE 0 END),
+        memory_size = memory_size - (CASE WHEN OLD.is_in_memory THEN OLD.file_size ELSE 0 END);
+END;
+
+-- Take the old row out of the totals and put the new one in; purged rows count as neither
+CREATE TRIGGER IF NOT EXISTS file_mappings_storage_totals_update
+AFTER UPDATE OF file_size, is_in_memory, purged_at ON file_mappings
+BEGIN
+    UPDATE storage_totals
+    SET total_files = total_files
+            - (CASE WHEN OLD.purged_at IS NULL THEN 1 ELSE 0 END)
+            + (CASE WHEN NEW.purged_at IS NULL THEN 1 ELSE 0 END),
+        total_size = total_size
+            - (CASE WHEN OLD.purged_at IS NULL THEN OLD.file_size ELSE 0 END)
+            + (CASE WHEN NEW.purged_at IS NULL THEN NEW.file_size ELSE 0 END),
+        memory_files = memory_files
+            - (CASE WHEN OLD.purged_at IS NULL AND OLD.is_in_memory THEN 1 ELSE 0 END)
+            + (CASE WHEN NEW.purged_at IS NULL AND NEW.is_in_memory THEN 1 ELSE 0 END),
+        memory_size = memory_size
+            - (CASE WHEN OLD.purged_at IS NULL AND OLD.is_in_memory THEN OLD.file_size ELSE 0 END)
+            + (CASE WHEN NEW.purged_at IS NULL AND NEW.is_in_memory THEN NEW.file_size ELSE 0 END);
+END;
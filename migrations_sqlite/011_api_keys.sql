@@ -0,0 +1,17 @@
+-- API keys, each with its own files, storage quota and rate limit multiplier.
+-- Only a hash of each key is kept; the key itself is shown once, when created.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id BLOB PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE, -- SHA-256 of the key, hex
+    quota_bytes INTEGER, -- NULL for no limit
+    quota_files INTEGER,
+    rate_limit_multiplier REAL NOT NULL DEFAULT 1,
+    created_at TEXT NOT NULL,
+    revoked_at TEXT
+);
+
+-- The key a file was uploaded with; NULL for anonymous uploads
+ALTER TABLE file_mappings ADD COLUMN api_key_id BLOB;
+
+CREATE INDEX IF NOT EXISTS idx_file_mappings_api_key_id ON file_mappings(api_key_id);
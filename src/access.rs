@@ -0,0 +1,209 @@
+use axum::{body::Body, http::StatusCode, response::Response};
+use chrono::{NaiveDate, Utc};
+use futures_util::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::database::DailyTraffic;
+
+// How often buffered downloads are written to the database
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// Buffered downloads that trigger a write without waiting for the interval
+const FLUSH_BATCH: usize = 1000;
+
+/// Downloads of files without a download limit, counted in memory and
+/// written to the database in batches. Files with a limit are counted as
+/// they are served, since the count decides whether they may be.
+#[derive(Clone, Debug, Default)]
+pub struct AccessCounts {
+    pending: Arc<Mutex<PendingAccesses>>,
+}
+
+#[derive(Debug, Default)]
+struct PendingAccesses {
+    counts: HashMap<Uuid, i32>,
+    events: usize, // Downloads since the last flush
+    traffic: BTreeMap<NaiveDate, (i64, i64)>, // Downloads and bytes served per UTC day
+}
+
+impl AccessCounts {
+    // Add counts back, e.g. after a failed write
+    fn restore(&self, counts: Vec<(Uuid, i32)>) {
+        if let Ok(mut pending) = self.pending.lock() {
+            for (id, downloads) in counts {
+                *pending.counts.entry(id).or_default() += downloads;
+            }
+        }
+    }
+
+    fn add_traffic(&self, day: NaiveDate, downloads: i64, bytes_served: i64) {
+        match self.pending.lock() {
+            Ok(mut pending) => {
+                let totals = pending.traffic.entry(day).or_default();
+                totals.0 += downloads;
+                totals.1 += bytes_served;
+            }
+            Err(e) => error!("Failed to acquire lock on access counts: {}", e),
+        }
+    }
+
+    /// Daily traffic not yet written to the database, oldest day first.
+    /// Without a database this is all traffic since startup.
+    pub fn pending_traffic(&self) -> Vec<DailyTraffic> {
+        match self.pending.lock() {
+            Ok(pending) => daily_traffic(&pending.traffic),
+            Err(e) => {
+                error!("Failed to acquire lock on access counts: {}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn daily_traffic(traffic: &BTreeMap<NaiveDate, (i64, i64)>) -> Vec<DailyTraffic> {
+    traffic
+        .iter()
+        .map(|(day, (downloads, bytes_served))| DailyTraffic {
+            day: day.to_string(),
+            downloads: *downloads,
+            bytes_served: *bytes_served,
+        })
+        .collect()
+}
+
+// Adds the bytes of a download body to its day's total once the body is done
+// with, whether it was sent in full or the client went away
+struct ServedBytes {
+    access_counts: AccessCounts,
+    day: NaiveDate,
+    bytes: i64,
+}
+
+impl Drop for ServedBytes {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            self.access_counts.add_traffic(self.day, 0, self.bytes);
+        }
+    }
+}
+
+/// Count a download response in the daily traffic: a full (200) response
+/// counts as a download, and the body bytes of full and partial responses
+/// as served as they are sent.
+pub fn track_traffic(app_state: &AppState, response: Response) -> Response {
+    if !matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+        return response;
+    }
+    let day = Utc::now().date_naive();
+    if response.status() == StatusCode::OK {
+        app_state.access_counts.add_traffic(day, 1, 0);
+    }
+
+    let mut served = ServedBytes {
+        access_counts: app_state.access_counts.clone(),
+        day,
+        bytes: 0,
+    };
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            // Naming only served.bytes would capture a copy, and the guard would count nothing
+            let served = &mut served;
+            if let Ok(ref data) = chunk {
+                served.bytes += data.len() as i64;
+            }
+            chunk
+        }))
+    })
+}
+
+/// Count a successful download, writing the batch in the background once
+/// enough have built up.
+pub fn record_access(app_state: &AppState, id: Uuid) {
+    let full = match app_state.access_counts.pending.lock() {
+        Ok(mut pending) => {
+            *pending.counts.entry(id).or_default() += 1;
+            pending.events += 1;
+            pending.events >= FLUSH_BATCH
+        }
+        Err(e) => {
+            error!("Failed to acquire lock on access counts: {}", e);
+            return;
+        }
+    };
+
+    if full {
+        let app_state = app_state.clone();
+        tokio::spawn(async move {
+            flush_accesses(&app_state).await;
+        });
+    }
+}
+
+/// Write buffered downloads to the database in one statement, along with
+/// the daily traffic. Both are kept for the next flush while the database
+/// is unavailable. Returns how many files were updated.
+pub async fn flush_accesses(app_state: &AppState) -> usize {
+    let Some(ref db) = app_state.database else {
+        return 0;
+    };
+    if !app_state.database_available() {
+        return 0;
+    }
+
+    let (counts, traffic): (Vec<(Uuid, i32)>, _) = match app_state.access_counts.pending.lock() {
+        Ok(mut pending) => {
+            pending.events = 0;
+            (pending.counts.drain().collect(), std::mem::take(&mut pending.traffic))
+        }
+        Err(e) => {
+            error!("Failed to acquire lock on access counts during flush: {}", e);
+            return 0;
+        }
+    };
+
+    if !traffic.is_empty()
+        && let Err(e) = db.record_traffic(&daily_traffic(&traffic)).await
+    {
+        warn!("Failed to record daily traffic, retrying on the next flush: {}", e);
+        app_state.set_database_healthy(false);
+        for (day, (downloads, bytes_served)) in traffic {
+            app_state.access_counts.add_traffic(day, downloads, bytes_served);
+        }
+        app_state.access_counts.restore(counts);
+        return 0;
+    }
+
+    if counts.is_empty() {
+        return 0;
+    }
+
+    match db.record_accesses(&counts).await {
+        Ok(()) => counts.len(),
+        Err(e) => {
+            warn!("Failed to record downloads, retrying on the next flush: {}", e);
+            app_state.set_database_healthy(false);
+            app_state.access_counts.restore(counts);
+            0
+        }
+    }
+}
+
+/// Spawn the loop that writes buffered downloads every few seconds.
+pub fn spawn_access_flush_task(app_state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            let flushed = flush_accesses(&app_state).await;
+            if flushed > 0 {
+                info!("Recorded downloads of {} files", flushed);
+            }
+        }
+    })
+}
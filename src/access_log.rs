@@ -0,0 +1,231 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Context, Result, bail};
+use futures_util::StreamExt;
+use serde_json::json;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{LineWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+use crate::{AppState, Config, client_ip::get_client_ip, request_id::X_REQUEST_ID};
+
+/// How each access log line is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    Json,
+    Combined, // Apache combined, with the duration and request id appended
+}
+
+/// Parse `json`, `combined`, or `off` (or empty) for no access log.
+pub fn parse_format(value: &str) -> Result<Option<AccessLogFormat>> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "off" => Ok(None),
+        "json" => Ok(Some(AccessLogFormat::Json)),
+        "combined" => Ok(Some(AccessLogFormat::Combined)),
+        _ => bail!("expected json, combined or off, got '{}'", value),
+    }
+}
+
+/// Where access log lines go: one line per request, written whole.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sink: Arc<Mutex<LineWriter<Box<dyn Write + Send>>>>,
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLog").field("format", &self.format).finish_non_exhaustive()
+    }
+}
+
+impl AccessLog {
+    /// The access log `access_log_format` asks for, writing to
+    /// `access_log_path` or stdout. Fails if the file can't be opened.
+    pub fn open(config: &Config) -> Result<Option<Self>> {
+        let Some(format) = config.access_log_format else {
+            return Ok(None);
+        };
+        let sink: Box<dyn Write + Send> = match config.access_log_path {
+            Some(ref path) => Box::new(open_append(path)?),
+            None => Box::new(std::io::stdout()),
+        };
+        Ok(Some(Self::new(format, sink)))
+    }
+
+    pub fn new(format: AccessLogFormat, sink: Box<dyn Write + Send>) -> Self {
+        Self {
+            format,
+            sink: Arc::new(Mutex::new(LineWriter::new(sink))),
+        }
+    }
+
+    fn write(&self, entry: &Entry) {
+        let line = match self.format {
+            AccessLogFormat::Json => entry.json(),
+            AccessLogFormat::Combined => entry.combined(),
+        };
+        let mut sink = self.sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = writeln!(sink, "{}", line) {
+            error!("Failed to write access log: {}", e);
+        }
+    }
+}
+
+fn open_append(path: &Path) -> Result<std::fs::File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open access log {}", path.display()))
+}
+
+// One request, as logged. Bodies never are.
+struct Entry {
+    at: DateTime<Utc>,
+    started: Instant,
+    method: String,
+    path: String, // Includes the file id or short code; never the query string
+    version: String,
+    status: u16,
+    bytes: u64, // Response body bytes actually sent
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    request_id: Option<String>,
+}
+
+impl Entry {
+    fn duration(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn json(&self) -> String {
+        json!({
+            "timestamp": self.at.to_rfc3339(),
+            "method": self.method,
+            "path": self.path,
+            "status": self.status,
+            "bytes": self.bytes,
+            "duration_ms": self.duration().as_millis() as u64,
+            "client_ip": self.client_ip,
+            "user_agent": self.user_agent,
+            "request_id": self.request_id,
+        })
+        .to_string()
+    }
+
+    fn combined(&self) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\" {}ms {}",
+            self.client_ip.as_deref().unwrap_or("-"),
+            self.at.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.path,
+            self.version,
+            self.status,
+            if self.bytes == 0 { "-".to_string() } else { self.bytes.to_string() },
+            quoted(self.referer.as_deref()),
+            quoted(self.user_agent.as_deref()),
+            self.duration().as_millis(),
+            self.request_id.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+// A header value inside a quoted combined-format field
+fn quoted(value: Option<&str>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn header_text(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+// Logs its entry once the response body is done with, sent in full or not
+struct PendingEntry {
+    entry: Entry,
+    log: Option<AccessLog>,
+    slow_after: Option<Duration>,
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        if let Some(ref log) = self.log {
+            log.write(&self.entry);
+        }
+        let duration = self.entry.duration();
+        if self.slow_after.is_some_and(|threshold| duration > threshold) {
+            let entry = &self.entry;
+            warn!(
+                method = %entry.method,
+                path = %entry.path,
+                status = entry.status,
+                bytes = entry.bytes,
+                duration_ms = duration.as_millis() as u64,
+                client_ip = entry.client_ip.as_deref().unwrap_or("-"),
+                user_agent = entry.user_agent.as_deref().unwrap_or("-"),
+                request_id = entry.request_id.as_deref().unwrap_or("-"),
+                "Slow request"
+            );
+        }
+    }
+}
+
+/// Middleware writing one access log line per request, and a warning for
+/// requests slower than `slow_request_threshold`. The line is written when
+/// the response body finishes, so the size and duration cover the whole
+/// transfer; a client that disconnects is logged with what it got.
+pub async fn log_request(State(app_state): State<AppState>, request: Request, next: Next) -> Response {
+    let log = app_state.access_log.clone();
+    let slow_after = app_state.config.slow_request_threshold;
+    if log.is_none() && slow_after.is_none() {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers();
+    let client_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| get_client_ip(&app_state.config, *addr, headers).to_string());
+    let mut entry = Entry {
+        at: Utc::now(),
+        started: Instant::now(),
+        method: request.method().to_string(),
+        path: request.uri().path().to_string(),
+        version: format!("{:?}", request.version()),
+        status: 0,
+        bytes: 0,
+        client_ip,
+        user_agent: header_text(headers, header::USER_AGENT),
+        referer: header_text(headers, header::REFERER),
+        request_id: None,
+    };
+
+    let response = next.run(request).await;
+    entry.status = response.status().as_u16();
+    entry.request_id = header_text(response.headers(), X_REQUEST_ID);
+
+    let mut pending = PendingEntry { entry, log, slow_after };
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            // Borrow the whole entry so the closure owns it, not just a copy of its byte count
+            let pending = &mut pending;
+            if let Ok(ref bytes) = chunk {
+                pending.entry.bytes += bytes.len() as u64;
+            }
+            chunk
+        }))
+    })
+}
@@ -0,0 +1,652 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::{JsonRejection, QueryRejection}},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::{error, info, instrument, warn};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::database::{FileCursor, FileListQuery, FileMapping, FileSort};
+use crate::openapi::ErrorResponse;
+use crate::storage::StorageRef;
+use crate::{
+    AppState, FileData, invalidate_cached_file, recovery, remove_mapped_file, remove_memory_file, resolve_id_or_short_code_db,
+    token_matches,
+};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Check the `X-Admin-Token` header. Admin endpoints don't exist (404) unless
+/// `DROP_ADMIN_TOKEN` is configured.
+pub fn authorize(app_state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(ref admin_token) = app_state.config.admin_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let provided_token = headers
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok());
+    if !token_matches(admin_token, provided_token) {
+        warn!("Rejected admin request: invalid admin token");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FileListParams {
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    created_before: Option<DateTime<Utc>>,
+    created_after: Option<DateTime<Utc>>,
+    content_type: Option<String>, // Prefix, e.g. "image/"
+    in_memory: Option<bool>,
+    pinned: Option<bool>,
+    api_key_id: Option<Uuid>, // Only files uploaded with this API key
+    sort: Option<String>,  // size, created_at (default) or access_count
+    order: Option<String>, // asc or desc (default)
+    limit: Option<usize>,
+    cursor: Option<String>, // next_cursor from the previous page
+}
+
+/// A stored file as seen by an operator.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AdminFile {
+    pub id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub is_in_memory: bool,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accessed_at: Option<DateTime<Utc>>, // Not tracked without the database
+    pub access_count: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader_ip: Option<String>,
+    pub pinned: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<Uuid>, // The key it was uploaded with
+}
+
+impl AdminFile {
+    fn from_mapping(file_mapping: FileMapping) -> Self {
+        Self {
+            id: file_mapping.id,
+            filename: file_mapping.filename,
+            content_type: file_mapping.content_type,
+            size: file_mapping.file_size.max(0) as u64,
+            is_in_memory: file_mapping.is_in_memory,
+            created_at: file_mapping.created_at,
+            accessed_at: Some(file_mapping.accessed_at),
+            access_count: file_mapping.access_count,
+            expires_at: file_mapping.expires_at,
+            max_downloads: file_mapping.max_downloads,
+            uploader_ip: file_mapping.uploader_ip,
+            pinned: file_mapping.pinned,
+            api_key_id: file_mapping.api_key_id,
+        }
+    }
+
+    fn from_file_data(id: Uuid, file_data: FileData) -> Self {
+        Self {
+            id,
+            is_in_memory: file_data.storage.as_ref().is_some_and(|storage| storage.is_in_memory()),
+            filename: file_data.filename,
+            content_type: file_data.content_type,
+            size: file_data.size,
+            created_at: file_data.created_at,
+            accessed_at: None,
+            access_count: file_data.download_count,
+            expires_at: file_data.expires_at,
+            max_downloads: file_data.max_downloads,
+            uploader_ip: file_data.uploader_ip.map(|ip| ip.to_string()),
+            pinned: file_data.pinned,
+            api_key_id: file_data.api_key_id,
+        }
+    }
+
+    fn sort_key(&self, sort: FileSort) -> i64 {
+        match sort {
+            FileSort::Size => self.size.try_into().unwrap_or(i64::MAX),
+            FileSort::CreatedAt => self.created_at.timestamp_nanos_opt().unwrap_or_default(),
+            FileSort::AccessCount => i64::from(self.access_count),
+        }
+    }
+
+    // Whether the in-memory listing should include this file
+    fn matches(&self, list: &FileListQuery) -> bool {
+        let size = self.size.try_into().unwrap_or(i64::MAX);
+        list.min_size.is_none_or(|min| size >= min)
+            && list.max_size.is_none_or(|max| size <= max)
+            && list.created_before.is_none_or(|before| self.created_at < before)
+            && list.created_after.is_none_or(|after| self.created_at > after)
+            && list
+                .content_type_prefix
+                .as_ref()
+                .is_none_or(|prefix| self.content_type.starts_with(prefix.as_str()))
+            && list.is_in_memory.is_none_or(|in_memory| self.is_in_memory == in_memory)
+            && list.pinned.is_none_or(|pinned| self.pinned == pinned)
+            && list.api_key_id.is_none_or(|api_key_id| self.api_key_id == Some(api_key_id))
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileListResponse {
+    pub files: Vec<AdminFile>,
+    pub next_cursor: Option<String>, // Absent on the last page
+}
+
+fn parse_sort(value: Option<&str>) -> Result<FileSort, StatusCode> {
+    match value {
+        None | Some("created_at") => Ok(FileSort::CreatedAt),
+        Some("size") => Ok(FileSort::Size),
+        Some("access_count") => Ok(FileSort::AccessCount),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+fn sort_name(sort: FileSort) -> &'static str {
+    match sort {
+        FileSort::Size => "size",
+        FileSort::CreatedAt => "created_at",
+        FileSort::AccessCount => "access_count",
+    }
+}
+
+// Cursors read "<sort>.<key>.<id>" so one can't be reused with a different sort
+fn encode_cursor(sort: FileSort, file: &AdminFile) -> String {
+    format!("{}.{}.{}", sort_name(sort), file.sort_key(sort), file.id)
+}
+
+fn decode_cursor(sort: FileSort, cursor: &str) -> Result<FileCursor, StatusCode> {
+    let mut parts = cursor.splitn(3, '.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(name), Some(key), Some(id)) if name == sort_name(sort) => Ok(FileCursor {
+            key: key.parse().map_err(|_| StatusCode::BAD_REQUEST)?,
+            id: id.parse().map_err(|_| StatusCode::BAD_REQUEST)?,
+        }),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+impl FileListParams {
+    pub(crate) fn into_query(self) -> Result<FileListQuery, StatusCode> {
+        let sort = parse_sort(self.sort.as_deref())?;
+        let ascending = match self.order.as_deref() {
+            None | Some("desc") => false,
+            Some("asc") => true,
+            Some(_) => return Err(StatusCode::BAD_REQUEST),
+        };
+        let after = self.cursor.as_deref().map(|cursor| decode_cursor(sort, cursor)).transpose()?;
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+        Ok(FileListQuery {
+            min_size: self.min_size.map(|size| size.try_into().unwrap_or(i64::MAX)),
+            max_size: self.max_size.map(|size| size.try_into().unwrap_or(i64::MAX)),
+            created_before: self.created_before,
+            created_after: self.created_after,
+            content_type_prefix: self.content_type.filter(|prefix| !prefix.is_empty()),
+            is_in_memory: self.in_memory,
+            pinned: self.pinned,
+            api_key_id: self.api_key_id,
+            sort,
+            ascending,
+            after,
+            limit: limit as i64,
+        })
+    }
+}
+
+// Page through the in-memory fallback the same way the database query does
+async fn list_memory_files(app_state: &AppState, list: &FileListQuery) -> Result<Vec<AdminFile>, StatusCode> {
+    let mut files: Vec<AdminFile> = app_state
+        .file_storage
+        .iter()
+        .filter(|entry| entry.purged_at.is_none())
+        .map(|entry| AdminFile::from_file_data(*entry.key(), entry.value().clone()))
+        .filter(|file| file.matches(list))
+        .collect();
+
+    let sort = list.sort;
+    files.sort_by_key(|file| (file.sort_key(sort), file.id));
+    if !list.ascending {
+        files.reverse();
+    }
+    if let Some(cursor) = list.after {
+        let after = (cursor.key, cursor.id);
+        files.retain(|file| {
+            let key = (file.sort_key(sort), file.id);
+            if list.ascending { key > after } else { key < after }
+        });
+    }
+    files.truncate(list.limit as usize);
+    Ok(files)
+}
+
+// GET /admin/files - page through stored files; requires X-Admin-Token
+#[utoipa::path(
+    get,
+    path = "/admin/files",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), FileListParams),
+    responses(
+        (status = 200, description = "One page of files", body = FileListResponse),
+        (status = 400, description = "An invalid filter, sort or cursor", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn list_files(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<FileListParams>, QueryRejection>,
+) -> Result<Json<FileListResponse>, StatusCode> {
+    authorize(&app_state, &headers)?;
+    let Query(params) = params.map_err(|_| StatusCode::BAD_REQUEST)?;
+    let list = params.into_query()?;
+    list_page(&app_state, &list).await.map(Json)
+}
+
+/// One page of the files `list` matches, database first with in-memory fallback.
+pub(crate) async fn list_page(app_state: &AppState, list: &FileListQuery) -> Result<FileListResponse, StatusCode> {
+    let mut files = None;
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.list_file_mappings(list).await {
+            Ok(mappings) => files = Some(mappings.into_iter().map(AdminFile::from_mapping).collect()),
+            Err(e) => {
+                warn!("Database file listing failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+    let files: Vec<AdminFile> = match files {
+        Some(files) => files,
+        None => list_memory_files(app_state, list).await?,
+    };
+
+    // A full page may have more behind it
+    let next_cursor = (files.len() as i64 == list.limit)
+        .then(|| files.last().map(|file| encode_cursor(list.sort, file)))
+        .flatten();
+    Ok(FileListResponse { files, next_cursor })
+}
+
+/// Remove a file from whichever index holds it, without a delete token.
+/// Returns the bytes reclaimed, or None if there was no such file.
+pub(crate) async fn remove_file(app_state: &AppState, id: Uuid) -> Result<Option<u64>, StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) => {
+                // A tombstone's bytes are already gone
+                let size = match file_mapping.purged_at {
+                    Some(_) => 0,
+                    None => file_mapping.file_size.max(0) as u64,
+                };
+                return match remove_mapped_file(app_state, db.as_ref(), &file_mapping).await {
+                    Ok(true) => {
+                        info!("Admin deleted file '{}' with ID: {}", file_mapping.filename, id);
+                        Ok(Some(size))
+                    }
+                    Ok(false) => Ok(None),
+                    Err(e) => {
+                        error!("Failed to delete file mapping from database: {}", e);
+                        Err(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                };
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let file_data = app_state.file_storage.get(&id).map(|file_data| file_data.value().clone());
+    let Some(file_data) = file_data else {
+        return Ok(None);
+    };
+    let size = remove_memory_file(app_state, id, &file_data).await;
+    info!("Admin deleted file '{}' with ID: {}", file_data.filename, id);
+    Ok(Some(size))
+}
+
+// DELETE /admin/files/{id} - delete any file without its delete token; requires X-Admin-Token
+#[utoipa::path(
+    delete,
+    path = "/admin/files/{id}",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), ("id" = String, Path, description = "File ID or short code")),
+    responses(
+        (status = 204, description = "The file was deleted"),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "No such file, or admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn delete_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if let Err(status) = authorize(&app_state, &headers) {
+        return status;
+    }
+    let Some(uuid) = resolve_id_or_short_code_db(&id, &app_state).await else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    match remove_file(&app_state, uuid).await {
+        Ok(Some(_)) => StatusCode::NO_CONTENT,
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(status) => status,
+    }
+}
+
+/// Which files `POST /admin/files/purge` removes. At least one filter is
+/// required; given both, a file has to match both.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PurgeRequest {
+    older_than_days: Option<u64>,
+    ids: Option<Vec<Uuid>>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PurgeParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct PurgeReport {
+    pub dry_run: bool,
+    pub files_removed: usize, // Or that would be, on a dry run
+    pub bytes_reclaimed: u64,
+    pub ids: Vec<Uuid>,
+}
+
+/// A single file by ID, from whichever index holds it; purged tombstones don't count.
+pub(crate) async fn find_file(app_state: &AppState, id: Uuid) -> Result<Option<AdminFile>, StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) if file_mapping.purged_at.is_none() => {
+                return Ok(Some(AdminFile::from_mapping(file_mapping)));
+            }
+            Ok(_) => {
+                // Not (or no longer) in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let list = FileListQuery {
+        limit: i64::MAX,
+        ..FileListQuery::default()
+    };
+    Ok(list_memory_files(app_state, &list).await?.into_iter().find(|file| file.id == id))
+}
+
+// Every file created before `cutoff`, across the database and the in-memory fallback
+async fn files_created_before(app_state: &AppState, cutoff: DateTime<Utc>) -> Result<Vec<AdminFile>, StatusCode> {
+    let mut list = FileListQuery {
+        created_before: Some(cutoff),
+        ascending: true,
+        limit: MAX_PAGE_SIZE as i64,
+        ..FileListQuery::default()
+    };
+    let mut files = Vec::new();
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        loop {
+            let page = match db.list_file_mappings(&list).await {
+                Ok(page) => page,
+                Err(e) => {
+                    error!("Database file listing failed during purge: {}", e);
+                    app_state.set_database_healthy(false);
+                    return Err(StatusCode::SERVICE_UNAVAILABLE);
+                }
+            };
+            let full_page = page.len() as i64 == list.limit;
+            files.extend(page.into_iter().map(AdminFile::from_mapping));
+            match files.last() {
+                Some(last) if full_page => {
+                    list.after = Some(FileCursor {
+                        key: last.sort_key(list.sort),
+                        id: last.id,
+                    })
+                }
+                _ => break,
+            }
+        }
+    }
+
+    list.after = None;
+    list.limit = i64::MAX;
+    let known: HashSet<Uuid> = files.iter().map(|file| file.id).collect();
+    let memory_files = list_memory_files(app_state, &list).await?;
+    files.extend(memory_files.into_iter().filter(|file| !known.contains(&file.id)));
+    Ok(files)
+}
+
+// POST /admin/files/purge - delete files by age or ID; requires X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/admin/files/purge",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), PurgeParams),
+    request_body = PurgeRequest,
+    responses(
+        (status = 200, description = "What was, or on a dry run would be, deleted", body = PurgeReport),
+        (status = 400, description = "Neither `older_than_days` nor `ids`, or a malformed request", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn purge_files(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<PurgeParams>, QueryRejection>,
+    request: Result<Json<PurgeRequest>, JsonRejection>,
+) -> Result<Json<PurgeReport>, StatusCode> {
+    authorize(&app_state, &headers)?;
+    let Query(params) = params.map_err(|_| StatusCode::BAD_REQUEST)?;
+    let Json(request) = request.map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let cutoff = match request.older_than_days {
+        Some(days) => {
+            let age = chrono::Duration::try_days(days.try_into().unwrap_or(i64::MAX)).ok_or(StatusCode::BAD_REQUEST)?;
+            Some(Utc::now().checked_sub_signed(age).ok_or(StatusCode::BAD_REQUEST)?)
+        }
+        None => None,
+    };
+
+    let candidates = match (&request.ids, cutoff) {
+        (Some(ids), _) => {
+            let mut files = Vec::new();
+            let mut seen = HashSet::new();
+            for id in ids.iter().filter(|id| seen.insert(**id)) {
+                if let Some(file) = find_file(&app_state, *id).await?
+                    && cutoff.is_none_or(|cutoff| file.created_at < cutoff)
+                {
+                    files.push(file);
+                }
+            }
+            files
+        }
+        (None, Some(cutoff)) => files_created_before(&app_state, cutoff).await?,
+        // Refuse to purge everything by accident
+        (None, None) => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let mut report = PurgeReport {
+        dry_run: request.dry_run || params.dry_run,
+        ..PurgeReport::default()
+    };
+    for file in candidates {
+        let reclaimed = if report.dry_run {
+            Some(file.size)
+        } else {
+            remove_file(&app_state, file.id).await?
+        };
+        // Someone else may have deleted it in the meantime
+        if let Some(bytes) = reclaimed {
+            report.files_removed += 1;
+            report.bytes_reclaimed += bytes;
+            report.ids.push(file.id);
+        }
+    }
+
+    info!(
+        "Admin purge {} {} files, {} bytes",
+        if report.dry_run { "would remove" } else { "removed" },
+        report.files_removed,
+        report.bytes_reclaimed
+    );
+    Ok(Json(report))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PinResponse {
+    pub id: Uuid,
+    pub pinned: bool,
+}
+
+// Pin or unpin a file in whichever index holds it. False if there is no such
+// file or it has been purged.
+async fn set_pinned(app_state: &AppState, id: Uuid, pinned: bool) -> Result<bool, StatusCode> {
+    let mut file_path = None;
+    let mut stored = None;
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) => {
+                if let Some(StorageRef::Disk(path)) = StorageRef::from_mapping(&file_mapping, &app_state.config.temp_directory) {
+                    file_path = Some(path);
+                }
+                stored = Some(db.set_file_pinned(id, pinned).await.map_err(|e| {
+                    error!("Failed to pin file in database: {}", e);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?);
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let stored = match stored {
+        Some(stored) => stored,
+        None => match app_state.file_storage.get_mut(&id) {
+            Some(mut file_data) if file_data.purged_at.is_none() => {
+                file_data.pinned = pinned;
+                if let Some(StorageRef::Disk(ref path)) = file_data.storage {
+                    file_path = Some(path.clone());
+                }
+                true
+            }
+            _ => false,
+        },
+    };
+    if !stored {
+        return Ok(false);
+    }
+
+    invalidate_cached_file(app_state, id);
+    if let Some(file_path) = file_path
+        && let Err(e) = recovery::update_sidecar_pinned(&file_path, pinned).await
+    {
+        warn!("Failed to update sidecar for {}, a restart would undo the pin: {:?}", id, e);
+    }
+    Ok(true)
+}
+
+async fn pin_request(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    id: &str,
+    pinned: bool,
+) -> Result<Json<PinResponse>, StatusCode> {
+    authorize(app_state, headers)?;
+    let uuid = resolve_id_or_short_code_db(id, app_state).await.ok_or(StatusCode::NOT_FOUND)?;
+    if !set_pinned(app_state, uuid, pinned).await? {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    info!("Admin {} file {}", if pinned { "pinned" } else { "unpinned" }, uuid);
+    Ok(Json(PinResponse { id: uuid, pinned }))
+}
+
+// POST /admin/files/{id}/pin - keep a file whatever its expiry or age; requires X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/admin/files/{id}/pin",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), ("id" = String, Path, description = "File ID or short code")),
+    responses(
+        (status = 200, description = "The file is pinned", body = PinResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "No such file, or admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn pin_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<PinResponse>, StatusCode> {
+    pin_request(&app_state, &headers, &id, true).await
+}
+
+// DELETE /admin/files/{id}/pin - return a file to the usual expiry rules; requires X-Admin-Token
+#[utoipa::path(
+    delete,
+    path = "/admin/files/{id}/pin",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), ("id" = String, Path, description = "File ID or short code")),
+    responses(
+        (status = 200, description = "The file is no longer pinned", body = PinResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "No such file, or admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn unpin_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<PinResponse>, StatusCode> {
+    pin_request(&app_state, &headers, &id, false).await
+}
@@ -0,0 +1,225 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    AppState, UploadResponse, admin, openapi::ErrorResponse, claim_short_code, file_data_is_gone, mapping_is_gone, public_base_url,
+    resolve_id_or_short_code_db, token_matches,
+};
+
+const MIN_ALIAS_LEN: usize = 3;
+const MAX_ALIAS_LEN: usize = 64;
+// Segments fixed routes under /drop use, where an alias would never be reached
+const RESERVED_ALIASES: &[&str] = &["admin", "bundle", "by-hash", "delete", "info", "sessions"];
+
+#[derive(Serialize, ToSchema)]
+pub struct AliasErrorResponse {
+    error: String,
+    alias: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AddAliasRequest {
+    alias: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AliasResponse {
+    alias: String,
+    short_url: String,
+}
+
+fn alias_error(status: StatusCode, error: &str, alias: &str) -> Response {
+    let body = AliasErrorResponse {
+        error: error.to_string(),
+        alias: alias.to_string(),
+    };
+    (status, Json(body)).into_response()
+}
+
+fn is_alias_byte(b: u8) -> bool {
+    b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_'
+}
+
+/// Whether `input` has the shape of a short code: generated codes and
+/// aliases alike are 3-64 characters of `[a-z0-9-_]`.
+pub fn is_short_code_syntax(input: &str) -> bool {
+    (MIN_ALIAS_LEN..=MAX_ALIAS_LEN).contains(&input.len()) && input.bytes().all(is_alias_byte)
+}
+
+/// What is wrong with a requested alias, if anything: it must be 3-64
+/// characters of `[a-z0-9-_]`, not a UUID and not a name one of the routes uses.
+pub fn alias_problem(alias: &str) -> Option<&'static str> {
+    if !(MIN_ALIAS_LEN..=MAX_ALIAS_LEN).contains(&alias.len()) {
+        Some("Alias must be 3 to 64 characters long")
+    } else if !alias.bytes().all(is_alias_byte) {
+        Some("Alias may only contain a-z, 0-9, '-' and '_'")
+    } else if alias.parse::<Uuid>().is_ok() {
+        Some("Alias must not be a file ID")
+    } else if RESERVED_ALIASES.contains(&alias) {
+        Some("Alias is reserved")
+    } else {
+        None
+    }
+}
+
+/// A requested alias [`alias_problem`] found fault with, answered with a
+/// 400 JSON error.
+pub struct InvalidAlias {
+    problem: &'static str,
+    alias: String,
+}
+
+impl IntoResponse for InvalidAlias {
+    fn into_response(self) -> Response {
+        alias_error(StatusCode::BAD_REQUEST, self.problem, &self.alias)
+    }
+}
+
+/// Check a requested alias with [`alias_problem`].
+pub fn validate_alias(alias: &str) -> Result<(), InvalidAlias> {
+    match alias_problem(alias) {
+        Some(problem) => {
+            warn!("Rejecting alias '{}': {}", alias, problem);
+            Err(InvalidAlias {
+                problem,
+                alias: alias.to_string(),
+            })
+        }
+        None => Ok(()),
+    }
+}
+
+/// Fail with 409 if the alias already points at a file, in the database or
+/// the in-memory fallback.
+pub async fn check_alias_available(app_state: &AppState, alias: &str) -> Result<(), Response> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.get_file_id_by_short_code(alias).await {
+            Ok(Some(_)) => return Err(alias_taken(alias)),
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Database short code lookup failed, checking memory only: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    if app_state.short_url_storage.contains_key(alias) {
+        return Err(alias_taken(alias));
+    }
+    Ok(())
+}
+
+fn alias_taken(alias: &str) -> Response {
+    warn!("Alias '{}' is already taken", alias);
+    alias_error(StatusCode::CONFLICT, "Alias already taken", alias)
+}
+
+/// Give a just-registered upload its alias and link to it. If the alias was
+/// taken in the meantime the upload is removed again and 409 returned.
+pub(crate) async fn attach_alias(
+    app_state: &AppState,
+    alias: &str,
+    file: &mut UploadResponse,
+    base_url: &str,
+) -> Result<(), Response> {
+    let file_id = file.id.parse::<Uuid>().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    match claim_short_code(app_state, alias, file_id).await {
+        Ok(true) => {
+            info!("Stored alias '{}' for file {}", alias, file_id);
+            file.short_url = format!("{}/drop/{}", base_url, alias);
+            Ok(())
+        }
+        result => {
+            if let Err(status) = admin::remove_file(app_state, file_id).await {
+                error!("Failed to remove upload {} after its alias was refused: {}", file_id, status);
+            }
+            match result {
+                Ok(_) => Err(alias_taken(alias)),
+                Err(status) => Err(status.into_response()),
+            }
+        }
+    }
+}
+
+// The delete token of a live file, which also authorizes adding aliases to it
+async fn delete_token(app_state: &AppState, id: Uuid) -> Result<Option<String>, StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) if mapping_is_gone(&file_mapping) => return Ok(None),
+            Ok(Some(file_mapping)) => return Ok(Some(file_mapping.delete_token.unwrap_or_default())),
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    Ok(app_state
+        .file_storage
+        .get(&id)
+        .filter(|file_data| !file_data_is_gone(file_data))
+        .map(|file_data| file_data.delete_token.clone()))
+}
+
+// POST /drop/{id}/aliases - another vanity link for an existing file; requires X-Delete-Token
+#[utoipa::path(
+    post,
+    path = "/drop/{id}/aliases",
+    tag = "files",
+    params(("id" = String, Path, description = "File ID or short code"), ("X-Delete-Token" = String, Header, description = "Returned by the upload")),
+    request_body = AddAliasRequest,
+    responses(
+        (status = 201, description = "The alias now leads to the file", body = AliasResponse),
+        (status = 400, description = "Not a usable alias", body = AliasErrorResponse),
+        (status = 403, description = "Missing or wrong delete token", body = ErrorResponse),
+        (status = 404, description = "No such file", body = ErrorResponse),
+        (status = 409, description = "The alias is already taken", body = AliasErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn add_alias(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<AddAliasRequest>,
+) -> Result<(StatusCode, Json<AliasResponse>), Response> {
+    let alias = request.alias;
+    validate_alias(&alias).map_err(IntoResponse::into_response)?;
+
+    let file_id = resolve_id_or_short_code_db(&id, &app_state)
+        .await
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+    let expected = delete_token(&app_state, file_id)
+        .await
+        .map_err(IntoResponse::into_response)?
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+    let provided_token = headers.get("x-delete-token").and_then(|v| v.to_str().ok());
+    if !token_matches(&expected, provided_token) {
+        warn!("Rejected alias for {}: invalid delete token", file_id);
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+
+    if !claim_short_code(&app_state, &alias, file_id).await.map_err(IntoResponse::into_response)? {
+        return Err(alias_taken(&alias));
+    }
+    info!("Stored alias '{}' for file {}", alias, file_id);
+
+    let short_url = format!("{}/drop/{}", public_base_url(&app_state.config, &headers), alias);
+    Ok((StatusCode::CREATED, Json(AliasResponse { alias, short_url })))
+}
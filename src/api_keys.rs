@@ -0,0 +1,530 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State, rejection::{JsonRejection, QueryRejection}},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::admin::{self, FileListParams, FileListResponse};
+use crate::database::{ApiKey, ApiKeyUsage};
+use crate::metadata::MetadataStore;
+use crate::openapi::ErrorResponse;
+use crate::{AppState, resolve_id_or_short_code_db};
+
+// Prefix of every key, so a leaked one is easy to recognise
+const KEY_PREFIX: &str = "drop_";
+const MAX_NAME_LEN: usize = 128;
+
+// A new secret: two random UUIDs' worth of hex after the prefix
+fn generate_key() -> String {
+    format!("{}{}{}", KEY_PREFIX, Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+// What `generate_key` makes: the prefix, then 64 hex digits
+fn is_well_formed(key: &str) -> bool {
+    key.strip_prefix(KEY_PREFIX)
+        .is_some_and(|secret| secret.len() == 64 && secret.bytes().all(|byte| byte.is_ascii_hexdigit()))
+}
+
+// Keys are looked up by this, so the database never holds one that works
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+// The database, which keys need; 503 without one
+fn database(app_state: &AppState) -> Result<&dyn MetadataStore, StatusCode> {
+    match app_state.database {
+        Some(ref db) if app_state.database_available() => Ok(db.as_ref()),
+        _ => Err(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+// 401 asking for a bearer key
+fn unauthorized() -> Response {
+    let mut response = StatusCode::UNAUTHORIZED.into_response();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+/// The API key a request carries as `Authorization: Bearer drop_<key>`, or
+/// None when it carries none. Other schemes and other bearer tokens, such as
+/// credentials for a proxy in front, leave the request anonymous. A
+/// malformed, unknown or revoked key is refused with 401, and 503 when the
+/// database can't be asked.
+pub async fn authenticate(app_state: &AppState, headers: &HeaderMap) -> Result<Option<ApiKey>, Response> {
+    let Some(key) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, token)| token.trim())
+        .filter(|token| token.starts_with(KEY_PREFIX))
+    else {
+        return Ok(None);
+    };
+    if !is_well_formed(key) {
+        warn!("Rejected request: malformed API key");
+        return Err(unauthorized());
+    }
+
+    let db = database(app_state).map_err(IntoResponse::into_response)?;
+    match db.find_api_key_by_hash(&hash_key(key)).await {
+        Ok(Some(api_key)) if api_key.revoked_at.is_none() => Ok(Some(api_key)),
+        Ok(Some(api_key)) => {
+            warn!("Rejected request: API key {} has been revoked", api_key.id);
+            Err(unauthorized())
+        }
+        Ok(None) => {
+            warn!("Rejected request: unknown API key");
+            Err(unauthorized())
+        }
+        Err(e) => {
+            error!("API key lookup failed: {}", e);
+            app_state.set_database_healthy(false);
+            Err(StatusCode::SERVICE_UNAVAILABLE.into_response())
+        }
+    }
+}
+
+/// The key an upload is made with. Without one the upload is anonymous,
+/// unless `DROP_ANONYMOUS_UPLOADS` is off, when it is refused with 401.
+pub async fn upload_key(app_state: &AppState, headers: &HeaderMap) -> Result<Option<ApiKey>, Response> {
+    let api_key = authenticate(app_state, headers).await?;
+    if api_key.is_none() && !app_state.config.anonymous_uploads {
+        warn!("Rejected anonymous upload: an API key is required");
+        return Err(unauthorized());
+    }
+    Ok(api_key)
+}
+
+// The key a /my request must carry
+async fn require_key(app_state: &AppState, headers: &HeaderMap) -> Result<ApiKey, Response> {
+    authenticate(app_state, headers).await?.ok_or_else(unauthorized)
+}
+
+async fn usage(app_state: &AppState, api_key: &ApiKey) -> Result<ApiKeyUsage, StatusCode> {
+    let db = database(app_state)?;
+    db.api_key_usage(api_key.id).await.map_err(|e| {
+        error!("Failed to get usage of API key {}: {}", api_key.id, e);
+        app_state.set_database_healthy(false);
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct KeyQuotaExceededResponse {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quota_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quota_files: Option<u64>,
+    remaining_bytes: Option<u64>, // None for no byte quota
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_files: Option<u64>, // Left out when the refusal was over bytes alone
+}
+
+/// The 429 sent when an upload would take a key past its quota.
+pub fn quota_exceeded(api_key: &ApiKey, remaining_bytes: Option<u64>, remaining_files: Option<u64>) -> Response {
+    warn!(
+        "Storage quota exceeded for API key {} ({:?} bytes, {:?} files left)",
+        api_key.id, remaining_bytes, remaining_files
+    );
+    let body = KeyQuotaExceededResponse {
+        error: "API key quota exceeded".to_string(),
+        quota_bytes: api_key.quota_bytes.map(|quota| quota.max(0) as u64),
+        quota_files: api_key.quota_files.map(|quota| quota.max(0) as u64),
+        remaining_bytes,
+        remaining_files,
+    };
+    (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response()
+}
+
+/// Bytes `api_key` may still store, or None when it has no byte quota.
+pub async fn remaining_quota(app_state: &AppState, api_key: &ApiKey) -> Result<Option<u64>, StatusCode> {
+    let Some(quota_bytes) = api_key.quota_bytes else {
+        return Ok(None);
+    };
+    let usage = usage(app_state, api_key).await?;
+    Ok(Some(quota_bytes.saturating_sub(usage.bytes).max(0) as u64))
+}
+
+/// Refuse an upload of `files` files and `projected` bytes that won't fit in
+/// what is left of the key's quota. Returns the bytes left, so streaming can
+/// stop there.
+pub async fn check_quota(app_state: &AppState, api_key: &ApiKey, projected: u64, files: usize) -> Result<Option<u64>, Response> {
+    if api_key.quota_bytes.is_none() && api_key.quota_files.is_none() {
+        return Ok(None);
+    }
+    let usage = usage(app_state, api_key).await.map_err(IntoResponse::into_response)?;
+    let remaining_bytes = api_key
+        .quota_bytes
+        .map(|quota| quota.saturating_sub(usage.bytes).max(0) as u64);
+    let remaining_files = api_key
+        .quota_files
+        .map(|quota| quota.saturating_sub(usage.files).max(0) as u64);
+
+    let over_bytes = remaining_bytes.is_some_and(|remaining| remaining == 0 || projected > remaining);
+    let over_files = remaining_files.is_some_and(|remaining| files as u64 > remaining);
+    if over_bytes || over_files {
+        return Err(quota_exceeded(api_key, remaining_bytes, remaining_files));
+    }
+    Ok(remaining_bytes)
+}
+
+/// The key's limits and what its files take up, as admins see them.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiKeyResponse {
+    id: Uuid,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>, // The secret; only in the response that created the key
+    quota_bytes: Option<u64>, // None for no limit
+    quota_files: Option<u64>,
+    rate_limit_multiplier: f64,
+    created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revoked_at: Option<DateTime<Utc>>,
+    used_bytes: u64,
+    used_files: u64,
+}
+
+impl ApiKeyResponse {
+    fn new(api_key: ApiKey, usage: ApiKeyUsage) -> Self {
+        Self {
+            id: api_key.id,
+            name: api_key.name,
+            key: None,
+            quota_bytes: api_key.quota_bytes.map(|quota| quota.max(0) as u64),
+            quota_files: api_key.quota_files.map(|quota| quota.max(0) as u64),
+            rate_limit_multiplier: api_key.rate_limit_multiplier,
+            created_at: api_key.created_at,
+            revoked_at: api_key.revoked_at,
+            used_bytes: usage.bytes.max(0) as u64,
+            used_files: usage.files.max(0) as u64,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiKeyListResponse {
+    keys: Vec<ApiKeyResponse>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    #[serde(default)]
+    quota_bytes: Option<u64>, // Bytes the key's files may take up; no limit if absent
+    #[serde(default)]
+    quota_files: Option<u64>,
+    #[serde(default)]
+    rate_limit_multiplier: Option<f64>, // Scales the upload rate limit; 1 if absent
+}
+
+/// New limits for a key. Fields left out are unchanged, and a quota set to
+/// null is lifted.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateApiKeyRequest {
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<u64>)]
+    quota_bytes: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<u64>)]
+    quota_files: Option<Option<u64>>,
+    #[serde(default)]
+    rate_limit_multiplier: Option<f64>,
+}
+
+// Tells a field sent as null (Some(None)) from one left out (None)
+fn present<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+fn quota_value(quota: Option<u64>) -> Result<Option<i64>, StatusCode> {
+    quota.map(|quota| i64::try_from(quota).map_err(|_| StatusCode::BAD_REQUEST)).transpose()
+}
+
+fn check_multiplier(multiplier: f64) -> Result<f64, StatusCode> {
+    if multiplier.is_finite() && multiplier > 0.0 {
+        Ok(multiplier)
+    } else {
+        warn!("Invalid rate limit multiplier: {}", multiplier);
+        Err(StatusCode::BAD_REQUEST)
+    }
+}
+
+fn key_id(id: &str) -> Result<Uuid, StatusCode> {
+    id.parse().map_err(|_| StatusCode::NOT_FOUND)
+}
+
+async fn key_response(app_state: &AppState, api_key: ApiKey) -> Result<ApiKeyResponse, StatusCode> {
+    let usage = usage(app_state, &api_key).await?;
+    Ok(ApiKeyResponse::new(api_key, usage))
+}
+
+// POST /admin/api-keys - create a key; requires X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/admin/api-keys",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`")),
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 201, description = "The key, with its secret; it isn't shown again", body = ApiKeyResponse),
+        (status = 400, description = "An empty or overlong name, or an invalid limit", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn create_api_key(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    request: Result<Json<CreateApiKeyRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<ApiKeyResponse>), StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    let Json(request) = request.map_err(|_| StatusCode::BAD_REQUEST)?;
+    let name = request.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        warn!("Rejecting API key name of {} characters", name.chars().count());
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let api_key = ApiKey {
+        id: app_state.ids.uuid(),
+        name,
+        quota_bytes: quota_value(request.quota_bytes)?,
+        quota_files: quota_value(request.quota_files)?,
+        rate_limit_multiplier: check_multiplier(request.rate_limit_multiplier.unwrap_or(1.0))?,
+        created_at: Utc::now(),
+        revoked_at: None,
+    };
+
+    let key = generate_key();
+    let db = database(&app_state)?;
+    if let Err(e) = db.store_api_key(&api_key, &hash_key(&key)).await {
+        error!("Failed to store API key: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    info!("Admin created API key {} ('{}')", api_key.id, api_key.name);
+
+    let mut response = ApiKeyResponse::new(api_key, ApiKeyUsage::default());
+    response.key = Some(key);
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+// GET /admin/api-keys - every key with its usage; requires X-Admin-Token
+#[utoipa::path(
+    get,
+    path = "/admin/api-keys",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`")),
+    responses(
+        (status = 200, description = "Every key, revoked ones included, oldest first", body = ApiKeyListResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn list_api_keys(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<ApiKeyListResponse>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    let db = database(&app_state)?;
+    let api_keys = db.list_api_keys().await.map_err(|e| {
+        error!("Failed to list API keys: {}", e);
+        app_state.set_database_healthy(false);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+
+    let mut keys = Vec::with_capacity(api_keys.len());
+    for api_key in api_keys {
+        keys.push(key_response(&app_state, api_key).await?);
+    }
+    Ok(Json(ApiKeyListResponse { keys }))
+}
+
+// PATCH /admin/api-keys/{id} - change a key's quotas or rate limit multiplier; requires X-Admin-Token
+#[utoipa::path(
+    patch,
+    path = "/admin/api-keys/{id}",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), ("id" = Uuid, Path, description = "The key's ID")),
+    request_body = UpdateApiKeyRequest,
+    responses(
+        (status = 200, description = "The key with its new limits", body = ApiKeyResponse),
+        (status = 400, description = "An invalid limit or a malformed request", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "No such key, it was revoked, or admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn update_api_key(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    request: Result<Json<UpdateApiKeyRequest>, JsonRejection>,
+) -> Result<Json<ApiKeyResponse>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    let Json(request) = request.map_err(|_| StatusCode::BAD_REQUEST)?;
+    let id = key_id(&id)?;
+    let db = database(&app_state)?;
+
+    let mut api_key = match db.find_api_key(id).await {
+        Ok(Some(api_key)) if api_key.revoked_at.is_none() => api_key,
+        Ok(_) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to find API key {}: {}", id, e);
+            app_state.set_database_healthy(false);
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    };
+    if let Some(quota_bytes) = request.quota_bytes {
+        api_key.quota_bytes = quota_value(quota_bytes)?;
+    }
+    if let Some(quota_files) = request.quota_files {
+        api_key.quota_files = quota_value(quota_files)?;
+    }
+    if let Some(multiplier) = request.rate_limit_multiplier {
+        api_key.rate_limit_multiplier = check_multiplier(multiplier)?;
+    }
+
+    match db.update_api_key_limits(&api_key).await {
+        Ok(true) => {}
+        // Revoked in the meantime
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to update API key {}: {}", id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    info!(
+        "Admin set limits of API key {}: {:?} bytes, {:?} files, rate x{}",
+        id, api_key.quota_bytes, api_key.quota_files, api_key.rate_limit_multiplier
+    );
+    key_response(&app_state, api_key).await.map(Json)
+}
+
+// DELETE /admin/api-keys/{id} - revoke a key, keeping its files; requires X-Admin-Token
+#[utoipa::path(
+    delete,
+    path = "/admin/api-keys/{id}",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), ("id" = Uuid, Path, description = "The key's ID")),
+    responses(
+        (status = 204, description = "The key no longer authenticates"),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "No such key, it was already revoked, or admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn revoke_api_key(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> StatusCode {
+    if let Err(status) = admin::authorize(&app_state, &headers) {
+        return status;
+    }
+    let Ok(id) = key_id(&id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let db = match database(&app_state) {
+        Ok(db) => db,
+        Err(status) => return status,
+    };
+
+    match db.revoke_api_key(id).await {
+        Ok(true) => {
+            info!("Admin revoked API key {}", id);
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Failed to revoke API key {}: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// GET /my/files - page through the files uploaded with the key sent
+#[utoipa::path(
+    get,
+    path = "/my/files",
+    tag = "files",
+    params(("Authorization" = String, Header, description = "`Bearer <key>`"), FileListParams),
+    responses(
+        (status = 200, description = "One page of the key's files", body = FileListResponse),
+        (status = 400, description = "An invalid filter, sort or cursor", body = ErrorResponse),
+        (status = 401, description = "No key, or an unknown or revoked one", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn list_my_files(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<FileListParams>, QueryRejection>,
+) -> Result<Json<FileListResponse>, Response> {
+    let api_key = require_key(&app_state, &headers).await?;
+    let Query(params) = params.map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+    let mut list = params.into_query().map_err(IntoResponse::into_response)?;
+    list.api_key_id = Some(api_key.id);
+
+    admin::list_page(&app_state, &list)
+        .await
+        .map(Json)
+        .map_err(IntoResponse::into_response)
+}
+
+// DELETE /my/files/{id} - delete a file uploaded with the key sent, without its delete token
+#[utoipa::path(
+    delete,
+    path = "/my/files/{id}",
+    tag = "files",
+    params(("Authorization" = String, Header, description = "`Bearer <key>`"), ("id" = String, Path, description = "File ID or short code")),
+    responses(
+        (status = 204, description = "The file was deleted"),
+        (status = 401, description = "No key, or an unknown or revoked one", body = ErrorResponse),
+        (status = 404, description = "No such file among the key's", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn delete_my_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, Response> {
+    let api_key = require_key(&app_state, &headers).await?;
+    let uuid = resolve_id_or_short_code_db(&id, &app_state)
+        .await
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+
+    // Another key's files are as good as missing
+    match admin::find_file(&app_state, uuid).await.map_err(IntoResponse::into_response)? {
+        Some(file) if file.api_key_id == Some(api_key.id) => {}
+        _ => return Err(StatusCode::NOT_FOUND.into_response()),
+    }
+    match admin::remove_file(&app_state, uuid).await.map_err(IntoResponse::into_response)? {
+        Some(_) => {
+            info!("API key {} deleted file {}", api_key.id, uuid);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(StatusCode::NOT_FOUND.into_response()),
+    }
+}
@@ -0,0 +1,171 @@
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::database::{Database, FileMapping, ShortUrl};
+use crate::storage::{recorded_path, resolve_recorded_path};
+
+// Rows read from the database per query while exporting
+const PAGE_SIZE: i64 = 1000;
+
+/// One line of a backup. Files are written before short codes, so each short
+/// code's file is already in place when it is imported.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BackupRecord {
+    File(Box<FileMapping>),
+    ShortUrl(ShortUrl),
+}
+
+/// `--rewrite-prefix OLD=NEW`: file paths under `OLD` are moved under `NEW`,
+/// e.g. after the temp directory was moved to another disk.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrefixRewrite {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl FromStr for PrefixRewrite {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let (from, to) = value
+            .split_once('=')
+            .filter(|(from, to)| !from.is_empty() && !to.is_empty())
+            .ok_or_else(|| format!("expected OLD=NEW, got '{}'", value))?;
+        Ok(Self {
+            from: PathBuf::from(from),
+            to: PathBuf::from(to),
+        })
+    }
+}
+
+impl PrefixRewrite {
+    // `path` moved under the new prefix, or None if it isn't under the old one.
+    // Whole components only, so /data/drop doesn't match /data/dropped.
+    fn apply(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.from).ok().map(|rest| self.to.join(rest))
+    }
+}
+
+/// What an export wrote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub files: u64,
+    pub short_urls: u64,
+}
+
+/// What an import did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub files_imported: u64,
+    pub files_skipped: u64, // Their ID was already taken
+    pub short_urls_imported: u64,
+    pub short_urls_skipped: u64, // Taken, or their file isn't there
+    pub missing: Vec<(Uuid, PathBuf)>, // Imported live files whose contents aren't at their path
+}
+
+/// Write every file mapping, tombstones included, then every short code to
+/// `output` as JSON Lines. File contents aren't included.
+pub async fn export(db: &Database, output: &mut impl Write) -> Result<ExportSummary> {
+    let mut summary = ExportSummary::default();
+
+    let mut after = Uuid::nil();
+    loop {
+        let page = db.file_mappings_after(after, PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.id;
+        for file_mapping in page {
+            write_record(output, &BackupRecord::File(Box::new(file_mapping)))?;
+            summary.files += 1;
+        }
+    }
+
+    let mut after = String::new();
+    loop {
+        let page = db.short_urls_after(&after, PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.short_code.clone();
+        for short_url in page {
+            write_record(output, &BackupRecord::ShortUrl(short_url))?;
+            summary.short_urls += 1;
+        }
+    }
+
+    output.flush().context("Failed to write backup")?;
+    Ok(summary)
+}
+
+fn write_record(output: &mut impl Write, record: &BackupRecord) -> Result<()> {
+    serde_json::to_writer(&mut *output, record).context("Failed to write backup")?;
+    output.write_all(b"\n").context("Failed to write backup")
+}
+
+/// Insert the records of a backup, skipping files and short codes that are
+/// already present so an interrupted import can simply be run again. Each
+/// file path is rewritten by the first of `rewrites` it falls under, and
+/// recorded relative to `temp_directory` if it ends up inside it. File
+/// contents are never touched; live files whose contents aren't at their
+/// (rewritten) path are imported anyway and reported as missing.
+pub async fn import(
+    db: &Database,
+    input: impl BufRead,
+    rewrites: &[PrefixRewrite],
+    temp_directory: &Path,
+) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+
+    for (index, line) in input.lines().enumerate() {
+        let line = line.context("Failed to read backup")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: BackupRecord =
+            serde_json::from_str(&line).with_context(|| format!("Invalid backup record on line {}", index + 1))?;
+
+        match record {
+            BackupRecord::File(mut file_mapping) => {
+                let rewritten = file_mapping
+                    .file_path
+                    .as_deref()
+                    .and_then(|file_path| rewrites.iter().find_map(|rewrite| rewrite.apply(Path::new(file_path))));
+                if let Some(rewritten) = rewritten {
+                    let recorded = recorded_path(temp_directory, &rewritten);
+                    file_mapping.file_path = Some(recorded.to_string_lossy().into_owned());
+                }
+
+                if !db.import_file_mapping(&file_mapping).await? {
+                    report.files_skipped += 1;
+                    continue;
+                }
+                report.files_imported += 1;
+
+                // A tombstone's contents are meant to be gone
+                if let (Some(file_path), None) = (file_mapping.file_path, file_mapping.purged_at) {
+                    let file_path = resolve_recorded_path(temp_directory, &file_path);
+                    if !tokio::fs::try_exists(&file_path).await.unwrap_or(false) {
+                        warn!("Contents of {} are missing: {:?}", file_mapping.id, file_path);
+                        report.missing.push((file_mapping.id, file_path));
+                    }
+                }
+            }
+            BackupRecord::ShortUrl(short_url) => {
+                if db.import_short_url(&short_url).await? {
+                    report.short_urls_imported += 1;
+                } else {
+                    report.short_urls_skipped += 1;
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,473 @@
+use async_zip::{Compression, ZipDateTime, ZipEntryBuilder, tokio::write::ZipFileWriter};
+use axum::{
+    Json,
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_util::compat::FuturesAsyncWriteCompatExt;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info, instrument, warn};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    AppState, content_disposition, file_data_is_gone, format_size, get_client_ip, mapping_is_gone,
+    public_base_url, resolve_id_or_short_code_db, concurrency,
+    openapi::ErrorResponse,
+    database::Bundle,
+    rate_limit::{RateLimitAction, RateLimitStatus, check_rate_limit},
+    storage::{FileStore, StorageBackend, StorageRef, StoredObject},
+    throttle, transfer_rate,
+};
+
+// Bundles created while the database is unavailable: bundle_id -> bundle
+pub type BundleStorage = Arc<Mutex<HashMap<Uuid, Bundle>>>;
+
+// Most files a single bundle may name
+const MAX_BUNDLE_FILES: usize = 1000;
+// Bytes buffered between the archive writer and the response body
+const ARCHIVE_BUFFER_SIZE: usize = 64 * 1024;
+// Entry listing the members that were left out of an archive
+const MISSING_ENTRY: &str = "MISSING.txt";
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateBundleRequest {
+    files: Vec<String>, // IDs or short codes, in archive order
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BundleResponse {
+    id: Uuid,
+    short_url: String,
+    full_url: String,
+    files: usize,
+    size: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct BundleParams {
+    format: ArchiveFormat,
+    strict: bool, // Refuse with 404 instead of leaving out members that are gone
+}
+
+// A bundled file that is still available, and where its bytes are
+struct Member {
+    id: Uuid,
+    filename: String,
+    storage_ref: StorageRef,
+    size: u64,
+    max_downloads: Option<i32>,
+    created_at: DateTime<Utc>,
+}
+
+// A live file by ID, from whichever index holds it; None if it is unknown or gone
+async fn find_member(app_state: &AppState, id: Uuid) -> Result<Option<Member>, StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) => {
+                if mapping_is_gone(&file_mapping) {
+                    return Ok(None);
+                }
+                return Ok(StorageRef::from_mapping(&file_mapping, &app_state.config.temp_directory).map(|storage_ref| Member {
+                    id,
+                    size: u64::try_from(file_mapping.file_size).unwrap_or_default(),
+                    filename: file_mapping.filename,
+                    storage_ref,
+                    max_downloads: file_mapping.max_downloads,
+                    created_at: file_mapping.created_at,
+                }));
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let lookup = app_state.file_storage.get(&id).map(|file_data| file_data.value().clone());
+    let Some(file_data) = lookup.filter(|file_data| !file_data_is_gone(file_data)) else {
+        return Ok(None);
+    };
+    let Some(storage_ref) = file_data.storage else {
+        return Ok(None);
+    };
+    let size = app_state.storage.size(&storage_ref).await.map_err(|e| {
+        error!("Failed to read stored file size: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Some(Member {
+        id,
+        filename: file_data.filename,
+        storage_ref,
+        size,
+        max_downloads: file_data.max_downloads,
+        created_at: file_data.created_at,
+    }))
+}
+
+fn check_bundle_size(app_state: &AppState, size: u64) -> Result<(), StatusCode> {
+    if size > app_state.config.max_bundle_size {
+        warn!(
+            "Rejecting bundle: {} exceeds limit of {}",
+            format_size(size as usize),
+            format_size(app_state.config.max_bundle_size as usize)
+        );
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    Ok(())
+}
+
+// Store a bundle - try database first, fallback to memory
+async fn store_bundle(app_state: &AppState, bundle: Bundle) -> Result<(), StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.store_bundle(&bundle).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                warn!("Failed to store bundle in database, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    match app_state.bundle_storage.lock() {
+        Ok(mut storage_guard) => {
+            storage_guard.insert(bundle.id, bundle);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to acquire lock on bundle storage: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Look a bundle up by ID or short code - database first, then memory
+async fn find_bundle(app_state: &AppState, id_or_short_code: &str) -> Result<Option<Bundle>, StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_bundle(id_or_short_code).await {
+            Ok(Some(bundle)) => return Ok(Some(bundle)),
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database bundle lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let by_id = id_or_short_code.parse::<Uuid>().ok();
+    match app_state.bundle_storage.lock() {
+        Ok(storage_guard) => Ok(storage_guard
+            .values()
+            .find(|bundle| Some(bundle.id) == by_id || bundle.short_code == id_or_short_code)
+            .cloned()),
+        Err(e) => {
+            error!("Failed to acquire lock on bundle storage: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// POST /drop/bundle - one link handing out several existing files as an archive
+#[utoipa::path(
+    post,
+    path = "/drop/bundle",
+    tag = "bundles",
+    request_body = CreateBundleRequest,
+    responses(
+        (status = 201, description = "The bundle was created", body = BundleResponse),
+        (status = 400, description = "No files, or too many", body = ErrorResponse),
+        (status = 404, description = "A named file doesn't exist", body = ErrorResponse),
+        (status = 413, description = "The files together are over the bundle size limit", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request), fields(client_ip))]
+pub async fn create_bundle(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<CreateBundleRequest>,
+) -> Result<(StatusCode, Option<RateLimitStatus>, Json<BundleResponse>), Response> {
+    let client_ip = get_client_ip(&app_state.config, addr, &headers);
+    let rate_limit = check_rate_limit(client_ip, RateLimitAction::Upload, &app_state).await?;
+
+    if request.files.is_empty() || request.files.len() > MAX_BUNDLE_FILES {
+        warn!("Rejecting bundle of {} files", request.files.len());
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
+    let mut file_ids = Vec::with_capacity(request.files.len());
+    let mut seen = HashSet::new();
+    let mut size = 0u64;
+    for file in &request.files {
+        let member = match resolve_id_or_short_code_db(file, &app_state).await {
+            Some(id) => find_member(&app_state, id).await.map_err(IntoResponse::into_response)?,
+            None => None,
+        };
+        let Some(member) = member else {
+            warn!("Cannot bundle unknown or unavailable file: {}", file);
+            return Err(StatusCode::NOT_FOUND.into_response());
+        };
+        // Archive downloads aren't counted, so they would get around the limit
+        if member.max_downloads.is_some() {
+            warn!("Cannot bundle file {} with a download limit", member.id);
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        }
+        if seen.insert(member.id) {
+            size += member.size;
+            file_ids.push(member.id);
+        }
+    }
+    check_bundle_size(&app_state, size).map_err(IntoResponse::into_response)?;
+
+    let bundle = Bundle {
+        id: app_state.ids.uuid(),
+        short_code: app_state.ids.short_code(),
+        file_ids,
+        created_at: Utc::now(),
+    };
+    let base_url = public_base_url(&app_state.config, &headers);
+    let response = BundleResponse {
+        id: bundle.id,
+        short_url: format!("{}/drop/bundle/{}", base_url, bundle.short_code),
+        full_url: format!("{}/drop/bundle/{}", base_url, bundle.id),
+        files: bundle.file_ids.len(),
+        size,
+    };
+    info!("Created bundle {} of {} files ({})", bundle.id, response.files, format_size(size as usize));
+    store_bundle(&app_state, bundle)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    Ok((StatusCode::CREATED, rate_limit, Json(response)))
+}
+
+// GET /drop/bundle/{id} - stream the bundled files as a zip (or ?format=tar) archive
+#[utoipa::path(
+    get,
+    path = "/drop/bundle/{id}",
+    tag = "bundles",
+    params(("id" = String, Path, description = "Bundle ID or short code"), BundleParams),
+    responses(
+        (status = 200, description = "The archive", content_type = "application/zip"),
+        (status = 404, description = "No such bundle, or a member is gone with `strict`", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers), fields(client_ip))]
+pub async fn download_bundle(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<BundleParams>,
+    headers: HeaderMap,
+) -> Result<(Option<RateLimitStatus>, Response), Response> {
+    let client_ip = get_client_ip(&app_state.config, addr, &headers);
+    let rate_limit = check_rate_limit(client_ip, RateLimitAction::Download, &app_state).await?;
+
+    let bundle = find_bundle(&app_state, &id)
+        .await
+        .map_err(IntoResponse::into_response)?
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+
+    let mut members = Vec::with_capacity(bundle.file_ids.len());
+    let mut missing = Vec::new();
+    for &file_id in &bundle.file_ids {
+        match find_member(&app_state, file_id).await.map_err(IntoResponse::into_response)? {
+            Some(member) => members.push(member),
+            None if params.strict => {
+                warn!("Bundle {} member {} is no longer available", bundle.id, file_id);
+                return Err(StatusCode::NOT_FOUND.into_response());
+            }
+            None => missing.push(file_id),
+        }
+    }
+    // The limit may have been lowered since the bundle was made
+    let size = members.iter().map(|member| member.size).sum();
+    check_bundle_size(&app_state, size).map_err(IntoResponse::into_response)?;
+
+    info!(
+        "Streaming bundle {} as {:?}: {} files, {} missing",
+        bundle.id,
+        params.format,
+        members.len(),
+        missing.len()
+    );
+    let (writer, reader) = tokio::io::duplex(ARCHIVE_BUFFER_SIZE);
+    let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+    let storage = app_state.storage.clone();
+    tokio::spawn(async move {
+        let result = write_archive(&storage, params.format, params.strict, members, missing, writer).await;
+        let _ = result_tx.send(result);
+    });
+
+    // A failed archive ends the body with an error, so the client sees a broken
+    // transfer rather than an archive that is silently short
+    let failure = futures_util::stream::once(result_rx).filter_map(|result| async move {
+        match result {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(e)),
+            Err(_) => Some(Err(io::Error::other("archive writer stopped"))),
+        }
+    });
+    let mut response = Body::from_stream(ReaderStream::new(reader).chain(failure)).into_response();
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(params.format.content_type()));
+    let filename = format!("bundle-{}.{}", bundle.short_code, params.format.extension());
+    if let Ok(value) = HeaderValue::from_str(&content_disposition("attachment", &filename)) {
+        response_headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    let response = throttle::limit_download(&app_state, client_ip, response);
+    let response = transfer_rate::limit_download_idle(&app_state.config, response);
+    Ok((rate_limit, concurrency::track_download(response)))
+}
+
+enum ArchiveWriter<W: AsyncWrite + Unpin + Send> {
+    Zip(ZipFileWriter<W>),
+    Tar(tokio_tar::Builder<W>),
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> ArchiveWriter<W> {
+    fn new(format: ArchiveFormat, writer: W) -> Self {
+        match format {
+            ArchiveFormat::Zip => Self::Zip(ZipFileWriter::with_tokio(writer)),
+            ArchiveFormat::Tar => Self::Tar(tokio_tar::Builder::new(writer)),
+        }
+    }
+
+    async fn append(&mut self, name: &str, mut object: StoredObject, modified: DateTime<Utc>) -> io::Result<()> {
+        match self {
+            // Stored, not deflated: most large uploads are already compressed
+            Self::Zip(zip) => {
+                let entry = ZipEntryBuilder::new(name.to_string().into(), Compression::Stored)
+                    .last_modification_date(ZipDateTime::from_chrono(&modified))
+                    .unix_permissions(0o644);
+                let mut entry_writer = zip.write_entry_stream(entry).await.map_err(io::Error::other)?.compat_write();
+                tokio::io::copy(&mut object.reader, &mut entry_writer).await?;
+                entry_writer.into_inner().close().await.map_err(io::Error::other)
+            }
+            Self::Tar(tar) => {
+                let mut entry = tokio_tar::Header::new_gnu();
+                entry.set_entry_type(tokio_tar::EntryType::Regular);
+                entry.set_size(object.len);
+                entry.set_mode(0o644);
+                entry.set_mtime(u64::try_from(modified.timestamp()).unwrap_or_default());
+                tar.append_data(&mut entry, name, object.reader).await
+            }
+        }
+    }
+
+    async fn finish(self) -> io::Result<()> {
+        let mut writer = match self {
+            Self::Zip(zip) => zip.close().await.map_err(io::Error::other)?.into_inner(),
+            Self::Tar(tar) => tar.into_inner().await?,
+        };
+        writer.shutdown().await
+    }
+}
+
+// Write the members as an archive. A member that can't be opened any more
+// fails the archive in strict mode; otherwise it joins the ones already gone
+// in a MISSING.txt entry at the end.
+async fn write_archive(
+    storage: &FileStore,
+    format: ArchiveFormat,
+    strict: bool,
+    members: Vec<Member>,
+    mut missing: Vec<Uuid>,
+    writer: DuplexStream,
+) -> io::Result<()> {
+    let mut archive = ArchiveWriter::new(format, writer);
+    let mut names = HashSet::new();
+
+    for member in members {
+        let object = match storage.get(&member.storage_ref).await {
+            Ok(object) => object,
+            Err(e) if strict => return Err(e),
+            Err(e) => {
+                warn!("Leaving bundle member {} out of the archive: {:?}", member.id, e);
+                missing.push(member.id);
+                continue;
+            }
+        };
+        let name = unique_name(&member.filename, &mut names);
+        archive.append(&name, object, member.created_at).await?;
+    }
+
+    if !missing.is_empty() {
+        let mut note = String::from("These files were no longer available when this archive was made:\n");
+        for id in &missing {
+            note.push_str(&format!("{}\n", id));
+        }
+        let object = StoredObject::in_memory(note.into_bytes().into());
+        let name = unique_name(MISSING_ENTRY, &mut names);
+        archive.append(&name, object, Utc::now()).await?;
+    }
+
+    archive.finish().await
+}
+
+// Archive entry names must be unique; later duplicates become "name (2).ext"
+fn unique_name(filename: &str, names: &mut HashSet<String>) -> String {
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (filename, String::new()),
+    };
+    let mut name = filename.to_string();
+    let mut copy = 1;
+    while !names.insert(name.clone()) {
+        copy += 1;
+        name = format!("{} ({}){}", stem, copy, extension);
+    }
+    name
+}
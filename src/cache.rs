@@ -0,0 +1,138 @@
+use color_eyre::eyre::{Context, Result};
+use redis::AsyncCommands;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::rate_limit::{RateLimitAction, RateLimitPolicy, RateLimitStatus};
+
+// Cached short codes are refreshed from the database after this long
+const SHORT_URL_TTL_SECONDS: u64 = 24 * 60 * 60;
+
+// Keep requests from stalling on an unreachable Redis; the caller falls back instead
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn short_url_key(short_code: &str) -> String {
+    format!("drop:short:{}", short_code)
+}
+
+// Refill a token bucket stored as a hash and take a token if there is one.
+// ARGV: burst, tokens per millisecond, now (ms), idle expiry (ms)
+static TOKEN_BUCKET_SCRIPT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r#"
+        local burst = tonumber(ARGV[1])
+        local rate = tonumber(ARGV[2])
+        local now = tonumber(ARGV[3])
+        local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'refilled_at')
+        local tokens = tonumber(bucket[1]) or burst
+        local refilled_at = tonumber(bucket[2]) or now
+        tokens = math.min(burst, tokens + math.max(0, now - refilled_at) * rate)
+        local allowed = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+            allowed = 1
+        end
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'refilled_at', now)
+        redis.call('PEXPIRE', KEYS[1], ARGV[4])
+        return {allowed, tostring(tokens)}
+        "#,
+    )
+});
+
+fn rate_limit_key(client_ip: std::net::IpAddr, action: RateLimitAction) -> String {
+    format!("drop:ratelimit:{}:{}", action, client_ip)
+}
+
+/// Redis layer in front of Postgres for short code lookups and rate limiting.
+#[derive(Clone)]
+pub struct RedisStore {
+    connection: ConnectionManager,
+}
+
+impl RedisStore {
+    pub async fn new(redis_url: &str) -> Result<Self> {
+        let client = redis::Client::open(redis_url)
+            .with_context(|| format!("Invalid Redis URL: {}", redis_url))?;
+
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(REDIS_TIMEOUT)
+            .set_response_timeout(REDIS_TIMEOUT);
+        let connection = ConnectionManager::new_with_config(client, config)
+            .await
+            .context("Failed to connect to Redis")?;
+
+        info!("Redis connected successfully");
+        Ok(Self { connection })
+    }
+
+    pub async fn health_check(&self) -> bool {
+        let mut connection = self.connection.clone();
+        match redis::cmd("PING").query_async::<String>(&mut connection).await {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Redis health check failed: {}", e);
+                false
+            }
+        }
+    }
+
+    pub async fn store_short_url(&self, short_code: &str, file_id: Uuid) -> Result<()> {
+        let mut connection = self.connection.clone();
+        connection
+            .set_ex::<_, _, ()>(short_url_key(short_code), file_id.to_string(), SHORT_URL_TTL_SECONDS)
+            .await
+            .with_context(|| format!("Failed to cache short URL: {}", short_code))?;
+
+        Ok(())
+    }
+
+    pub async fn remove_short_urls(&self, short_codes: &[String]) -> Result<()> {
+        let keys: Vec<String> = short_codes.iter().map(|short_code| short_url_key(short_code)).collect();
+        let mut connection = self.connection.clone();
+        connection
+            .del::<_, ()>(keys)
+            .await
+            .context("Failed to remove cached short URLs")?;
+
+        Ok(())
+    }
+
+    pub async fn get_file_id_by_short_code(&self, short_code: &str) -> Result<Option<Uuid>> {
+        let mut connection = self.connection.clone();
+        let cached: Option<String> = connection
+            .get(short_url_key(short_code))
+            .await
+            .with_context(|| format!("Failed to get cached short URL: {}", short_code))?;
+
+        Ok(cached.and_then(|file_id| file_id.parse().ok()))
+    }
+
+    /// Take a token from the client's bucket (see `RateLimitPolicy`) and
+    /// report how many are left.
+    pub async fn check_rate_limit(
+        &self,
+        client_ip: std::net::IpAddr,
+        action: RateLimitAction,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitStatus> {
+        let mut connection = self.connection.clone();
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        // A full bucket is the same as no bucket, so let idle ones expire
+        let ttl_ms = (policy.seconds_to_refill(f64::from(policy.burst)) * 1000).max(1000);
+
+        let (allowed, tokens): (bool, f64) = TOKEN_BUCKET_SCRIPT
+            .key(rate_limit_key(client_ip, action))
+            .arg(policy.burst)
+            .arg(policy.refill_per_second / 1000.0)
+            .arg(now_ms)
+            .arg(ttl_ms)
+            .invoke_async(&mut connection)
+            .await
+            .with_context(|| format!("Failed to check rate limit for IP: {}", client_ip))?;
+
+        Ok(policy.status(tokens, allowed))
+    }
+}
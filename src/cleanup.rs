@@ -0,0 +1,164 @@
+use axum::{
+    Json,
+    extract::{Query, State, rejection::QueryRejection},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{error, info, instrument};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{AppState, admin, openapi::ErrorResponse, preview_cleanup, run_cleanup};
+
+/// What one cleanup pass removed, or on a dry run would remove.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub files_removed: usize, // Expired files purged, in the database and the in-memory fallback
+    pub bytes_reclaimed: u64, // Their sizes, less contents identical uploads still share
+    pub ids: Vec<Uuid>,       // Of the expired files
+    pub tombstones_removed: u64, // Purged files past DROP_TOMBSTONE_RETENTION_SECS, now forgotten
+    pub rate_limits_removed: u64,
+    pub files_evicted: usize, // Oldest fallback entries dropped to stay within DROP_FALLBACK_CAPACITY
+    pub delete_failures: usize, // Contents that couldn't be deleted; orphan collection retries them
+}
+
+// What became of a purged file's contents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ContentsRemoval {
+    Deleted,
+    Shared, // Identical uploads still use them, or the database couldn't say
+    Failed,
+}
+
+impl CleanupReport {
+    pub(crate) fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            started_at: Utc::now(),
+            duration_ms: 0,
+            files_removed: 0,
+            bytes_reclaimed: 0,
+            ids: Vec::new(),
+            tombstones_removed: 0,
+            rate_limits_removed: 0,
+            files_evicted: 0,
+            delete_failures: 0,
+        }
+    }
+
+    pub(crate) fn record_removal(&mut self, id: Uuid, size: u64, removal: ContentsRemoval) {
+        self.files_removed += 1;
+        self.ids.push(id);
+        match removal {
+            ContentsRemoval::Deleted => self.bytes_reclaimed += size,
+            ContentsRemoval::Shared => {}
+            ContentsRemoval::Failed => self.delete_failures += 1,
+        }
+    }
+
+    pub(crate) fn finish(mut self, started: Instant) -> Self {
+        self.duration_ms = started.elapsed().as_millis().try_into().unwrap_or(u64::MAX);
+        self
+    }
+}
+
+/// Keeps scheduled and admin-triggered passes from overlapping, and the
+/// report of the last one that ran.
+#[derive(Clone, Debug, Default)]
+pub struct CleanupState {
+    running: Arc<tokio::sync::Mutex<()>>,
+    last: Arc<Mutex<Option<CleanupReport>>>,
+}
+
+impl CleanupState {
+    pub(crate) async fn start(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.running.lock().await
+    }
+
+    pub(crate) fn record(&self, report: &CleanupReport) {
+        match self.last.lock() {
+            Ok(mut last) => *last = Some(report.clone()),
+            Err(e) => error!("Failed to acquire lock on the last cleanup report: {}", e),
+        }
+    }
+
+    /// The report of the last pass that wasn't a dry run, if one has run.
+    pub fn last(&self) -> Option<CleanupReport> {
+        self.last.lock().ok().and_then(|last| last.clone())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CleanupParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// GET /admin/cleanup/last - the report of the last cleanup pass; requires X-Admin-Token
+#[utoipa::path(
+    get,
+    path = "/admin/cleanup/last",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`")),
+    responses(
+        (status = 200, description = "What the last pass removed", body = CleanupReport),
+        (status = 204, description = "No pass has run yet"),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn last_cleanup(State(app_state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Err(status) = admin::authorize(&app_state, &headers) {
+        return status.into_response();
+    }
+    match app_state.cleanup.last() {
+        Some(report) => Json(report).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+// POST /admin/cleanup - run a cleanup pass now, or with ?dry_run=true report
+// what one would remove; requires X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/admin/cleanup",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), CleanupParams),
+    responses(
+        (status = 200, description = "What was, or on a dry run would be, removed", body = CleanupReport),
+        (status = 400, description = "A malformed query string", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn trigger_cleanup(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<CleanupParams>, QueryRejection>,
+) -> Result<Json<CleanupReport>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    let Query(params) = params.map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let report = if params.dry_run {
+        preview_cleanup(&app_state).await
+    } else {
+        run_cleanup(&app_state).await
+    };
+    info!(
+        "Admin cleanup {} {} expired files, {} bytes",
+        if report.dry_run { "would remove" } else { "removed" },
+        report.files_removed,
+        report.bytes_reclaimed
+    );
+    Ok(Json(report))
+}
@@ -0,0 +1,86 @@
+use axum::http::{HeaderMap, HeaderName};
+use color_eyre::eyre::{Result, eyre};
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::Config;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// Parse a comma-separated list of IPs and CIDR blocks, v4 or v6. Fails on
+/// the first entry that is neither.
+pub fn parse_ip_ranges(value: &str) -> Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| eyre!("'{}' is not an IP address or CIDR block", entry))
+        })
+        .collect()
+}
+
+/// Whether `ip` falls in any of `ranges`. IPv4-mapped IPv6 addresses match their IPv4 ranges.
+pub fn in_ranges(ranges: &[IpNet], ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    ranges.iter().any(|net| net.contains(&ip))
+}
+
+// One X-Forwarded-For hop; proxies may add a port, bracketing v6 addresses when they do
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| hop.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>())
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+/// The client a request is for. When the connecting peer is one of
+/// `DROP_TRUSTED_PROXIES`, this is the rightmost `X-Forwarded-For` hop that
+/// isn't a trusted proxy, or `X-Real-IP` without one. Otherwise the headers
+/// could be forged and only the peer counts. The result is recorded on the
+/// current span's `client_ip` field.
+pub fn get_client_ip(config: &Config, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let peer = peer.ip().to_canonical();
+    let client_ip = if in_ranges(&config.trusted_proxies, peer) {
+        forwarded_client(config, headers).unwrap_or(peer)
+    } else {
+        peer
+    };
+
+    tracing::Span::current().record("client_ip", tracing::field::display(client_ip));
+    client_ip
+}
+
+fn forwarded_client(config: &Config, headers: &HeaderMap) -> Option<IpAddr> {
+    // Later headers were appended by later proxies, so hops read left to right across all of them
+    let hops: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+
+    // Walk back from the nearest hop until one isn't ours. A hop that won't
+    // parse ends the walk; nothing left of it can be trusted.
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        let Some(ip) = parse_hop(hop) else { break };
+        client = Some(ip);
+        if !in_ranges(&config.trusted_proxies, ip) {
+            break;
+        }
+    }
+
+    client.or_else(|| {
+        headers
+            .get(X_REAL_IP)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_hop)
+    })
+}
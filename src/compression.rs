@@ -0,0 +1,154 @@
+use async_compression::tokio::bufread::{GzipEncoder, ZstdEncoder};
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderValue, header},
+};
+use tokio::io::{AsyncRead, BufReader};
+use tokio_util::io::ReaderStream;
+
+use crate::{config::Config, content_type_matches};
+
+// Too small to be worth a compressor
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+// Already compressed formats; never compressed again, whatever the configured list says
+const PRECOMPRESSED_CONTENT_TYPES: &[&str] = &[
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/x-xz",
+    "application/x-bzip2",
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "audio/*",
+    "video/*",
+];
+
+/// Content types compressed by default; `DROP_COMPRESSIBLE_TYPES` replaces the list.
+pub fn default_compressible_types() -> Vec<String> {
+    [
+        "text/*",
+        "application/json",
+        "application/javascript",
+        "application/xml",
+        "application/sql",
+        "application/x-ndjson",
+        "application/yaml",
+        "image/svg+xml",
+        "image/bmp",
+    ]
+    .into_iter()
+    .map(str::to_string)
+    .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Gzip,
+    Zstd,
+}
+
+impl ContentEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+/// Whether downloads of this type may be compressed at all.
+pub fn is_compressible(config: &Config, content_type: &str) -> bool {
+    !config.disable_compression
+        && !PRECOMPRESSED_CONTENT_TYPES
+            .iter()
+            .any(|pattern| content_type_matches(content_type, pattern))
+        && config
+            .compressible_types
+            .iter()
+            .any(|pattern| content_type_matches(content_type, pattern))
+}
+
+/// The encoding to send a whole file of `len` bytes with, or None to send it
+/// as stored. Range requests are never compressed, so offsets keep meaning
+/// bytes of the file.
+pub fn negotiate(config: &Config, request_headers: &HeaderMap, content_type: &str, len: u64) -> Option<ContentEncoding> {
+    if request_headers.contains_key(header::RANGE)
+        || !(MIN_COMPRESS_SIZE..=config.compression_max_size).contains(&len)
+        || !is_compressible(config, content_type)
+    {
+        return None;
+    }
+
+    let accept_encoding = request_headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let zstd = quality(&accept_encoding, "zstd");
+    let gzip = quality(&accept_encoding, "gzip");
+    match (zstd, gzip) {
+        (zstd, gzip) if zstd > 0.0 && zstd >= gzip => Some(ContentEncoding::Zstd),
+        (_, gzip) if gzip > 0.0 => Some(ContentEncoding::Gzip),
+        _ => None,
+    }
+}
+
+/// Mark download headers for the chosen encoding. Compressible types always
+/// vary on `Accept-Encoding`; compressed bodies get a weak ETag since their
+/// bytes differ from the stored file.
+pub fn annotate(config: &Config, headers: &mut HeaderMap, content_type: &str, encoding: Option<ContentEncoding>) {
+    if is_compressible(config, content_type) {
+        headers.insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    let Some(encoding) = encoding else {
+        return;
+    };
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    headers.remove(header::CONTENT_LENGTH);
+    let weak_etag = headers
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"))
+        .and_then(|etag| HeaderValue::from_str(&format!("W/{}", etag)).ok());
+    if let Some(weak_etag) = weak_etag {
+        headers.insert(header::ETAG, weak_etag);
+    }
+}
+
+/// Stream `reader` through the encoder as a response body.
+pub fn encode<R: AsyncRead + Unpin + Send + 'static>(encoding: ContentEncoding, reader: R) -> Body {
+    let reader = BufReader::new(reader);
+    match encoding {
+        ContentEncoding::Gzip => Body::from_stream(ReaderStream::new(GzipEncoder::new(reader))),
+        ContentEncoding::Zstd => Body::from_stream(ReaderStream::new(ZstdEncoder::new(reader))),
+    }
+}
+
+// The q-value Accept-Encoding gives a coding, falling back to `*`; zero if neither is listed
+fn quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let q = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        if name == coding {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
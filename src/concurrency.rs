@@ -0,0 +1,129 @@
+use axum::{
+    body::Body,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+use crate::{ACTIVE_DOWNLOADS, ACTIVE_UPLOADS, ALLOCATED_MEMORY, AppState, Config, MEMORY_POOL};
+
+// What overloaded clients are told to wait before trying again
+const RETRY_AFTER_SECONDS: u64 = 5;
+
+/// Concurrent upload bodies when `DROP_MAX_CONCURRENT_UPLOADS` isn't set: two
+/// per CPU, since uploads mostly wait on the network and the disk.
+pub fn default_max_concurrent_uploads() -> usize {
+    std::thread::available_parallelism().map_or(4, |cpus| cpus.get()) * 2
+}
+
+/// The semaphore behind `AppState::upload_permits`.
+pub fn upload_permits(config: &Config) -> Arc<Semaphore> {
+    Arc::new(Semaphore::new(config.max_concurrent_uploads.clamp(1, Semaphore::MAX_PERMITS)))
+}
+
+/// One upload or download in progress, counted in `/health` from creation
+/// until it is dropped, however the request ends.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    counter: &'static AtomicUsize,
+}
+
+impl ConnectionGuard {
+    /// Counted in `active_uploads`.
+    pub fn upload() -> Self {
+        Self::new(&ACTIVE_UPLOADS)
+    }
+
+    /// Counted in `active_downloads`.
+    pub fn download() -> Self {
+        Self::new(&ACTIVE_DOWNLOADS)
+    }
+
+    fn new(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self { counter }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A claim on one of the concurrent upload slots, counted in
+/// `active_uploads` while held. Dropping it frees the slot, so it is
+/// released however the upload ends, including the client going away.
+#[derive(Debug)]
+pub struct UploadSlot {
+    _permit: OwnedSemaphorePermit,
+    _connection: ConnectionGuard,
+}
+
+impl UploadSlot {
+    fn new(permit: OwnedSemaphorePermit) -> Self {
+        Self {
+            _permit: permit,
+            _connection: ConnectionGuard::upload(),
+        }
+    }
+}
+
+/// Count a download in `active_downloads` until its body has been sent, or
+/// the client stops reading it.
+pub fn track_download(response: Response) -> Response {
+    let connection = ConnectionGuard::download();
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _connection = &connection;
+            chunk
+        }))
+    })
+}
+
+/// Whether the server is too loaded to take another upload: the memory pool
+/// is nearly full and enough uploads are already in flight to keep it that way.
+pub fn should_shed_load(config: &Config) -> bool {
+    let pool = MEMORY_POOL.load(Ordering::Acquire);
+    let allocated = ALLOCATED_MEMORY.load(Ordering::Acquire);
+    let active = ACTIVE_UPLOADS.load(Ordering::Relaxed);
+    pool > 0 && allocated as f64 > pool as f64 * config.shed_memory_ratio && active > config.shed_threshold()
+}
+
+/// Wait for an upload slot. Fails with 503 and `Retry-After` when load is
+/// being shed or no slot frees up within `DROP_UPLOAD_QUEUE_TIMEOUT_MS`.
+pub async fn acquire_upload_slot(app_state: &AppState) -> Result<UploadSlot, Response> {
+    if should_shed_load(&app_state.config) {
+        warn!("Shedding upload: memory pool under pressure");
+        return Err(unavailable());
+    }
+
+    let permits = app_state.upload_permits.clone();
+    let timeout = Duration::from_millis(app_state.config.upload_queue_timeout_ms);
+    match tokio::time::timeout(timeout, permits.acquire_owned()).await {
+        Ok(Ok(permit)) => Ok(UploadSlot::new(permit)),
+        Ok(Err(_)) => Err(unavailable()), // Closed; never happens while the server runs
+        Err(_) => {
+            warn!(
+                "Rejecting upload: all {} upload slots busy for {:?}",
+                app_state.config.max_concurrent_uploads, timeout
+            );
+            Err(unavailable())
+        }
+    }
+}
+
+fn unavailable() -> Response {
+    let mut response = StatusCode::SERVICE_UNAVAILABLE.into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECONDS));
+    response
+}
@@ -0,0 +1,91 @@
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use color_eyre::eyre::{Result, bail, eyre};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{config::Config, idempotency, rate_limit, request_id::X_REQUEST_ID, sessions::UPLOAD_OFFSET_HEADER};
+
+// Request headers a browser client may send besides the CORS-safelisted ones
+const ALLOWED_HEADERS: [HeaderName; 8] = [
+    header::AUTHORIZATION,
+    header::CONTENT_TYPE,
+    header::RANGE,
+    HeaderName::from_static("x-delete-token"),
+    HeaderName::from_static("x-admin-token"),
+    X_REQUEST_ID,
+    HeaderName::from_static(UPLOAD_OFFSET_HEADER),
+    HeaderName::from_static(idempotency::IDEMPOTENCY_KEY),
+];
+
+// Response headers scripts may read
+const EXPOSED_HEADERS: [HeaderName; 8] = [
+    rate_limit::X_RATELIMIT_LIMIT,
+    rate_limit::X_RATELIMIT_REMAINING,
+    rate_limit::X_RATELIMIT_RESET,
+    header::RETRY_AFTER,
+    X_REQUEST_ID,
+    HeaderName::from_static(UPLOAD_OFFSET_HEADER),
+    header::CONTENT_DISPOSITION,
+    HeaderName::from_static(idempotency::IDEMPOTENT_REPLAYED),
+];
+
+/// Origins allowed to call the API from a browser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+/// Parse a comma-separated list of origins such as `https://app.example.com`,
+/// or `*` for any. An empty value turns CORS off.
+pub fn parse_origins(value: &str) -> Result<Option<AllowedOrigins>> {
+    let origins: Vec<&str> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    if origins.contains(&"*") {
+        if origins.len() > 1 {
+            bail!("'*' can't be combined with other origins");
+        }
+        return Ok(Some(AllowedOrigins::Any));
+    }
+
+    origins
+        .into_iter()
+        .map(|origin| {
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                bail!("'{}' is not an origin like https://app.example.com", origin);
+            }
+            HeaderValue::from_str(origin.trim_end_matches('/'))
+                .map_err(|_| eyre!("'{}' is not a valid origin", origin))
+        })
+        .collect::<Result<_>>()
+        .map(|origins| Some(AllowedOrigins::List(origins)))
+}
+
+/// The CORS layer for `cors_allowed_origins`, or `None` to send no CORS
+/// headers at all. Preflight requests are answered here, before routing, so
+/// they never count against rate limits.
+pub fn cors_layer(config: &Config) -> Option<CorsLayer> {
+    let allow_origin = match config.cors_allowed_origins.as_ref()? {
+        AllowedOrigins::Any => AllowOrigin::any(),
+        AllowedOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+    };
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers(ALLOWED_HEADERS.to_vec())
+            .expose_headers(EXPOSED_HEADERS.to_vec()),
+    )
+}
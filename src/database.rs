@@ -1,11 +1,20 @@
 use chrono::{DateTime, Utc};
-use color_eyre::eyre::{Context, Result};
-use sqlx::{PgPool, Row};
+use color_eyre::eyre::{Context, Result, eyre};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "postgres")]
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use sqlx::Row;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use tracing::{info, warn};
 use uuid::Uuid;
 
-#[derive(Clone, Debug, sqlx::FromRow)]
+use crate::rate_limit::{RateLimitAction, RateLimitPolicy, RateLimitStatus};
+
+#[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct FileMapping {
     pub id: Uuid,
     pub filename: String,
@@ -17,15 +26,180 @@ pub struct FileMapping {
     pub accessed_at: DateTime<Utc>,
     pub access_count: i32,
     pub expires_at: Option<DateTime<Utc>>,
+    pub delete_token: Option<String>,
+    pub purged_at: Option<DateTime<Utc>>,
+    pub max_downloads: Option<i32>,
+    pub content_hash: Option<String>,
+    pub declared_content_type: Option<String>,
+    pub detected_content_type: Option<String>,
+    pub uploader_ip: Option<String>,
+    pub sha256: Option<String>,
+    pub encrypted: bool, // Contents are encrypted at rest
+    pub pinned: bool, // Kept whatever its expiry or age
+    pub api_key_id: Option<Uuid>, // The key it was uploaded with; None for anonymous uploads
+    #[serde(default)]
+    pub corrupt: bool, // Missing or changed contents, found by the integrity scrubber
+    #[serde(default)]
+    pub embeddable: bool, // Downloads skip the referer check
 }
 
-#[derive(Clone, Debug, sqlx::FromRow)]
+/// Metadata for a newly uploaded file, as written by `store_file_mapping`.
+#[derive(Clone, Debug)]
+pub struct NewFileMapping<'a> {
+    pub id: Uuid,
+    pub filename: &'a str,
+    pub content_type: &'a str,
+    pub declared_content_type: Option<&'a str>,
+    pub detected_content_type: Option<&'a str>,
+    pub file_path: Option<&'a PathBuf>,
+    pub file_size: i64,
+    pub is_in_memory: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub delete_token: &'a str,
+    pub max_downloads: Option<i32>,
+    pub content_hash: &'a str,
+    pub sha256: Option<&'a str>,
+    pub created_at: DateTime<Utc>,
+    pub access_count: i32, // Downloads already served, e.g. from the in-memory fallback
+    pub uploader_ip: Option<IpAddr>, // Charged for the file against its upload quota
+    pub encrypted: bool,
+    pub pinned: bool,
+    pub api_key_id: Option<Uuid>,
+    pub embeddable: bool,
+}
+
+/// Column a file listing is ordered by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FileSort {
+    Size,
+    #[default]
+    CreatedAt,
+    AccessCount,
+}
+
+impl FileSort {
+    fn column(&self) -> &'static str {
+        match self {
+            FileSort::Size => "file_size",
+            FileSort::CreatedAt => "created_at",
+            FileSort::AccessCount => "access_count",
+        }
+    }
+}
+
+/// Where the previous page of a listing ended: its last sort key (creation
+/// times in nanoseconds) and file ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileCursor {
+    pub key: i64,
+    pub id: Uuid,
+}
+
+/// Filters and ordering for `list_file_mappings`. Purged files are never listed.
+#[derive(Clone, Debug, Default)]
+pub struct FileListQuery {
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub content_type_prefix: Option<String>,
+    pub is_in_memory: Option<bool>,
+    pub pinned: Option<bool>,
+    pub api_key_id: Option<Uuid>, // Only files uploaded with this key
+    pub sort: FileSort,
+    pub ascending: bool,
+    pub after: Option<FileCursor>,
+    pub limit: i64,
+}
+
+// A value bound into a query assembled at runtime
+enum Bind {
+    Int(i64),
+    Time(DateTime<Utc>),
+    Text(String),
+    Flag(bool),
+    Id(Uuid),
+}
+
+/// A file past its expiry, as found by `cleanup_expired_files`.
+#[derive(Clone, Debug, PartialEq, Eq, sqlx::FromRow)]
+pub struct ExpiredFile {
+    pub id: Uuid,
+    pub file_path: Option<String>,
+    pub file_size: i64,
+}
+
+/// What `get_storage_stats` reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageTotals {
+    pub total_files: i64,
+    pub total_size: i64,
+    pub memory_files: i64,
+    pub memory_size: i64, // Bytes of files held in the memory pool; the rest are on disk
+    pub expiring_files: i64, // Files that expire within the horizon asked about
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct ShortUrl {
     pub short_code: String,
     pub file_id: Uuid,
     pub created_at: DateTime<Utc>,
 }
 
+/// Files handed out together as one archive, in archive order.
+#[derive(Clone, Debug)]
+pub struct Bundle {
+    pub id: Uuid,
+    pub short_code: String,
+    pub file_ids: Vec<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An API key as stored; the key itself is never kept, only its hash.
+#[derive(Clone, Debug, PartialEq, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub quota_bytes: Option<i64>, // None for no limit
+    pub quota_files: Option<i64>,
+    pub rate_limit_multiplier: f64,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Downloads and bytes served on one UTC day ('YYYY-MM-DD').
+#[derive(Clone, Debug, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct DailyTraffic {
+    pub day: String,
+    pub downloads: i64,
+    pub bytes_served: i64,
+}
+
+/// Live files falling in one of the buckets asked of `file_size_distribution`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, sqlx::FromRow)]
+pub struct SizeBucketCount {
+    pub bucket: i64,
+    pub files: i64,
+    pub bytes: i64,
+}
+
+/// What an API key's files take up, purged files aside.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApiKeyUsage {
+    pub files: i64,
+    pub bytes: i64,
+}
+
+const API_KEY_COLUMNS: &str = "id, name, quota_bytes, quota_files, rate_limit_multiplier, created_at, revoked_at";
+
+/// What claiming an `Idempotency-Key` found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyClaim {
+    Claimed,           // New or expired; the caller runs the upload
+    Completed(String), // The first upload's files, as JSON
+    InProgress,
+}
+
 #[derive(Clone, Debug, sqlx::FromRow)]
 pub struct RateLimit {
     pub client_ip: String,
@@ -34,37 +208,214 @@ pub struct RateLimit {
     pub updated_at: DateTime<Utc>,
 }
 
+// The configured backend: Postgres for production, SQLite for single-box
+// deployments. Postgres is only there with the `postgres` feature.
+#[derive(Clone)]
+enum DbPool {
+    #[cfg(feature = "postgres")]
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+// Run the same sqlx expression against whichever pool is configured. Queries
+// must stick to SQL both backends accept and bind timestamps instead of NOW().
+macro_rules! with_pool {
+    ($pool:expr, $conn:ident => $body:expr) => {
+        match $pool {
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres($conn) => $body,
+            DbPool::Sqlite($conn) => $body,
+        }
+    };
+}
+
+// Bind the fields of a `NewFileMapping` to a query from `file_mapping_insert`
+macro_rules! bind_file_mapping {
+    ($query:expr, $mapping:expr) => {
+        $query
+            .bind($mapping.id)
+            .bind($mapping.filename)
+            .bind($mapping.content_type)
+            .bind($mapping.file_path.map(|p| p.to_string_lossy().to_string()))
+            .bind($mapping.file_size)
+            .bind($mapping.is_in_memory)
+            .bind($mapping.expires_at)
+            .bind($mapping.delete_token)
+            .bind($mapping.max_downloads)
+            .bind($mapping.content_hash)
+            .bind($mapping.created_at)
+            .bind($mapping.declared_content_type)
+            .bind($mapping.detected_content_type)
+            .bind($mapping.access_count)
+            .bind($mapping.uploader_ip.map(|ip| ip.to_string()))
+            .bind($mapping.sha256)
+            .bind($mapping.encrypted)
+            .bind($mapping.pinned)
+            .bind($mapping.api_key_id)
+            .bind($mapping.embeddable)
+    };
+}
+
+fn file_mapping_insert(on_conflict: &str) -> String {
+    format!(
+        r#"
+            INSERT INTO file_mappings (id, filename, content_type, file_path, file_size, is_in_memory, expires_at, delete_token, max_downloads, content_hash, created_at, accessed_at, declared_content_type, detected_content_type, access_count, uploader_ip, sha256, encrypted, pinned, api_key_id, embeddable)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)
+            {}
+        "#,
+        on_conflict
+    )
+}
+
+// Inserts a short code unless it is taken: $1 short code, $2 file ID, $3 now
+const SHORT_URL_INSERT: &str = r#"
+    INSERT INTO short_urls (short_code, file_id, created_at)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (short_code) DO NOTHING
+"#;
+
+// Tombstones purged before this are deleted; none if the retention reaches back further than dates go
+pub(crate) fn tombstone_cutoff(retention: Option<chrono::Duration>) -> Option<DateTime<Utc>> {
+    retention.and_then(|retention| Utc::now().checked_sub_signed(retention))
+}
+
+// "$1, $2, ..." for an IN list of `count` values
+fn placeholders(count: usize) -> String {
+    (1..=count).map(|n| format!("${}", n)).collect::<Vec<_>>().join(", ")
+}
+
+// Rate limit records untouched since this are deleted; they are kept for 10 minutes
+pub(crate) fn rate_limit_cutoff() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::minutes(10)
+}
+
 #[derive(Clone)]
 pub struct Database {
-    pool: PgPool,
+    pool: DbPool,
+}
+
+/// Connection pool sizing and timeouts.
+#[derive(Clone, Debug)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration, // Waiting for a free connection
+    pub connect_timeout: Duration, // Establishing the first connection at startup
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 20,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Current pool usage, as reported by `/health`.
+#[derive(Clone, Debug, Serialize, utoipa::ToSchema)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+}
+
+// Hide credentials when logging a connection string
+fn redact_database_url(database_url: &str) -> String {
+    match (database_url.split_once("://"), database_url.rsplit_once('@')) {
+        (Some((scheme, _)), Some((_, host))) => format!("{}://***@{}", scheme, host),
+        _ => database_url.to_string(),
+    }
+}
+
+#[cfg(feature = "postgres")]
+async fn connect_postgres(database_url: &str, settings: &PoolSettings) -> Result<DbPool> {
+    let options = PgConnectOptions::from_str(database_url)
+        .with_context(|| format!("Invalid database URL: {}", redact_database_url(database_url)))?;
+    let connecting = PgPoolOptions::new()
+        .max_connections(settings.max_connections)
+        .min_connections(settings.min_connections)
+        .acquire_timeout(settings.acquire_timeout)
+        .connect_with(options);
+    let pool = tokio::time::timeout(settings.connect_timeout, connecting)
+        .await
+        .map_err(|_| eyre!("Timed out after {:?}", settings.connect_timeout))
+        .and_then(|result| result.map_err(Into::into))
+        .with_context(|| format!("Failed to connect to database: {}", redact_database_url(database_url)))?;
+
+    // Run migrations
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .context("Failed to run database migrations")?;
+    Ok(DbPool::Postgres(pool))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn connect_postgres(database_url: &str, _settings: &PoolSettings) -> Result<DbPool> {
+    Err(eyre!(
+        "Cannot connect to {}: built without the `postgres` feature, only sqlite: URLs are supported",
+        redact_database_url(database_url)
+    ))
 }
 
 impl Database {
+    /// Connect with the default pool settings and migrate.
     pub async fn new(database_url: &str) -> Result<Self> {
-        info!("Connecting to database: {}", database_url.replace(
-            &database_url.split('@').collect::<Vec<&str>>()[0].split("://").collect::<Vec<&str>>()[1],
-            "***"
-        ));
-        
-        let pool = PgPool::connect(database_url)
-            .await
-            .with_context(|| format!("Failed to connect to database: {}", database_url))?;
+        Self::connect(database_url, &PoolSettings::default()).await
+    }
 
-        // Run migrations
-        sqlx::migrate!("./migrations")
-            .run(&pool)
-            .await
-            .context("Failed to run database migrations")?;
+    /// Connect and migrate. URLs starting with `sqlite:` use an SQLite file
+    /// (created if missing); anything else is treated as PostgreSQL, which
+    /// needs the `postgres` feature. Gives up
+    /// after `settings.connect_timeout` rather than hanging startup.
+    pub async fn connect(database_url: &str, settings: &PoolSettings) -> Result<Self> {
+        info!("Connecting to database: {}", redact_database_url(database_url));
+
+        let pool = if database_url.starts_with("sqlite:") {
+            let options = SqliteConnectOptions::from_str(database_url)
+                .with_context(|| format!("Invalid SQLite database URL: {}", database_url))?
+                .create_if_missing(true)
+                .foreign_keys(true);
+            let connecting = SqlitePoolOptions::new()
+                .max_connections(settings.max_connections)
+                .min_connections(settings.min_connections)
+                .acquire_timeout(settings.acquire_timeout)
+                .connect_with(options);
+            let pool = tokio::time::timeout(settings.connect_timeout, connecting)
+                .await
+                .map_err(|_| eyre!("Timed out after {:?}", settings.connect_timeout))
+                .and_then(|result| result.map_err(Into::into))
+                .with_context(|| format!("Failed to open SQLite database: {}", database_url))?;
+
+            // Run migrations
+            sqlx::migrate!("./migrations_sqlite")
+                .run(&pool)
+                .await
+                .context("Failed to run SQLite database migrations")?;
+            DbPool::Sqlite(pool)
+        } else {
+            connect_postgres(database_url, settings).await?
+        };
 
         info!("Database connected and migrations applied successfully");
         Ok(Self { pool })
     }
 
+    pub fn pool_stats(&self) -> PoolStats {
+        let (size, idle) = with_pool!(&self.pool, pool => (pool.size(), pool.num_idle()));
+        PoolStats {
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+        }
+    }
+
     pub async fn health_check(&self) -> bool {
-        match sqlx::query("SELECT 1")
-            .fetch_one(&self.pool)
-            .await
-        {
+        let result = with_pool!(&self.pool, pool => sqlx::query("SELECT 1").fetch_one(pool).await.map(|_| ()));
+        match result {
             Ok(_) => true,
             Err(e) => {
                 warn!("Database health check failed: {}", e);
@@ -73,187 +424,797 @@ impl Database {
         }
     }
 
-    pub async fn store_file_mapping(
-        &self,
-        id: Uuid,
-        filename: &str,
-        content_type: &str,
-        file_path: Option<&PathBuf>,
-        file_size: i64,
-        is_in_memory: bool,
-        expires_at: Option<DateTime<Utc>>,
-    ) -> Result<()> {
-        let file_path_str = file_path.map(|p| p.to_string_lossy().to_string());
-        
+    pub async fn store_file_mapping(&self, mapping: &NewFileMapping<'_>) -> Result<()> {
+        self.insert_file_mapping(mapping, "").await.map(|_| ())
+    }
+
+    /// Insert a mapping unless one with the same ID already exists, so replaying
+    /// a write twice is harmless. Returns false if the row was already there.
+    pub async fn store_file_mapping_if_absent(&self, mapping: &NewFileMapping<'_>) -> Result<bool> {
+        self.insert_file_mapping(mapping, "ON CONFLICT (id) DO NOTHING")
+            .await
+            .map(|rows_affected| rows_affected > 0)
+    }
+
+    async fn insert_file_mapping(&self, mapping: &NewFileMapping<'_>, on_conflict: &str) -> Result<u64> {
+        let query = file_mapping_insert(on_conflict);
+
+        let rows_affected = with_pool!(&self.pool, pool => bind_file_mapping!(sqlx::query(&query), mapping)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to store file mapping for ID: {}", mapping.id))?;
+
+        Ok(rows_affected)
+    }
+
+    /// Store a new upload's mapping and its short code in one transaction, so
+    /// a short code never points at a file that was not stored. Returns false,
+    /// storing neither, if the short code is already taken.
+    pub async fn store_upload(&self, mapping: &NewFileMapping<'_>, short_code: &str) -> Result<bool> {
+        let query = file_mapping_insert("");
+
+        let stored = with_pool!(&self.pool, pool => async {
+            let mut tx = pool.begin().await?;
+            bind_file_mapping!(sqlx::query(&query), mapping).execute(&mut *tx).await?;
+            let claimed = sqlx::query(SHORT_URL_INSERT)
+                .bind(short_code)
+                .bind(mapping.id)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                > 0;
+            if claimed {
+                tx.commit().await?;
+            } else {
+                tx.rollback().await?;
+            }
+            Ok::<_, sqlx::Error>(claimed)
+        }
+        .await)
+        .with_context(|| format!("Failed to store upload {} with short code {}", mapping.id, short_code))?;
+
+        Ok(stored)
+    }
+
+    /// Look up a file that can still be downloaded, without counting a
+    /// download. Returns `None` both for unknown IDs and for files that are
+    /// expired, purged or out of downloads; use `find_file_mapping` to tell
+    /// them apart.
+    pub async fn get_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
         let query = r#"
-            INSERT INTO file_mappings (id, filename, content_type, file_path, file_size, is_in_memory, expires_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            SELECT * FROM file_mappings
+            WHERE id = $1
+              AND purged_at IS NULL
+              AND (pinned OR expires_at IS NULL OR expires_at > $2)
+              AND (max_downloads IS NULL OR access_count < max_downloads)
         "#;
 
-        sqlx::query(query)
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
             .bind(id)
-            .bind(filename)
-            .bind(content_type)
-            .bind(file_path_str)
-            .bind(file_size)
-            .bind(is_in_memory)
-            .bind(expires_at)
-            .execute(&self.pool)
-            .await
-            .with_context(|| format!("Failed to store file mapping for ID: {}", id))?;
+            .bind(Utc::now())
+            .fetch_optional(pool)
+            .await)
+            .with_context(|| format!("Failed to get file mapping for ID: {}", id))?;
 
-        Ok(())
+        Ok(result)
     }
 
-    pub async fn get_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
+    /// Count a download of a file with a download limit and return the
+    /// mapping, or `None` if it can't be downloaded any more. The check and
+    /// the count happen in one statement, so no two requests get the last one.
+    pub async fn claim_download(&self, id: Uuid) -> Result<Option<FileMapping>> {
         let query = r#"
-            UPDATE file_mappings 
-            SET accessed_at = NOW(), access_count = access_count + 1
+            UPDATE file_mappings
+            SET accessed_at = $2, access_count = access_count + 1
             WHERE id = $1
+              AND purged_at IS NULL
+              AND (pinned OR expires_at IS NULL OR expires_at > $2)
+              AND (max_downloads IS NULL OR access_count < max_downloads)
             RETURNING *
         "#;
 
-        let result = sqlx::query_as::<_, FileMapping>(query)
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .bind(id)
+            .bind(Utc::now())
+            .fetch_optional(pool)
+            .await)
+            .with_context(|| format!("Failed to claim download for ID: {}", id))?;
+
+        Ok(result)
+    }
+
+    /// Add batched download counts (file ID, downloads) to the files' access
+    /// statistics in one round trip.
+    pub async fn record_accesses(&self, counts: &[(Uuid, i32)]) -> Result<()> {
+        let now = Utc::now();
+
+        match &self.pool {
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let query = r#"
+                    UPDATE file_mappings f
+                    SET accessed_at = $3, access_count = f.access_count + c.downloads
+                    FROM unnest($1::uuid[], $2::int[]) AS c(id, downloads)
+                    WHERE f.id = c.id
+                "#;
+                let (ids, downloads): (Vec<Uuid>, Vec<i32>) = counts.iter().copied().unzip();
+
+                sqlx::query(query)
+                    .bind(ids)
+                    .bind(downloads)
+                    .bind(now)
+                    .execute(pool)
+                    .await
+                    .context("Failed to record file accesses")?;
+            }
+            DbPool::Sqlite(pool) => {
+                // No arrays in SQLite; one transaction keeps it a single write
+                let query = r#"
+                    UPDATE file_mappings
+                    SET accessed_at = $3, access_count = access_count + $2
+                    WHERE id = $1
+                "#;
+
+                let mut tx = pool.begin().await.context("Failed to record file accesses")?;
+                for (id, downloads) in counts {
+                    sqlx::query(query)
+                        .bind(id)
+                        .bind(downloads)
+                        .bind(now)
+                        .execute(&mut *tx)
+                        .await
+                        .context("Failed to record file accesses")?;
+                }
+                tx.commit().await.context("Failed to record file accesses")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Look up a file mapping without touching its access statistics.
+    pub async fn find_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        let query = "SELECT * FROM file_mappings WHERE id = $1";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .bind(id)
+            .fetch_optional(pool)
+            .await)
+            .with_context(|| format!("Failed to find file mapping for ID: {}", id))?;
+
+        Ok(result)
+    }
+
+    /// Files with any of these IDs, in one query. IDs with no row are left out.
+    pub async fn find_file_mappings(&self, ids: &[Uuid]) -> Result<Vec<FileMapping>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = format!("SELECT * FROM file_mappings WHERE id IN ({})", placeholders(ids.len()));
+
+        let result = with_pool!(&self.pool, pool => {
+            let mut query = sqlx::query_as::<_, FileMapping>(&query);
+            for id in ids {
+                query = query.bind(*id);
+            }
+            query.fetch_all(pool).await
+        })
+        .with_context(|| format!("Failed to find {} file mappings", ids.len()))?;
+
+        Ok(result)
+    }
+
+    /// Mark a file's contents as gone while keeping its row as a tombstone.
+    pub async fn mark_file_purged(&self, id: Uuid) -> Result<()> {
+        let query = r#"
+            UPDATE file_mappings
+            SET purged_at = $2, file_path = NULL, is_in_memory = FALSE
+            WHERE id = $1
+        "#;
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
             .bind(id)
-            .fetch_optional(&self.pool)
+            .bind(Utc::now())
+            .execute(pool)
             .await
-            .with_context(|| format!("Failed to get file mapping for ID: {}", id))?;
+            .map(|_| ()))
+            .with_context(|| format!("Failed to mark file as purged for ID: {}", id))?;
+
+        Ok(())
+    }
+
+    /// Pin or unpin a file. False if the file is gone or already purged.
+    pub async fn set_file_pinned(&self, id: Uuid, pinned: bool) -> Result<bool> {
+        let query = "UPDATE file_mappings SET pinned = $2 WHERE id = $1 AND purged_at IS NULL";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(id)
+            .bind(pinned)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to pin file for ID: {}", id))?;
+
+        Ok(result > 0)
+    }
+
+    /// Live disk-backed files, least recently scrubbed first; files never
+    /// scrubbed come before all others.
+    pub async fn files_to_scrub(&self, limit: i64) -> Result<Vec<FileMapping>> {
+        let query = r#"
+            SELECT * FROM file_mappings
+            WHERE purged_at IS NULL AND NOT is_in_memory AND file_path IS NOT NULL
+            ORDER BY scrubbed_at IS NOT NULL, scrubbed_at, id
+            LIMIT $1
+        "#;
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .bind(limit)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to find files to scrub")?;
+
+        Ok(result)
+    }
+
+    /// Record the outcome of scrubbing a file now.
+    pub async fn record_scrub(&self, id: Uuid, corrupt: bool) -> Result<()> {
+        let query = "UPDATE file_mappings SET corrupt = $2, scrubbed_at = $3 WHERE id = $1";
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(id)
+            .bind(corrupt)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .with_context(|| format!("Failed to record scrub for ID: {}", id))?;
+
+        Ok(())
+    }
+
+    /// Live files whose last scrub found them corrupt, oldest first.
+    pub async fn corrupt_files(&self) -> Result<Vec<FileMapping>> {
+        let query = "SELECT * FROM file_mappings WHERE corrupt AND purged_at IS NULL ORDER BY created_at, id";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to list corrupt files")?;
+
+        Ok(result)
+    }
+
+    /// Move a file's expiry. False if the file is gone or already purged.
+    pub async fn set_file_expiry(&self, id: Uuid, expires_at: DateTime<Utc>) -> Result<bool> {
+        let query = "UPDATE file_mappings SET expires_at = $2 WHERE id = $1 AND purged_at IS NULL";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(id)
+            .bind(expires_at)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to set expiry for ID: {}", id))?;
+
+        Ok(result > 0)
+    }
+
+    /// The newest live file with these contents, if any.
+    pub async fn find_file_by_sha256(&self, sha256: &str) -> Result<Option<FileMapping>> {
+        let query = r#"
+            SELECT * FROM file_mappings
+            WHERE sha256 = $1
+              AND purged_at IS NULL
+              AND (pinned OR expires_at IS NULL OR expires_at > $2)
+              AND (max_downloads IS NULL OR access_count < max_downloads)
+            ORDER BY created_at DESC
+            LIMIT 1
+        "#;
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .bind(sha256)
+            .bind(Utc::now())
+            .fetch_optional(pool)
+            .await)
+            .with_context(|| format!("Failed to find file mapping for SHA-256: {}", sha256))?;
 
         Ok(result)
     }
 
-    pub async fn store_short_url(&self, short_code: &str, file_id: Uuid) -> Result<()> {
+    /// Take a reference on the stored file holding these contents, recording
+    /// `file_path` as that file if there isn't one yet. Returns the path the
+    /// contents live at; anything other than `file_path` means it is a duplicate.
+    pub async fn claim_blob(&self, sha256: &str, file_size: i64, file_path: &str) -> Result<String> {
         let query = r#"
-            INSERT INTO short_urls (short_code, file_id)
-            VALUES ($1, $2)
-            ON CONFLICT (short_code) DO UPDATE SET file_id = $2
+            INSERT INTO file_blobs (sha256, file_size, file_path, ref_count, created_at)
+            VALUES ($1, $2, $3, 1, $4)
+            ON CONFLICT (sha256, file_size) DO UPDATE SET ref_count = file_blobs.ref_count + 1
+            RETURNING file_path
         "#;
 
-        sqlx::query(query)
+        let stored_path = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(sha256)
+            .bind(file_size)
+            .bind(file_path)
+            .bind(Utc::now())
+            .fetch_one(pool)
+            .await
+            .map(|row| row.get("file_path")))
+            .with_context(|| format!("Failed to claim stored file for SHA-256: {}", sha256))?;
+
+        Ok(stored_path)
+    }
+
+    /// Drop a reference on the stored file at `file_path`. Returns true if its
+    /// bytes can be removed: the last reference is gone, or it was never shared.
+    pub async fn release_blob(&self, file_path: &str) -> Result<bool> {
+        let query = "UPDATE file_blobs SET ref_count = ref_count - 1 WHERE file_path = $1 RETURNING ref_count";
+
+        let ref_count: Option<i32> = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(file_path)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(|row| row.get("ref_count"))))
+            .with_context(|| format!("Failed to release stored file: {}", file_path))?;
+
+        match ref_count {
+            None => Ok(true),
+            Some(remaining) if remaining > 0 => Ok(false),
+            Some(_) => {
+                // Only the caller whose delete wins removes the bytes; a
+                // concurrent claim in between keeps the row and the file
+                let query = "DELETE FROM file_blobs WHERE file_path = $1 AND ref_count <= 0";
+
+                let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+                    .bind(file_path)
+                    .execute(pool)
+                    .await
+                    .map(|result| result.rows_affected()))
+                    .with_context(|| format!("Failed to forget stored file: {}", file_path))?;
+
+                Ok(rows_affected > 0)
+            }
+        }
+    }
+
+    /// Whether `file_path` holds contents shared through `claim_blob`.
+    pub async fn is_blob(&self, file_path: &str) -> Result<bool> {
+        let query = "SELECT 1 FROM file_blobs WHERE file_path = $1";
+
+        let found = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(file_path)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.is_some()))
+            .with_context(|| format!("Failed to look up stored file: {}", file_path))?;
+
+        Ok(found)
+    }
+
+    /// Make file paths recorded whole under `prefix`, which ends in a separator,
+    /// relative to it like the paths recorded now. Returns how many rows changed.
+    pub async fn strip_file_path_prefix(&self, prefix: &str) -> Result<u64> {
+        let length = prefix.chars().count() as i32;
+        let mut rows_affected = 0;
+        for table in ["file_mappings", "file_blobs"] {
+            let query = format!(
+                "UPDATE {} SET file_path = SUBSTR(file_path, $2) WHERE SUBSTR(file_path, 1, $3) = $1",
+                table
+            );
+
+            rows_affected += with_pool!(&self.pool, pool => sqlx::query(&query)
+                .bind(prefix)
+                .bind(length + 1)
+                .bind(length)
+                .execute(pool)
+                .await
+                .map(|result| result.rows_affected()))
+                .with_context(|| format!("Failed to rewrite file paths in {}", table))?;
+        }
+
+        Ok(rows_affected)
+    }
+
+    /// Point the rows recorded at `from` at `to`, once the file has been moved
+    /// there. Returns how many rows changed.
+    pub async fn relocate_file_path(&self, from: &str, to: &str) -> Result<u64> {
+        let mut rows_affected = 0;
+        for table in ["file_mappings", "file_blobs"] {
+            let query = format!("UPDATE {} SET file_path = $2 WHERE file_path = $1", table);
+
+            rows_affected += with_pool!(&self.pool, pool => sqlx::query(&query)
+                .bind(from)
+                .bind(to)
+                .execute(pool)
+                .await
+                .map(|result| result.rows_affected()))
+                .with_context(|| format!("Failed to relocate {} in {}", from, table))?;
+        }
+
+        Ok(rows_affected)
+    }
+
+    /// Delete a file mapping (short URLs cascade). Returns false if no row existed.
+    pub async fn delete_file_mapping(&self, id: Uuid) -> Result<bool> {
+        let query = "DELETE FROM file_mappings WHERE id = $1";
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(id)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to delete file mapping for ID: {}", id))?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Short codes pointing at `file_id`.
+    pub async fn short_codes_for_file(&self, file_id: Uuid) -> Result<Vec<String>> {
+        let query = "SELECT short_code FROM short_urls WHERE file_id = $1";
+
+        let short_codes = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(file_id)
+            .fetch_all(pool)
+            .await
+            .map(|rows| rows.iter().map(|row| row.get("short_code")).collect()))
+            .with_context(|| format!("Failed to get short codes for file ID: {}", file_id))?;
+
+        Ok(short_codes)
+    }
+
+    /// Insert a short code unless it is already taken. Returns false if it was,
+    /// leaving the existing mapping untouched.
+    pub async fn store_short_url(&self, short_code: &str, file_id: Uuid) -> Result<bool> {
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(SHORT_URL_INSERT)
             .bind(short_code)
             .bind(file_id)
-            .execute(&self.pool)
+            .bind(Utc::now())
+            .execute(pool)
             .await
+            .map(|result| result.rows_affected()))
             .with_context(|| format!("Failed to store short URL: {}", short_code))?;
 
-        Ok(())
+        Ok(rows_affected > 0)
     }
 
+    /// The file a short code points at. Codes of files whose row is gone never
+    /// resolve; those of tombstones do, so downloads can answer 410 Gone.
     pub async fn get_file_id_by_short_code(&self, short_code: &str) -> Result<Option<Uuid>> {
-        let query = "SELECT file_id FROM short_urls WHERE short_code = $1";
+        let query = r#"
+            SELECT s.file_id
+            FROM short_urls s
+            JOIN file_mappings f ON f.id = s.file_id
+            WHERE s.short_code = $1
+        "#;
 
-        let result = sqlx::query(query)
+        let result = with_pool!(&self.pool, pool => sqlx::query(query)
             .bind(short_code)
-            .fetch_optional(&self.pool)
+            .fetch_optional(pool)
             .await
+            .map(|row| row.map(|row| row.get("file_id"))))
             .with_context(|| format!("Failed to get file ID for short code: {}", short_code))?;
 
-        Ok(result.map(|row| row.get("file_id")))
+        Ok(result)
+    }
+
+    /// File IDs for any of these short codes, in one query. Unknown codes are left out.
+    pub async fn get_file_ids_by_short_codes(&self, short_codes: &[&str]) -> Result<Vec<(String, Uuid)>> {
+        if short_codes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = format!(
+            r#"
+            SELECT s.short_code, s.file_id
+            FROM short_urls s
+            JOIN file_mappings f ON f.id = s.file_id
+            WHERE s.short_code IN ({})
+            "#,
+            placeholders(short_codes.len())
+        );
+
+        let result = with_pool!(&self.pool, pool => {
+            let mut query = sqlx::query(&query);
+            for short_code in short_codes {
+                query = query.bind(*short_code);
+            }
+            query
+                .fetch_all(pool)
+                .await
+                .map(|rows| rows.iter().map(|row| (row.get("short_code"), row.get("file_id"))).collect::<Vec<_>>())
+        })
+        .with_context(|| format!("Failed to get file IDs for {} short codes", short_codes.len()))?;
+
+        Ok(result)
     }
 
+    pub async fn store_bundle(&self, bundle: &Bundle) -> Result<()> {
+        let query = r#"
+            INSERT INTO bundles (id, short_code, file_ids, created_at)
+            VALUES ($1, $2, $3, $4)
+        "#;
+        let file_ids = bundle.file_ids.iter().map(Uuid::to_string).collect::<Vec<_>>().join(",");
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(bundle.id)
+            .bind(&bundle.short_code)
+            .bind(&file_ids)
+            .bind(bundle.created_at)
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .with_context(|| format!("Failed to store bundle: {}", bundle.id))?;
+
+        Ok(())
+    }
+
+    /// Look a bundle up by its ID or short code.
+    pub async fn find_bundle(&self, id_or_short_code: &str) -> Result<Option<Bundle>> {
+        let by_id = id_or_short_code.parse::<Uuid>().ok();
+        let query = if by_id.is_some() {
+            "SELECT id, short_code, file_ids, created_at FROM bundles WHERE id = $1"
+        } else {
+            "SELECT id, short_code, file_ids, created_at FROM bundles WHERE short_code = $1"
+        };
+
+        let row: Option<(Uuid, String, String, DateTime<Utc>)> = with_pool!(&self.pool, pool => {
+            let query = sqlx::query_as(query);
+            match by_id {
+                Some(id) => query.bind(id),
+                None => query.bind(id_or_short_code),
+            }
+            .fetch_optional(pool)
+            .await
+        })
+        .with_context(|| format!("Failed to find bundle: {}", id_or_short_code))?;
+
+        let Some((id, short_code, file_ids, created_at)) = row else {
+            return Ok(None);
+        };
+        let file_ids = file_ids
+            .split(',')
+            .filter(|file_id| !file_id.is_empty())
+            .map(Uuid::parse_str)
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Malformed member list for bundle: {}", id))?;
+        Ok(Some(Bundle {
+            id,
+            short_code,
+            file_ids,
+            created_at,
+        }))
+    }
+
+    /// Take a token from the client's bucket (see `RateLimitPolicy`) and
+    /// report how many are left. The refill and the take happen in one upsert,
+    /// which skips the update when the bucket is empty, so concurrent requests
+    /// can never both take the last token.
     pub async fn check_rate_limit(
         &self,
         client_ip: std::net::IpAddr,
-        window_seconds: u64,
-        max_requests: i32,
-    ) -> Result<bool> {
+        action: RateLimitAction,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitStatus> {
         let client_ip_str = client_ip.to_string();
         let now = Utc::now();
-        let window_start = now - chrono::Duration::seconds(window_seconds as i64);
+        let now_ms = now.timestamp_millis();
+        let burst = f64::from(policy.burst);
+        let rate_per_ms = policy.refill_per_second / 1000.0;
+
+        // $3 burst, $4 tokens per millisecond, $5 now in milliseconds
+        let take_query = r#"
+            INSERT INTO rate_limits (client_ip, action, tokens, refilled_at, request_count, window_start, updated_at)
+            VALUES ($1, $2, $3 - 1, $5, 1, $6, $6)
+            ON CONFLICT (client_ip, action)
+            DO UPDATE SET
+                tokens = CASE
+                    WHEN rate_limits.tokens + ($5 - rate_limits.refilled_at) * $4 > $3 THEN $3
+                    ELSE rate_limits.tokens + ($5 - rate_limits.refilled_at) * $4
+                END - 1,
+                refilled_at = $5,
+                request_count = rate_limits.request_count + 1,
+                updated_at = $6
+            WHERE rate_limits.tokens + ($5 - rate_limits.refilled_at) * $4 >= 1
+            RETURNING tokens
+        "#;
 
-        // First, try to get existing rate limit record
+        let taken: Option<f64> = with_pool!(&self.pool, pool => sqlx::query(take_query)
+            .bind(&client_ip_str)
+            .bind(action.as_str())
+            .bind(burst)
+            .bind(rate_per_ms)
+            .bind(now_ms)
+            .bind(now)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(|row| row.get("tokens"))))
+            .context("Failed to update rate limit")?;
+
+        if let Some(tokens) = taken {
+            return Ok(policy.status(tokens, true));
+        }
+
+        // The bucket was empty; see how close it is to the next token
         let query = r#"
-            SELECT request_count, window_start
+            SELECT tokens, refilled_at
             FROM rate_limits
-            WHERE client_ip = $1 AND window_start > $2
+            WHERE client_ip = $1 AND action = $2
         "#;
 
-        let existing = sqlx::query(query)
+        let (tokens, refilled_at): (f64, i64) = with_pool!(&self.pool, pool => sqlx::query(query)
             .bind(&client_ip_str)
-            .bind(window_start)
-            .fetch_optional(&self.pool)
+            .bind(action.as_str())
+            .fetch_one(pool)
             .await
+            .map(|row| (row.get("tokens"), row.get("refilled_at"))))
             .context("Failed to check existing rate limit")?;
 
-        match existing {
-            Some(row) => {
-                let request_count: i32 = row.get("request_count");
-                if request_count >= max_requests {
-                    return Ok(false); // Rate limit exceeded
-                }
+        let elapsed = Duration::from_millis(now_ms.saturating_sub(refilled_at).max(0) as u64);
+        Ok(policy.status(policy.refill(tokens, elapsed), false))
+    }
 
-                // Update existing record
-                let update_query = r#"
-                    UPDATE rate_limits
-                    SET request_count = request_count + 1, updated_at = NOW()
-                    WHERE client_ip = $1
+    /// Mark expired files, and any created before `created_before` whatever
+    /// their expiry, as purged and return their IDs, disk paths and sizes so
+    /// the caller can remove the contents. The rows stay behind as tombstones
+    /// so downloads can answer 410 Gone instead of 404.
+    pub async fn cleanup_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>> {
+        let now = Utc::now();
+
+        let expired: Vec<ExpiredFile> = match &self.pool {
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let query = r#"
+                    WITH expired AS (
+                        SELECT id, file_path, file_size
+                        FROM file_mappings
+                        WHERE purged_at IS NULL
+                          AND NOT pinned
+                          AND ((expires_at IS NOT NULL AND expires_at < $1) OR created_at < $2)
+                        FOR UPDATE SKIP LOCKED
+                    )
+                    UPDATE file_mappings f
+                    SET purged_at = $1, file_path = NULL, is_in_memory = FALSE
+                    FROM expired
+                    WHERE f.id = expired.id
+                    RETURNING expired.id, expired.file_path, expired.file_size
                 "#;
 
-                sqlx::query(update_query)
-                    .bind(&client_ip_str)
-                    .execute(&self.pool)
+                sqlx::query_as::<_, ExpiredFile>(query)
+                    .bind(now)
+                    .bind(created_before)
+                    .fetch_all(pool)
                     .await
-                    .context("Failed to update rate limit")?;
+                    .context("Failed to cleanup expired files")?
             }
-            None => {
-                // Create new record or reset if outside window
-                let upsert_query = r#"
-                    INSERT INTO rate_limits (client_ip, request_count, window_start)
-                    VALUES ($1, 1, $2)
-                    ON CONFLICT (client_ip)
-                    DO UPDATE SET 
-                        request_count = 1,
-                        window_start = $2,
-                        updated_at = NOW()
+            DbPool::Sqlite(pool) => {
+                // SQLite's RETURNING only sees new values, so return the paths
+                // while marking the rows and clear them in a second statement
+                let mark_query = r#"
+                    UPDATE file_mappings
+                    SET purged_at = $1
+                    WHERE purged_at IS NULL
+                      AND NOT pinned
+                      AND ((expires_at IS NOT NULL AND expires_at < $1) OR created_at < $2)
+                    RETURNING id, file_path, file_size
                 "#;
 
-                sqlx::query(upsert_query)
-                    .bind(&client_ip_str)
+                let expired: Vec<ExpiredFile> = sqlx::query_as::<_, ExpiredFile>(mark_query)
                     .bind(now)
-                    .execute(&self.pool)
+                    .bind(created_before)
+                    .fetch_all(pool)
+                    .await
+                    .context("Failed to cleanup expired files")?;
+
+                let clear_query = r#"
+                    UPDATE file_mappings
+                    SET file_path = NULL, is_in_memory = FALSE
+                    WHERE purged_at IS NOT NULL AND (file_path IS NOT NULL OR is_in_memory)
+                "#;
+
+                sqlx::query(clear_query)
+                    .execute(pool)
                     .await
-                    .context("Failed to create rate limit record")?;
+                    .context("Failed to clear purged file locations")?;
+
+                expired
             }
+        };
+
+        if !expired.is_empty() {
+            info!("Cleaned up {} expired files", expired.len());
         }
 
-        Ok(true) // Rate limit not exceeded
+        Ok(expired)
     }
 
-    pub async fn cleanup_expired_files(&self) -> Result<Vec<Uuid>> {
+    /// The files `cleanup_expired_files` would purge now, without purging them.
+    pub async fn find_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>> {
         let query = r#"
-            DELETE FROM file_mappings
-            WHERE expires_at IS NOT NULL AND expires_at < NOW()
-            RETURNING id
+            SELECT id, file_path, file_size
+            FROM file_mappings
+            WHERE purged_at IS NULL
+              AND NOT pinned
+              AND ((expires_at IS NOT NULL AND expires_at < $1) OR created_at < $2)
+        "#;
+
+        let expired = with_pool!(&self.pool, pool => sqlx::query_as::<_, ExpiredFile>(query)
+            .bind(Utc::now())
+            .bind(created_before)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to find expired files")?;
+
+        Ok(expired)
+    }
+
+    /// Delete tombstones purged longer than `retention` ago, and their short
+    /// codes; none when it reaches back further than dates go. Returns how
+    /// many were deleted and the short codes removed, so caches can forget
+    /// them too.
+    pub async fn cleanup_purged_files(&self, retention: Option<chrono::Duration>) -> Result<(u64, Vec<String>)> {
+        let Some(cutoff) = tombstone_cutoff(retention) else {
+            return Ok((0, Vec::new()));
+        };
+
+        // The cascade would take these anyway; deleting them first says which went
+        let short_codes_query = r#"
+            DELETE FROM short_urls
+            WHERE file_id IN (SELECT id FROM file_mappings WHERE purged_at IS NOT NULL AND purged_at < $1)
+            RETURNING short_code
         "#;
+        let query = "DELETE FROM file_mappings WHERE purged_at IS NOT NULL AND purged_at < $1";
 
-        let results = sqlx::query(query)
-            .fetch_all(&self.pool)
+        let short_codes: Vec<String> = with_pool!(&self.pool, pool => sqlx::query(short_codes_query)
+            .bind(cutoff)
+            .fetch_all(pool)
             .await
-            .context("Failed to cleanup expired files")?;
+            .map(|rows| rows.iter().map(|row| row.get("short_code")).collect()))
+            .context("Failed to cleanup short codes of purged files")?;
 
-        let expired_ids: Vec<Uuid> = results.into_iter()
-            .map(|row| row.get("id"))
-            .collect();
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(cutoff)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .context("Failed to cleanup purged file records")?;
 
-        if !expired_ids.is_empty() {
-            info!("Cleaned up {} expired files", expired_ids.len());
+        if rows_affected > 0 {
+            info!("Cleaned up {} purged file records", rows_affected);
         }
 
-        Ok(expired_ids)
+        Ok((rows_affected, short_codes))
     }
 
-    pub async fn cleanup_old_rate_limits(&self) -> Result<i64> {
-        let cutoff = Utc::now() - chrono::Duration::minutes(10); // Keep rate limits for 10 minutes
+    /// How many tombstones `cleanup_purged_files` would delete now.
+    pub async fn count_purged_files(&self, retention: Option<chrono::Duration>) -> Result<u64> {
+        let Some(cutoff) = tombstone_cutoff(retention) else {
+            return Ok(0);
+        };
+        let query = "SELECT COUNT(*) as purged FROM file_mappings WHERE purged_at IS NOT NULL AND purged_at < $1";
 
+        let purged: i64 = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(cutoff)
+            .fetch_one(pool)
+            .await
+            .map(|row| row.get("purged")))
+            .context("Failed to count purged file records")?;
+
+        Ok(purged.max(0) as u64)
+    }
+
+    pub async fn cleanup_old_rate_limits(&self) -> Result<i64> {
         let query = "DELETE FROM rate_limits WHERE updated_at < $1";
 
-        let result = sqlx::query(query)
-            .bind(cutoff)
-            .execute(&self.pool)
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(rate_limit_cutoff())
+            .execute(pool)
             .await
+            .map(|result| result.rows_affected()))
             .context("Failed to cleanup old rate limits")?;
 
-        let deleted_count = result.rows_affected() as i64;
+        let deleted_count = rows_affected as i64;
         if deleted_count > 0 {
             info!("Cleaned up {} old rate limit records", deleted_count);
         }
@@ -261,24 +1222,639 @@ impl Database {
         Ok(deleted_count)
     }
 
-    pub async fn get_storage_stats(&self) -> Result<(i64, i64, i64)> {
+    /// How many rate limit records `cleanup_old_rate_limits` would delete now.
+    pub async fn count_old_rate_limits(&self) -> Result<i64> {
+        let query = "SELECT COUNT(*) as stale FROM rate_limits WHERE updated_at < $1";
+
+        let stale = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(rate_limit_cutoff())
+            .fetch_one(pool)
+            .await
+            .map(|row| row.get("stale")))
+            .context("Failed to count old rate limits")?;
+
+        Ok(stale)
+    }
+
+    /// Bytes `client_ip` has uploaded in the quota window starting at `window_start`.
+    pub async fn quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>) -> Result<i64> {
+        let query = "SELECT bytes_used FROM ip_quotas WHERE client_ip = $1 AND window_start = $2";
+
+        let bytes_used: Option<i64> = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(client_ip.to_string())
+            .bind(window_start)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(|row| row.get("bytes_used"))))
+            .context("Failed to check upload quota")?;
+
+        Ok(bytes_used.unwrap_or_default())
+    }
+
+    /// Add `bytes` to `client_ip`'s usage, starting from zero if its record is
+    /// from an earlier window.
+    pub async fn add_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()> {
+        let query = r#"
+            INSERT INTO ip_quotas (client_ip, window_start, bytes_used, updated_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (client_ip)
+            DO UPDATE SET
+                bytes_used = CASE
+                    WHEN ip_quotas.window_start = excluded.window_start THEN ip_quotas.bytes_used + excluded.bytes_used
+                    ELSE excluded.bytes_used
+                END,
+                window_start = excluded.window_start,
+                updated_at = excluded.updated_at
+        "#;
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(client_ip.to_string())
+            .bind(window_start)
+            .bind(bytes)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .context("Failed to update upload quota")?;
+
+        Ok(())
+    }
+
+    /// Give `bytes` back to `client_ip`, if its usage is still for `window_start`.
+    pub async fn credit_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()> {
         let query = r#"
-            SELECT 
-                COUNT(*) as total_files,
-                COALESCE(SUM(file_size)::BIGINT, 0) as total_size,
-                COUNT(*) FILTER (WHERE is_in_memory = true) as memory_files
+            UPDATE ip_quotas
+            SET bytes_used = CASE WHEN bytes_used > $3 THEN bytes_used - $3 ELSE 0 END,
+                updated_at = $4
+            WHERE client_ip = $1 AND window_start = $2
+        "#;
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(client_ip.to_string())
+            .bind(window_start)
+            .bind(bytes)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .context("Failed to credit upload quota")?;
+
+        Ok(())
+    }
+
+    pub async fn cleanup_old_quotas(&self, current_window: DateTime<Utc>) -> Result<i64> {
+        let query = "DELETE FROM ip_quotas WHERE window_start < $1";
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(current_window)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .context("Failed to cleanup old upload quotas")?;
+
+        let deleted_count = rows_affected as i64;
+        if deleted_count > 0 {
+            info!("Cleaned up {} old upload quota records", deleted_count);
+        }
+
+        Ok(deleted_count)
+    }
+
+    /// Claim `key` for an upload in `scope` until `expires_at`. A key
+    /// past its expiry is taken over; the insert only succeeds for one of
+    /// several concurrent claims.
+    pub async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        key: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KeyClaim> {
+        let expire = "DELETE FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2 AND expires_at <= $3";
+        let insert = r#"
+            INSERT INTO idempotency_keys (scope, idempotency_key, response, created_at, expires_at)
+            VALUES ($1, $2, NULL, $3, $4)
+            ON CONFLICT (scope, idempotency_key) DO NOTHING
+        "#;
+        let select = "SELECT response FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2";
+
+        with_pool!(&self.pool, pool => sqlx::query(expire)
+            .bind(scope)
+            .bind(key)
+            .bind(now)
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .with_context(|| format!("Failed to expire idempotency key: {}", key))?;
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(insert)
+            .bind(scope)
+            .bind(key)
+            .bind(now)
+            .bind(expires_at)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to claim idempotency key: {}", key))?;
+        if rows_affected > 0 {
+            return Ok(KeyClaim::Claimed);
+        }
+
+        let response: Option<Option<String>> = with_pool!(&self.pool, pool => sqlx::query(select)
+            .bind(scope)
+            .bind(key)
+            .fetch_optional(pool)
+            .await
+            .map(|row| row.map(|row| row.get("response"))))
+            .with_context(|| format!("Failed to look up idempotency key: {}", key))?;
+
+        // A row gone by now was released by a failed upload; the client's next retry claims it
+        Ok(match response.flatten() {
+            Some(response) => KeyClaim::Completed(response),
+            None => KeyClaim::InProgress,
+        })
+    }
+
+    /// Record the response of the upload that claimed `key`.
+    pub async fn complete_idempotency_key(&self, scope: &str, key: &str, response: &str) -> Result<()> {
+        let query = "UPDATE idempotency_keys SET response = $3 WHERE scope = $1 AND idempotency_key = $2";
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(scope)
+            .bind(key)
+            .bind(response)
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .with_context(|| format!("Failed to complete idempotency key: {}", key))?;
+
+        Ok(())
+    }
+
+    /// Forget `key` if its upload never completed.
+    pub async fn release_idempotency_key(&self, scope: &str, key: &str) -> Result<()> {
+        let query = "DELETE FROM idempotency_keys WHERE scope = $1 AND idempotency_key = $2 AND response IS NULL";
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(scope)
+            .bind(key)
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .with_context(|| format!("Failed to release idempotency key: {}", key))?;
+
+        Ok(())
+    }
+
+    pub async fn cleanup_expired_idempotency_keys(&self) -> Result<i64> {
+        let query = "DELETE FROM idempotency_keys WHERE expires_at <= $1";
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .context("Failed to cleanup expired idempotency keys")?;
+
+        let deleted_count = rows_affected as i64;
+        if deleted_count > 0 {
+            info!("Cleaned up {} expired idempotency keys", deleted_count);
+        }
+
+        Ok(deleted_count)
+    }
+
+    /// One page of files matching `list`, keyset-paginated on the sort column
+    /// and ID so deep pages cost the same as the first.
+    pub async fn list_file_mappings(&self, list: &FileListQuery) -> Result<Vec<FileMapping>> {
+        let mut conditions = vec!["purged_at IS NULL".to_string()];
+        let mut binds = Vec::new();
+        let mut condition = |sql: &str, value: Bind, binds: &mut Vec<Bind>| {
+            binds.push(value);
+            conditions.push(sql.replace('?', &format!("${}", binds.len())));
+        };
+
+        if let Some(min_size) = list.min_size {
+            condition("file_size >= ?", Bind::Int(min_size), &mut binds);
+        }
+        if let Some(max_size) = list.max_size {
+            condition("file_size <= ?", Bind::Int(max_size), &mut binds);
+        }
+        if let Some(created_before) = list.created_before {
+            condition("created_at < ?", Bind::Time(created_before), &mut binds);
+        }
+        if let Some(created_after) = list.created_after {
+            condition("created_at > ?", Bind::Time(created_after), &mut binds);
+        }
+        if let Some(ref prefix) = list.content_type_prefix {
+            let escaped = prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+            condition("content_type LIKE ? ESCAPE '\\'", Bind::Text(format!("{}%", escaped)), &mut binds);
+        }
+        if let Some(is_in_memory) = list.is_in_memory {
+            condition("is_in_memory = ?", Bind::Flag(is_in_memory), &mut binds);
+        }
+        if let Some(pinned) = list.pinned {
+            condition("pinned = ?", Bind::Flag(pinned), &mut binds);
+        }
+        if let Some(api_key_id) = list.api_key_id {
+            condition("api_key_id = ?", Bind::Id(api_key_id), &mut binds);
+        }
+
+        let column = list.sort.column();
+        let (direction, beyond) = if list.ascending { ("ASC", ">") } else { ("DESC", "<") };
+        if let Some(cursor) = list.after {
+            let key = match list.sort {
+                FileSort::CreatedAt => Bind::Time(DateTime::from_timestamp_nanos(cursor.key)),
+                _ => Bind::Int(cursor.key),
+            };
+            binds.push(key);
+            binds.push(Bind::Id(cursor.id));
+            let (key_param, id_param) = (binds.len() - 1, binds.len());
+            conditions.push(format!(
+                "({column} {beyond} ${key_param} OR ({column} = ${key_param} AND id {beyond} ${id_param}))"
+            ));
+        }
+
+        binds.push(Bind::Int(list.limit));
+        let query = format!(
+            "SELECT * FROM file_mappings WHERE {} ORDER BY {column} {direction}, id {direction} LIMIT ${}",
+            conditions.join(" AND "),
+            binds.len()
+        );
+
+        let result = with_pool!(&self.pool, pool => {
+            let mut query = sqlx::query_as::<_, FileMapping>(&query);
+            for value in &binds {
+                query = match value {
+                    Bind::Int(value) => query.bind(*value),
+                    Bind::Time(value) => query.bind(*value),
+                    Bind::Text(value) => query.bind(value.as_str()),
+                    Bind::Flag(value) => query.bind(*value),
+                    Bind::Id(value) => query.bind(*value),
+                };
+            }
+            query.fetch_all(pool).await
+        })
+        .context("Failed to list file mappings")?;
+
+        Ok(result)
+    }
+
+    /// Store a new API key under the hash of its secret.
+    pub async fn store_api_key(&self, api_key: &ApiKey, key_hash: &str) -> Result<()> {
+        let query = r#"
+            INSERT INTO api_keys (id, name, key_hash, quota_bytes, quota_files, rate_limit_multiplier, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#;
+
+        with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(api_key.id)
+            .bind(&api_key.name)
+            .bind(key_hash)
+            .bind(api_key.quota_bytes)
+            .bind(api_key.quota_files)
+            .bind(api_key.rate_limit_multiplier)
+            .bind(api_key.created_at)
+            .execute(pool)
+            .await
+            .map(|_| ()))
+            .with_context(|| format!("Failed to store API key: {}", api_key.id))?;
+
+        Ok(())
+    }
+
+    /// The key whose secret hashes to `key_hash`, revoked or not.
+    pub async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let query = format!("SELECT {} FROM api_keys WHERE key_hash = $1", API_KEY_COLUMNS);
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, ApiKey>(&query)
+            .bind(key_hash)
+            .fetch_optional(pool)
+            .await)
+            .context("Failed to look up API key")?;
+
+        Ok(result)
+    }
+
+    pub async fn find_api_key(&self, id: Uuid) -> Result<Option<ApiKey>> {
+        let query = format!("SELECT {} FROM api_keys WHERE id = $1", API_KEY_COLUMNS);
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, ApiKey>(&query)
+            .bind(id)
+            .fetch_optional(pool)
+            .await)
+            .with_context(|| format!("Failed to find API key: {}", id))?;
+
+        Ok(result)
+    }
+
+    /// Every API key, revoked ones included, oldest first.
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        let query = format!("SELECT {} FROM api_keys ORDER BY created_at, id", API_KEY_COLUMNS);
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, ApiKey>(&query)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to list API keys")?;
+
+        Ok(result)
+    }
+
+    /// Replace a key's quotas and rate limit multiplier. False if there is no
+    /// such key or it has been revoked.
+    pub async fn update_api_key_limits(&self, api_key: &ApiKey) -> Result<bool> {
+        let query = r#"
+            UPDATE api_keys
+            SET quota_bytes = $2, quota_files = $3, rate_limit_multiplier = $4
+            WHERE id = $1 AND revoked_at IS NULL
+        "#;
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(api_key.id)
+            .bind(api_key.quota_bytes)
+            .bind(api_key.quota_files)
+            .bind(api_key.rate_limit_multiplier)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to update API key: {}", api_key.id))?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Stop a key from authenticating. Its files are kept. False if there is
+    /// no such key or it was already revoked.
+    pub async fn revoke_api_key(&self, id: Uuid) -> Result<bool> {
+        let query = "UPDATE api_keys SET revoked_at = $2 WHERE id = $1 AND revoked_at IS NULL";
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(id)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to revoke API key: {}", id))?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Files uploaded with `api_key_id` that haven't been purged, and their bytes.
+    pub async fn api_key_usage(&self, api_key_id: Uuid) -> Result<ApiKeyUsage> {
+        let query = r#"
+            SELECT COUNT(*) as files, CAST(COALESCE(SUM(file_size), 0) AS BIGINT) as bytes
             FROM file_mappings
+            WHERE api_key_id = $1 AND purged_at IS NULL
         "#;
 
-        let row = sqlx::query(query)
-            .fetch_one(&self.pool)
+        let usage = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(api_key_id)
+            .fetch_one(pool)
             .await
+            .map(|row| ApiKeyUsage {
+                files: row.get("files"),
+                bytes: row.get("bytes"),
+            }))
+            .with_context(|| format!("Failed to get usage of API key: {}", api_key_id))?;
+
+        Ok(usage)
+    }
+
+    /// Up to `limit` file mappings, tombstones included, in ID order after `after`.
+    pub async fn file_mappings_after(&self, after: Uuid, limit: i64) -> Result<Vec<FileMapping>> {
+        let query = "SELECT * FROM file_mappings WHERE id > $1 ORDER BY id LIMIT $2";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .bind(after)
+            .bind(limit)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to page through file mappings")?;
+
+        Ok(result)
+    }
+
+    /// Up to `limit` short codes, in order after `after`.
+    pub async fn short_urls_after(&self, after: &str, limit: i64) -> Result<Vec<ShortUrl>> {
+        let query = "SELECT short_code, file_id, created_at FROM short_urls WHERE short_code > $1 ORDER BY short_code LIMIT $2";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, ShortUrl>(query)
+            .bind(after)
+            .bind(limit)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to page through short URLs")?;
+
+        Ok(result)
+    }
+
+    /// Insert a file mapping exactly as given, counts and tombstone included.
+    /// Returns false, changing nothing, if its ID is already taken.
+    pub async fn import_file_mapping(&self, file_mapping: &FileMapping) -> Result<bool> {
+        let query = r#"
+            INSERT INTO file_mappings (id, filename, content_type, file_path, file_size, is_in_memory, created_at, accessed_at, access_count, expires_at, delete_token, purged_at, max_downloads, content_hash, declared_content_type, detected_content_type, uploader_ip, sha256, encrypted, pinned, api_key_id, corrupt, embeddable)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23)
+            ON CONFLICT (id) DO NOTHING
+        "#;
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(file_mapping.id)
+            .bind(&file_mapping.filename)
+            .bind(&file_mapping.content_type)
+            .bind(&file_mapping.file_path)
+            .bind(file_mapping.file_size)
+            .bind(file_mapping.is_in_memory)
+            .bind(file_mapping.created_at)
+            .bind(file_mapping.accessed_at)
+            .bind(file_mapping.access_count)
+            .bind(file_mapping.expires_at)
+            .bind(&file_mapping.delete_token)
+            .bind(file_mapping.purged_at)
+            .bind(file_mapping.max_downloads)
+            .bind(&file_mapping.content_hash)
+            .bind(&file_mapping.declared_content_type)
+            .bind(&file_mapping.detected_content_type)
+            .bind(&file_mapping.uploader_ip)
+            .bind(&file_mapping.sha256)
+            .bind(file_mapping.encrypted)
+            .bind(file_mapping.pinned)
+            .bind(file_mapping.api_key_id)
+            .bind(file_mapping.corrupt)
+            .bind(file_mapping.embeddable)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to import file mapping for ID: {}", file_mapping.id))?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Insert a short code as given. Returns false, changing nothing, if the
+    /// code is taken or its file isn't there.
+    pub async fn import_short_url(&self, short_url: &ShortUrl) -> Result<bool> {
+        let query = r#"
+            INSERT INTO short_urls (short_code, file_id, created_at)
+            SELECT $1, $2, $3 WHERE EXISTS (SELECT 1 FROM file_mappings WHERE id = $2)
+            ON CONFLICT (short_code) DO NOTHING
+        "#;
+
+        let rows_affected = with_pool!(&self.pool, pool => sqlx::query(query)
+            .bind(&short_url.short_code)
+            .bind(short_url.file_id)
+            .bind(short_url.created_at)
+            .execute(pool)
+            .await
+            .map(|result| result.rows_affected()))
+            .with_context(|| format!("Failed to import short code: {}", short_url.short_code))?;
+
+        Ok(rows_affected > 0)
+    }
+
+    /// Totals over files that haven't been purged, from the row the
+    /// `storage_totals` triggers keep, plus how many expire within `horizon`.
+    pub async fn get_storage_stats(&self, horizon: chrono::Duration) -> Result<StorageTotals> {
+        let totals_query = r#"
+            SELECT total_files, total_size, memory_files, memory_size
+            FROM storage_totals
+        "#;
+        // Served by the expires_at index rather than a scan
+        let expiring_query = r#"
+            SELECT COUNT(*) as expiring_files
+            FROM file_mappings
+            WHERE expires_at > $1 AND expires_at <= $2 AND purged_at IS NULL AND NOT pinned
+        "#;
+        let now = Utc::now();
+
+        let (total_files, total_size, memory_files, memory_size): (i64, i64, i64, i64) = with_pool!(&self.pool, pool => sqlx::query(totals_query)
+            .fetch_optional(pool)
+            .await
+            .map(|row| {
+                row.map_or((0, 0, 0, 0), |row| {
+                    (row.get("total_files"), row.get("total_size"), row.get("memory_files"), row.get("memory_size"))
+                })
+            }))
             .context("Failed to get storage stats")?;
 
-        let total_files: i64 = row.get("total_files");
-        let total_size: i64 = row.get("total_size");
-        let memory_files: i64 = row.get("memory_files");
+        let expiring_files: i64 = with_pool!(&self.pool, pool => sqlx::query(expiring_query)
+            .bind(now)
+            .bind(now + horizon)
+            .fetch_one(pool)
+            .await
+            .map(|row| row.get("expiring_files")))
+            .context("Failed to count expiring files")?;
 
-        Ok((total_files, total_size, memory_files))
+        Ok(StorageTotals {
+            total_files,
+            total_size,
+            memory_files,
+            memory_size,
+            expiring_files,
+        })
     }
-}
\ No newline at end of file
+
+    /// Add downloads and bytes served to their days' totals.
+    pub async fn record_traffic(&self, traffic: &[DailyTraffic]) -> Result<()> {
+        let query = r#"
+            INSERT INTO daily_traffic (day, downloads, bytes_served)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (day) DO UPDATE SET
+                downloads = daily_traffic.downloads + excluded.downloads,
+                bytes_served = daily_traffic.bytes_served + excluded.bytes_served
+        "#;
+
+        for day in traffic {
+            with_pool!(&self.pool, pool => sqlx::query(query)
+                .bind(&day.day)
+                .bind(day.downloads)
+                .bind(day.bytes_served)
+                .execute(pool)
+                .await
+                .map(|_| ()))
+                .with_context(|| format!("Failed to record traffic for {}", day.day))?;
+        }
+
+        Ok(())
+    }
+
+    /// Daily traffic from `since` ('YYYY-MM-DD') on, oldest first. Days
+    /// without downloads have no row.
+    pub async fn traffic_since(&self, since: &str) -> Result<Vec<DailyTraffic>> {
+        let query = "SELECT day, downloads, bytes_served FROM daily_traffic WHERE day >= $1 ORDER BY day";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, DailyTraffic>(query)
+            .bind(since)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to get daily traffic")?;
+
+        Ok(result)
+    }
+
+    /// Files uploaded per UTC day from `since` on, tombstones included, as
+    /// ('YYYY-MM-DD', count) oldest first.
+    pub async fn uploads_per_day(&self, since: DateTime<Utc>) -> Result<Vec<(String, i64)>> {
+        let day = match &self.pool {
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(_) => "TO_CHAR(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD')",
+            // Timestamps are stored as text starting with the date
+            DbPool::Sqlite(_) => "SUBSTR(created_at, 1, 10)",
+        };
+        let query = format!(
+            "SELECT {day} AS day, COUNT(*) AS uploads FROM file_mappings WHERE created_at >= $1 GROUP BY 1 ORDER BY 1"
+        );
+
+        let result: Vec<(String, i64)> = with_pool!(&self.pool, pool => sqlx::query(&query)
+            .bind(since)
+            .fetch_all(pool)
+            .await
+            .map(|rows| rows.iter().map(|row| (row.get("day"), row.get("uploads"))).collect()))
+            .context("Failed to count uploads per day")?;
+
+        Ok(result)
+    }
+
+    /// The `limit` most downloaded files that haven't been purged.
+    pub async fn most_downloaded_files(&self, limit: i64) -> Result<Vec<FileMapping>> {
+        let query = "SELECT * FROM file_mappings WHERE purged_at IS NULL ORDER BY access_count DESC, id LIMIT $1";
+
+        let result = with_pool!(&self.pool, pool => sqlx::query_as::<_, FileMapping>(query)
+            .bind(limit)
+            .fetch_all(pool)
+            .await)
+            .context("Failed to find the most downloaded files")?;
+
+        Ok(result)
+    }
+
+    /// Files that haven't been purged, counted into buckets by size: bucket
+    /// `n` holds files smaller than `upper_bounds[n]` (and not in an earlier
+    /// bucket), and the last bucket, `upper_bounds.len()`, everything else.
+    /// Empty buckets have no row.
+    pub async fn file_size_distribution(&self, upper_bounds: &[i64]) -> Result<Vec<SizeBucketCount>> {
+        let cases: String = (1..=upper_bounds.len())
+            .map(|n| format!("WHEN file_size < ${} THEN {} ", n, n - 1))
+            .collect();
+        let query = format!(
+            r#"
+            SELECT CAST(CASE {cases}ELSE {last} END AS BIGINT) AS bucket,
+                   COUNT(*) AS files,
+                   CAST(COALESCE(SUM(file_size), 0) AS BIGINT) AS bytes
+            FROM file_mappings
+            WHERE purged_at IS NULL
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+            last = upper_bounds.len()
+        );
+
+        let result = with_pool!(&self.pool, pool => {
+            let mut query = sqlx::query_as::<_, SizeBucketCount>(&query);
+            for bound in upper_bounds {
+                query = query.bind(*bound);
+            }
+            query.fetch_all(pool).await
+        })
+        .context("Failed to count files by size")?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,429 @@
+use aes_gcm::{
+    Aes256Gcm, Key, Nonce,
+    aead::{Aead, KeyInit, OsRng, rand_core::RngCore},
+};
+use axum::http::StatusCode;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use color_eyre::eyre::{Result, eyre};
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufWriter, ReadBuf};
+use tracing::error;
+
+use crate::config::Config;
+use crate::storage::StoredObject;
+
+// Encrypted files start with MAGIC, the id of the key they were written
+// under and a random nonce prefix, followed by the contents in sealed chunks
+const MAGIC: &[u8; 8] = b"DROPENC1";
+const KEY_ID_LEN: usize = 8;
+const NONCE_PREFIX_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_PREFIX_LEN;
+
+// Plaintext per chunk; each is sealed separately so downloads can seek
+const CHUNK_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+const SEALED_CHUNK_LEN: u64 = (CHUNK_LEN + TAG_LEN) as u64;
+
+type KeyId = [u8; KEY_ID_LEN];
+
+/// A 256-bit AES key, given base64-encoded. Never printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn parse(value: &str) -> Result<Self> {
+        let bytes = STANDARD
+            .decode(value.trim())
+            .map_err(|_| eyre!("expected a base64-encoded 32-byte key"))?;
+        let key = <[u8; 32]>::try_from(bytes.as_slice())
+            .map_err(|_| eyre!("expected a 32-byte key, got {} bytes", bytes.len()))?;
+        Ok(Self(key))
+    }
+
+    // First bytes of the key's SHA-256, recorded in each file to pick the key back out
+    fn id(&self) -> KeyId {
+        let digest = Sha256::digest(self.0);
+        let mut id = KeyId::default();
+        id.copy_from_slice(&digest[..KEY_ID_LEN]);
+        id
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(<redacted>)")
+    }
+}
+
+/// The keys files can be decrypted with, and the one new files are encrypted
+/// with, if any. Old keys only decrypt, so they can be rotated out.
+#[derive(Clone)]
+pub struct Keyring {
+    active: Option<KeyId>,
+    ciphers: Vec<(KeyId, Aes256Gcm)>,
+}
+
+impl Keyring {
+    /// None unless `DROP_ENCRYPTION_KEY` or `DROP_ENCRYPTION_OLD_KEYS` is set.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let keys: Vec<&EncryptionKey> = config
+            .encryption_key
+            .iter()
+            .chain(&config.encryption_old_keys)
+            .collect();
+        if keys.is_empty() {
+            return None;
+        }
+        Some(Self {
+            active: config.encryption_key.as_ref().map(EncryptionKey::id),
+            ciphers: keys
+                .into_iter()
+                .map(|key| (key.id(), Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0))))
+                .collect(),
+        })
+    }
+
+    /// Whether new files are written encrypted.
+    pub fn encrypts(&self) -> bool {
+        self.active.is_some()
+    }
+
+    fn cipher(&self, key_id: &KeyId) -> Option<&Aes256Gcm> {
+        self.ciphers
+            .iter()
+            .find(|(id, _)| id == key_id)
+            .map(|(_, cipher)| cipher)
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("encrypts", &self.encrypts())
+            .field("keys", &self.ciphers.len())
+            .finish()
+    }
+}
+
+// 96-bit nonce of a chunk: the file's random prefix, the chunk's index and
+// whether it is the last one, so chunks can't be reordered or truncated
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u64, last: bool) -> io::Result<[u8; 12]> {
+    let index = u32::try_from(index).map_err(|_| io::Error::other("encrypted file has too many chunks"))?;
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = u8::from(last);
+    Ok(nonce)
+}
+
+/// Like `append_stream_to_file`, but seals the stream into `file` under the
+/// active key. Returns the plaintext size.
+pub(crate) async fn append_encrypted_stream<S, E>(
+    field: S,
+    file: &mut File,
+    max_size: usize,
+    buffer_size: usize,
+    keyring: &Keyring,
+) -> Result<usize, StatusCode>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Debug,
+{
+    let (key_id, cipher) = keyring
+        .active
+        .and_then(|key_id| Some((key_id, keyring.cipher(&key_id)?)))
+        .ok_or_else(|| {
+            error!("Asked to encrypt a file without an active encryption key");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut nonce_prefix);
+
+    let mut writer = BufWriter::with_capacity(buffer_size, file);
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&key_id);
+    header.extend_from_slice(&nonce_prefix);
+    write_sealed(&mut writer, &header).await?;
+
+    let mut field = std::pin::pin!(field);
+    let mut total_size = 0usize;
+    let mut buffer = Vec::with_capacity(CHUNK_LEN * 2);
+    let mut index = 0u64;
+
+    while let Some(chunk) = field.next().await.transpose().map_err(|e| {
+        error!("Failed to read chunk during streaming: {:?}", e);
+        StatusCode::BAD_REQUEST
+    })? {
+        total_size += chunk.len();
+
+        // Check size limit during streaming
+        if total_size > max_size {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        buffer.extend_from_slice(&chunk);
+
+        // A full chunk is only sealed once more data follows it, so the last
+        // chunk is known when the stream ends
+        while buffer.len() > CHUNK_LEN {
+            let sealed = seal(cipher, &nonce_prefix, index, false, &buffer[..CHUNK_LEN])?;
+            write_sealed(&mut writer, &sealed).await?;
+            buffer.drain(..CHUNK_LEN);
+            index += 1;
+        }
+    }
+
+    // Always seal a last chunk, even an empty one, so truncation is detected
+    let sealed = seal(cipher, &nonce_prefix, index, true, &buffer)?;
+    write_sealed(&mut writer, &sealed).await?;
+
+    writer.flush().await.map_err(|e| {
+        error!("Failed to flush file to disk: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(total_size)
+}
+
+fn seal(
+    cipher: &Aes256Gcm,
+    nonce_prefix: &[u8; NONCE_PREFIX_LEN],
+    index: u64,
+    last: bool,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, StatusCode> {
+    let nonce = chunk_nonce(nonce_prefix, index, last).map_err(|e| {
+        error!("Failed to encrypt chunk: {:?}", e);
+        StatusCode::PAYLOAD_TOO_LARGE
+    })?;
+    cipher.encrypt(Nonce::from_slice(&nonce), plaintext).map_err(|e| {
+        error!("Failed to encrypt chunk: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+async fn write_sealed<W: AsyncWrite + Unpin>(writer: &mut W, bytes: &[u8]) -> Result<(), StatusCode> {
+    writer.write_all(bytes).await.map_err(|e| {
+        error!("Failed to write chunk to disk: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+struct Header {
+    key_id: KeyId,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+// The encryption header at the start of `file`, if it has one. Leaves the
+// file positioned after the header, or at the start if there is none.
+async fn read_header(file: &mut File, file_len: u64) -> io::Result<Option<Header>> {
+    if file_len < (HEADER_LEN + TAG_LEN) as u64 {
+        return Ok(None);
+    }
+    let mut header = [0u8; HEADER_LEN];
+    file.read_exact(&mut header).await?;
+    if !header.starts_with(MAGIC) {
+        file.seek(SeekFrom::Start(0)).await?;
+        return Ok(None);
+    }
+
+    let mut key_id = KeyId::default();
+    key_id.copy_from_slice(&header[MAGIC.len()..MAGIC.len() + KEY_ID_LEN]);
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    nonce_prefix.copy_from_slice(&header[MAGIC.len() + KEY_ID_LEN..]);
+    Ok(Some(Header { key_id, nonce_prefix }))
+}
+
+/// Whether the file at `path` was written encrypted.
+pub async fn is_encrypted(path: &Path) -> io::Result<bool> {
+    let mut file = File::open(path).await?;
+    let file_len = file.metadata().await?.len();
+    Ok(read_header(&mut file, file_len).await?.is_some())
+}
+
+/// Open a stored file, decrypting it while it is read if it was written
+/// encrypted. The length is always that of the plaintext.
+pub(crate) async fn open(path: &Path, keyring: Option<&Keyring>) -> io::Result<StoredObject> {
+    let mut file = File::open(path).await?;
+    let file_len = file.metadata().await?.len();
+    let Some(header) = read_header(&mut file, file_len).await? else {
+        return Ok(StoredObject {
+            reader: Box::new(file),
+            len: file_len,
+            contents: None,
+        });
+    };
+
+    let cipher = keyring
+        .ok_or_else(|| io::Error::other("file is encrypted but no encryption key is configured"))?
+        .cipher(&header.key_id)
+        .ok_or_else(|| io::Error::other("file is encrypted with a key that is not configured"))?
+        .clone();
+    let body_len = file_len - HEADER_LEN as u64;
+    let chunks = body_len.div_ceil(SEALED_CHUNK_LEN);
+    let len = body_len - chunks * TAG_LEN as u64;
+    Ok(StoredObject {
+        reader: Box::new(DecryptingReader {
+            file,
+            cipher,
+            nonce_prefix: header.nonce_prefix,
+            body_len,
+            chunks,
+            len,
+            position: 0,
+            next_chunk: 0,
+            sealed: vec![0u8; SEALED_CHUNK_LEN as usize],
+            filled: 0,
+            plain: Vec::new(),
+            plain_pos: 0,
+            skip: 0,
+            pending_seek: None,
+        }),
+        len,
+        contents: None,
+    })
+}
+
+// Plaintext position a seek is heading for, the chunk it lands in and
+// whether the file still has to be moved there
+#[derive(Clone, Copy)]
+struct PendingSeek {
+    target: u64,
+    chunk: u64,
+    file_seek: bool,
+}
+
+/// Reads the plaintext of an encrypted file one chunk at a time, checking
+/// each chunk's tag before any of it is returned. Seeks land on the chunk
+/// holding the target and skip into it.
+struct DecryptingReader {
+    file: File,
+    cipher: Aes256Gcm,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    body_len: u64, // Bytes after the header
+    chunks: u64,
+    len: u64, // Plaintext length
+    position: u64, // Plaintext offset of the next byte returned
+    next_chunk: u64, // Chunk the file is positioned at
+    sealed: Vec<u8>,
+    filled: usize, // Bytes of `next_chunk` read into `sealed` so far
+    plain: Vec<u8>,
+    plain_pos: usize,
+    skip: usize, // Plaintext to drop from the next chunk, after a seek
+    pending_seek: Option<PendingSeek>,
+}
+
+impl DecryptingReader {
+    fn sealed_len(&self, chunk: u64) -> usize {
+        if chunk + 1 < self.chunks {
+            SEALED_CHUNK_LEN as usize
+        } else {
+            (self.body_len - (self.chunks - 1) * SEALED_CHUNK_LEN) as usize
+        }
+    }
+
+    fn open_chunk(&mut self, sealed_len: usize) -> io::Result<()> {
+        let last = self.next_chunk + 1 == self.chunks;
+        let nonce = chunk_nonce(&self.nonce_prefix, self.next_chunk, last)?;
+        self.plain = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), &self.sealed[..sealed_len])
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "encrypted chunk failed authentication"))?;
+        self.plain_pos = self.skip.min(self.plain.len());
+        self.skip = 0;
+        self.next_chunk += 1;
+        self.filled = 0;
+        Ok(())
+    }
+}
+
+impl AsyncRead for DecryptingReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.plain_pos);
+                buf.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                this.position += n as u64;
+                return Poll::Ready(Ok(()));
+            }
+            if this.next_chunk >= this.chunks {
+                return Poll::Ready(Ok(()));
+            }
+
+            let sealed_len = this.sealed_len(this.next_chunk);
+            while this.filled < sealed_len {
+                let mut chunk_buf = ReadBuf::new(&mut this.sealed[this.filled..sealed_len]);
+                ready!(Pin::new(&mut this.file).poll_read(cx, &mut chunk_buf))?;
+                let read = chunk_buf.filled().len();
+                if read == 0 {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "encrypted file is truncated",
+                    )));
+                }
+                this.filled += read;
+            }
+            this.open_chunk(sealed_len)?;
+        }
+    }
+}
+
+impl AsyncSeek for DecryptingReader {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = &mut *self;
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => this.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => this.position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+
+        let chunk = (target / CHUNK_LEN as u64).min(this.chunks);
+        let file_seek = target < this.len;
+        if file_seek {
+            Pin::new(&mut this.file).start_seek(SeekFrom::Start(HEADER_LEN as u64 + chunk * SEALED_CHUNK_LEN))?;
+        }
+        this.pending_seek = Some(PendingSeek {
+            target,
+            chunk,
+            file_seek,
+        });
+        Ok(())
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = &mut *self;
+        let Some(seek) = this.pending_seek else {
+            return Poll::Ready(Ok(this.position));
+        };
+        if seek.file_seek {
+            ready!(Pin::new(&mut this.file).poll_complete(cx))?;
+        }
+
+        this.pending_seek = None;
+        this.position = seek.target;
+        this.plain.clear();
+        this.plain_pos = 0;
+        this.filled = 0;
+        if seek.file_seek {
+            this.next_chunk = seek.chunk;
+            this.skip = (seek.target % CHUNK_LEN as u64) as usize;
+        } else {
+            // At or past the end: nothing more to read
+            this.next_chunk = this.chunks;
+            this.skip = 0;
+        }
+        Poll::Ready(Ok(seek.target))
+    }
+}
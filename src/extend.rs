@@ -0,0 +1,233 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::{info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    AppState, FileData, admin, config::parse_duration, database::FileMapping, invalidate_cached_file, openapi::ErrorResponse,
+    recovery, resolve_id_or_short_code_db, storage::StorageRef, token_matches,
+};
+
+/// A new expiry, given either as time to add or as a moment.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExtendRequest {
+    #[serde(default)]
+    extend_by: Option<String>, // e.g. "48h" or "7d", added to the current expiry
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    resurrect: bool, // Bring back a file that has expired but not yet been purged
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ExtendResponse {
+    id: Uuid,
+    expires_at: Option<DateTime<Utc>>, // None for a file that never expires
+}
+
+// The parts of a file's metadata an extension looks at
+struct Lifetime {
+    delete_token: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    gone: bool, // Purged, or used up its downloads; no expiry brings it back
+    file_path: Option<PathBuf>, // Where the sidecar lives, for disk-backed files
+    in_database: bool,
+}
+
+impl Lifetime {
+    fn from_mapping(file_mapping: FileMapping, temp_directory: &std::path::Path) -> Self {
+        let exhausted = file_mapping.max_downloads.is_some_and(|max| file_mapping.access_count >= max);
+        let file_path = match StorageRef::from_mapping(&file_mapping, temp_directory) {
+            Some(StorageRef::Disk(path)) => Some(path),
+            _ => None,
+        };
+        Self {
+            delete_token: file_mapping.delete_token.unwrap_or_default(),
+            created_at: file_mapping.created_at,
+            expires_at: file_mapping.expires_at,
+            gone: file_mapping.purged_at.is_some() || exhausted,
+            file_path,
+            in_database: true,
+        }
+    }
+
+    fn from_file_data(file_data: &FileData) -> Self {
+        let exhausted = file_data.max_downloads.is_some_and(|max| file_data.download_count >= max);
+        let file_path = match file_data.storage {
+            Some(StorageRef::Disk(ref path)) => Some(path.clone()),
+            _ => None,
+        };
+        Self {
+            delete_token: file_data.delete_token.clone(),
+            created_at: file_data.created_at,
+            expires_at: file_data.expires_at,
+            gone: file_data.purged_at.is_some() || exhausted,
+            file_path,
+            in_database: false,
+        }
+    }
+}
+
+async fn find_lifetime(app_state: &AppState, id: Uuid) -> Option<Lifetime> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) => return Some(Lifetime::from_mapping(file_mapping, &app_state.config.temp_directory)),
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    app_state.file_storage.get(&id).map(|file_data| Lifetime::from_file_data(&file_data))
+}
+
+// The upload's delete token, or the admin token when one is sent instead
+fn authorized(app_state: &AppState, headers: &HeaderMap, delete_token: &str) -> bool {
+    if headers.contains_key("x-admin-token") {
+        return admin::authorize(app_state, headers).is_ok();
+    }
+    token_matches(delete_token, headers.get("x-delete-token").and_then(|v| v.to_str().ok()))
+}
+
+// The expiry asked for, before DROP_MAX_TTL has its say
+fn requested_expiry(
+    request: &ExtendRequest,
+    current: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, StatusCode> {
+    match (&request.extend_by, request.expires_at) {
+        (Some(extend_by), None) => {
+            let extend_by = parse_duration(extend_by)
+                .ok()
+                .and_then(|extend_by| chrono::Duration::from_std(extend_by).ok())
+                .filter(|extend_by| *extend_by > chrono::Duration::zero())
+                .ok_or_else(|| {
+                    warn!("Invalid extend_by value: {}", extend_by);
+                    StatusCode::BAD_REQUEST
+                })?;
+            // An expired file being brought back gets the time from now
+            current.max(now).checked_add_signed(extend_by).ok_or(StatusCode::BAD_REQUEST)
+        }
+        (None, Some(expires_at)) if expires_at > now => Ok(expires_at),
+        (None, Some(expires_at)) => {
+            warn!("Rejecting expiry in the past: {}", expires_at);
+            Err(StatusCode::BAD_REQUEST)
+        }
+        _ => {
+            warn!("Extension needs exactly one of extend_by and expires_at");
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+// Record the new expiry wherever the file is indexed. False if it was purged meanwhile.
+async fn store_expiry(app_state: &AppState, id: Uuid, lifetime: &Lifetime, expires_at: DateTime<Utc>) -> Result<bool, StatusCode> {
+    let stored = match app_state.database {
+        Some(ref db) if lifetime.in_database => db.set_file_expiry(id, expires_at).await.map_err(|e| {
+            warn!("Failed to extend {} in database: {}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?,
+        _ => match app_state.file_storage.get_mut(&id) {
+            Some(mut file_data) if file_data.purged_at.is_none() => {
+                file_data.expires_at = Some(expires_at);
+                true
+            }
+            _ => false,
+        },
+    };
+    if !stored {
+        return Ok(false);
+    }
+
+    invalidate_cached_file(app_state, id);
+    if let Some(ref file_path) = lifetime.file_path
+        && let Err(e) = recovery::update_sidecar_expiry(file_path, Some(expires_at)).await
+    {
+        warn!("Failed to update sidecar for {}, a restart would restore its old expiry: {:?}", id, e);
+    }
+    Ok(true)
+}
+
+// POST /drop/{id}/extend - push a file's expiry back; requires X-Delete-Token or X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/drop/{id}/extend",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID or short code"),
+        ("X-Delete-Token" = Option<String>, Header, description = "Returned by the upload"),
+        ("X-Admin-Token" = Option<String>, Header, description = "`DROP_ADMIN_TOKEN`, instead of the delete token"),
+    ),
+    request_body = ExtendRequest,
+    responses(
+        (status = 200, description = "The file's expiry now; unchanged for a file that never expires", body = ExtendResponse),
+        (status = 400, description = "Neither or both of extend_by and expires_at, or a value that doesn't parse", body = ErrorResponse),
+        (status = 403, description = "Missing or wrong token", body = ErrorResponse),
+        (status = 404, description = "No such file", body = ErrorResponse),
+        (status = 410, description = "The file has expired, been purged or used up its downloads", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn extend_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ExtendRequest>,
+) -> Result<Json<ExtendResponse>, StatusCode> {
+    let file_id = resolve_id_or_short_code_db(&id, &app_state).await.ok_or(StatusCode::NOT_FOUND)?;
+    let lifetime = find_lifetime(&app_state, file_id).await.ok_or(StatusCode::NOT_FOUND)?;
+    if !authorized(&app_state, &headers, &lifetime.delete_token) {
+        warn!("Rejected extension for {}: invalid token", file_id);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if lifetime.gone {
+        return Err(StatusCode::GONE);
+    }
+
+    let Some(current) = lifetime.expires_at else {
+        // Nothing to push back
+        return Ok(Json(ExtendResponse { id: file_id, expires_at: None }));
+    };
+    let now = Utc::now();
+    if current <= now && !request.resurrect {
+        return Err(StatusCode::GONE);
+    }
+
+    let mut expires_at = requested_expiry(&request, current, now)?;
+    let latest = app_state
+        .config
+        .max_ttl
+        .and_then(|max_ttl| chrono::Duration::from_std(max_ttl).ok())
+        .and_then(|max_ttl| lifetime.created_at.checked_add_signed(max_ttl));
+    if let Some(latest) = latest {
+        expires_at = expires_at.min(latest);
+    }
+    if expires_at <= now {
+        // Past DROP_MAX_TTL already; cleanup will purge it
+        return Err(StatusCode::GONE);
+    }
+
+    if !store_expiry(&app_state, file_id, &lifetime, expires_at).await? {
+        return Err(StatusCode::GONE);
+    }
+    info!("Extended {} from {} to {}", file_id, current, expires_at);
+    Ok(Json(ExtendResponse {
+        id: file_id,
+        expires_at: Some(expires_at),
+    }))
+}
@@ -0,0 +1,189 @@
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::recovery::{FILE_PREFIX, PART_SUFFIX, list_stored_files, sidecar_path};
+use crate::storage::{StorageBackend, StorageRef, recorded_path};
+use crate::{AppState, admin, openapi::ErrorResponse};
+
+#[derive(Debug, Default, Serialize, utoipa::ToSchema)]
+pub struct GcReport {
+    pub files_removed: usize,
+    pub bytes_reclaimed: u64,
+}
+
+// Whether anything in the index still points at a stored file
+enum Reference {
+    Referenced,
+    Unreferenced,
+    Unknown, // The database couldn't be asked; leave the file alone
+}
+
+async fn file_reference(app_state: &AppState, id: Uuid, path: &Path) -> Reference {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(id).await {
+            Ok(Some(file_mapping)) if file_mapping.purged_at.is_none() => return Reference::Referenced,
+            Ok(_) => {
+                // Not (or no longer) in the database; it may still hold contents
+                // shared with duplicate uploads, or have been uploaded during an outage
+                let recorded = recorded_path(&app_state.config.temp_directory, path);
+                match db.is_blob(&recorded.to_string_lossy()).await {
+                    Ok(true) => return Reference::Referenced,
+                    Ok(false) => {}
+                    Err(e) => {
+                        warn!("Database stored file lookup failed during orphan collection: {}", e);
+                        app_state.set_database_healthy(false);
+                        return Reference::Unknown;
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Database file lookup failed during orphan collection: {}", e);
+                app_state.set_database_healthy(false);
+                return Reference::Unknown;
+            }
+        }
+    }
+
+    match app_state.file_storage.get(&id) {
+        Some(file_data) if file_data.storage.is_some() => Reference::Referenced,
+        _ => Reference::Unreferenced,
+    }
+}
+
+// Size of a directory entry old enough to collect, or None if it is too recent
+// (e.g. an upload still being written) or can't be inspected
+async fn collectable_size(path: &Path, max_age: Duration) -> Option<u64> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    let age = metadata.modified().ok()?.elapsed().ok()?;
+    (age >= max_age).then_some(metadata.len())
+}
+
+async fn existing_size(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or_default()
+}
+
+/// Delete `file_*` entries in the temp directory and its shard directories
+/// that no file record refers to and that haven't been modified for
+/// `orphan_max_age_seconds`.
+pub async fn collect_orphans(app_state: &AppState) -> GcReport {
+    let mut report = GcReport::default();
+
+    // Without the database every file it knows about would look unreferenced
+    if app_state.database.is_some()
+        && !app_state.database_available()
+    {
+        warn!("Skipping orphan collection while the database is unavailable");
+        return report;
+    }
+
+    let directory = &app_state.config.temp_directory;
+    let names = match list_stored_files(directory).await {
+        Ok(names) => names,
+        Err(e) => {
+            error!("Failed to scan {:?} for orphaned files: {:?}", directory, e);
+            return report;
+        }
+    };
+    let max_age = Duration::from_secs(app_state.config.orphan_max_age_seconds);
+
+    for (name, path) in &names {
+
+        // Sidecars go with their file; only collect ones whose file is gone.
+        // Partial uploads are never referenced; the age check spares ones in progress.
+        let sidecar = name.ends_with(".json");
+        if sidecar || name.ends_with(PART_SUFFIX) {
+            if name.strip_suffix(".json").is_some_and(|stem| names.contains_key(stem)) {
+                continue;
+            }
+            let Some(size) = collectable_size(path, max_age).await else {
+                continue;
+            };
+            match tokio::fs::remove_file(path).await {
+                Ok(_) => {
+                    report.files_removed += 1;
+                    report.bytes_reclaimed += size;
+                }
+                Err(e) if sidecar => warn!("Failed to remove orphaned sidecar {:?}: {:?}", path, e),
+                Err(e) => warn!("Failed to remove interrupted upload {:?}: {:?}", path, e),
+            }
+            continue;
+        }
+
+        let Some(size) = collectable_size(path, max_age).await else {
+            continue;
+        };
+        if let Ok(id) = name[FILE_PREFIX.len()..].parse::<Uuid>() {
+            match file_reference(app_state, id, path).await {
+                Reference::Unreferenced => {}
+                Reference::Referenced => continue,
+                Reference::Unknown => break,
+            }
+        }
+
+        let sidecar_size = existing_size(&sidecar_path(path)).await;
+        match app_state.storage.disk.delete(&StorageRef::Disk(path.clone())).await {
+            Ok(_) => {
+                info!("Removed orphaned file {:?}", path);
+                report.files_removed += 1;
+                report.bytes_reclaimed += size + sidecar_size;
+            }
+            Err(e) => warn!("Failed to remove orphaned file {:?}: {:?}", path, e),
+        }
+    }
+
+    if report.files_removed > 0 {
+        info!(
+            "Orphan collection removed {} files, reclaiming {} bytes",
+            report.files_removed, report.bytes_reclaimed
+        );
+    }
+    report
+}
+
+// Spawn the periodic orphan collection loop; runs every `orphan_gc_interval_seconds`
+pub fn spawn_orphan_gc_task(app_state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(app_state.config.orphan_gc_interval_seconds));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            collect_orphans(&app_state).await;
+        }
+    })
+}
+
+// POST /admin/gc - run orphan collection now; requires X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/admin/gc",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`")),
+    responses(
+        (status = 200, description = "What was collected", body = GcReport),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn run_orphan_gc(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<GcReport>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    Ok(Json(collect_orphans(&app_state).await))
+}
@@ -0,0 +1,89 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{FileData, database::FileMapping};
+
+/// Why a file that did exist can no longer be downloaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GoneReason {
+    Expired,  // Past its expires_at
+    Consumed, // Out of downloads
+    Deleted,  // Removed by its owner or an operator
+}
+
+/// The body of a 410: what happened to the file, and when. Tombstones are
+/// kept for `DROP_TOMBSTONE_RETENTION_SECS`; after that the file is a 404.
+#[derive(Serialize, ToSchema)]
+pub struct GoneResponse {
+    error: String,
+    reason: GoneReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gone_at: Option<DateTime<Utc>>, // When the contents were removed, if they have been yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_downloads: Option<i32>,
+    download_count: i32,
+}
+
+impl GoneResponse {
+    fn new(
+        expires_at: Option<DateTime<Utc>>,
+        purged_at: Option<DateTime<Utc>>,
+        max_downloads: Option<i32>,
+        download_count: i32,
+    ) -> Self {
+        // Running out of downloads is decided as it happens; expiry may only be noticed later
+        let reason = if max_downloads.is_some_and(|max| download_count >= max) {
+            GoneReason::Consumed
+        } else if expires_at.is_some_and(|expires_at| expires_at <= purged_at.unwrap_or_else(Utc::now)) {
+            GoneReason::Expired
+        } else {
+            GoneReason::Deleted
+        };
+        let error = match reason {
+            GoneReason::Expired => "File has expired",
+            GoneReason::Consumed => "File has used up its downloads",
+            GoneReason::Deleted => "File has been deleted",
+        };
+        Self {
+            error: error.to_string(),
+            reason,
+            expires_at,
+            gone_at: purged_at,
+            max_downloads,
+            download_count,
+        }
+    }
+
+    pub fn from_mapping(file_mapping: &FileMapping) -> Self {
+        Self::new(
+            file_mapping.expires_at,
+            file_mapping.purged_at,
+            file_mapping.max_downloads,
+            file_mapping.access_count,
+        )
+    }
+
+    pub fn from_file_data(file_data: &FileData) -> Self {
+        Self::new(
+            file_data.expires_at,
+            file_data.purged_at,
+            file_data.max_downloads,
+            file_data.download_count,
+        )
+    }
+}
+
+impl IntoResponse for GoneResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::GONE, Json(self)).into_response()
+    }
+}
@@ -0,0 +1,182 @@
+use axum::{Json, extract::State, http::StatusCode, response::{IntoResponse, Response}};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::{AppState, DiskStats, StorageStats, database_status};
+
+// Written and removed by each readiness check to prove the temp directory takes writes.
+// Outside the `file_*` namespace, so recovery and collection never see it.
+const READINESS_PROBE_FILE: &str = ".readyz";
+
+// How long `/health` reuses the figures it last gathered
+pub const HEALTH_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// The parts of `/health` that cost a syscall or a query to gather, reused
+/// for `HEALTH_CACHE_TTL` so a burst of probes does the work once.
+#[derive(Clone, Debug, Default)]
+pub struct HealthCache {
+    disk: Snapshot<DiskStats>,
+    storage: Snapshot<Option<StorageStats>>,
+}
+
+impl HealthCache {
+    pub async fn disk<F, Fut>(&self, gather: F) -> DiskStats
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = DiskStats>,
+    {
+        self.disk.get_or_refresh(gather).await
+    }
+
+    pub async fn storage<F, Fut>(&self, gather: F) -> Option<StorageStats>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Option<StorageStats>>,
+    {
+        self.storage.get_or_refresh(gather).await
+    }
+}
+
+#[derive(Debug)]
+struct Snapshot<T> {
+    value: Arc<Mutex<Option<(Instant, T)>>>,
+}
+
+impl<T> Default for Snapshot<T> {
+    fn default() -> Self {
+        Self { value: Arc::new(Mutex::new(None)) }
+    }
+}
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self { value: self.value.clone() }
+    }
+}
+
+impl<T: Clone> Snapshot<T> {
+    // Callers arriving while a refresh runs wait for it instead of starting their own
+    async fn get_or_refresh<F, Fut>(&self, gather: F) -> T
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let mut value = self.value.lock().await;
+        if let Some((gathered_at, ref cached)) = *value
+            && gathered_at.elapsed() < HEALTH_CACHE_TTL
+        {
+            return cached.clone();
+        }
+        let fresh = gather().await;
+        *value = Some((Instant::now(), fresh.clone()));
+        fresh
+    }
+}
+
+/// Whether this instance should be sent traffic, as `/readyz` last reported it.
+#[derive(Clone, Debug, Default)]
+pub struct Readiness {
+    draining: Arc<AtomicBool>,
+    not_ready: Arc<AtomicBool>, // Last reported state, so only transitions are logged
+}
+
+impl Readiness {
+    /// Report not ready from now on, so load balancers stop routing here before shutdown.
+    pub fn start_draining(&self) {
+        if !self.draining.swap(true, Ordering::AcqRel) {
+            info!("Draining: /readyz now reports not ready");
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    fn record(&self, reasons: &[&str]) {
+        let not_ready = !reasons.is_empty();
+        match (self.not_ready.swap(not_ready, Ordering::AcqRel), not_ready) {
+            (false, true) => warn!("Instance is not ready: {}", reasons.join(", ")),
+            (true, false) => info!("Instance is ready again"),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ReadinessResponse {
+    #[schema(value_type = String)]
+    status: &'static str,
+    #[schema(value_type = Vec<String>)]
+    reasons: Vec<&'static str>, // Why traffic shouldn't come here; empty when ready
+    #[schema(value_type = String)]
+    database: &'static str, // Reported only: the in-memory fallback still serves while it's down
+}
+
+/// Liveness: answers as long as the runtime is scheduling requests. Checks
+/// nothing else, so a slow disk or database never gets the process restarted.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = 200, description = "The server is answering requests")),
+)]
+pub async fn liveness() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Readiness: `503` while the temp directory can't be written, disk headroom
+/// is used up, or the instance is draining for shutdown.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready for traffic", body = ReadinessResponse),
+        (status = 503, description = "Not ready; `reasons` says why", body = ReadinessResponse),
+    ),
+)]
+pub async fn readiness(State(app_state): State<AppState>) -> Response {
+    let mut reasons = Vec::new();
+    if app_state.readiness.is_draining() {
+        reasons.push("draining");
+    }
+    if !temp_directory_writable(&app_state).await {
+        reasons.push("temp_directory_not_writable");
+    }
+    let disk = app_state.health_cache.disk(|| async { DiskStats::collect(&app_state) }).await;
+    if disk.headroom_bytes == Some(0) {
+        reasons.push("disk_reserve_reached");
+    }
+    app_state.readiness.record(&reasons);
+
+    let status = if reasons.is_empty() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    let response = ReadinessResponse {
+        status: if reasons.is_empty() { "ready" } else { "not_ready" },
+        reasons,
+        database: database_status(&app_state),
+    };
+    (status, Json(response)).into_response()
+}
+
+async fn temp_directory_writable(app_state: &AppState) -> bool {
+    let directory = &app_state.config.temp_directory;
+    let path = directory.join(READINESS_PROBE_FILE);
+    let written = async {
+        tokio::fs::create_dir_all(directory).await?;
+        tokio::fs::write(&path, b"ok").await?;
+        tokio::fs::remove_file(&path).await
+    };
+    match written.await {
+        Ok(()) => true,
+        Err(e) => {
+            // Logged once per transition by `Readiness::record`
+            debug!("Temp directory {:?} is not writable: {:?}", directory, e);
+            false
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use axum::{
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::config::Config;
+
+/// A download linked from a site that isn't in `allowed_referers`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hotlink {
+    pub from: Option<String>, // The linking host; None when the request named no site
+}
+
+impl IntoResponse for Hotlink {
+    fn into_response(self) -> Response {
+        let message = match self.from {
+            Some(ref host) => {
+                warn!("Refusing download linked from {}", host);
+                format!("Downloads of this file aren't allowed from {}", host)
+            }
+            None => {
+                warn!("Refusing download without a referer");
+                "Downloads of this file must come from an allowed site".to_string()
+            }
+        };
+        (StatusCode::FORBIDDEN, message).into_response()
+    }
+}
+
+// The lowercase host of an Origin or Referer value, without scheme, userinfo,
+// port or path. None for anything that isn't a URL, including "null".
+fn url_host(value: &str) -> Option<String> {
+    let (_, rest) = value.trim().split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = if let Some(bracketed) = host_port.strip_prefix('[') {
+        // IPv6 literal
+        bracketed.split(']').next()?
+    } else {
+        host_port.split(':').next()?
+    };
+    Some(host.to_ascii_lowercase()).filter(|host| !host.is_empty())
+}
+
+// `host` without a port, for comparing with the Host header
+fn strip_port(host: &str) -> &str {
+    match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or(bracketed),
+        None => host.split(':').next().unwrap_or(host),
+    }
+}
+
+// Match `host` against a pattern where `*` stands for any run of characters
+fn glob_matches(pattern: &str, host: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == host;
+    };
+    let Some(mut remaining) = host.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(last)
+}
+
+/// Whether `host` may link to downloads: it is listed in `allowed_referers`,
+/// or it is this instance itself.
+fn host_allowed(config: &Config, headers: &HeaderMap, host: &str) -> bool {
+    let own_host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| strip_port(value).to_ascii_lowercase());
+    let public_host = config.public_base_url.as_deref().and_then(url_host);
+    if own_host.as_deref() == Some(host) || public_host.as_deref() == Some(host) {
+        return true;
+    }
+    config.allowed_referers.iter().any(|pattern| glob_matches(pattern, host))
+}
+
+/// Check where a download was linked from, by its Origin header or else its
+/// Referer. Returns the refusal to send unless the file is embeddable; None
+/// when `allowed_referers` isn't set or the site is allowed. Requests naming
+/// no site pass unless `block_empty_referer` is set.
+pub fn check(config: &Config, headers: &HeaderMap) -> Option<Hotlink> {
+    if config.allowed_referers.is_empty() {
+        return None;
+    }
+    let linked_from = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .map(|value| value.to_str().ok().and_then(url_host));
+    match linked_from {
+        // A sandboxed or privacy-preserving page sends "null"; it could be anywhere
+        Some(None) => Some(Hotlink { from: Some("an unknown site".to_string()) }),
+        Some(Some(host)) if host_allowed(config, headers, &host) => None,
+        Some(Some(host)) => Some(Hotlink { from: Some(host) }),
+        None if config.block_empty_referer => Some(Hotlink { from: None }),
+        None => None,
+    }
+}
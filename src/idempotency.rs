@@ -0,0 +1,246 @@
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use dashmap::{DashMap, mapref::entry::Entry};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::{
+    AppState, UploadBatchResponse, UploadResponse, database::KeyClaim, negotiate::ResponseFormat, quota::QuotaOwner,
+};
+
+// Idempotency keys used while the database is unavailable: (scope, key) -> record
+pub type IdempotencyStorage = Arc<DashMap<(String, String), IdempotencyRecord>>;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+const MAX_KEY_LEN: usize = 255;
+
+#[derive(Clone, Debug)]
+pub struct IdempotencyRecord {
+    pub response: Option<String>, // The upload's files as JSON; None while it is still running
+    pub expires_at: DateTime<Utc>,
+}
+
+/// An `Idempotency-Key` held by the upload running under it. Dropping it
+/// unfinished, because the upload failed or the client went away, releases
+/// the key so a retry runs the upload again.
+pub struct IdempotencyClaim {
+    app_state: AppState,
+    scope: String,
+    key: String,
+    in_database: bool, // Where the claim was recorded
+    finished: bool,
+}
+
+// Capped at a year so timestamp arithmetic can't overflow
+fn key_ttl(app_state: &AppState) -> chrono::Duration {
+    const MAX_TTL_SECONDS: u64 = 365 * 24 * 60 * 60;
+    chrono::Duration::seconds(app_state.config.idempotency_key_ttl_seconds.min(MAX_TTL_SECONDS) as i64)
+}
+
+/// An `Idempotency-Key` header that is empty, too long or not visible ASCII.
+#[derive(Debug)]
+pub struct InvalidKey;
+
+impl IntoResponse for InvalidKey {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, "Idempotency-Key must be 1 to 255 visible ASCII characters").into_response()
+    }
+}
+
+// The request's key, if it sent one; 1-255 visible ASCII characters
+fn request_key(headers: &HeaderMap) -> Result<Option<String>, InvalidKey> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key.to_string())),
+        _ => Err(InvalidKey),
+    }
+}
+
+// Whose keys these are: an API key's follow it from any address, anonymous
+// uploads share them per client IP
+fn key_scope(owner: &QuotaOwner) -> String {
+    match owner {
+        QuotaOwner::ApiKey(api_key) => format!("key:{}", api_key.id),
+        QuotaOwner::Ip(client_ip) => format!("ip:{}", client_ip),
+    }
+}
+
+/// Claim the request's `Idempotency-Key` for its upload. Keys are scoped to
+/// the API key the upload is made with, or else the client IP, so two
+/// clients can't collide. Returns None when no key was sent. A key already
+/// used gets the first upload's response back with a 200 and
+/// `Idempotent-Replayed: true`, or a 409 while that upload is still running;
+/// a key past its TTL counts as new.
+pub async fn claim_key(
+    app_state: &AppState,
+    owner: &QuotaOwner,
+    headers: &HeaderMap,
+    format: ResponseFormat,
+) -> Result<Option<IdempotencyClaim>, Response> {
+    let Some(key) = request_key(headers).map_err(IntoResponse::into_response)? else {
+        return Ok(None);
+    };
+    let scope = key_scope(owner);
+    let now = Utc::now();
+    let expires_at = now + key_ttl(app_state);
+
+    let mut in_database = false;
+    let mut claim = None;
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.claim_idempotency_key(&scope, &key, now, expires_at).await {
+            Ok(result) => {
+                in_database = true;
+                claim = Some(result);
+            }
+            Err(e) => {
+                warn!("Database idempotency key claim failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+    let claim = match claim {
+        Some(claim) => claim,
+        None => claim_in_memory(app_state, &scope, &key, now, expires_at),
+    };
+
+    match claim {
+        KeyClaim::Claimed => Ok(Some(IdempotencyClaim {
+            app_state: app_state.clone(),
+            scope,
+            key,
+            in_database,
+            finished: false,
+        })),
+        KeyClaim::Completed(response) => {
+            info!("Replaying upload for Idempotency-Key '{}' in {}", key, scope);
+            Err(replay(format, &response))
+        }
+        KeyClaim::InProgress => {
+            warn!("Upload for Idempotency-Key '{}' in {} is still in progress", key, scope);
+            Err((StatusCode::CONFLICT, "An upload with this Idempotency-Key is still in progress").into_response())
+        }
+    }
+}
+
+fn claim_in_memory(
+    app_state: &AppState,
+    scope: &str,
+    key: &str,
+    now: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> KeyClaim {
+    let record = IdempotencyRecord {
+        response: None,
+        expires_at,
+    };
+    // Checked and claimed under the entry's lock, so only one upload gets the key
+    match app_state.idempotency_keys.entry((scope.to_string(), key.to_string())) {
+        Entry::Occupied(entry) if entry.get().expires_at > now => match entry.get().response {
+            Some(ref response) => KeyClaim::Completed(response.clone()),
+            None => KeyClaim::InProgress,
+        },
+        Entry::Occupied(mut entry) => {
+            entry.insert(record);
+            KeyClaim::Claimed
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(record);
+            KeyClaim::Claimed
+        }
+    }
+}
+
+// The first upload's response again, as a 200 so clients can tell it apart
+fn replay(format: ResponseFormat, response: &str) -> Response {
+    let files: Vec<UploadResponse> = match serde_json::from_str(response) {
+        Ok(files) => files,
+        Err(e) => {
+            error!("Stored idempotent upload response is malformed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let mut response = format.respond(UploadBatchResponse { files });
+    response.headers_mut().insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    response
+}
+
+impl IdempotencyClaim {
+    /// Record the stored files, so requests with the same key get them back.
+    pub async fn complete(mut self, files: &[UploadResponse]) {
+        self.finished = true;
+        let response = match serde_json::to_string(files) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to serialize upload response for Idempotency-Key '{}': {}", self.key, e);
+                release(self.app_state.clone(), self.scope.clone(), self.key.clone(), self.in_database).await;
+                return;
+            }
+        };
+
+        if self.in_database
+            && let Some(ref db) = self.app_state.database
+        {
+            match db.complete_idempotency_key(&self.scope, &self.key, &response).await {
+                Ok(()) => return,
+                Err(e) => {
+                    warn!("Failed to record idempotent upload in database, falling back to memory: {}", e);
+                    self.app_state.set_database_healthy(false);
+                    // Left claimed, the row would answer every retry with a 409 once the database is back
+                    release(self.app_state.clone(), self.scope.clone(), self.key.clone(), true).await;
+                }
+            }
+        }
+
+        let record = IdempotencyRecord {
+            response: Some(response),
+            expires_at: Utc::now() + key_ttl(&self.app_state),
+        };
+        self.app_state
+            .idempotency_keys
+            .insert((self.scope.clone(), self.key.clone()), record);
+    }
+}
+
+impl Drop for IdempotencyClaim {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let app_state = self.app_state.clone();
+        let key = std::mem::take(&mut self.key);
+        let scope = std::mem::take(&mut self.scope);
+        tokio::spawn(release(app_state, scope, key, self.in_database));
+    }
+}
+
+// Forget an unfinished claim, wherever it was recorded
+async fn release(app_state: AppState, scope: String, key: String, in_database: bool) {
+    if in_database {
+        if let Some(ref db) = app_state.database
+            && let Err(e) = db.release_idempotency_key(&scope, &key).await
+        {
+            warn!("Failed to release Idempotency-Key '{}' in database: {}", key, e);
+        }
+        return;
+    }
+
+    app_state
+        .idempotency_keys
+        .remove_if(&(scope, key), |_, record| record.response.is_none());
+}
+
+/// Drop fallback keys past their TTL.
+pub fn cleanup_idempotency_keys(idempotency_keys: &IdempotencyStorage) -> usize {
+    let now = Utc::now();
+    let before = idempotency_keys.len();
+    idempotency_keys.retain(|_, record| record.expires_at > now);
+    before.saturating_sub(idempotency_keys.len())
+}
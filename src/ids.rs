@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::warn;
+use uuid::Uuid;
+use xxhash_rust::xxh3::Xxh3;
+
+const SHORT_CODE_ALPHABET: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const SHORT_CODE_LEN: usize = 8;
+
+/// Where file, bundle and session IDs and short codes come from. Swapped
+/// for a [`SeededIds`] in tests that need to predict or collide them.
+pub trait IdGenerator: Send + Sync {
+    fn uuid(&self) -> Uuid;
+
+    /// An 8-character base36 short code. Callers retry on a collision.
+    fn short_code(&self) -> String;
+}
+
+/// Random IDs, and short codes hashed from a random UUID, the time and the
+/// process and thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+
+    fn short_code(&self) -> String {
+        // Use current timestamp + random UUID to generate unique short code
+        let uuid = Uuid::new_v4();
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_else(|_| {
+                warn!("System time is before Unix epoch, using fallback timestamp");
+                std::time::Duration::from_nanos(0)
+            })
+            .as_nanos();
+
+        // Use XXH3 for superior speed and distribution properties
+        let mut hasher = Xxh3::new();
+        uuid.hash(&mut hasher);
+        timestamp.hash(&mut hasher);
+
+        // Add some extra entropy from process-specific data
+        std::process::id().hash(&mut hasher);
+        std::thread::current().id().hash(&mut hasher);
+
+        base36(hasher.finish())
+    }
+}
+
+// Simple base36 encoding of the low digits of `n`
+fn base36(mut n: u64) -> String {
+    let mut result = String::with_capacity(SHORT_CODE_LEN);
+    for _ in 0..SHORT_CODE_LEN {
+        result.push(SHORT_CODE_ALPHABET[(n % 36) as usize] as char);
+        n /= 36;
+    }
+    result
+}
+
+/// The same sequence of IDs and short codes for the same seed. Short codes
+/// queued with [`SeededIds::with_short_codes`] are handed out first, so a
+/// test can make a generated code collide with one already taken.
+#[derive(Debug)]
+pub struct SeededIds {
+    state: AtomicU64,
+    queued: Mutex<VecDeque<String>>,
+}
+
+impl SeededIds {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+            queued: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn with_short_codes<I, S>(self, short_codes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        if let Ok(mut queued) = self.queued.lock() {
+            queued.extend(short_codes.into_iter().map(Into::into));
+        }
+        self
+    }
+
+    // SplitMix64: a full-period sequence, well mixed from any seed
+    fn next(&self) -> u64 {
+        let mut z = self.state.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl IdGenerator for SeededIds {
+    fn uuid(&self) -> Uuid {
+        uuid::Builder::from_random_bytes(((u128::from(self.next()) << 64) | u128::from(self.next())).to_be_bytes())
+            .into_uuid()
+    }
+
+    fn short_code(&self) -> String {
+        let queued = self.queued.lock().ok().and_then(|mut queued| queued.pop_front());
+        queued.unwrap_or_else(|| base36(self.next()))
+    }
+}
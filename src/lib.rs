@@ -1,198 +1,515 @@
 use axum::{
     Router,
     body::Body,
-    extract::{Multipart, Path, State, ConnectInfo},
-    http::{StatusCode, header},
+    extract::{ConnectInfo, DefaultBodyLimit, Extension, FromRequestParts, Multipart, Path, Query, State},
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    middleware,
     response::{IntoResponse, Json},
     routing::{get, post},
 };
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::Result;
+use dashmap::{DashMap, mapref::entry::Entry};
+use futures_util::StreamExt;
 use sanitize_filename::sanitize;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::env;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{
     Arc, Mutex,
     atomic::{AtomicUsize, Ordering},
 };
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use sysinfo::System;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
 use tracing::{error, info, instrument, warn};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
+use negotiate::{PlainText, ResponseFormat};
+use sha2::{Digest, Sha256};
 use xxhash_rust::xxh3::Xxh3;
 
+pub mod access;
+pub mod access_log;
+pub mod admin;
+pub mod aliases;
+pub mod api_keys;
+pub mod backup;
+pub mod bundles;
+pub mod cache;
+pub mod cleanup;
+pub mod client_ip;
+pub mod compression;
+pub mod concurrency;
+pub mod config;
+pub mod cors;
 pub mod database;
-use database::Database;
-
-// Fallback in-memory storage for when database is down
-pub type FileStorage = Arc<Mutex<HashMap<String, FileData>>>;
+pub mod encryption;
+pub mod extend;
+pub mod gc;
+pub mod gone;
+pub mod health;
+pub mod hotlink;
+pub mod idempotency;
+pub mod ids;
+pub mod lru;
+pub mod maintenance;
+pub mod memory_store;
+pub mod metadata;
+pub mod negative_cache;
+pub mod negotiate;
+pub mod openapi;
+pub mod preview;
+pub mod quota;
+pub mod rate_limit;
+pub mod recovery;
+pub mod redirect;
+pub mod request_id;
+pub mod reshard;
+pub mod scrub;
+pub mod security_headers;
+pub mod server;
+pub mod sessions;
+pub mod sniff;
+pub mod stats;
+pub mod storage;
+pub mod throttle;
+pub mod tls;
+pub mod transfer_rate;
+pub mod ui;
+pub mod upload_options;
+pub use config::Config;
+pub use server::{Server, ServerBuilder, ServerHandle};
+use bundles::BundleStorage;
+use cache::RedisStore;
+use cleanup::{CleanupReport, ContentsRemoval};
+use client_ip::get_client_ip;
+use compression::ContentEncoding;
+use database::{FileMapping, NewFileMapping, PoolStats};
+use gone::GoneResponse;
+use idempotency::IdempotencyStorage;
+use lru::LruCache;
+use metadata::MetadataStore;
+use quota::{QuotaOwner, QuotaStorage};
+use rate_limit::{RateLimitAction, RateLimitStatus, TokenBucket, check_key_rate_limit, check_rate_limit};
+use request_id::RequestId;
+use sessions::UploadSessionStorage;
+use storage::{FileStore, StorageBackend, StorageRef, StoredObject};
+use upload_options::{InvalidOption, OptionsParser, PART_OPTIONS_HEADER, RequestedOptions};
+
+// Fallback in-memory storage for when database is down. Concurrent maps, so
+// requests for different entries never wait on each other. Never hold an
+// entry across another access to the same map or an `.await`.
+pub type FileStorage = Arc<DashMap<Uuid, FileData>>;
 // URL shortener mapping: short_code -> full_uuid (fallback)
-pub type ShortUrlStorage = Arc<Mutex<HashMap<String, String>>>;
-// Rate limiting: IP -> (last_request_time, request_count) (fallback)
-pub type RateLimitStorage = Arc<Mutex<HashMap<String, (Instant, u32)>>>;
-
-#[derive(Clone, Debug)]
-pub struct Config {
-    pub min_file_size_limit: usize,
-    pub max_file_size_limit: usize,
-    pub max_total_size_per_request: usize,
-    pub stream_threshold: usize,
-    pub temp_directory: PathBuf,
-    pub bind_address: String,
-    pub memory_pool_ratio: f64,
-    pub reserved_memory_mb: usize,
-    pub rate_limit_requests_per_minute: u32,
-    pub rate_limit_window_seconds: u64,
-    pub database_url: Option<String>,
-    pub redis_url: Option<String>,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            min_file_size_limit: 50 * 1024 * 1024,               // 50MB
-            max_file_size_limit: 5 * 1024 * 1024 * 1024,         // 5GB
-            max_total_size_per_request: 10 * 1024 * 1024 * 1024, // 10GB
-            stream_threshold: 50 * 1024 * 1024,                  // 50MB
-            temp_directory: PathBuf::from("./temp"),
-            bind_address: "0.0.0.0:3000".to_string(),
-            memory_pool_ratio: 0.5,
-            reserved_memory_mb: 200,
-            rate_limit_requests_per_minute: 60,
-            rate_limit_window_seconds: 60,
-            database_url: None,
-            redis_url: None,
-        }
-    }
-}
-
-impl Config {
-    pub fn from_env() -> Self {
-        let mut config = Self::default();
+pub type ShortUrlStorage = Arc<DashMap<String, Uuid>>;
+// Rate limiting: (IP, action) -> token bucket (fallback)
+pub type RateLimitStorage = Arc<DashMap<(std::net::IpAddr, RateLimitAction), TokenBucket>>;
+// Hot database lookups: file_id -> mapping, short_code -> file_id
+pub type MappingCache = Arc<Mutex<LruCache<Uuid, FileMapping>>>;
+pub type ShortCodeCache = Arc<Mutex<LruCache<String, Uuid>>>;
 
-        if let Ok(val) = env::var("DROP_MIN_FILE_SIZE_MB") {
-            if let Ok(size) = val.parse::<usize>() {
-                config.min_file_size_limit = size * 1024 * 1024;
-            }
-        }
+// Application state
+#[derive(Clone)]
+pub struct AppState {
+    pub file_storage: FileStorage,       // Fallback in-memory storage
+    pub short_url_storage: ShortUrlStorage, // Fallback short URL storage
+    pub rate_limit_storage: RateLimitStorage, // Fallback rate limiting
+    pub quota_storage: QuotaStorage,     // Fallback daily upload quotas
+    pub upload_sessions: UploadSessionStorage, // In-progress resumable uploads
+    pub bundle_storage: BundleStorage,   // Fallback bundle storage
+    pub idempotency_keys: IdempotencyStorage, // Fallback Idempotency-Key records
+    pub mapping_cache: MappingCache,     // Recently downloaded file mappings
+    pub short_code_cache: ShortCodeCache, // Recently resolved short codes
+    pub negative_cache: negative_cache::NegativeCache, // IDs and short codes recently found not to exist
+    pub storage: FileStore,              // Where file bytes live (memory pool or disk)
+    pub config: Config,
+    pub database: Option<Arc<dyn MetadataStore>>, // Primary metadata store, normally the database
+    pub database_healthy: Arc<std::sync::atomic::AtomicBool>, // Database health status
+    pub redis: Option<RedisStore>,       // Cache layer in front of the database
+    pub redis_healthy: Arc<std::sync::atomic::AtomicBool>, // Redis health status
+    pub upload_permits: Arc<tokio::sync::Semaphore>, // Slots for concurrent upload bodies
+    pub download_bandwidth: Option<Arc<throttle::TokenBucket>>, // Shared by all downloads, if capped
+    pub access_counts: access::AccessCounts, // Downloads not yet written to the database
+    pub health_cache: health::HealthCache, // Recently gathered /health figures
+    pub readiness: health::Readiness,    // Drain state and the last /readyz answer
+    pub access_log: Option<access_log::AccessLog>, // Where request lines go, if anywhere
+    pub ids: Arc<dyn ids::IdGenerator>, // File, bundle and session IDs and short codes
+    pub cleanup: cleanup::CleanupState, // Serializes cleanup passes and keeps the last report
+    pub read_only: Arc<std::sync::atomic::AtomicBool>, // Writes are refused for maintenance
+    pub stats_cache: stats::StatsCache, // Recently gathered /admin/stats figures
+}
 
-        if let Ok(val) = env::var("DROP_MAX_FILE_SIZE_GB") {
-            if let Ok(size) = val.parse::<usize>() {
-                config.max_file_size_limit = size * 1024 * 1024 * 1024;
-            }
-        }
+impl AppState {
+    /// Fresh state for `config`: empty fallback maps and caches, and file
+    /// storage, limits and the access log as configured. Fails if the access
+    /// log can't be opened.
+    pub fn new(config: Config, database: Option<Arc<dyn MetadataStore>>, redis: Option<RedisStore>) -> Result<Self> {
+        let lookup_cache_ttl = Duration::from_secs(config.lookup_cache_ttl_seconds);
+        Ok(Self {
+            file_storage: Arc::new(DashMap::new()),
+            short_url_storage: Arc::new(DashMap::new()),
+            rate_limit_storage: Arc::new(DashMap::new()),
+            quota_storage: Arc::new(Mutex::new(HashMap::new())),
+            upload_sessions: Arc::new(Mutex::new(HashMap::new())),
+            bundle_storage: Arc::new(Mutex::new(HashMap::new())),
+            idempotency_keys: Arc::new(DashMap::new()),
+            mapping_cache: Arc::new(Mutex::new(LruCache::new(config.lookup_cache_capacity, lookup_cache_ttl))),
+            short_code_cache: Arc::new(Mutex::new(LruCache::new(config.lookup_cache_capacity, lookup_cache_ttl))),
+            negative_cache: negative_cache::NegativeCache::new(
+                config.negative_cache_capacity,
+                Duration::from_secs(config.negative_cache_ttl_seconds),
+            ),
+            storage: FileStore::from_config(&config),
+            database_healthy: Arc::new(std::sync::atomic::AtomicBool::new(database.is_some())),
+            database,
+            redis_healthy: Arc::new(std::sync::atomic::AtomicBool::new(redis.is_some())),
+            redis,
+            upload_permits: concurrency::upload_permits(&config),
+            download_bandwidth: throttle::download_bandwidth(&config),
+            access_counts: access::AccessCounts::default(),
+            health_cache: health::HealthCache::default(),
+            readiness: health::Readiness::default(),
+            access_log: access_log::AccessLog::open(&config)?,
+            ids: Arc::new(ids::RandomIds),
+            cleanup: cleanup::CleanupState::default(),
+            read_only: Arc::new(std::sync::atomic::AtomicBool::new(config.read_only)),
+            stats_cache: stats::StatsCache::default(),
+            config,
+        })
+    }
 
-        if let Ok(val) = env::var("DROP_MAX_TOTAL_SIZE_GB") {
-            if let Ok(size) = val.parse::<usize>() {
-                config.max_total_size_per_request = size * 1024 * 1024 * 1024;
-            }
-        }
+    /// Whether database calls should be attempted; false routes requests to the in-memory fallback.
+    pub fn database_available(&self) -> bool {
+        self.database_healthy.load(Ordering::Acquire)
+    }
 
-        if let Ok(val) = env::var("DROP_STREAM_THRESHOLD_MB") {
-            if let Ok(size) = val.parse::<usize>() {
-                config.stream_threshold = size * 1024 * 1024;
+    /// Record the outcome of a database call or probe, logging only when the state changes.
+    pub fn set_database_healthy(&self, healthy: bool) {
+        let was_healthy = self.database_healthy.swap(healthy, Ordering::AcqRel);
+        match (was_healthy, healthy) {
+            (true, false) => warn!("Database marked unhealthy, falling back to in-memory storage"),
+            (false, true) => {
+                info!("Database is healthy again, resuming database storage");
+                // Writes that landed in the fallback during the outage belong in the database
+                let app_state = self.clone();
+                tokio::spawn(async move {
+                    recovery::replay_fallback_writes(&app_state).await;
+                });
             }
+            _ => {}
         }
+    }
 
-        if let Ok(val) = env::var("DROP_TEMP_DIR") {
-            config.temp_directory = PathBuf::from(val);
-        }
-
-        if let Ok(val) = env::var("DROP_BIND_ADDRESS") {
-            config.bind_address = val;
-        }
-
-        if let Ok(val) = env::var("DROP_MEMORY_POOL_RATIO") {
-            if let Ok(ratio) = val.parse::<f64>() {
-                if ratio > 0.0 && ratio <= 1.0 {
-                    config.memory_pool_ratio = ratio;
-                }
-            }
-        }
+    /// Whether uploads, deletes and other writes are currently refused.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
 
-        if let Ok(val) = env::var("DROP_RATE_LIMIT_RPM") {
-            if let Ok(rpm) = val.parse::<u32>() {
-                config.rate_limit_requests_per_minute = rpm;
-            }
+    /// Switch read-only mode, logging only when it changes.
+    pub fn set_read_only(&self, read_only: bool) {
+        match (self.read_only.swap(read_only, Ordering::AcqRel), read_only) {
+            (false, true) => warn!("Read-only mode on: refusing uploads, deletes and other writes"),
+            (true, false) => info!("Read-only mode off: accepting writes again"),
+            _ => {}
         }
-
-        // Database configuration
-        config.database_url = env::var("DATABASE_URL").ok();
-        config.redis_url = env::var("REDIS_URL").ok();
-
-        config
     }
 }
 
-// Application state
-#[derive(Clone)]
-pub struct AppState {
-    pub file_storage: FileStorage,       // Fallback in-memory storage
-    pub short_url_storage: ShortUrlStorage, // Fallback short URL storage
-    pub rate_limit_storage: RateLimitStorage, // Fallback rate limiting
-    pub config: Config,
-    pub database: Option<Database>,      // Primary database (PostgreSQL)
-    pub database_healthy: Arc<std::sync::atomic::AtomicBool>, // Database health status
-}
-
 // Memory pool for tracking allocated memory
 static MEMORY_POOL: AtomicUsize = AtomicUsize::new(0);
 static ALLOCATED_MEMORY: AtomicUsize = AtomicUsize::new(0);
-static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static ACTIVE_UPLOADS: AtomicUsize = AtomicUsize::new(0);
+static ACTIVE_DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct FileData {
     pub filename: String,
-    pub content_type: String,
+    pub content_type: String, // Type the file is served with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub declared_content_type: Option<String>, // Type sent by the client, if any
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub data: Option<Vec<u8>>, // In-memory data
+    pub detected_content_type: Option<String>, // Type sniffed from the first bytes, if known
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub file_path: Option<PathBuf>, // Disk-based path
+    pub storage: Option<StorageRef>, // Where the bytes live; None once purged
+    #[serde(default)]
+    pub size: u64, // Length of the contents, so serving never has to ask the storage
+    #[serde(skip_serializing)]
+    pub delete_token: String, // Secret required to delete the file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>, // When the file stops being downloadable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<i32>, // Downloads allowed before the file is consumed
+    #[serde(default)]
+    pub download_count: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purged_at: Option<DateTime<Utc>>, // Set once the contents are gone (tombstone)
+    pub content_hash: String, // XXH3-128 of the contents, served as the ETag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>, // Used to find identical uploads
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader_ip: Option<std::net::IpAddr>, // Credited back if the file is deleted
+    #[serde(default)]
+    pub encrypted: bool, // Contents are encrypted at rest
+    #[serde(default)]
+    pub pinned: bool, // Kept whatever its expiry or age
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<Uuid>, // The key it was uploaded with
+    #[serde(default)]
+    pub embeddable: bool, // Downloads skip the referer check
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadParams {
+    expires_in: Option<String>,
+    max_downloads: Option<String>,
+    alias: Option<String>, // Custom short code for a single-file upload
+    pinned: Option<String>, // Requires X-Admin-Token
+    embeddable: Option<String>, // Let other sites link to the files despite DROP_ALLOWED_REFERERS
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct UploadResponse {
     id: String,
+    filename: String,
+    size: usize,
     short_url: String,
     full_url: String,
+    #[serde(default)] // Absent from responses kept for idempotent replay before it was added
+    pretty_url: String, // Short URL ending in the filename
+    delete_token: String,
+    hash: String,
+    sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_downloads: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FileInfoResponse {
+    id: Uuid,
+    filename: String,
+    size: u64,
+    content_type: String,
+    declared_content_type: Option<String>,
+    detected_content_type: Option<String>,
+    hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_downloads: Option<i32>,
+    download_count: i32,
+    encrypted: bool,
+}
+
+// Most files one batch info request may ask about
+const MAX_INFO_BATCH: usize = 100;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FileInfoBatchRequest {
+    ids: Vec<String>, // File IDs or short codes, at most 100
+}
+
+/// What `GET /drop/{id}/info` would answer for one ID: the file's metadata,
+/// why it is gone, or that there is no such file.
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum FileInfoEntry {
+    Found(FileInfoResponse),
+    Gone(GoneResponse),
+    Missing(MissingFileEntry),
+}
+
+impl FileInfoEntry {
+    fn missing() -> Self {
+        FileInfoEntry::Missing(MissingFileEntry {
+            error: "Not Found".to_string(),
+        })
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MissingFileEntry {
+    error: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct FileInfoBatchResponse {
+    files: HashMap<String, FileInfoEntry>, // Keyed by the IDs as sent
+}
+
+// Most files one batch delete request may name
+const MAX_DELETE_BATCH: usize = 100;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BatchDeleteRequest {
+    #[serde(default)]
+    files: Vec<DeleteItem>,
+    #[serde(default)]
+    ids: Vec<String>, // Deleted without their tokens; needs X-Admin-Token
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeleteItem {
+    id: String, // File ID or short code
+    delete_token: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteOutcome {
+    Deleted,
+    NotFound,
+    Forbidden, // Wrong delete token
+    Failed,    // The database couldn't delete it; worth retrying
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+pub struct DeleteResult {
+    id: String, // As sent
+    result: DeleteOutcome,
+}
+
+impl DeleteResult {
+    fn new(id: String, status: StatusCode) -> Self {
+        let result = match status {
+            StatusCode::NO_CONTENT => DeleteOutcome::Deleted,
+            StatusCode::NOT_FOUND => DeleteOutcome::NotFound,
+            StatusCode::FORBIDDEN => DeleteOutcome::Forbidden,
+            _ => DeleteOutcome::Failed,
+        };
+        Self { id, result }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BatchDeleteResponse {
+    results: Vec<DeleteResult>, // `files` first, then `ids`, each in the order sent
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UploadBatchResponse {
+    files: Vec<UploadResponse>,
+}
+
+impl PlainText for UploadBatchResponse {
+    // One short URL per line
+    fn plain_text(&self) -> String {
+        self.files.iter().map(|file| format!("{}\n", file.short_url)).collect()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct HealthResponse {
     status: String,
     database: String,
+    redis: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    database_pool: Option<PoolStats>,
     memory_pool: String,
-    active_connections: usize,
+    active_uploads: usize,
+    active_downloads: usize,
+    negative_cache_hits: u64, // Lookups answered 404 from the negative cache since startup
+    corrupt_files_found: u64, // Files the integrity scrubber found missing or changed since startup
+    read_only: bool, // Writes are refused for maintenance
+    #[serde(skip_serializing_if = "Option::is_none")]
     storage_stats: Option<StorageStats>,
+    disk: DiskStats,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HealthQuery {
+    #[serde(default)]
+    detailed: bool,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct DiskStats {
+    stored_bytes: u64,
+    available_bytes: Option<u64>, // Free space on the temp directory's filesystem
+    max_usage_bytes: Option<u64>,
+    headroom_bytes: Option<u64>, // How much more can be uploaded before uploads are refused
+    durable_writes: bool, // Uploads are fsynced before they are acknowledged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    durable_writes_min_bytes: Option<u64>, // ...when at least this large
+}
+
+impl DiskStats {
+    fn collect(app_state: &AppState) -> Self {
+        let stored_bytes = app_state.storage.disk.bytes_stored();
+        let available_bytes = app_state.storage.disk.available_space();
+        let max_usage_bytes = app_state.config.max_disk_usage;
+        let headroom_bytes = [
+            available_bytes.map(|available| available.saturating_sub(app_state.config.min_free_disk)),
+            max_usage_bytes.map(|max_usage| max_usage.saturating_sub(stored_bytes)),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let sync_min_size = app_state.storage.disk.sync_min_size();
+        Self {
+            stored_bytes,
+            available_bytes,
+            max_usage_bytes,
+            headroom_bytes,
+            durable_writes: sync_min_size.is_some(),
+            durable_writes_min_bytes: sync_min_size.filter(|&min_size| min_size > 0),
+        }
+    }
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, Serialize, ToSchema)]
 pub struct StorageStats {
     total_files: i64,
     total_size: i64,
     memory_files: i64,
+    memory_bytes: i64, // Size of the files held in the memory pool
+    disk_bytes: i64,   // ...and of the rest, before any sharing of identical contents
+    expiring_within_24h: i64,
     memory_usage_mb: usize,
     pool_size_mb: usize,
 }
 
-pub fn initialize_memory_pool() {
+// How far ahead storage stats count files about to expire
+const EXPIRING_SOON: chrono::Duration = chrono::Duration::hours(24);
+
+// How often the memory pool is re-sized to match the host
+const MEMORY_POOL_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Ignore pool size changes smaller than this fraction of the current pool
+const MEMORY_POOL_HYSTERESIS: f64 = 0.1;
+
+// Pool size for the given memory reading: `memory_pool_ratio` of what is left after `reserved_memory_mb`
+fn target_pool_size(config: &Config, available_memory: u64) -> usize {
+    // Bytes already in the pool are ours to give back, so they count as available
+    let available_memory = available_memory as usize + ALLOCATED_MEMORY.load(Ordering::Acquire);
+    let reserved_memory = config.reserved_memory_mb.saturating_mul(1024 * 1024);
+    if available_memory > reserved_memory {
+        ((available_memory - reserved_memory) as f64 * config.memory_pool_ratio) as usize
+    } else {
+        100 * 1024 * 1024 // Fallback to 100MB if low memory
+    }
+}
+
+pub fn initialize_memory_pool(config: &Config) {
     let mut system = System::new_all();
     system.refresh_memory();
 
     let total_memory = system.total_memory();
     let available_memory = system.available_memory();
-
-    // Reserve 200MB for system and other processes, use 50% of remaining available memory
-    let reserved_memory = 200 * 1024 * 1024; // 200MB
-    let pool_size = if available_memory > reserved_memory {
-        ((available_memory - reserved_memory) as f64 * 0.5) as usize
-    } else {
-        100 * 1024 * 1024 // Fallback to 100MB if low memory
-    };
+    let pool_size = target_pool_size(config, available_memory);
 
     MEMORY_POOL.store(pool_size, Ordering::Relaxed);
 
@@ -207,6 +524,39 @@ pub fn initialize_memory_pool() {
     );
 }
 
+/// Re-read available system memory and grow or shrink the pool to match.
+/// The pool never drops below what is already allocated, and small changes
+/// are ignored so it doesn't thrash. Returns the new size if it changed.
+pub fn resize_memory_pool(config: &Config) -> Option<usize> {
+    let mut system = System::new();
+    system.refresh_memory();
+
+    let current = MEMORY_POOL.load(Ordering::Acquire);
+    let target = target_pool_size(config, system.available_memory())
+        .max(ALLOCATED_MEMORY.load(Ordering::Acquire));
+    if (target.abs_diff(current) as f64) < current as f64 * MEMORY_POOL_HYSTERESIS {
+        return None;
+    }
+
+    MEMORY_POOL.store(target, Ordering::Release);
+    info!(
+        "Resized memory pool from {} MB to {} MB",
+        current / (1024 * 1024),
+        target / (1024 * 1024)
+    );
+    Some(target)
+}
+
+/// Current memory pool size in bytes.
+pub fn memory_pool_size() -> usize {
+    MEMORY_POOL.load(Ordering::Acquire)
+}
+
+/// Bytes of file contents currently held in the memory pool.
+pub fn allocated_memory() -> usize {
+    ALLOCATED_MEMORY.load(Ordering::Acquire)
+}
+
 fn try_allocate_memory(size: usize) -> bool {
     let current_allocated = ALLOCATED_MEMORY.load(Ordering::Acquire);
     let pool_size = MEMORY_POOL.load(Ordering::Acquire);
@@ -243,7 +593,6 @@ fn try_allocate_memory(size: usize) -> bool {
     }
 }
 
-#[allow(dead_code)]
 fn deallocate_memory(size: usize) {
     let old_value = ALLOCATED_MEMORY.fetch_sub(size, Ordering::AcqRel);
     info!(
@@ -254,146 +603,307 @@ fn deallocate_memory(size: usize) {
     );
 }
 
-fn generate_short_code() -> String {
-    use std::hash::{Hash, Hasher};
+// Random secret handed back to the uploader so they can delete the file later
+fn generate_delete_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+// Constant-time comparison so the token can't be guessed byte by byte
+fn token_matches(expected: &str, provided: Option<&str>) -> bool {
+    match provided {
+        Some(provided) if !expected.is_empty() && expected.len() == provided.len() => expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0,
+        _ => false,
+    }
+}
+
+// Parse an `expires_in` value (seconds from now) into an absolute expiry time
+fn parse_expires_in(value: &str) -> Result<DateTime<Utc>, StatusCode> {
+    let expiry = value
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .and_then(|secs| i64::try_from(secs).ok())
+        .and_then(chrono::Duration::try_seconds)
+        .and_then(|ttl| Utc::now().checked_add_signed(ttl));
+
+    expiry.ok_or_else(|| {
+        warn!("Invalid expires_in value: {}", value);
+        StatusCode::BAD_REQUEST
+    })
+}
 
-    // Use current timestamp + random UUID to generate unique short code
-    let uuid = Uuid::new_v4();
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_else(|_| {
-            warn!("System time is before Unix epoch, using fallback timestamp");
-            std::time::Duration::from_nanos(0)
+// Parse a `max_downloads` value; must be a positive count
+fn parse_max_downloads(value: &str) -> Result<i32, StatusCode> {
+    value
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|max| *max > 0)
+        .ok_or_else(|| {
+            warn!("Invalid max_downloads value: {}", value);
+            StatusCode::BAD_REQUEST
         })
-        .as_nanos();
+}
 
-    // Use XXH3 for superior speed and distribution properties
-    let mut hasher = Xxh3::new();
-    uuid.hash(&mut hasher);
-    timestamp.hash(&mut hasher);
+fn is_expired(expires_at: Option<DateTime<Utc>>) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+}
 
-    // Add some extra entropy from process-specific data
-    std::process::id().hash(&mut hasher);
-    std::thread::current().id().hash(&mut hasher);
+fn cached_short_code(app_state: &AppState, short_code: &str) -> Option<Uuid> {
+    match app_state.short_code_cache.lock() {
+        Ok(mut cache) => cache.get(&short_code.to_string()),
+        Err(e) => {
+            error!("Failed to acquire lock on short code cache: {}", e);
+            None
+        }
+    }
+}
 
-    let hash = hasher.finish();
+fn cache_short_code(app_state: &AppState, short_code: &str, file_id: Uuid) {
+    if let Ok(mut cache) = app_state.short_code_cache.lock() {
+        cache.insert(short_code.to_string(), file_id);
+    }
+}
 
-    // Simple base36 encoding using alphanumeric characters
-    let chars = "0123456789abcdefghijklmnopqrstuvwxyz";
-    let mut result = String::new();
-    let mut n = hash;
+fn cached_mapping(app_state: &AppState, uuid: Uuid) -> Option<FileMapping> {
+    match app_state.mapping_cache.lock() {
+        Ok(mut cache) => cache.get(&uuid),
+        Err(e) => {
+            error!("Failed to acquire lock on mapping cache: {}", e);
+            None
+        }
+    }
+}
 
-    for _ in 0..8 {
-        let idx = (n % 36) as usize;
-        result.push(chars.chars().nth(idx).unwrap()); // This unwrap is safe - idx is always 0-35
-        n /= 36;
+// Only files without a download limit are cached: limited files must be
+// counted by the database's conditional update before they are served
+fn cache_mapping(app_state: &AppState, file_mapping: &FileMapping) {
+    if file_mapping.max_downloads.is_some() {
+        return;
     }
+    if let Ok(mut cache) = app_state.mapping_cache.lock() {
+        cache.insert(file_mapping.id, file_mapping.clone());
+    }
+}
 
-    result
+// Forget a file in the lookup caches once it is deleted, consumed or expired
+fn invalidate_cached_file(app_state: &AppState, uuid: Uuid) {
+    if let Ok(mut cache) = app_state.mapping_cache.lock() {
+        cache.remove(&uuid);
+    } else {
+        error!("Failed to acquire lock on mapping cache during invalidation");
+    }
+    if let Ok(mut cache) = app_state.short_code_cache.lock() {
+        cache.retain(|_, file_id| *file_id != uuid);
+    } else {
+        error!("Failed to acquire lock on short code cache during invalidation");
+    }
 }
 
-async fn resolve_id_or_short_code_db(
+pub(crate) async fn resolve_id_or_short_code_db(
     input: &str,
     app_state: &AppState,
 ) -> Option<Uuid> {
-    // First try to parse as UUID
+    // Neither a UUID nor shaped like a short code, so nothing to look up
+    if !negative_cache::could_be_file_id(input) {
+        return None;
+    }
+
+    // First try to parse as UUID; one recently not found stays not found
     if let Ok(uuid) = input.parse::<Uuid>() {
+        if app_state.negative_cache.contains(&uuid.to_string()) {
+            return None;
+        }
         return Some(uuid);
     }
 
-    // Otherwise, try to resolve as short code - database first
+    // Otherwise, try to resolve as short code - in-process cache first
+    if let Some(file_id) = cached_short_code(app_state, input) {
+        return Some(file_id);
+    }
+    if app_state.negative_cache.contains(input) {
+        return None;
+    }
+
+    // Then Redis
+    let redis = app_state
+        .redis
+        .as_ref()
+        .filter(|_| app_state.redis_healthy.load(std::sync::atomic::Ordering::Relaxed));
+    if let Some(redis) = redis {
+        match redis.get_file_id_by_short_code(input).await {
+            Ok(Some(file_id)) => {
+                cache_short_code(app_state, input, file_id);
+                return Some(file_id);
+            }
+            Ok(None) => {}, // Not cached, try database
+            Err(e) => {
+                warn!("Redis short code lookup failed, falling back to database: {}", e);
+                app_state.redis_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Then the database, backfilling Redis on a hit. A miss is only worth
+    // remembering if the database was asked, or there is none.
+    let mut authoritative = app_state.database.is_none();
     if let Some(ref db) = app_state.database {
-        if app_state.database_healthy.load(std::sync::atomic::Ordering::Relaxed) {
+        if app_state.database_available() {
             match db.get_file_id_by_short_code(input).await {
-                Ok(Some(file_id)) => return Some(file_id),
-                Ok(None) => {}, // Not found in database, try memory
+                Ok(Some(file_id)) => {
+                    if let Some(redis) = redis
+                        && let Err(e) = redis.store_short_url(input, file_id).await
+                    {
+                        warn!("Failed to backfill short code into Redis: {}", e);
+                        app_state.redis_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+                    }
+                    cache_short_code(app_state, input, file_id);
+                    return Some(file_id);
+                }
+                Ok(None) => authoritative = true, // Not found in database, try memory
                 Err(e) => {
                     warn!("Database short code lookup failed: {}", e);
-                    app_state.database_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+                    app_state.set_database_healthy(false);
                 }
             }
         }
     }
 
     // Fallback to in-memory storage
-    if let Ok(storage_guard) = app_state.short_url_storage.lock() {
-        if let Some(full_id) = storage_guard.get(input) {
-            if let Ok(uuid) = full_id.parse::<Uuid>() {
-                return Some(uuid);
-            }
-        }
-    } else {
-        error!("Failed to acquire lock on short URL storage");
+    let file_id = app_state
+        .short_url_storage
+        .get(input)
+        .map(|file_id| *file_id);
+    if file_id.is_none() && authoritative {
+        app_state.negative_cache.insert(input);
     }
-
-    None
+    file_id
 }
 
-// Health check endpoint
-#[instrument(skip(app_state))]
-pub async fn health_check(State(app_state): State<AppState>) -> impl IntoResponse {
-    let database_status = if let Some(ref db) = app_state.database {
-        if db.health_check().await {
-            app_state.database_healthy.store(true, std::sync::atomic::Ordering::Relaxed);
-            "healthy".to_string()
-        } else {
-            app_state.database_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
-            "unhealthy".to_string()
-        }
-    } else {
-        "not_configured".to_string()
+// Health check endpoint. Database and Redis status come from the flags the
+// background probe maintains, so a probe here never touches either; storage
+// stats need a query and are only included with `?detailed=true` or an admin token.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    params(HealthQuery),
+    responses((status = 200, description = "Component status; `storage_stats` only when detailed", body = HealthResponse)),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn health_check(
+    State(app_state): State<AppState>,
+    Query(query): Query<HealthQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let database_status = database_status(&app_state);
+
+    let redis_status = match app_state.redis {
+        Some(_) if app_state.redis_healthy.load(Ordering::Relaxed) => "healthy",
+        Some(_) => "unhealthy",
+        None => "not_configured",
     };
 
-    let storage_stats = if let Some(ref db) = app_state.database {
-        if let Ok((total_files, total_size, memory_files)) = db.get_storage_stats().await {
-            Some(StorageStats {
-                total_files,
-                total_size,
-                memory_files,
-                memory_usage_mb: ALLOCATED_MEMORY.load(Ordering::Acquire) / (1024 * 1024),
-                pool_size_mb: MEMORY_POOL.load(Ordering::Acquire) / (1024 * 1024),
-            })
-        } else {
-            None
-        }
+    let detailed = query.detailed || admin::authorize(&app_state, &headers).is_ok();
+    let storage_stats = if detailed {
+        app_state.health_cache.storage(|| collect_storage_stats(&app_state)).await
     } else {
-        // Fallback stats from in-memory storage
-        let file_count = if let Ok(storage) = app_state.file_storage.lock() {
-            storage.len() as i64
-        } else {
-            0
-        };
-
-        Some(StorageStats {
-            total_files: file_count,
-            total_size: 0, // We don't track this in memory storage
-            memory_files: file_count,
-            memory_usage_mb: ALLOCATED_MEMORY.load(Ordering::Acquire) / (1024 * 1024),
-            pool_size_mb: MEMORY_POOL.load(Ordering::Acquire) / (1024 * 1024),
-        })
+        None
     };
 
-    let overall_status = if database_status == "healthy" || database_status == "not_configured" {
+    let overall_status = if database_status != "unhealthy" && redis_status != "unhealthy" {
         "healthy"
     } else {
-        "degraded" // Database is down but we can fall back to in-memory
+        "degraded" // Database or Redis is down but we can fall back to in-memory
     };
 
     let response = HealthResponse {
         status: overall_status.to_string(),
-        database: database_status,
+        database: database_status.to_string(),
+        redis: redis_status.to_string(),
+        database_pool: app_state.database.as_ref().and_then(|db| db.pool_stats()),
         memory_pool: format!(
             "{} MB / {} MB", 
             ALLOCATED_MEMORY.load(Ordering::Acquire) / (1024 * 1024),
             MEMORY_POOL.load(Ordering::Acquire) / (1024 * 1024)
         ),
-        active_connections: ACTIVE_CONNECTIONS.load(Ordering::Acquire),
+        active_uploads: ACTIVE_UPLOADS.load(Ordering::Acquire),
+        active_downloads: ACTIVE_DOWNLOADS.load(Ordering::Acquire),
+        negative_cache_hits: app_state.negative_cache.hits(),
+        corrupt_files_found: scrub::corrupt_files_found(),
+        read_only: app_state.is_read_only(),
         storage_stats,
+        disk: app_state.health_cache.disk(|| async { DiskStats::collect(&app_state) }).await,
     };
 
     Json(response)
 }
 
+pub(crate) fn database_status(app_state: &AppState) -> &'static str {
+    match app_state.database {
+        Some(_) if app_state.database_available() => "healthy",
+        Some(_) => "unhealthy",
+        None => "not_configured",
+    }
+}
+
+async fn collect_storage_stats(app_state: &AppState) -> Option<StorageStats> {
+    if let Some(ref db) = app_state.database {
+        if !app_state.database_available() {
+            return None;
+        }
+        let totals = match db.get_storage_stats(EXPIRING_SOON).await {
+            Ok(totals) => totals,
+            Err(e) => {
+                warn!("Failed to get storage stats: {}", e);
+                return None;
+            }
+        };
+        Some(StorageStats::new(totals))
+    } else {
+        // Fallback stats from in-memory storage; tombstones hold nothing
+        let mut totals = database::StorageTotals::default();
+        let now = Utc::now();
+        for entry in app_state.file_storage.iter() {
+            let Some(ref storage_ref) = entry.storage else {
+                continue;
+            };
+            let size = entry.size as i64;
+            totals.total_files += 1;
+            totals.total_size += size;
+            if storage_ref.is_in_memory() {
+                totals.memory_files += 1;
+                totals.memory_size += size;
+            }
+            let expiring = entry.expires_at.is_some_and(|expires_at| expires_at > now && expires_at <= now + EXPIRING_SOON);
+            if expiring && !entry.pinned {
+                totals.expiring_files += 1;
+            }
+        }
+        Some(StorageStats::new(totals))
+    }
+}
+
+impl StorageStats {
+    fn new(totals: database::StorageTotals) -> Self {
+        Self {
+            total_files: totals.total_files,
+            total_size: totals.total_size,
+            memory_files: totals.memory_files,
+            memory_bytes: totals.memory_size,
+            disk_bytes: totals.total_size - totals.memory_size,
+            expiring_within_24h: totals.expiring_files,
+            memory_usage_mb: ALLOCATED_MEMORY.load(Ordering::Acquire) / (1024 * 1024),
+            pool_size_mb: MEMORY_POOL.load(Ordering::Acquire) / (1024 * 1024),
+        }
+    }
+}
+
 // Helper function to create temp directory and handle cleanup
 async fn ensure_temp_directory(temp_dir: &PathBuf) -> Result<(), StatusCode> {
     if let Err(e) = tokio::fs::create_dir_all(temp_dir).await {
@@ -419,500 +929,2461 @@ fn format_size(size: usize) -> String {
 
 // Security: Sanitize filename to prevent path traversal attacks
 fn sanitize_filename(filename: &str) -> String {
-    let sanitized = sanitize(filename);
+    try_sanitize_filename(filename).unwrap_or_else(|| "unknown_file".to_string())
+}
+
+// The sanitized filename, or None if nothing usable is left of it
+fn try_sanitize_filename(filename: &str) -> Option<String> {
+    // Control characters (including CR/LF) never belong in a filename or a header
+    let sanitized: String = sanitize(filename).chars().filter(|c| !c.is_control()).collect();
 
     // Additional security checks
-    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
-        return "unknown_file".to_string();
+    if sanitized.trim().is_empty() || sanitized == "." || sanitized == ".." {
+        return None;
     }
 
-    // Limit filename length
-    if sanitized.len() > 200 {
-        return format!(
-            "{}...{}",
-            &sanitized[..100],
-            &sanitized[sanitized.len() - 50..]
-        );
+    // Limit filename length, counting characters so multibyte names are never split
+    let char_count = sanitized.chars().count();
+    if char_count > 200 {
+        let head: String = sanitized.chars().take(100).collect();
+        let tail: String = sanitized.chars().skip(char_count - 50).collect();
+        return Some(format!("{}...{}", head, tail));
     }
 
-    sanitized
+    Some(sanitized)
 }
 
-// Extract client IP from connection info or headers
-fn get_client_ip(connect_info: Option<&ConnectInfo<SocketAddr>>) -> std::net::IpAddr {
-    connect_info
-        .map(|ci| ci.0.ip())
-        .unwrap_or_else(|| "127.0.0.1".parse().unwrap())
+// Sub-routes of /drop/{id}, which a filename segment would never get past
+const FILE_SUBROUTES: &[&str] = &["info", "preview", "aliases"];
+
+// `/drop/{short_code}/{filename}`, so browsers and link previews see the
+// name; just the short URL for a filename that is one of the sub-routes
+fn pretty_url(base_url: &str, short_code: &str, filename: &str) -> String {
+    if FILE_SUBROUTES.contains(&filename) {
+        return format!("{}/drop/{}", base_url, short_code);
+    }
+    format!("{}/drop/{}/{}", base_url, short_code, encode_path_segment(filename))
 }
 
-// Rate limiting check - tries database first, falls back to in-memory
-async fn check_rate_limit(
-    client_ip: std::net::IpAddr,
-    app_state: &AppState,
-) -> Result<(), StatusCode> {
-    // Try database first if available and healthy
-    if let Some(ref db) = app_state.database {
-        if app_state.database_healthy.load(std::sync::atomic::Ordering::Relaxed) {
-            match db.check_rate_limit(
-                client_ip,
-                app_state.config.rate_limit_window_seconds,
-                app_state.config.rate_limit_requests_per_minute as i32,
-            ).await {
-                Ok(allowed) => {
-                    if !allowed {
-                        warn!("Rate limit exceeded for IP: {}", client_ip);
-                        return Err(StatusCode::TOO_MANY_REQUESTS);
-                    }
-                    return Ok(());
-                }
-                Err(e) => {
-                    warn!("Database rate limit check failed, falling back to memory: {}", e);
-                    app_state.database_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
-                }
-            }
+// Percent-encode all but the RFC 3986 unreserved characters
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
         }
     }
-
-    // Fallback to in-memory rate limiting
-    check_rate_limit_memory(&client_ip.to_string(), &app_state.rate_limit_storage, &app_state.config)
+    encoded
 }
 
-// In-memory rate limiting (fallback)
-fn check_rate_limit_memory(
-    client_ip: &str,
-    rate_storage: &RateLimitStorage,
-    config: &Config,
-) -> Result<(), StatusCode> {
-    let now = Instant::now();
-    let window_duration = Duration::from_secs(config.rate_limit_window_seconds);
-
-    if let Ok(mut storage) = rate_storage.lock() {
-        let entry = storage.entry(client_ip.to_string()).or_insert((now, 0));
-
-        // Reset counter if window has passed
-        if now.duration_since(entry.0) > window_duration {
-            entry.0 = now;
-            entry.1 = 0;
+// Content-Disposition value with an ASCII `filename` fallback and an RFC 5987
+// UTF-8 `filename*` so non-ASCII names survive intact
+fn content_disposition(disposition: &str, filename: &str) -> String {
+    let mut fallback = String::with_capacity(filename.len());
+    for c in filename.chars().filter(|c| !c.is_control()) {
+        match c {
+            // Escape per the quoted-string grammar
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            c if c.is_ascii() => fallback.push(c),
+            _ => fallback.push('_'),
         }
+    }
 
-        entry.1 += 1;
+    if filename.is_ascii() && fallback == filename {
+        return format!("{}; filename=\"{}\"", disposition, fallback);
+    }
 
-        if entry.1 > config.rate_limit_requests_per_minute {
-            warn!("Rate limit exceeded for IP: {}", client_ip);
-            return Err(StatusCode::TOO_MANY_REQUESTS);
+    let mut encoded = String::with_capacity(filename.len() * 3);
+    for byte in filename.bytes() {
+        // RFC 5987 attr-char
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
         }
-
-        Ok(())
-    } else {
-        error!("Failed to acquire rate limit storage lock");
-        Err(StatusCode::INTERNAL_SERVER_ERROR)
     }
-}
 
-// Helper function to stream large files directly to disk
-async fn stream_field_to_disk(
-    mut field: axum::extract::multipart::Field<'_>,
-    file_path: &PathBuf,
-    max_size: usize,
-) -> Result<usize, StatusCode> {
-    let mut file = tokio::fs::File::create(file_path).await.map_err(|e| {
-        error!("Failed to create file for streaming: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition, fallback, encoded
+    )
+}
 
-    let mut total_size = 0usize;
-    let mut buffer = Vec::with_capacity(8192); // 8KB buffer
+// Build the externally visible base URL (scheme + host) for links returned to clients.
+// Prefers the configured public URL, then the request's Host header, then the bind address.
+fn public_base_url(config: &Config, headers: &HeaderMap) -> String {
+    if let Some(ref base) = config.public_base_url {
+        return base.clone();
+    }
 
-    while let Some(chunk) = field.chunk().await.map_err(|e| {
-        error!("Failed to read chunk during streaming: {:?}", e);
-        StatusCode::BAD_REQUEST
-    })? {
-        total_size += chunk.len();
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .filter(|h| !h.is_empty());
 
-        // Check size limit during streaming
-        if total_size > max_size {
-            // Clean up partial file
-            let _ = tokio::fs::remove_file(file_path).await;
-            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    match host {
+        Some(host) => {
+            if config.tls_enabled() {
+                return format!("https://{}", host);
+            }
+            let scheme = headers
+                .get("x-forwarded-proto")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| v == "http" || v == "https")
+                .unwrap_or_else(|| "http".to_string());
+            format!("{}://{}", scheme, host)
         }
+        None if config.tls_enabled() => format!("https://{}", config.bind_address),
+        None => format!("http://{}", config.bind_address),
+    }
+}
 
-        buffer.extend_from_slice(&chunk);
+/// What was learned about an upload while its bytes were stored, in the
+/// same pass: where it lives, its size, both digests and the sniffed type.
+pub(crate) struct StreamedUpload {
+    pub(crate) storage_ref: StorageRef,
+    pub(crate) size: usize,
+    pub(crate) content_hash: String, // XXH3-128
+    pub(crate) sha256: String,
+    pub(crate) detected_content_type: Option<&'static str>,
+}
 
-        // Write in larger chunks for better performance
-        if buffer.len() >= 8192 {
-            file.write_all(&buffer).await.map_err(|e| {
-                error!("Failed to write chunk to disk: {:?}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
-            })?;
-            buffer.clear();
-        }
+/// Feeds each chunk of an upload through the hashers and keeps its first
+/// bytes for the magic-byte sniffer, so the stored file is never read back.
+#[derive(Clone, Default)]
+pub(crate) struct UploadInspector {
+    hasher: ContentHasher,
+    head: Vec<u8>,
+}
+
+impl UploadInspector {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        let wanted = sniff::SNIFF_LEN.saturating_sub(self.head.len()).min(bytes.len());
+        self.head.extend_from_slice(&bytes[..wanted]);
+        self.hasher.update(bytes);
     }
 
-    // Write remaining data
-    if !buffer.is_empty() {
-        file.write_all(&buffer).await.map_err(|e| {
-            error!("Failed to write final chunk to disk: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+    pub(crate) fn finish(&self, storage_ref: StorageRef, size: usize) -> StreamedUpload {
+        StreamedUpload {
+            storage_ref,
+            size,
+            content_hash: self.hasher.content_hash(),
+            sha256: self.hasher.sha256(),
+            detected_content_type: sniff::sniff_content_type(&self.head),
+        }
     }
+}
 
-    file.flush().await.map_err(|e| {
-        error!("Failed to flush file to disk: {:?}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+// Store an upload through the storage backend, hashing and sniffing it on the way.
+// Accepts any stream of body chunks: a multipart field or a raw request body.
+// Fails with 408 if the client sends slower than the minimum upload rate.
+async fn store_upload_stream<S, E>(
+    app_state: &AppState,
+    id: Uuid,
+    field: S,
+    max_size: usize,
+) -> Result<StreamedUpload, StatusCode>
+where
+    S: futures_util::Stream<Item = Result<bytes::Bytes, E>> + Send,
+    E: std::fmt::Debug + Send,
+{
+    let mut inspector = UploadInspector::default();
+    let (field, watch) = transfer_rate::watch_upload(&app_state.config, field);
+    let field = field.inspect(|chunk| {
+        if let Ok(bytes) = chunk {
+            inspector.update(bytes);
+        }
+    });
+
+    let (storage_ref, size) = app_state
+        .storage
+        .put(id, field, max_size)
+        .await
+        .map_err(|status| watch.status(status))?;
+    Ok(inspector.finish(storage_ref, size))
+}
 
-    Ok(total_size)
+// Both digests of an upload, computed in one pass: XXH3 for the ETag and
+// SHA-256 for spotting identical uploads
+#[derive(Clone, Default)]
+pub(crate) struct ContentHasher {
+    xxh3: Xxh3,
+    sha256: Sha256,
 }
 
-#[instrument(skip(app_state, multipart))]
-pub async fn upload_file(
-    State(app_state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    mut multipart: Multipart,
-) -> Result<Json<UploadResponse>, StatusCode> {
-    info!("Starting file upload");
+impl ContentHasher {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        self.xxh3.update(bytes);
+        self.sha256.update(bytes);
+    }
 
-    // Rate limiting
-    let client_ip = get_client_ip(Some(&ConnectInfo(addr)));
-    check_rate_limit(client_ip, &app_state).await?;
+    pub(crate) fn content_hash(&self) -> String {
+        content_hash_hex(&self.xxh3)
+    }
+
+    pub(crate) fn sha256(&self) -> String {
+        format!("{:x}", self.sha256.clone().finalize())
+    }
+}
+
+// Hex form of the XXH3-128 content hash, used as the file's strong ETag
+fn content_hash_hex(hasher: &Xxh3) -> String {
+    format!("{:032x}", hasher.digest128())
+}
+
+// Options that apply to every file in an upload request
+#[derive(Clone, Copy, Debug, Default)]
+struct UploadOptions {
+    expires_at: Option<DateTime<Utc>>,
+    max_downloads: Option<i32>,
+    pinned: bool,
+    embeddable: bool,
+    client_ip: Option<std::net::IpAddr>, // Charged for an anonymous upload against its daily quota
+    api_key_id: Option<Uuid>, // The key the upload was made with, whose quota it counts against instead
+}
+
+impl UploadOptions {
+    fn from_params(params: &UploadParams) -> Result<Self, StatusCode> {
+        let mut options = Self::default();
+        if let Some(ref value) = params.expires_in {
+            options.expires_at = Some(parse_expires_in(value)?);
+        }
+        if let Some(ref value) = params.max_downloads {
+            options.max_downloads = Some(parse_max_downloads(value)?);
+        }
+        if let Some(ref value) = params.pinned {
+            options.pinned = upload_options::parse_bool(value).map_err(|_| {
+                warn!("Invalid pinned value: {}", value);
+                StatusCode::BAD_REQUEST
+            })?;
+        }
+        if let Some(ref value) = params.embeddable {
+            options.embeddable = upload_options::parse_bool(value).map_err(|_| {
+                warn!("Invalid embeddable value: {}", value);
+                StatusCode::BAD_REQUEST
+            })?;
+        }
+        Ok(options)
+    }
+
+    // These options with the ones a client asked for in the form laid over them
+    fn with_requested(self, requested: &RequestedOptions) -> Self {
+        let expires_at = requested
+            .expires_in
+            .and_then(|secs| i64::try_from(secs).ok())
+            .and_then(chrono::Duration::try_seconds)
+            .and_then(|ttl| Utc::now().checked_add_signed(ttl));
+        Self {
+            expires_at: expires_at.or(self.expires_at),
+            max_downloads: requested.max_downloads.or(self.max_downloads),
+            pinned: requested.pinned.unwrap_or(self.pinned),
+            embeddable: requested.embeddable.unwrap_or(self.embeddable),
+            client_ip: self.client_ip,
+            api_key_id: self.api_key_id,
+        }
+    }
+
+    // DROP_DEFAULT_TTL for a file that asked for no expiry, and DROP_MAX_TTL
+    // as a cap on any expiry
+    fn with_retention(self, config: &Config) -> Self {
+        let now = Utc::now();
+        let from_now = |ttl: Duration| {
+            chrono::Duration::from_std(ttl)
+                .ok()
+                .and_then(|ttl| now.checked_add_signed(ttl))
+        };
+        let expires_at = self.expires_at.or_else(|| config.default_ttl.and_then(from_now));
+        let expires_at = match (expires_at, config.max_ttl.and_then(from_now)) {
+            (Some(expires_at), Some(latest)) => Some(expires_at.min(latest)),
+            (expires_at, latest) => expires_at.or(latest),
+        };
+        Self { expires_at, ..self }
+    }
+}
+
+// A file that has been stored but not yet registered
+struct PendingUpload {
+    id: Uuid,
+    filename: String,
+    content_type: String,
+    declared_content_type: Option<String>,
+    detected_content_type: Option<&'static str>,
+    storage_ref: StorageRef,
+    file_size: usize,
+    content_hash: String,
+    sha256: String,
+    options: Option<UploadOptions>, // This file's own, replacing the request's
+}
+
+impl PendingUpload {
+    fn new(
+        id: Uuid,
+        filename: String,
+        declared_content_type: Option<String>,
+        streamed: StreamedUpload,
+    ) -> Self {
+        let content_type = sniff::resolve_content_type(
+            &filename,
+            declared_content_type.as_deref(),
+            streamed.detected_content_type,
+        );
+        Self {
+            id,
+            filename,
+            content_type,
+            declared_content_type,
+            detected_content_type: streamed.detected_content_type,
+            storage_ref: streamed.storage_ref,
+            file_size: streamed.size,
+            content_hash: streamed.content_hash,
+            sha256: streamed.sha256,
+            options: None,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UploadRejectedResponse {
+    error: String,
+    #[schema(value_type = String)]
+    rule: &'static str,
+    value: String,
+    filename: String,
+}
+
+// Whether a content type matches a configured pattern such as "text/html" or "image/*"
+fn content_type_matches(content_type: &str, pattern: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => essence.split('/').next() == Some(top_level),
+        None => essence == pattern,
+    }
+}
+
+// Check a received file against the configured blocklists and allowlist,
+// naming the rule and value that refused it
+fn upload_policy_violation(config: &Config, upload: &PendingUpload) -> Option<(&'static str, String)> {
+    let extension = upload
+        .filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    if let Some(ext) = extension.filter(|ext| config.blocked_extensions.contains(ext)) {
+        return Some(("DROP_BLOCKED_EXTENSIONS", ext));
+    }
+
+    // The sniffed type is checked too, so a blocked format can't hide behind an alias
+    let checked_types = std::iter::once(upload.content_type.as_str()).chain(upload.detected_content_type);
+    for content_type in checked_types {
+        if config
+            .blocked_content_types
+            .iter()
+            .any(|pattern| content_type_matches(content_type, pattern))
+        {
+            return Some(("DROP_BLOCKED_CONTENT_TYPES", content_type.to_string()));
+        }
+    }
+
+    let allowed = config.allowed_content_types.is_empty()
+        || config
+            .allowed_content_types
+            .iter()
+            .any(|pattern| content_type_matches(&upload.content_type, pattern));
+    if !allowed {
+        return Some(("DROP_ALLOWED_CONTENT_TYPES", upload.content_type.clone()));
+    }
+
+    None
+}
+
+// Refuse the whole request with 415 if any file breaks the upload policy,
+// removing everything that was streamed for it
+async fn enforce_upload_policy(app_state: &AppState, pending: &[PendingUpload]) -> Result<(), axum::response::Response> {
+    let Some((upload, (rule, value))) = pending
+        .iter()
+        .find_map(|upload| upload_policy_violation(&app_state.config, upload).map(|violation| (upload, violation)))
+    else {
+        return Ok(());
+    };
+
+    warn!(
+        "Rejecting upload '{}': {} matched {}",
+        upload.filename, value, rule
+    );
+    let body = UploadRejectedResponse {
+        error: format!("File type not allowed: {}", value),
+        rule,
+        value,
+        filename: upload.filename.clone(),
+    };
+    discard_pending_uploads(&app_state.storage, pending).await;
+    Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(body)).into_response())
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UploadFailedResponse {
+    error: String,
+    filename: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InsufficientStorageResponse {
+    error: String,
+    required_bytes: u64,
+    available_bytes: u64,
+}
+
+impl IntoResponse for InsufficientStorageResponse {
+    fn into_response(self) -> axum::response::Response {
+        (StatusCode::INSUFFICIENT_STORAGE, Json(self)).into_response()
+    }
+}
+
+// Refuse with 507 when writing `projected` more bytes would leave less than
+// `min_free_disk` on the temp directory's disk, or take this instance past
+// `max_disk_usage`. `projected` is what the client declared, or 0 if unknown.
+pub(crate) fn check_disk_space(app_state: &AppState, projected: u64) -> Result<(), InsufficientStorageResponse> {
+    let config = &app_state.config;
+    let disk = &app_state.storage.disk;
+
+    let breaches = |headroom: u64| headroom == 0 || projected > headroom;
+    let mut shortfall = None;
+    if let Some(available) = disk.available_space() {
+        let headroom = available.saturating_sub(config.min_free_disk);
+        if breaches(headroom) {
+            shortfall = Some(("Not enough free disk space", headroom));
+        }
+    }
+    if let Some(max_disk_usage) = config.max_disk_usage {
+        let headroom = max_disk_usage.saturating_sub(disk.bytes_stored());
+        if breaches(headroom) && shortfall.is_none_or(|(_, other)| headroom < other) {
+            shortfall = Some(("Disk usage limit reached", headroom));
+        }
+    }
+
+    let Some((error, available_bytes)) = shortfall else {
+        return Ok(());
+    };
+    warn!(
+        "Rejecting upload of {}: {} ({} available)",
+        format_size(projected as usize),
+        error,
+        format_size(available_bytes as usize)
+    );
+    Err(InsufficientStorageResponse {
+        error: error.to_string(),
+        required_bytes: projected,
+        available_bytes,
+    })
+}
+
+// Size the client says it is sending, if any
+fn declared_content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+}
+
+// Remove files written for a request that is being rejected
+async fn discard_pending_uploads(storage: &FileStore, pending: &[PendingUpload]) {
+    for upload in pending {
+        if let Err(e) = storage.delete(&upload.storage_ref).await {
+            warn!("Failed to remove partial upload {:?}: {:?}", upload.storage_ref, e);
+        }
+    }
+}
+
+// Parts of a multipart request written so far. Dropped with files still in
+// it, because the client went away mid-request, it removes them in the
+// background; an async fn that is cancelled never gets to clean up itself.
+struct ReceivedParts {
+    storage: FileStore,
+    uploads: Vec<PendingUpload>,
+}
+
+impl ReceivedParts {
+    fn new(storage: &FileStore) -> Self {
+        Self {
+            storage: storage.clone(),
+            uploads: Vec::new(),
+        }
+    }
+
+    // Remove the files written so far
+    async fn discard(&mut self) {
+        discard_pending_uploads(&self.storage, &self.uploads).await;
+        self.uploads.clear();
+    }
+
+    fn into_uploads(mut self) -> Vec<PendingUpload> {
+        std::mem::take(&mut self.uploads)
+    }
+}
+
+impl Drop for ReceivedParts {
+    fn drop(&mut self) {
+        if self.uploads.is_empty() {
+            return;
+        }
+        warn!("Upload abandoned mid-request, removing {} stored file(s)", self.uploads.len());
+        let storage = self.storage.clone();
+        let uploads = std::mem::take(&mut self.uploads);
+        tokio::spawn(async move { discard_pending_uploads(&storage, &uploads).await });
+    }
+}
+
+// Form fields without a filename accepted alongside the files, and how long each may be
+const MAX_FORM_FIELDS: usize = 8;
+const MAX_FORM_FIELD_SIZE: usize = 1024;
+
+#[derive(Serialize, ToSchema)]
+pub struct MultipartLimitResponse {
+    error: String,
+    limit: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MissingFileResponse {
+    error: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InvalidOptionsResponse {
+    error: String,
+    fields: Vec<InvalidOption>, // Every option that couldn't be used, not just the first
+}
+
+// Options this server parses but has no feature behind yet; refused rather
+// than silently dropped
+fn unsupported_options(requested: &RequestedOptions, suffix: &str) -> Vec<InvalidOption> {
+    let mut unsupported = Vec::new();
+    if requested.password.is_some() {
+        unsupported.push(InvalidOption::new(&format!("password{}", suffix), "is not supported by this server"));
+    }
+    if requested.disposition.is_some() {
+        unsupported.push(InvalidOption::new(&format!("disposition{}", suffix), "is not supported by this server"));
+    }
+    unsupported
+}
+
+fn multipart_limit(status: StatusCode, error: &str, limit: usize) -> axum::response::Response {
+    warn!("Rejecting upload: {} (limit {})", error, limit);
+    let body = MultipartLimitResponse {
+        error: error.to_string(),
+        limit,
+    };
+    (status, Json(body)).into_response()
+}
 
-    // Increment active connections
-    ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+// The text of a form field, refused once it grows past MAX_FORM_FIELD_SIZE
+async fn read_form_field(field: &mut axum::extract::multipart::Field<'_>) -> Result<String, axum::response::Response> {
+    let mut value = Vec::new();
+    loop {
+        match field.chunk().await {
+            Ok(Some(chunk)) if value.len() + chunk.len() > MAX_FORM_FIELD_SIZE => {
+                return Err(multipart_limit(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Form field too large",
+                    MAX_FORM_FIELD_SIZE,
+                ));
+            }
+            Ok(Some(chunk)) => value.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read form field: {:?}", e);
+                return Err(StatusCode::BAD_REQUEST.into_response());
+            }
+        }
+    }
+    String::from_utf8(value).map_err(|_| StatusCode::BAD_REQUEST.into_response())
+}
 
+// Stream every file part of a multipart request to disk. Option fields update
+// `options` and `alias`, and a file part's Upload-Options header sets options
+// for that file alone; on any failure or disconnect the parts written so far
+// are removed.
+async fn receive_multipart_files(
+    app_state: &AppState,
+    multipart: &mut Multipart,
+    options: &mut UploadOptions,
+    alias: &mut Option<String>,
+    quota_owner: &QuotaOwner,
+    remaining_quota: Option<u64>,
+) -> Result<Vec<PendingUpload>, axum::response::Response> {
+    let mut received = ReceivedParts::new(&app_state.storage);
     let mut total_size = 0usize;
+    let mut parts = 0usize;
+    let mut form_fields = 0usize;
+    let mut parser = OptionsParser::default();
+    let mut part_errors = Vec::new();
+    let mut overrides = Vec::new(); // Per stored file, in order
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to get next field: {:?}", e);
+                received.discard().await;
+                return Err(StatusCode::BAD_REQUEST.into_response());
+            }
+        };
 
-    // Process the multipart form data
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
-        error!("Failed to get next field: {:?}", e);
-        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-        StatusCode::BAD_REQUEST
-    })? {
-        let raw_filename = field.file_name().unwrap_or("unknown").to_string();
+        // Checked before anything is read, so a flood of tiny parts costs nothing
+        parts += 1;
+        let refused = if parts > app_state.config.max_multipart_parts {
+            Some(multipart_limit(StatusCode::PAYLOAD_TOO_LARGE, "Too many parts", app_state.config.max_multipart_parts))
+        } else if field.file_name().is_some() && received.uploads.len() >= app_state.config.max_files_per_request {
+            Some(multipart_limit(StatusCode::PAYLOAD_TOO_LARGE, "Too many files", app_state.config.max_files_per_request))
+        } else if field.file_name().is_none() && form_fields >= MAX_FORM_FIELDS {
+            Some(multipart_limit(StatusCode::UNPROCESSABLE_ENTITY, "Too many form fields", MAX_FORM_FIELDS))
+        } else {
+            None
+        };
+        if let Some(response) = refused {
+            received.discard().await;
+            return Err(response);
+        }
+
+        // Fields without a filename are metadata, never stored as files: upload
+        // options, wherever they come in the form, and anything else is read and ignored
+        if field.file_name().is_none() {
+            form_fields += 1;
+            let name = field.name().unwrap_or_default().to_string();
+            let value = match read_form_field(&mut field).await {
+                Ok(value) => value,
+                Err(response) => {
+                    received.discard().await;
+                    return Err(response);
+                }
+            };
+            if OptionsParser::is_option(&name) {
+                parser.field(&name, &value);
+            } else {
+                info!("Ignoring form field '{}' without a filename", name);
+            }
+            continue;
+        }
+
+        let raw_filename = field.file_name().unwrap_or_default().to_string();
         let filename = sanitize_filename(&raw_filename);
         info!(
             "Processing file: {} (sanitized from: {})",
             filename, raw_filename
         );
 
-        let content_type = field
-            .content_type()
-            .unwrap_or("application/octet-stream") // Standard fallback for binary data
-            .to_string();
-
-        // Generate a unique ID for the file early
-        let id = Uuid::new_v4();
-        let short_code = generate_short_code();
-        info!("Generated file ID: {}, short code: {}", id, short_code);
-
-        // Store the short URL mapping - try database first, fallback to memory
-        let short_url_stored = if let Some(ref db) = app_state.database {
-            if app_state.database_healthy.load(std::sync::atomic::Ordering::Relaxed) {
-                match db.store_short_url(&short_code, id).await {
-                    Ok(_) => {
-                        info!("Stored short URL in database: {}", short_code);
-                        true
+        // A part whose own options are bad is skipped unread; the request is
+        // refused once every field has been seen
+        let part_options = match field.headers().get(PART_OPTIONS_HEADER) {
+            Some(value) => {
+                let suffix = format!(" ({})", filename);
+                let parsed = value
+                    .to_str()
+                    .map_err(|_| vec![InvalidOption::new(PART_OPTIONS_HEADER, "must be a JSON object")])
+                    .and_then(upload_options::parse_json);
+                match parsed {
+                    Ok(requested) if requested.alias.is_some() => {
+                        part_errors.push(InvalidOption::new(
+                            &format!("alias{}", suffix),
+                            "can only be set for the whole upload",
+                        ));
+                        continue;
                     }
-                    Err(e) => {
-                        warn!("Failed to store short URL in database, falling back to memory: {}", e);
-                        app_state.database_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
-                        false
+                    Ok(requested) => {
+                        part_errors.extend(unsupported_options(&requested, &suffix));
+                        Some(requested)
+                    }
+                    Err(errors) => {
+                        part_errors.extend(errors.into_iter().map(|mut invalid| {
+                            invalid.field.push_str(&suffix);
+                            invalid
+                        }));
+                        continue;
                     }
                 }
-            } else {
-                false
             }
-        } else {
-            false
+            None => None,
         };
 
-        if !short_url_stored {
-            // Fallback to in-memory storage
-            if let Ok(mut storage_guard) = app_state.short_url_storage.lock() {
-                storage_guard.insert(short_code.clone(), id.to_string());
-                info!("Stored short URL in memory: {}", short_code);
-            } else {
-                error!("Failed to acquire lock on short URL storage during upload");
-                ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-        }
+        let declared_content_type = field.content_type().map(str::to_string);
 
-        // Create temp directory if it doesn't exist
-        ensure_temp_directory(&app_state.config.temp_directory).await?;
+        // Stop streaming once the file would go over what is left of the quota
+        let max_size = app_state.config.max_file_size_limit;
+        let quota_left = remaining_quota.map(|remaining| remaining.saturating_sub(total_size as u64));
 
-        // Always stream to disk first for large file support
-        let file_path = app_state.config.temp_directory.join(format!("file_{}", id));
-        let file_size =
-            stream_field_to_disk(field, &file_path, app_state.config.max_file_size_limit).await?;
+        let id = app_state.ids.uuid();
+        let limit = quota::cap_upload_size(max_size, quota_left);
+        let streamed = match store_upload_stream(app_state, id, field, limit).await {
+            Ok(streamed) => streamed,
+            Err(status) => {
+                warn!("Rejecting upload: file '{}' failed with {}", filename, status);
+                received.discard().await;
+                return Err(quota::upload_stream_error(app_state, status, quota_owner, quota_left, max_size));
+            }
+        };
+
+        let upload = PendingUpload::new(id, filename, declared_content_type, streamed);
+        total_size += upload.file_size;
+        received.uploads.push(upload);
+        overrides.push(part_options);
 
         // Check total request size limit
-        total_size += file_size;
         if total_size > app_state.config.max_total_size_per_request {
             error!(
                 "Total request size exceeds maximum limit of {}",
                 format_size(app_state.config.max_total_size_per_request)
             );
-            // Clean up the file we just wrote
-            let _ = tokio::fs::remove_file(&file_path).await;
-            ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            received.discard().await;
+            return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
         }
+    }
 
-        info!(
-            "File size: {}, content_type: {}, total_request_size: {}",
-            format_size(file_size),
-            content_type,
-            format_size(total_size)
-        );
+    // Request-wide problems first, then each file's
+    let (requested, mut invalid) = match parser.finish() {
+        Ok(requested) => {
+            let unsupported = unsupported_options(&requested, "");
+            (requested, unsupported)
+        }
+        Err(errors) => (RequestedOptions::default(), errors),
+    };
+    invalid.append(&mut part_errors);
+    if !invalid.is_empty() {
+        warn!("Rejecting upload: {} invalid option(s)", invalid.len());
+        received.discard().await;
+        let body = InvalidOptionsResponse {
+            error: "Invalid upload options".to_string(),
+            fields: invalid,
+        };
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response());
+    }
 
-        // Decide whether to keep in memory or on disk based on size and memory availability
-        let file_data =
-            if file_size < app_state.config.stream_threshold && try_allocate_memory(file_size) {
-                info!(
-                    "Moving file '{}' to memory pool (size: {})",
-                    filename,
-                    format_size(file_size)
-                );
-
-                // Read file into memory and delete from disk
-                match tokio::fs::read(&file_path).await {
-                    Ok(data) => {
-                        // Delete the temporary file since we have it in memory
-                        if let Err(e) = tokio::fs::remove_file(&file_path).await {
-                            warn!("Failed to remove temporary file: {:?}", e);
-                        }
+    // Form fields win over the query string, and a part's own options over both
+    let from_query = *options;
+    *options = from_query.with_requested(&requested);
+    let mut uploads = received.into_uploads();
+    for (upload, part_options) in uploads.iter_mut().zip(overrides) {
+        upload.options = part_options.map(|part_options| {
+            from_query.with_requested(&requested.overridden_by(&part_options))
+        });
+    }
+    if requested.alias.is_some() {
+        *alias = requested.alias;
+    }
+    Ok(uploads)
+}
 
-                        FileData {
-                            filename: filename.clone(),
-                            content_type: content_type.clone(),
-                            data: Some(data),
-                            file_path: None,
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to read file into memory: {:?}", e);
-                        deallocate_memory(file_size);
-                        FileData {
-                            filename: filename.clone(),
-                            content_type: content_type.clone(),
-                            data: None,
-                            file_path: Some(file_path),
-                        }
-                    }
+// Fresh codes tried before giving up on an upload
+const SHORT_CODE_ATTEMPTS: usize = 5;
+
+/// Point a short code at a file unless it is taken - database first, fallback
+/// to memory. Returns false if it was taken, leaving the existing link alone.
+pub(crate) async fn claim_short_code(app_state: &AppState, short_code: &str, file_id: Uuid) -> Result<bool, StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.store_short_url(short_code, file_id).await {
+            Ok(stored) => {
+                if stored {
+                    info!("Stored short URL in database: {}", short_code);
+                    app_state.negative_cache.remove(short_code);
                 }
-            } else {
-                info!(
-                    "Keeping file '{}' on disk (size: {})",
-                    filename,
-                    format_size(file_size)
-                );
-                FileData {
-                    filename: filename.clone(),
-                    content_type: content_type.clone(),
-                    data: None,
-                    file_path: Some(file_path),
+                return Ok(stored);
+            }
+            Err(e) => {
+                warn!("Failed to store short URL in database, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    match app_state.short_url_storage.entry(short_code.to_string()) {
+        Entry::Vacant(entry) => {
+            entry.insert(file_id);
+            info!("Stored short URL in memory: {}", short_code);
+            app_state.negative_cache.remove(short_code);
+            Ok(true)
+        }
+        Entry::Occupied(_) => Ok(false),
+    }
+}
+
+// Generate a short code for a new file, regenerating on the rare collision
+async fn assign_short_code(app_state: &AppState, id: Uuid) -> Result<String, StatusCode> {
+    for _ in 0..SHORT_CODE_ATTEMPTS {
+        let short_code = app_state.ids.short_code();
+        if claim_short_code(app_state, &short_code, id).await? {
+            return Ok(short_code);
+        }
+        warn!("Short code {} is already taken, generating another", short_code);
+    }
+    error!("No free short code for {} after {} attempts", id, SHORT_CODE_ATTEMPTS);
+    Err(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// What a client is told about a file once it is registered
+struct RegisteredUpload {
+    id: Uuid,
+    short_code: String,
+    delete_token: String,
+    content_hash: String,
+    sha256: String,
+}
+
+// Register a file that has been written to disk: short code, memory pool
+// placement and metadata, database first with in-memory fallback
+async fn register_upload(
+    app_state: &AppState,
+    upload: PendingUpload,
+    options: UploadOptions,
+) -> Result<RegisteredUpload, StatusCode> {
+    let PendingUpload {
+        id,
+        filename,
+        content_type,
+        declared_content_type,
+        detected_content_type,
+        storage_ref,
+        file_size,
+        content_hash,
+        sha256,
+        options: _,
+    } = upload;
+    let detected_content_type = detected_content_type.map(str::to_string);
+    let UploadOptions {
+        expires_at,
+        max_downloads,
+        pinned,
+        embeddable,
+        client_ip,
+        api_key_id,
+    } = options;
+
+    let delete_token = generate_delete_token();
+    let created_at = Utc::now();
+    info!("Generated file ID: {}", id);
+
+    info!(
+        "File size: {}, content_type: {}",
+        format_size(file_size),
+        content_type
+    );
+
+    // Decide whether to keep in memory or on disk based on size and memory availability
+    let storage_ref = app_state
+        .storage
+        .settle(id, storage_ref, file_size, app_state.config.stream_threshold)
+        .await;
+    let (storage_ref, shared) = match storage_ref {
+        StorageRef::Disk(file_path) => {
+            let (file_path, shared) = share_identical_contents(app_state, &sha256, file_size, file_path).await;
+            (StorageRef::Disk(file_path), shared)
+        }
+        storage_ref => (storage_ref, false),
+    };
+    // Shared contents may predate encryption being turned on
+    let encrypted = app_state.storage.is_encrypted(&storage_ref).await;
+
+    // Store file mapping and short code - try database first, fallback to memory
+    let recorded_path = storage_ref
+        .file_path()
+        .map(|path| storage::recorded_path(&app_state.config.temp_directory, path));
+    let mapping = NewFileMapping {
+        id,
+        filename: &filename,
+        content_type: &content_type,
+        declared_content_type: declared_content_type.as_deref(),
+        detected_content_type: detected_content_type.as_deref(),
+        file_path: recorded_path.as_ref(),
+        file_size: file_size as i64,
+        is_in_memory: storage_ref.is_in_memory(),
+        expires_at,
+        delete_token: &delete_token,
+        max_downloads,
+        content_hash: &content_hash,
+        sha256: Some(&sha256),
+        created_at,
+        access_count: 0,
+        uploader_ip: client_ip,
+        encrypted,
+        pinned,
+        api_key_id,
+        embeddable,
+    };
+    let stored = match store_upload_in_database(app_state, &mapping).await {
+        Ok(Some(short_code)) => Ok(short_code),
+        Ok(None) => store_upload_in_memory(
+            app_state,
+            id,
+            FileData {
+                filename: filename.clone(),
+                content_type: content_type.clone(),
+                declared_content_type: declared_content_type.clone(),
+                detected_content_type: detected_content_type.clone(),
+                storage: Some(storage_ref.clone()),
+                size: file_size as u64,
+                delete_token: delete_token.clone(),
+                expires_at,
+                max_downloads,
+                download_count: 0,
+                purged_at: None,
+                content_hash: content_hash.clone(),
+                sha256: Some(sha256.clone()),
+                created_at,
+                uploader_ip: client_ip,
+                encrypted,
+                pinned,
+                api_key_id,
+                embeddable,
+            },
+        )
+        .await,
+        Err(status) => Err(status),
+    };
+    let short_code = match stored {
+        Ok(short_code) => short_code,
+        Err(status) => {
+            // Nothing refers to the contents; give back the reference taken on them
+            if !contents_still_shared(app_state, &storage_ref).await
+                && let Err(e) = app_state.storage.delete(&storage_ref).await
+            {
+                warn!("Failed to remove unregistered upload {:?}: {:?}", storage_ref, e);
+            }
+            return Err(status);
+        }
+    };
+    info!("Stored file '{}' with ID: {}, short code: {}", filename, id, short_code);
+    app_state.negative_cache.remove(&id.to_string());
+    app_state.negative_cache.remove(&short_code);
+
+    // Shared contents keep the sidecar of the upload that first stored them
+    if let (StorageRef::Disk(file_path), false) = (&storage_ref, shared) {
+        let sidecar = recovery::FileSidecar {
+            short_code: short_code.clone(),
+            filename: filename.clone(),
+            content_type: content_type.clone(),
+            declared_content_type: declared_content_type.clone(),
+            detected_content_type: detected_content_type.clone(),
+            delete_token: delete_token.clone(),
+            expires_at,
+            max_downloads,
+            content_hash: content_hash.clone(),
+            sha256: Some(sha256.clone()),
+            created_at,
+            uploader_ip: client_ip,
+            pinned,
+            api_key_id,
+            embeddable,
+        };
+        if let Err(e) = recovery::write_sidecar(file_path, &sidecar).await {
+            warn!("Failed to write sidecar for {}, file won't survive a restart: {:?}", id, e);
+        }
+    }
+
+    // A key's quota counts its stored files, so there is nothing to charge
+    if let (Some(client_ip), None) = (client_ip, api_key_id) {
+        quota::charge_quota(app_state, client_ip, file_size as u64).await;
+    }
+
+    Ok(RegisteredUpload {
+        id,
+        short_code,
+        delete_token,
+        content_hash,
+        sha256,
+    })
+}
+
+// Store a new file's mapping together with a fresh short code, regenerating
+// the code on the rare collision. None when the upload should go to the
+// in-memory fallback because the database is absent, down or just failed.
+async fn store_upload_in_database(app_state: &AppState, mapping: &NewFileMapping<'_>) -> Result<Option<String>, StatusCode> {
+    let Some(ref db) = app_state.database else {
+        return Ok(None);
+    };
+    if !app_state.database_available() {
+        return Ok(None);
+    }
+
+    for _ in 0..SHORT_CODE_ATTEMPTS {
+        let short_code = app_state.ids.short_code();
+        match db.store_upload(mapping, &short_code).await {
+            Ok(true) => {
+                info!("Stored file mapping in database: {}", mapping.id);
+                return Ok(Some(short_code));
+            }
+            Ok(false) => warn!("Short code {} is already taken, generating another", short_code),
+            Err(e) => {
+                warn!("Failed to store upload in database, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+                return Ok(None);
+            }
+        }
+    }
+    error!("No free short code for {} after {} attempts", mapping.id, SHORT_CODE_ATTEMPTS);
+    Err(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+// Store a new file and a fresh short code in the in-memory fallback
+async fn store_upload_in_memory(app_state: &AppState, id: Uuid, file_data: FileData) -> Result<String, StatusCode> {
+    let short_code = assign_short_code(app_state, id).await?;
+    app_state.file_storage.insert(id, file_data);
+    Ok(short_code)
+}
+
+// Take a reference on stored contents identical to a new upload's, so that
+// duplicates share one copy on disk. Returns where the contents live and
+// whether they were already there, in which case the new copy is removed.
+// Needs the database; without it every upload keeps its own copy.
+async fn share_identical_contents(
+    app_state: &AppState,
+    sha256: &str,
+    file_size: usize,
+    file_path: PathBuf,
+) -> (PathBuf, bool) {
+    let Some(ref db) = app_state.database else {
+        return (file_path, false);
+    };
+    if !app_state.database_available() {
+        return (file_path, false);
+    }
+
+    let temp_directory = &app_state.config.temp_directory;
+    let path = storage::recorded_path(temp_directory, &file_path).to_string_lossy().into_owned();
+    match db.claim_blob(sha256, file_size as i64, &path).await {
+        Ok(stored_path) if stored_path != path => {
+            info!("Upload matches stored file {}, sharing its contents", stored_path);
+            if let Err(e) = app_state.storage.delete(&StorageRef::Disk(file_path)).await {
+                warn!("Failed to remove duplicate upload: {:?}", e);
+            }
+            (storage::resolve_recorded_path(temp_directory, &stored_path), true)
+        }
+        Ok(_) => (file_path, false),
+        Err(e) => {
+            warn!("Failed to look up identical uploads, keeping a separate copy: {}", e);
+            app_state.set_database_healthy(false);
+            (file_path, false)
+        }
+    }
+}
+
+// Register each received file and build the per-file responses
+async fn register_uploads(
+    app_state: &AppState,
+    pending: Vec<PendingUpload>,
+    options: UploadOptions,
+    base_url: &str,
+) -> Result<Vec<UploadResponse>, axum::response::Response> {
+    let mut responses = Vec::with_capacity(pending.len());
+    let mut remaining = pending.into_iter();
+    let mut failure = None;
+
+    for upload in remaining.by_ref() {
+        let filename = upload.filename.clone();
+        let size = upload.file_size;
+        let options = upload.options.unwrap_or(options).with_retention(&app_state.config);
+        let registered = match register_upload(app_state, upload, options).await {
+            Ok(registered) => registered,
+            Err(status) => {
+                failure = Some((status, filename));
+                break;
+            }
+        };
+
+        responses.push(UploadResponse {
+            id: registered.id.to_string(),
+            pretty_url: pretty_url(base_url, &registered.short_code, &filename),
+            filename,
+            size,
+            short_url: format!("{}/drop/{}", base_url, registered.short_code),
+            full_url: format!("{}/drop/{}", base_url, registered.id),
+            delete_token: registered.delete_token,
+            hash: registered.content_hash,
+            sha256: registered.sha256,
+            expires_at: options.expires_at,
+            max_downloads: options.max_downloads,
+        });
+    }
+
+    if let Some((status, filename)) = failure {
+        // Don't leave the files we never got to register behind on disk
+        discard_pending_uploads(&app_state.storage, &remaining.collect::<Vec<_>>()).await;
+        let body = UploadFailedResponse {
+            error: "Failed to store the upload".to_string(),
+            filename,
+        };
+        return Err((status, Json(body)).into_response());
+    }
+
+    Ok(responses)
+}
+
+// Pinning is for operators: an upload asking for it needs the admin token
+pub(crate) fn check_pin_allowed(app_state: &AppState, headers: &HeaderMap, pinned: bool) -> Result<(), StatusCode> {
+    if pinned && admin::authorize(app_state, headers).is_err() {
+        warn!("Rejecting pinned upload without the admin token");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+async fn process_multipart_upload(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    multipart: &mut Multipart,
+    options: &mut UploadOptions,
+    mut alias: Option<String>,
+    quota_owner: &QuotaOwner,
+    remaining_quota: Option<u64>,
+) -> Result<Vec<UploadResponse>, axum::response::Response> {
+    // Process the multipart form data
+    let pending = receive_multipart_files(app_state, multipart, options, &mut alias, quota_owner, remaining_quota).await?;
+    if pending.is_empty() {
+        warn!("No files found in multipart request");
+        let body = MissingFileResponse {
+            error: "No file part found".to_string(),
+        };
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response());
+    }
+
+    info!("Received {} file(s) in upload request", pending.len());
+    // Only now is it known how many files count against a key's quota
+    if let (QuotaOwner::ApiKey(api_key), true) = (quota_owner, pending.len() > 1)
+        && let Err(response) = api_keys::check_quota(app_state, api_key, 0, pending.len()).await
+    {
+        discard_pending_uploads(&app_state.storage, &pending).await;
+        return Err(response);
+    }
+    let pinned = pending.iter().any(|upload| upload.options.unwrap_or(*options).pinned);
+    if let Err(status) = check_pin_allowed(app_state, headers, pinned) {
+        discard_pending_uploads(&app_state.storage, &pending).await;
+        return Err(status.into_response());
+    }
+    if let Some(ref alias) = alias {
+        // An alias names one file; the form field is only checked once it has been read
+        let checked = if pending.len() == 1 {
+            aliases::check_alias_available(app_state, alias).await
+        } else {
+            warn!("Rejecting alias '{}' for an upload of {} files", alias, pending.len());
+            Err(StatusCode::BAD_REQUEST.into_response())
+        };
+        if let Err(response) = checked {
+            discard_pending_uploads(&app_state.storage, &pending).await;
+            return Err(response);
+        }
+    }
+    enforce_upload_policy(app_state, &pending).await?;
+
+    let base_url = public_base_url(&app_state.config, headers);
+    let mut files = register_uploads(app_state, pending, *options, &base_url).await?;
+    if let Some(ref alias) = alias {
+        aliases::attach_alias(app_state, alias, &mut files[0], &base_url).await?;
+    }
+    Ok(files)
+}
+
+#[utoipa::path(
+    post,
+    path = "/drop",
+    tag = "files",
+    params(UploadParams, ("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe: a repeat gets the first response back"), ("Authorization" = Option<String>, Header, description = "`Bearer <key>` to upload with an API key")),
+    request_body(content = openapi::UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "A repeated Idempotency-Key: the first upload's response, with Idempotent-Replayed: true", body = UploadBatchResponse),
+        (status = 201, description = "Every file was stored; Location points at the first", body = UploadBatchResponse),
+        (status = 400, description = "An invalid parameter or malformed body", body = openapi::ErrorResponse),
+        (status = 401, description = "An unknown or revoked API key, or none when anonymous uploads are off", body = openapi::ErrorResponse),
+        (status = 409, description = "The alias is already taken, or an upload with the same Idempotency-Key is still running", body = aliases::AliasErrorResponse),
+        (status = 413, description = "A file or the request is over the size limit, or has too many files or parts", body = MultipartLimitResponse),
+        (status = 415, description = "A file's type or extension is refused", body = UploadRejectedResponse),
+        (status = 422, description = "No file part, too many form fields or one too long, or invalid upload options (an InvalidOptionsResponse)", body = MultipartLimitResponse),
+        (status = 429, description = "Rate limit, daily quota or API key quota exceeded", body = quota::QuotaExceededResponse),
+        (status = 503, description = "No upload slot came free in time", body = openapi::ErrorResponse),
+        (status = 507, description = "Not enough disk space", body = InsufficientStorageResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, multipart, request_id), fields(client_ip, request_id = %request_id))]
+pub async fn upload_file(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(request_id): Extension<RequestId>,
+    Query(params): Query<UploadParams>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<(Option<RateLimitStatus>, axum::response::Response), axum::response::Response> {
+    info!("Starting file upload");
+
+    // Rate limiting, scaled for the API key if one is sent
+    let client_ip = get_client_ip(&app_state.config, addr, &headers);
+    let api_key = api_keys::upload_key(&app_state, &headers).await?;
+    let rate_limit = check_key_rate_limit(client_ip, api_key.as_ref(), RateLimitAction::Upload, &app_state).await?;
+
+    let mut options = UploadOptions::from_params(&params).map_err(IntoResponse::into_response)?;
+    let quota_owner = QuotaOwner::new(client_ip, api_key);
+    options.client_ip = Some(client_ip);
+    options.api_key_id = quota_owner.api_key_id();
+    // A retry of an upload already made gets its response back instead
+    let idempotency = idempotency::claim_key(&app_state, &quota_owner, &headers, format).await?;
+    if let Some(ref alias) = params.alias {
+        aliases::validate_alias(alias).map_err(IntoResponse::into_response)?;
+    }
+
+    let declared_size = declared_content_length(&headers).unwrap_or_default() as u64;
+    check_disk_space(&app_state, declared_size).map_err(IntoResponse::into_response)?;
+    let remaining_quota = quota::check_upload_quota(&app_state, &quota_owner, declared_size, 1).await?;
+
+    // Held until the body is consumed, however that ends
+    let slot = concurrency::acquire_upload_slot(&app_state).await?;
+    let result = process_multipart_upload(
+        &app_state,
+        &headers,
+        &mut multipart,
+        &mut options,
+        params.alias,
+        &quota_owner,
+        remaining_quota,
+    )
+    .await;
+    drop(slot);
+    // A failed upload drops the claim, releasing the key for a retry
+    let result = match (idempotency, result) {
+        (Some(claim), Ok(files)) => {
+            claim.complete(&files).await;
+            Ok(files)
+        }
+        (_, result) => result,
+    };
+
+    // Return the ID and short URL of every stored file, pointing at the first
+    result.map(|files| {
+        let location = files.first().map(|file| format!("/drop/{}", file.id));
+        (rate_limit, upload_created(format, location, files))
+    })
+}
+
+// 201 Created with `Location` set, keeping the usual upload body
+fn upload_created(format: ResponseFormat, location: Option<String>, files: Vec<UploadResponse>) -> axum::response::Response {
+    let mut response = format.respond(UploadBatchResponse { files });
+    *response.status_mut() = StatusCode::CREATED;
+    if let Some(value) = location.and_then(|location| header::HeaderValue::from_str(&location).ok()) {
+        response.headers_mut().insert(header::LOCATION, value);
+    }
+    response
+}
+
+// Stream a raw request body (PUT) to disk and register it like a multipart file
+async fn process_raw_upload(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    filename: &str,
+    body: Body,
+    options: UploadOptions,
+    quota_owner: &QuotaOwner,
+    alias: Option<&str>,
+) -> Result<Vec<UploadResponse>, axum::response::Response> {
+    let max_size = app_state
+        .config
+        .max_file_size_limit
+        .min(app_state.config.max_total_size_per_request);
+
+    // Reject early when the client announces a body that is too large
+    let declared_size = declared_content_length(headers);
+    if declared_size.is_some_and(|size| size > max_size) {
+        warn!("Rejecting raw upload: declared size exceeds limit of {}", format_size(max_size));
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    }
+    let declared_size = declared_size.unwrap_or_default() as u64;
+    check_disk_space(app_state, declared_size).map_err(IntoResponse::into_response)?;
+    let quota_left = quota::check_upload_quota(app_state, quota_owner, declared_size, 1).await?;
+
+    let filename = sanitize_filename(filename);
+    let declared_content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(str::to_string);
+    info!(
+        "Processing raw upload: {} ({})",
+        filename,
+        declared_content_type.as_deref().unwrap_or("no content type")
+    );
+
+    // Size limits are enforced while streaming, never by buffering the body
+    let id = app_state.ids.uuid();
+    let limit = quota::cap_upload_size(max_size, quota_left);
+    let streamed = store_upload_stream(app_state, id, body.into_data_stream(), limit)
+        .await
+        .map_err(|status| quota::upload_stream_error(app_state, status, quota_owner, quota_left, max_size))?;
+
+    let pending = vec![PendingUpload::new(id, filename, declared_content_type, streamed)];
+    enforce_upload_policy(app_state, &pending).await?;
+
+    let base_url = public_base_url(&app_state.config, headers);
+    let mut files = register_uploads(app_state, pending, options, &base_url).await?;
+    if let Some(alias) = alias {
+        aliases::attach_alias(app_state, alias, &mut files[0], &base_url).await?;
+    }
+    Ok(files)
+}
+
+/// What the raw upload handlers take from a request besides its body.
+pub struct RawUploadRequest {
+    addr: SocketAddr,
+    params: UploadParams,
+    format: ResponseFormat,
+    headers: HeaderMap,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for RawUploadRequest {
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(addr) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let Query(params) = Query::<UploadParams>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self {
+            addr,
+            params,
+            format: ResponseFormat::from_headers(&parts.headers),
+            headers: parts.headers.clone(),
+        })
+    }
+}
+
+async fn handle_raw_upload(
+    app_state: AppState,
+    request: RawUploadRequest,
+    filename: &str,
+    body: Body,
+) -> Result<(Option<RateLimitStatus>, axum::response::Response), axum::response::Response> {
+    info!("Starting raw file upload");
+    let RawUploadRequest {
+        addr,
+        params,
+        format,
+        headers,
+    } = request;
+
+    // Rate limiting, scaled for the API key if one is sent
+    let client_ip = get_client_ip(&app_state.config, addr, &headers);
+    let api_key = api_keys::upload_key(&app_state, &headers).await?;
+    let rate_limit = check_key_rate_limit(client_ip, api_key.as_ref(), RateLimitAction::Upload, &app_state).await?;
+
+    let mut options = UploadOptions::from_params(&params).map_err(IntoResponse::into_response)?;
+    let quota_owner = QuotaOwner::new(client_ip, api_key);
+    options.client_ip = Some(client_ip);
+    options.api_key_id = quota_owner.api_key_id();
+    check_pin_allowed(&app_state, &headers, options.pinned).map_err(IntoResponse::into_response)?;
+    // Before the alias check, which a replay's own alias would fail
+    let idempotency = idempotency::claim_key(&app_state, &quota_owner, &headers, format).await?;
+    if let Some(ref alias) = params.alias {
+        aliases::validate_alias(alias).map_err(IntoResponse::into_response)?;
+        aliases::check_alias_available(&app_state, alias).await?;
+    }
+
+    // Held until the body is consumed, however that ends
+    let slot = concurrency::acquire_upload_slot(&app_state).await?;
+    let result = process_raw_upload(&app_state, &headers, filename, body, options, &quota_owner, params.alias.as_deref()).await;
+    drop(slot);
+    let result = match (idempotency, result) {
+        (Some(claim), Ok(files)) => {
+            claim.complete(&files).await;
+            Ok(files)
+        }
+        (_, result) => result,
+    };
+
+    // Point at the canonical download URL
+    result.map(|files| {
+        let location = files.first().map(|file| file.full_url.clone());
+        (rate_limit, upload_created(format, location, files))
+    })
+}
+
+// PUT /drop/{filename} - curl-friendly upload of the raw request body
+#[utoipa::path(
+    put,
+    path = "/drop/{filename}",
+    tag = "files",
+    params(("filename" = String, Path, description = "Name to store the file under"), UploadParams, ("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe: a repeat gets the first response back"), ("Authorization" = Option<String>, Header, description = "`Bearer <key>` to upload with an API key")),
+    request_body(content = String, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "A repeated Idempotency-Key: the first upload's response, with Idempotent-Replayed: true", body = UploadBatchResponse),
+        (status = 201, description = "The file was stored; Location is its download URL", body = UploadBatchResponse),
+        (status = 400, description = "An invalid parameter", body = openapi::ErrorResponse),
+        (status = 401, description = "An unknown or revoked API key, or none when anonymous uploads are off", body = openapi::ErrorResponse),
+        (status = 409, description = "An upload with the same Idempotency-Key is still running", body = openapi::ErrorResponse),
+        (status = 413, description = "The body is over the size limit", body = openapi::ErrorResponse),
+        (status = 415, description = "The file's type or extension is refused", body = UploadRejectedResponse),
+        (status = 429, description = "Rate limit, daily quota or API key quota exceeded", body = quota::QuotaExceededResponse),
+        (status = 503, description = "No upload slot came free in time", body = openapi::ErrorResponse),
+        (status = 507, description = "Not enough disk space", body = InsufficientStorageResponse),
+    ),
+)]
+#[instrument(skip(app_state, request, body, request_id), fields(client_ip, request_id = %request_id, params = ?request.params))]
+pub async fn upload_raw_named(
+    Path(filename): Path<String>,
+    State(app_state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    request: RawUploadRequest,
+    body: Body,
+) -> Result<(Option<RateLimitStatus>, axum::response::Response), axum::response::Response> {
+    handle_raw_upload(app_state, request, &filename, body).await
+}
+
+// PUT /drop - raw body upload without a filename
+#[utoipa::path(
+    put,
+    path = "/drop",
+    tag = "files",
+    params(UploadParams, ("Idempotency-Key" = Option<String>, Header, description = "Makes retries safe: a repeat gets the first response back"), ("Authorization" = Option<String>, Header, description = "`Bearer <key>` to upload with an API key")),
+    request_body(content = String, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "A repeated Idempotency-Key: the first upload's response, with Idempotent-Replayed: true", body = UploadBatchResponse),
+        (status = 201, description = "The file was stored; Location is its download URL", body = UploadBatchResponse),
+        (status = 400, description = "An invalid parameter", body = openapi::ErrorResponse),
+        (status = 401, description = "An unknown or revoked API key, or none when anonymous uploads are off", body = openapi::ErrorResponse),
+        (status = 409, description = "An upload with the same Idempotency-Key is still running", body = openapi::ErrorResponse),
+        (status = 413, description = "The body is over the size limit", body = openapi::ErrorResponse),
+        (status = 415, description = "The file's type or extension is refused", body = UploadRejectedResponse),
+        (status = 429, description = "Rate limit, daily quota or API key quota exceeded", body = quota::QuotaExceededResponse),
+        (status = 503, description = "No upload slot came free in time", body = openapi::ErrorResponse),
+        (status = 507, description = "Not enough disk space", body = InsufficientStorageResponse),
+    ),
+)]
+#[instrument(skip(app_state, request, body, request_id), fields(client_ip, request_id = %request_id, params = ?request.params))]
+pub async fn upload_raw(
+    State(app_state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    request: RawUploadRequest,
+    body: Body,
+) -> Result<(Option<RateLimitStatus>, axum::response::Response), axum::response::Response> {
+    handle_raw_upload(app_state, request, "unknown", body).await
+}
+
+// What response headers need to know about a stored file
+struct FileMeta {
+    content_type: String,
+    filename: String,
+    etag: Option<String>,
+    last_modified: DateTime<Utc>,
+    inline: bool, // Content-Disposition: inline instead of attachment
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadParams {
+    disposition: Option<String>,
+    filename: Option<String>,
+}
+
+impl DownloadParams {
+    // Name the download after a trailing path segment, unless the query names
+    // it. A segment with nothing left after sanitizing keeps the stored name.
+    fn with_path_filename(mut self, segment: &str) -> Self {
+        if self.filename.is_none() {
+            self.filename = try_sanitize_filename(segment);
+        }
+        self
+    }
+}
+
+// Content types that are safe to render in the browser. Anything that can run
+// script (HTML, SVG, XML, JavaScript) is always served as an attachment.
+const INLINE_SAFE_CONTENT_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/bmp",
+    "application/pdf",
+    "text/plain",
+];
+
+fn is_inline_safe(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    INLINE_SAFE_CONTENT_TYPES.contains(&essence.as_str())
+}
+
+impl FileMeta {
+    fn from_mapping(file_mapping: &database::FileMapping) -> Self {
+        Self {
+            content_type: file_mapping.content_type.clone(),
+            filename: file_mapping.filename.clone(),
+            etag: file_mapping.content_hash.as_ref().map(|hash| format!("\"{}\"", hash)),
+            last_modified: file_mapping.created_at,
+            inline: false,
+        }
+    }
+
+    fn from_file_data(file_data: &FileData) -> Self {
+        Self {
+            content_type: file_data.content_type.clone(),
+            filename: file_data.filename.clone(),
+            etag: Some(format!("\"{}\"", file_data.content_hash)),
+            last_modified: file_data.created_at,
+            inline: false,
+        }
+    }
+
+    // Apply `?disposition=` and `?filename=` overrides; inline is only granted
+    // for allowlisted content types, everything else stays an attachment
+    fn with_params(mut self, params: &DownloadParams) -> Self {
+        if let Some(filename) = params.filename.as_deref().filter(|f| !f.trim().is_empty()) {
+            self.filename = sanitize_filename(filename);
+        }
+        let wants_inline = params
+            .disposition
+            .as_deref()
+            .is_some_and(|d| d.eq_ignore_ascii_case("inline"));
+        self.inline = wants_inline && is_inline_safe(&self.content_type);
+        self
+    }
+}
+
+// Expired, purged or out of downloads
+fn mapping_is_gone(file_mapping: &database::FileMapping) -> bool {
+    let exhausted = file_mapping
+        .max_downloads
+        .is_some_and(|max| file_mapping.access_count >= max);
+    let expired = !file_mapping.pinned && is_expired(file_mapping.expires_at);
+    file_mapping.purged_at.is_some() || expired || exhausted
+}
+
+fn file_data_is_gone(file_data: &FileData) -> bool {
+    let exhausted = file_data
+        .max_downloads
+        .is_some_and(|max| file_data.download_count >= max);
+    let expired = !file_data.pinned && is_expired(file_data.expires_at);
+    file_data.purged_at.is_some() || expired || exhausted
+}
+
+fn format_http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+// Response headers describing a stored file. Every download, HEAD and range
+// response builds its headers here so Content-Length stays consistent.
+fn download_headers(meta: &FileMeta, content_length: Option<u64>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = header::HeaderValue::from_str(&meta.content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    let disposition = if meta.inline { "inline" } else { "attachment" };
+    if let Ok(value) = header::HeaderValue::from_str(&content_disposition(disposition, &meta.filename)) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    // User content: keep browsers from sniffing it into something executable,
+    // and from running any script in it (an HTML or SVG upload) in our origin
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, header::HeaderValue::from_static("nosniff"));
+    headers.insert(header::CONTENT_SECURITY_POLICY, header::HeaderValue::from_static("sandbox"));
+    headers.insert(header::ACCEPT_RANGES, header::HeaderValue::from_static("bytes"));
+    if let Some(ref etag) = meta.etag
+        && let Ok(value) = header::HeaderValue::from_str(etag)
+    {
+        headers.insert(header::ETAG, value);
+    }
+    if let Ok(value) = header::HeaderValue::from_str(&format_http_date(meta.last_modified)) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+    if let Some(length) = content_length {
+        headers.insert(header::CONTENT_LENGTH, header::HeaderValue::from(length));
+    }
+    headers
+}
+
+// Weak comparison of an If-None-Match list against our ETag
+fn etag_matches(header_value: &str, etag: &str) -> bool {
+    header_value
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+fn has_conditional_headers(request_headers: &HeaderMap) -> bool {
+    request_headers.contains_key(header::IF_NONE_MATCH)
+        || request_headers.contains_key(header::IF_MODIFIED_SINCE)
+}
+
+// True when the client's cached copy is still current and a 304 should be sent.
+// If-None-Match takes precedence over If-Modified-Since.
+fn is_not_modified(request_headers: &HeaderMap, meta: &FileMeta) -> bool {
+    if let Some(if_none_match) = request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        return meta
+            .etag
+            .as_deref()
+            .is_some_and(|etag| etag_matches(if_none_match, etag));
+    }
+
+    request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date)
+        .is_some_and(|since| meta.last_modified.timestamp() <= since.timestamp())
+}
+
+fn not_modified_response(meta: &FileMeta) -> axum::response::Response {
+    let mut headers = HeaderMap::new();
+    if let Some(value) = meta
+        .etag
+        .as_deref()
+        .and_then(|etag| header::HeaderValue::from_str(etag).ok())
+    {
+        headers.insert(header::ETAG, value);
+    }
+    if let Ok(value) = header::HeaderValue::from_str(&format_http_date(meta.last_modified)) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+    (StatusCode::NOT_MODIFIED, headers).into_response()
+}
+
+// Byte range selected by the Range/If-Range request headers
+enum ByteRange {
+    Full,
+    Partial { start: u64, end: u64 }, // Inclusive
+    Unsatisfiable,
+}
+
+// Only a single `bytes=` range is supported; anything else serves the whole file
+fn requested_range(request_headers: &HeaderMap, meta: &FileMeta, len: u64) -> ByteRange {
+    let Some(range) = request_headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return ByteRange::Full;
+    };
+
+    // If-Range: only honor the range when the client has the current version
+    if let Some(if_range) = request_headers
+        .get(header::IF_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+    {
+        let current = if if_range.starts_with('"') {
+            meta.etag.as_deref() == Some(if_range)
+        } else {
+            parse_http_date(if_range).is_some_and(|date| date.timestamp() == meta.last_modified.timestamp())
+        };
+        if !current {
+            return ByteRange::Full;
+        }
+    }
+
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            _ => return ByteRange::Full,
+        },
+    };
+
+    if len == 0 || start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial { start, end }
+    }
+}
+
+fn range_not_satisfiable_response(len: u64) -> axum::response::Response {
+    let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+    if let Ok(value) = header::HeaderValue::from_str(&format!("bytes */{}", len)) {
+        response.headers_mut().insert(header::CONTENT_RANGE, value);
+    }
+    response
+}
+
+fn partial_content_headers(meta: &FileMeta, start: u64, end: u64, len: u64) -> HeaderMap {
+    let mut headers = download_headers(meta, Some(end - start + 1));
+    if let Ok(value) = header::HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len)) {
+        headers.insert(header::CONTENT_RANGE, value);
+    }
+    headers
+}
+
+// Headers for a whole-file response (GET or HEAD), with the encoding the
+// body is compressed with, if any
+fn full_download_headers(
+    config: &Config,
+    request_headers: &HeaderMap,
+    meta: &FileMeta,
+    len: Option<u64>,
+) -> (HeaderMap, Option<ContentEncoding>) {
+    let encoding = len.and_then(|len| compression::negotiate(config, request_headers, &meta.content_type, len));
+    let mut headers = download_headers(meta, len);
+    compression::annotate(config, &mut headers, &meta.content_type, encoding);
+    (headers, encoding)
+}
+
+// Build the download response for a file, honoring Range/If-Range
+async fn serve_file(
+    config: &Config,
+    request_headers: &HeaderMap,
+    meta: &FileMeta,
+    object: StoredObject,
+) -> axum::response::Response {
+    let StoredObject { mut reader, len, contents } = object;
+    info!("Serving file '{}', size: {} bytes", meta.filename, len);
+
+    // Stream the contents for better memory efficiency with large files
+    match requested_range(request_headers, meta, len) {
+        ByteRange::Full => {
+            // Explicit length so clients can show progress instead of chunked encoding,
+            // unless the body is compressed on the way out
+            let (headers, encoding) = full_download_headers(config, request_headers, meta, Some(len));
+            let body = match (encoding, contents) {
+                (Some(encoding), _) => compression::encode(encoding, reader),
+                // In-memory files are sent from the stored buffer itself, not a copy
+                (None, Some(contents)) => Body::from(contents),
+                (None, None) => Body::from_stream(ReaderStream::with_capacity(reader, config.io_buffer_size)),
+            };
+            (headers, body).into_response()
+        }
+        ByteRange::Partial { start, end } => {
+            let body = match contents {
+                Some(contents) => Body::from(contents.slice(start as usize..=end as usize)),
+                None => {
+                    if let Err(e) = reader.seek(std::io::SeekFrom::Start(start)).await {
+                        error!("Failed to seek in stored file: {:?}", e);
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                    Body::from_stream(ReaderStream::with_capacity(
+                        reader.take(end - start + 1),
+                        config.io_buffer_size,
+                    ))
                 }
             };
+            (
+                StatusCode::PARTIAL_CONTENT,
+                partial_content_headers(meta, start, end, len),
+                body,
+            )
+                .into_response()
+        }
+        ByteRange::Unsatisfiable => range_not_satisfiable_response(len),
+    }
+}
 
-        // Store file mapping - try database first, fallback to memory
-        let file_stored = if let Some(ref db) = app_state.database {
-            if app_state.database_healthy.load(std::sync::atomic::Ordering::Relaxed) {
-                let is_in_memory = file_data.data.is_some();
-                let file_path_for_db = if is_in_memory { None } else { file_data.file_path.as_ref() };
-                
-                match db.store_file_mapping(
-                    id,
-                    &filename,
-                    &content_type,
-                    file_path_for_db,
-                    file_size as i64,
-                    is_in_memory,
-                    None, // No expiration for now
-                ).await {
-                    Ok(_) => {
-                        info!("Stored file mapping in database: {}", id);
-                        true
-                    }
-                    Err(e) => {
-                        warn!("Failed to store file mapping in database, falling back to memory: {}", e);
-                        app_state.database_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
-                        false
-                    }
+// HEAD /drop/{id}/{filename} - headers of the download under that name
+#[utoipa::path(
+    head,
+    path = "/drop/{id}/{filename}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID or short code"),
+        ("filename" = String, Path, description = "Name to save the file under"),
+        DownloadParams,
+    ),
+    responses(
+        (status = 200, description = "Download headers without the body; not counted as a download"),
+        (status = 304, description = "Not modified since the cached copy"),
+        (status = 404, description = "No such file"),
+        (status = 410, description = "Expired or out of downloads"),
+    ),
+)]
+pub async fn head_file_named(
+    Path((id, filename)): Path<(String, String)>,
+    State(app_state): State<AppState>,
+    Query(params): Query<DownloadParams>,
+    request_headers: HeaderMap,
+) -> axum::response::Response {
+    let params = params.with_path_filename(&filename);
+    head_file(Path(id), State(app_state), Query(params), request_headers)
+        .await
+        .into_response()
+}
+
+// HEAD /drop/{id} - same headers as a download, without a body or counting an access
+#[utoipa::path(
+    head,
+    path = "/drop/{id}",
+    tag = "files",
+    params(("id" = String, Path, description = "File ID or short code"), DownloadParams),
+    responses(
+        (status = 200, description = "Download headers without the body; not counted as a download"),
+        (status = 304, description = "Not modified since the cached copy"),
+        (status = 404, description = "No such file"),
+        (status = 410, description = "Expired or out of downloads"),
+    ),
+)]
+#[instrument(skip(app_state, request_headers))]
+pub async fn head_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    Query(params): Query<DownloadParams>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some(uuid) = resolve_id_or_short_code_db(&id, &app_state).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Try database first, using the read-only lookup
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(uuid).await {
+            Ok(Some(file_mapping)) => {
+                if mapping_is_gone(&file_mapping) {
+                    return StatusCode::GONE.into_response();
                 }
-            } else {
-                false
+
+                let meta = FileMeta::from_mapping(&file_mapping).with_params(&params);
+                if is_not_modified(&request_headers, &meta) {
+                    return not_modified_response(&meta);
+                }
+                let len = u64::try_from(file_mapping.file_size).ok();
+                let (headers, _) = full_download_headers(&app_state.config, &request_headers, &meta, len);
+                return (StatusCode::OK, headers).into_response();
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    // Fallback to in-memory storage; copy out just what the headers need
+    let lookup = app_state.file_storage.get(&uuid).map(|file_data| {
+        (
+            file_data_is_gone(&file_data),
+            FileMeta::from_file_data(&file_data).with_params(&params),
+            file_data.storage.is_some().then_some(file_data.size),
+        )
+    });
+
+    let Some((gone, meta, content_length)) = lookup else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if gone {
+        return StatusCode::GONE.into_response();
+    }
+    if is_not_modified(&request_headers, &meta) {
+        return not_modified_response(&meta);
+    }
+
+    let (headers, _) = full_download_headers(&app_state.config, &request_headers, &meta, content_length);
+    (StatusCode::OK, headers).into_response()
+}
+
+// GET /drop/{id}/info - metadata, including declared vs detected content type.
+// Read-only: does not count as a download.
+#[utoipa::path(
+    get,
+    path = "/drop/{id}/info",
+    tag = "files",
+    params(("id" = String, Path, description = "File ID or short code")),
+    responses(
+        (status = 200, description = "File metadata", body = FileInfoResponse),
+        (status = 404, description = "No such file", body = openapi::ErrorResponse),
+        (status = 410, description = "Expired, out of downloads or deleted", body = GoneResponse),
+    ),
+)]
+#[instrument(skip(app_state))]
+pub async fn file_info(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+) -> Result<Json<FileInfoResponse>, axum::response::Response> {
+    let uuid = resolve_id_or_short_code_db(&id, &app_state)
+        .await
+        .ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+
+    // Try database first, using the read-only lookup
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(uuid).await {
+            Ok(Some(file_mapping)) => {
+                if mapping_is_gone(&file_mapping) {
+                    return Err(GoneResponse::from_mapping(&file_mapping).into_response());
+                }
+                return Ok(Json(FileInfoResponse::from_mapping(file_mapping)));
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    // Fallback to in-memory storage
+    let lookup = app_state.file_storage.get(&uuid).map(|file_data| file_data.value().clone());
+
+    let file_data = lookup.ok_or_else(|| StatusCode::NOT_FOUND.into_response())?;
+    if file_data_is_gone(&file_data) {
+        return Err(GoneResponse::from_file_data(&file_data).into_response());
+    }
+
+    Ok(Json(FileInfoResponse::from_file_data(uuid, file_data)))
+}
+
+// GET /drop/by-hash/{sha256} - info for a live file with these contents, so a
+// client can skip uploading something the server already has
+#[utoipa::path(
+    get,
+    path = "/drop/by-hash/{sha256}",
+    tag = "files",
+    params(("sha256" = String, Path, description = "Hex SHA-256 of the contents")),
+    responses(
+        (status = 200, description = "A live file with these contents", body = FileInfoResponse),
+        (status = 400, description = "Not a SHA-256 hex digest", body = openapi::ErrorResponse),
+        (status = 404, description = "No live file has these contents", body = openapi::ErrorResponse),
+        (status = 410, description = "Expired or out of downloads", body = openapi::ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state))]
+pub async fn file_info_by_hash(
+    Path(sha256): Path<String>,
+    State(app_state): State<AppState>,
+) -> Result<Json<FileInfoResponse>, StatusCode> {
+    let sha256 = sha256.to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_by_sha256(&sha256).await {
+            Ok(Some(file_mapping)) => return Ok(Json(FileInfoResponse::from_mapping(file_mapping))),
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database hash lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    // Fallback to in-memory storage: the newest live match
+    let lookup = app_state
+        .file_storage
+        .iter()
+        .filter(|entry| entry.sha256.as_deref() == Some(sha256.as_str()))
+        .filter(|entry| !file_data_is_gone(entry.value()))
+        .max_by_key(|entry| entry.created_at)
+        .map(|entry| (*entry.key(), entry.value().clone()));
+
+    let (uuid, file_data) = lookup.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(FileInfoResponse::from_file_data(uuid, file_data)))
+}
+
+// resolve_id_or_short_code_db for many inputs at once: the short codes the
+// in-process cache doesn't know are looked up in one query. Inputs that
+// resolve to nothing are left out.
+async fn resolve_ids_or_short_codes(inputs: &[String], app_state: &AppState) -> HashMap<String, Uuid> {
+    let mut resolved = HashMap::new();
+    let mut short_codes = Vec::new();
+    for input in inputs.iter().filter(|input| negative_cache::could_be_file_id(input)) {
+        if let Ok(uuid) = input.parse::<Uuid>() {
+            if !app_state.negative_cache.contains(&uuid.to_string()) {
+                resolved.insert(input.clone(), uuid);
+            }
+        } else if let Some(file_id) = cached_short_code(app_state, input) {
+            resolved.insert(input.clone(), file_id);
+        } else if !app_state.negative_cache.contains(input) {
+            short_codes.push(input.as_str());
+        }
+    }
+    short_codes.sort_unstable();
+    short_codes.dedup();
+
+    // Redis is skipped; the one query answers for every code it would have been asked about
+    let mut authoritative = app_state.database.is_none();
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+        && !short_codes.is_empty()
+    {
+        match db.get_file_ids_by_short_codes(&short_codes).await {
+            Ok(found) => {
+                authoritative = true;
+                for (short_code, file_id) in found {
+                    cache_short_code(app_state, &short_code, file_id);
+                    resolved.insert(short_code, file_id);
+                }
+            }
+            Err(e) => {
+                warn!("Database short code lookup failed: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    // Fallback to in-memory storage
+    for short_code in short_codes {
+        if resolved.contains_key(short_code) {
+            continue;
+        }
+        match app_state.short_url_storage.get(short_code).map(|file_id| *file_id) {
+            Some(file_id) => {
+                resolved.insert(short_code.to_string(), file_id);
             }
-        } else {
-            false
-        };
+            None if authoritative => app_state.negative_cache.insert(short_code),
+            None => {}
+        }
+    }
+    resolved
+}
 
-        if !file_stored {
-            // Fallback to in-memory storage
-            if let Ok(mut storage_guard) = app_state.file_storage.lock() {
-                storage_guard.insert(id.to_string(), file_data);
-                info!("Successfully stored file '{}' with ID: {}", filename, id);
-            } else {
-                error!("Failed to acquire lock on file storage during upload");
-                ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+// The entry for one file, from its database row if it has one, else from memory
+fn file_info_entry(app_state: &AppState, uuid: Uuid, file_mapping: Option<&FileMapping>) -> FileInfoEntry {
+    if let Some(file_mapping) = file_mapping {
+        if mapping_is_gone(file_mapping) {
+            return FileInfoEntry::Gone(GoneResponse::from_mapping(file_mapping));
+        }
+        return FileInfoEntry::Found(FileInfoResponse::from_mapping(file_mapping.clone()));
+    }
+
+    let lookup = app_state.file_storage.get(&uuid).map(|file_data| file_data.value().clone());
+    match lookup {
+        Some(file_data) if file_data_is_gone(&file_data) => FileInfoEntry::Gone(GoneResponse::from_file_data(&file_data)),
+        Some(file_data) => FileInfoEntry::Found(FileInfoResponse::from_file_data(uuid, file_data)),
+        None => FileInfoEntry::missing(),
+    }
+}
+
+// POST /drop/info - metadata for many files at once. Read-only, like GET /drop/{id}/info.
+#[utoipa::path(
+    post,
+    path = "/drop/info",
+    tag = "files",
+    request_body = FileInfoBatchRequest,
+    responses(
+        (status = 200, description = "An entry for each ID sent, even those with no file", body = FileInfoBatchResponse),
+        (status = 400, description = "More than 100 IDs", body = openapi::ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, request))]
+pub async fn batch_file_info(
+    State(app_state): State<AppState>,
+    Json(request): Json<FileInfoBatchRequest>,
+) -> Result<Json<FileInfoBatchResponse>, StatusCode> {
+    if request.ids.len() > MAX_INFO_BATCH {
+        warn!("Rejecting info request for {} files (at most {})", request.ids.len(), MAX_INFO_BATCH);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let resolved = resolve_ids_or_short_codes(&request.ids, &app_state).await;
+
+    // Every row in one query, using the read-only lookup
+    let mut file_mappings = HashMap::new();
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        let mut ids = resolved.values().copied().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        match db.find_file_mappings(&ids).await {
+            Ok(found) => file_mappings.extend(found.into_iter().map(|file_mapping| (file_mapping.id, file_mapping))),
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
             }
         }
+    }
 
-        // Decrement active connections
-        ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    let files = request
+        .ids
+        .into_iter()
+        .map(|input| {
+            let entry = match resolved.get(&input) {
+                Some(&uuid) => file_info_entry(&app_state, uuid, file_mappings.get(&uuid)),
+                None => FileInfoEntry::missing(),
+            };
+            (input, entry)
+        })
+        .collect();
+    Ok(Json(FileInfoBatchResponse { files }))
+}
 
-        // Return the ID and short URL
-        return Ok(Json(UploadResponse {
-            id: id.to_string(),
-            short_url: format!(
-                "http://{}/drop/{}",
-                app_state.config.bind_address, short_code
-            ),
-            full_url: format!("http://{}/drop/{}", app_state.config.bind_address, id),
-        }));
+impl FileInfoResponse {
+    fn from_mapping(file_mapping: FileMapping) -> Self {
+        Self {
+            id: file_mapping.id,
+            filename: file_mapping.filename,
+            size: u64::try_from(file_mapping.file_size).unwrap_or_default(),
+            content_type: file_mapping.content_type,
+            declared_content_type: file_mapping.declared_content_type,
+            detected_content_type: file_mapping.detected_content_type,
+            hash: file_mapping.content_hash,
+            sha256: file_mapping.sha256,
+            created_at: file_mapping.created_at,
+            expires_at: file_mapping.expires_at,
+            max_downloads: file_mapping.max_downloads,
+            download_count: file_mapping.access_count,
+            encrypted: file_mapping.encrypted,
+        }
     }
 
-    // Decrement active connections if no files found
-    ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
-    warn!("No files found in multipart request");
-    Err(StatusCode::BAD_REQUEST)
+    // Purged entries report no contents
+    fn from_file_data(id: Uuid, file_data: FileData) -> Self {
+        Self {
+            id,
+            filename: file_data.filename,
+            size: if file_data.storage.is_some() { file_data.size } else { 0 },
+            content_type: file_data.content_type,
+            declared_content_type: file_data.declared_content_type,
+            detected_content_type: file_data.detected_content_type,
+            hash: Some(file_data.content_hash),
+            sha256: file_data.sha256,
+            created_at: file_data.created_at,
+            expires_at: file_data.expires_at,
+            max_downloads: file_data.max_downloads,
+            download_count: file_data.download_count,
+            encrypted: file_data.encrypted,
+        }
+    }
 }
 
-#[instrument(skip(app_state))]
+// Open a database-backed file's bytes wherever they currently live
+async fn open_mapping(app_state: &AppState, file_mapping: &FileMapping) -> Option<StoredObject> {
+    let storage_ref = StorageRef::from_mapping(file_mapping, &app_state.config.temp_directory)?;
+    match app_state.storage.get(&storage_ref).await {
+        Ok(object) => Some(object),
+        Err(e) => {
+            warn!("Failed to open stored file {}: {:?}", file_mapping.id, e);
+            None
+        }
+    }
+}
+
+// Outcome of looking a file up in the in-memory fallback for download
+enum MemoryLookup {
+    Missing,
+    Gone(GoneResponse),
+    NotModified(FileMeta),
+    Serve(Box<FileData>, bool), // Contents and whether this is the final permitted download
+    Hotlinked(hotlink::Hotlink),
+}
+
+#[utoipa::path(
+    get,
+    path = "/drop/{id}",
+    tag = "files",
+    params(("id" = String, Path, description = "File ID or short code"), DownloadParams),
+    responses(
+        (status = 200, description = "The file contents", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range", content_type = "application/octet-stream"),
+        (status = 302, description = "A short code with DROP_SHORT_CODE_MODE=redirect: Location is /drop/{uuid}/{filename}"),
+        (status = 304, description = "Not modified since the cached copy"),
+        (status = 403, description = "Linked from a site not in `DROP_ALLOWED_REFERERS`", body = openapi::ErrorResponse),
+        (status = 404, description = "No such file", body = openapi::ErrorResponse),
+        (status = 410, description = "Expired, out of downloads or deleted", body = GoneResponse),
+        (status = 416, description = "Range not satisfiable", body = openapi::ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = openapi::ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, request_headers, request_id), fields(client_ip, request_id = %request_id))]
 pub async fn download_file(
     Path(id): Path<String>,
     State(app_state): State<AppState>,
-) -> impl IntoResponse {
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(request_id): Extension<RequestId>,
+    Query(params): Query<DownloadParams>,
+    request_headers: HeaderMap,
+) -> axum::response::Response {
+    // Rate limiting
+    let client_ip = get_client_ip(&app_state.config, addr, &request_headers);
+    let rate_limit = match check_rate_limit(client_ip, RateLimitAction::Download, &app_state).await {
+        Ok(rate_limit) => rate_limit,
+        Err(response) => return response,
+    };
+
+    // In redirect mode a short code only points at the file; the download is counted there
+    if let Some(response) = redirect::short_code_redirect(&app_state, &id, &params).await {
+        return (rate_limit, response).into_response();
+    }
+
+    let response = serve_download(&id, &app_state, &params, &request_headers).await;
+    let response = access::track_traffic(&app_state, response);
+    let response = throttle::limit_download(&app_state, client_ip, response);
+    let response = transfer_rate::limit_download_idle(&app_state.config, response);
+    (rate_limit, concurrency::track_download(response)).into_response()
+}
+
+// GET /drop/{id}/{filename} - download under the name in the last segment
+#[utoipa::path(
+    get,
+    path = "/drop/{id}/{filename}",
+    tag = "files",
+    params(
+        ("id" = String, Path, description = "File ID or short code; the file is looked up by this alone"),
+        ("filename" = String, Path, description = "Name to save the file under, unless `filename` is also in the query"),
+        DownloadParams,
+    ),
+    responses(
+        (status = 200, description = "The file contents", content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range", content_type = "application/octet-stream"),
+        (status = 304, description = "Not modified since the cached copy"),
+        (status = 403, description = "Linked from a site not in `DROP_ALLOWED_REFERERS`", body = openapi::ErrorResponse),
+        (status = 404, description = "No such file", body = openapi::ErrorResponse),
+        (status = 410, description = "Expired, out of downloads or deleted", body = GoneResponse),
+        (status = 416, description = "Range not satisfiable", body = openapi::ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = openapi::ErrorResponse),
+    ),
+)]
+pub async fn download_file_named(
+    Path((id, filename)): Path<(String, String)>,
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(request_id): Extension<RequestId>,
+    Query(params): Query<DownloadParams>,
+    request_headers: HeaderMap,
+) -> axum::response::Response {
+    let params = params.with_path_filename(&filename);
+    download_file(
+        Path(id),
+        State(app_state),
+        ConnectInfo(addr),
+        Extension(request_id),
+        Query(params),
+        request_headers,
+    )
+    .await
+}
+
+async fn serve_download(
+    id: &str,
+    app_state: &AppState,
+    params: &DownloadParams,
+    request_headers: &HeaderMap,
+) -> axum::response::Response {
     info!("Attempting to download file with ID: {}", id);
+    // Refused before any bytes go out, unless the file may be embedded anywhere
+    let hotlink = hotlink::check(&app_state.config, request_headers);
+    let refusal = |embeddable: bool| hotlink.clone().filter(|_| !embeddable);
 
     // Resolve short code to full UUID if needed
-    let resolved_id = resolve_id_or_short_code_db(&id, &app_state).await;
+    let resolved_id = resolve_id_or_short_code_db(id, app_state).await;
 
     if let Some(uuid) = resolved_id {
         info!("Resolved ID: {}", uuid);
 
         // Try to get file from database first
         if let Some(ref db) = app_state.database {
-            if app_state.database_healthy.load(std::sync::atomic::Ordering::Relaxed) {
-                match db.get_file_mapping(uuid).await {
+            if app_state.database_available() {
+                // Hot files skip the database round trip; the download is counted with the next batch
+                if let Some(file_mapping) = cached_mapping(app_state, uuid) {
+                    if mapping_is_gone(&file_mapping) {
+                        invalidate_cached_file(app_state, uuid);
+                        info!("File has expired: {}", uuid);
+                        return GoneResponse::from_mapping(&file_mapping).into_response();
+                    }
+                    if let Some(hotlink) = refusal(file_mapping.embeddable) {
+                        return hotlink.into_response();
+                    }
+
+                    let meta = FileMeta::from_mapping(&file_mapping).with_params(params);
+                    if is_not_modified(request_headers, &meta) {
+                        return not_modified_response(&meta);
+                    }
+
+                    if let Some(object) = open_mapping(app_state, &file_mapping).await {
+                        let response = serve_file(&app_state.config, request_headers, &meta, object).await;
+                        if response.status().is_success() {
+                            access::record_access(app_state, uuid);
+                        }
+                        return response;
+                    }
+
+                    // Contents moved or vanished; look it up properly
+                    invalidate_cached_file(app_state, uuid);
+                }
+
+                // Revalidation must not count as a download, so check it read-only first
+                if has_conditional_headers(request_headers)
+                    && let Ok(Some(file_mapping)) = db.find_file_mapping(uuid).await
+                {
+                    let meta = FileMeta::from_mapping(&file_mapping).with_params(params);
+                    if !mapping_is_gone(&file_mapping) && is_not_modified(request_headers, &meta) {
+                        return not_modified_response(&meta);
+                    }
+                }
+
+                // Limited files are counted before they are served; the count decides
+                // whether they may be. Others are counted in batches afterwards.
+                let lookup = match db.get_file_mapping(uuid).await {
+                    Ok(Some(file_mapping)) if file_mapping.corrupt => {
+                        warn!("Refusing to serve corrupt file: {}", uuid);
+                        return scrub::corrupt_file_response();
+                    }
+                    Ok(Some(file_mapping)) => match refusal(file_mapping.embeddable) {
+                        // Before claiming, so a refused request doesn't use up a download
+                        Some(hotlink) => return hotlink.into_response(),
+                        None if file_mapping.max_downloads.is_some() => db.claim_download(uuid).await,
+                        None => Ok(Some(file_mapping)),
+                    },
+                    lookup => lookup,
+                };
+                match lookup {
                     Ok(Some(file_mapping)) => {
-                        let headers = [
-                            (header::CONTENT_TYPE, file_mapping.content_type.clone()),
-                            (
-                                header::CONTENT_DISPOSITION,
-                                format!("attachment; filename=\"{}\"", file_mapping.filename),
-                            ),
-                        ];
-
-                        // Return data based on storage type
-                        if file_mapping.is_in_memory {
-                            // Try to get from in-memory storage
-                            if let Ok(storage_guard) = app_state.file_storage.lock() {
-                                if let Some(file_data) = storage_guard.get(&uuid.to_string()) {
-                                    if let Some(ref data) = file_data.data {
-                                        info!(
-                                            "Successfully serving file '{}' from memory, size: {} bytes",
-                                            file_mapping.filename,
-                                            data.len()
-                                        );
-                                        return (headers, data.clone()).into_response();
-                                    }
-                                }
-                            }
-                            // If not in memory, fall through to file system
+                        if file_mapping.purged_at.is_some() || (!file_mapping.pinned && is_expired(file_mapping.expires_at)) {
+                            info!("File has expired: {}", uuid);
+                            return GoneResponse::from_mapping(&file_mapping).into_response();
                         }
 
-                        // Serve from file system
-                        if let Some(file_path_str) = file_mapping.file_path {
-                            let file_path = PathBuf::from(file_path_str);
-                            match tokio::fs::File::open(&file_path).await {
-                                Ok(file) => {
-                                    let stream = ReaderStream::new(file);
-                                    let body = Body::from_stream(stream);
+                        // This request used up the last permitted download
+                        let final_download = file_mapping
+                            .max_downloads
+                            .is_some_and(|max| file_mapping.access_count >= max);
 
-                                    info!("Streaming file '{}' from disk", file_mapping.filename);
-                                    return (headers, body).into_response();
-                                }
-                                Err(e) => {
-                                    error!("Failed to open file from disk: {:?}", e);
-                                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                        let meta = FileMeta::from_mapping(&file_mapping).with_params(params);
+
+                        if let Some(object) = open_mapping(app_state, &file_mapping).await {
+                            if !final_download {
+                                cache_mapping(app_state, &file_mapping);
+                            }
+                            let response = serve_file(&app_state.config, request_headers, &meta, object).await;
+                            if file_mapping.max_downloads.is_none() && response.status().is_success() {
+                                access::record_access(app_state, uuid);
+                            }
+                            if final_download && response.status().is_success() {
+                                // An open handle keeps streaming after the file is unlinked
+                                info!("Download limit reached, consuming file: {}", uuid);
+                                if let Err(e) = db.mark_file_purged(uuid).await {
+                                    warn!("Failed to mark file as consumed: {}", e);
                                 }
+                                let storage_ref = StorageRef::from_mapping(&file_mapping, &app_state.config.temp_directory);
+                                purge_file_contents(app_state, uuid, storage_ref).await;
                             }
+                            return response;
                         }
                     }
                     Ok(None) => {
+                        // The row may exist but be expired or out of downloads
+                        if let Ok(Some(file_mapping)) = db.find_file_mapping(uuid).await {
+                            info!("File is no longer available: {}", uuid);
+                            return GoneResponse::from_mapping(&file_mapping).into_response();
+                        }
                         // File not found in database, try fallback
                     }
                     Err(e) => {
                         warn!("Database file lookup failed, falling back to memory: {}", e);
-                        app_state.database_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+                        app_state.set_database_healthy(false);
                     }
                 }
             }
         }
 
-        // Fallback to in-memory storage
-        let file_data = {
-            match app_state.file_storage.lock() {
-                Ok(storage_guard) => storage_guard.get(&uuid.to_string()).cloned(),
-                Err(e) => {
-                    error!(
-                        "Failed to acquire lock on file storage during download: {}",
-                        e
-                    );
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        // Fallback to in-memory storage: check and count the download while holding the entry
+        let lookup = match app_state.file_storage.get_mut(&uuid) {
+            Some(mut file_data) => {
+                let meta = FileMeta::from_file_data(&file_data).with_params(params);
+                if file_data_is_gone(&file_data) {
+                    MemoryLookup::Gone(GoneResponse::from_file_data(&file_data))
+                } else if let Some(hotlink) = refusal(file_data.embeddable) {
+                    MemoryLookup::Hotlinked(hotlink)
+                } else if is_not_modified(request_headers, &meta) {
+                    MemoryLookup::NotModified(meta)
+                } else {
+                    file_data.download_count += 1;
+                    let final_download = file_data
+                        .max_downloads
+                        .is_some_and(|max| file_data.download_count >= max);
+                    if final_download {
+                        // Hand the contents to this request and leave a tombstone behind
+                        let storage = file_data.storage.take();
+                        file_data.purged_at = Some(Utc::now());
+                        MemoryLookup::Serve(Box::new(FileData { storage, ..file_data.clone() }), true)
+                    } else {
+                        MemoryLookup::Serve(Box::new(file_data.clone()), false)
+                    }
                 }
             }
+            None => MemoryLookup::Missing,
         };
 
-        if let Some(file_data) = file_data {
-            let headers = [
-                (header::CONTENT_TYPE, file_data.content_type.clone()),
-                (
-                    header::CONTENT_DISPOSITION,
-                    format!("attachment; filename=\"{}\"", file_data.filename),
-                ),
-            ];
-
-            // Return data based on storage type
-            match (&file_data.data, &file_data.file_path) {
-                (Some(data), None) => {
-                    info!(
-                        "Successfully serving file '{}' from memory, size: {} bytes",
-                        file_data.filename,
-                        data.len()
-                    );
-                    (headers, data.clone()).into_response()
-                }
-                (None, Some(path)) => {
-                    info!(
-                        "Successfully serving file '{}' from disk with streaming",
-                        file_data.filename
-                    );
-
-                    // Use streaming for better memory efficiency with large files
-                    match tokio::fs::File::open(path).await {
-                        Ok(file) => {
-                            let stream = ReaderStream::new(file);
-                            let body = Body::from_stream(stream);
-
-                            info!("Streaming file '{}' from disk", file_data.filename);
-                            (headers, body).into_response()
-                        }
-                        Err(e) => {
-                            error!("Failed to open file from disk: {:?}", e);
-                            StatusCode::INTERNAL_SERVER_ERROR.into_response()
-                        }
-                    }
+        match lookup {
+            MemoryLookup::Missing => {
+                warn!("File not found for ID: {}", uuid);
+                // Only remember the miss if the database had its say
+                if app_state.database.is_none() || app_state.database_available() {
+                    app_state.negative_cache.insert(&uuid.to_string());
                 }
-                _ => {
+                StatusCode::NOT_FOUND.into_response()
+            }
+            MemoryLookup::Gone(gone) => {
+                info!("File is no longer available: {}", uuid);
+                gone.into_response()
+            }
+            MemoryLookup::NotModified(meta) => not_modified_response(&meta),
+            MemoryLookup::Hotlinked(hotlink) => hotlink.into_response(),
+            MemoryLookup::Serve(file_data, final_download) => {
+                let meta = FileMeta::from_file_data(&file_data).with_params(params);
+
+                let Some(storage_ref) = file_data.storage else {
                     error!("Invalid file data state for ID: {}", uuid);
-                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                };
+                let object = match app_state.storage.get(&storage_ref).await {
+                    Ok(object) => object,
+                    Err(e) => {
+                        error!("Failed to open stored file {}: {:?}", uuid, e);
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                };
+                let response = serve_file(&app_state.config, request_headers, &meta, object).await;
+
+                if final_download && !contents_still_shared(app_state, &storage_ref).await {
+                    // An open reader keeps streaming after the contents are deleted
+                    if let Err(e) = app_state.storage.delete(&storage_ref).await {
+                        warn!("Failed to remove consumed file {:?}: {:?}", storage_ref, e);
+                    }
                 }
+                response
             }
-        } else {
-            warn!("File not found for ID: {}", uuid);
-            StatusCode::NOT_FOUND.into_response()
         }
     } else {
         warn!("Invalid file ID or short code: {}", id);
@@ -920,10 +3391,675 @@ pub async fn download_file(
     }
 }
 
+// Remove a file's stored bytes and any in-memory fallback entries for it.
+// Memory pool allocations are returned by the memory backend on delete.
+async fn purge_file_contents(app_state: &AppState, uuid: Uuid, storage_ref: Option<StorageRef>) -> ContentsRemoval {
+    invalidate_cached_file(app_state, uuid);
+
+    let removed = app_state.file_storage.remove(&uuid).map(|(_, file_data)| file_data);
+
+    // The database no longer records in-memory placement once purged, so always check the pool
+    let mut refs_to_remove = vec![StorageRef::Memory(uuid)];
+    let known_refs = storage_ref
+        .into_iter()
+        .chain(removed.and_then(|file_data| file_data.storage));
+    for storage_ref in known_refs {
+        if !refs_to_remove.contains(&storage_ref) {
+            refs_to_remove.push(storage_ref);
+        }
+    }
+
+    app_state.short_url_storage.retain(|_, file_id| *file_id != uuid);
+
+    let mut removal = ContentsRemoval::Deleted;
+    for storage_ref in refs_to_remove {
+        if contents_still_shared(app_state, &storage_ref).await {
+            if removal == ContentsRemoval::Deleted {
+                removal = ContentsRemoval::Shared;
+            }
+            continue;
+        }
+        match app_state.storage.delete(&storage_ref).await {
+            Ok(_) => info!("Removed stored file: {:?}", storage_ref),
+            Err(e) => {
+                warn!("Failed to remove stored file {:?}: {:?}", storage_ref, e);
+                removal = ContentsRemoval::Failed;
+            }
+        }
+    }
+    removal
+}
+
+// Drop a file's reference on stored contents that identical uploads may share.
+// True if the bytes must stay: other files still use them, or the database
+// can't say, in which case orphan collection removes them once it can.
+async fn contents_still_shared(app_state: &AppState, storage_ref: &StorageRef) -> bool {
+    let (Some(file_path), Some(db)) = (storage_ref.file_path(), &app_state.database) else {
+        return false;
+    };
+    if !app_state.database_available() {
+        warn!("Keeping {:?} while the database is unavailable; orphan collection will remove it", file_path);
+        return true;
+    }
+    let recorded = storage::recorded_path(&app_state.config.temp_directory, file_path);
+    match db.release_blob(&recorded.to_string_lossy()).await {
+        Ok(removable) => {
+            if !removable {
+                info!("Keeping {:?}, identical uploads still use it", file_path);
+            }
+            !removable
+        }
+        Err(e) => {
+            warn!("Failed to release {:?}, keeping it: {}", file_path, e);
+            app_state.set_database_healthy(false);
+            true
+        }
+    }
+}
+
+// How long a purged file lingers as a tombstone, so downloads answer 410 rather than 404
+fn tombstone_retention(config: &Config) -> Option<chrono::Duration> {
+    chrono::Duration::from_std(Duration::from_secs(config.tombstone_retention_seconds)).ok()
+}
+
+// Files created before this are past DROP_MAX_TTL, whatever expiry they were
+// given; catches those uploaded before the limit was set or lowered
+fn max_age_cutoff(config: &Config, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let max_ttl = chrono::Duration::from_std(config.max_ttl?).ok()?;
+    now.checked_sub_signed(max_ttl)
+}
+
+// Whether a fallback entry is due to be purged
+fn memory_file_expired(file_data: &FileData, now: DateTime<Utc>, created_before: Option<DateTime<Utc>>) -> bool {
+    let expired = file_data.expires_at.is_some_and(|expires_at| expires_at <= now)
+        || created_before.is_some_and(|cutoff| file_data.created_at < cutoff);
+    expired && !file_data.pinned
+}
+
+// Fallback tombstones purged before this are forgotten
+fn memory_tombstone_cutoff(config: &Config, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    tombstone_retention(config).and_then(|retention| now.checked_sub_signed(retention))
+}
+
+// Drop the contents of expired fallback entries, keeping a tombstone until the retention passes
+async fn purge_expired_memory_files(app_state: &AppState, report: &mut CleanupReport) {
+    let now = Utc::now();
+    let tombstone_cutoff = memory_tombstone_cutoff(&app_state.config, now);
+    let created_before = max_age_cutoff(&app_state.config, now);
+
+    let mut expired = Vec::new();
+    let mut tombstones_removed = Vec::new();
+
+    app_state.file_storage.retain(|id, file_data| {
+        if let Some(purged_at) = file_data.purged_at {
+            let keep = tombstone_cutoff.is_none_or(|cutoff| purged_at > cutoff);
+            if !keep {
+                tombstones_removed.push(*id);
+            }
+            return keep;
+        }
+        if !memory_file_expired(file_data, now, created_before) {
+            return true;
+        }
+
+        expired.push((*id, file_data.size, file_data.storage.take()));
+        file_data.purged_at = Some(now);
+        true
+    });
+
+    // Short codes go with the tombstone; the file no longer answers 410
+    forget_short_codes(app_state, &tombstones_removed);
+    report.tombstones_removed += tombstones_removed.len() as u64;
+
+    let purged = expired.len();
+    for (id, size, storage_ref) in expired {
+        let removal = match storage_ref {
+            Some(storage_ref) if contents_still_shared(app_state, &storage_ref).await => ContentsRemoval::Shared,
+            Some(storage_ref) => match app_state.storage.delete(&storage_ref).await {
+                Ok(_) => ContentsRemoval::Deleted,
+                Err(e) => {
+                    warn!("Failed to remove expired file {:?}: {:?}", storage_ref, e);
+                    ContentsRemoval::Failed
+                }
+            },
+            None => ContentsRemoval::Deleted,
+        };
+        report.record_removal(id, size, removal);
+    }
+
+    if purged > 0 {
+        info!("Cleaned up {} expired in-memory files", purged);
+    }
+}
+
+// Drop fallback short codes pointing at any of `file_ids`
+fn forget_short_codes(app_state: &AppState, file_ids: &[Uuid]) {
+    if file_ids.is_empty() {
+        return;
+    }
+    let file_ids: HashSet<&Uuid> = file_ids.iter().collect();
+    app_state.short_url_storage.retain(|_, file_id| !file_ids.contains(file_id));
+}
+
+// Keep the in-memory fallback within `fallback_capacity` files, evicting
+// tombstones first and then the oldest unpinned files, with their short codes
+// and contents. Returns how many entries were evicted.
+async fn evict_fallback_overflow(app_state: &AppState) -> usize {
+    let capacity = app_state.config.fallback_capacity;
+    if capacity == 0 {
+        return 0;
+    }
+
+    let storage = &app_state.file_storage;
+    let overflow = storage.len().saturating_sub(capacity);
+    if overflow == 0 {
+        return 0;
+    }
+    let mut by_age: Vec<(bool, DateTime<Utc>, Uuid)> = storage
+        .iter()
+        .filter(|entry| !entry.pinned)
+        .map(|entry| (entry.purged_at.is_none(), entry.created_at, *entry.key()))
+        .collect();
+    by_age.sort_unstable();
+    let evicted: Vec<(Uuid, FileData)> = by_age
+        .into_iter()
+        .take(overflow)
+        .filter_map(|(_, _, id)| storage.remove(&id))
+        .collect();
+
+    let ids: Vec<Uuid> = evicted.iter().map(|(id, _)| *id).collect();
+    forget_short_codes(app_state, &ids);
+
+    let live = evicted.iter().filter(|(_, file_data)| file_data.purged_at.is_none()).count();
+    if live > 0 {
+        warn!("In-memory fallback is over capacity ({}), evicted {} of its oldest files", capacity, live);
+    }
+    for storage_ref in evicted.into_iter().filter_map(|(_, file_data)| file_data.storage) {
+        if contents_still_shared(app_state, &storage_ref).await {
+            continue;
+        }
+        if let Err(e) = app_state.storage.delete(&storage_ref).await {
+            warn!("Failed to remove evicted file {:?}: {:?}", storage_ref, e);
+        }
+    }
+    ids.len()
+}
+
+// Drop fallback short codes whose file is gone. With a database they may
+// point at files that only it holds, so they are left alone.
+fn prune_dangling_short_codes(app_state: &AppState) -> usize {
+    if app_state.database.is_some() {
+        return 0;
+    }
+    let files = &app_state.file_storage;
+    let mut removed = 0;
+    app_state.short_url_storage.retain(|_, file_id| {
+        let keep = files.contains_key(&*file_id);
+        if !keep {
+            removed += 1;
+        }
+        keep
+    });
+    removed
+}
+
+/// One pass of the background cleanup: expired files, old tombstones, stale rate limits,
+/// abandoned upload sessions and an overgrown in-memory fallback. A file whose contents
+/// can't be deleted is counted and skipped; orphan collection removes them later.
+/// The report is kept for `GET /admin/cleanup/last`.
+pub async fn run_cleanup(app_state: &AppState) -> CleanupReport {
+    let _running = app_state.cleanup.start().await;
+    let started = std::time::Instant::now();
+    let mut report = CleanupReport::new(false);
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.cleanup_expired_files(max_age_cutoff(&app_state.config, Utc::now())).await {
+            Ok(expired) => {
+                for file in expired {
+                    let storage_ref = file.file_path.map(|path| {
+                        StorageRef::Disk(storage::resolve_recorded_path(&app_state.config.temp_directory, &path))
+                    });
+                    let removal = purge_file_contents(app_state, file.id, storage_ref).await;
+                    report.record_removal(file.id, file.file_size.max(0) as u64, removal);
+                }
+            }
+            Err(e) => warn!("Failed to clean up expired files in database: {}", e),
+        }
+
+        match db.cleanup_purged_files(tombstone_retention(&app_state.config)).await {
+            Ok((removed, short_codes)) => {
+                report.tombstones_removed += removed;
+                forget_cached_short_codes(app_state, &short_codes).await;
+            }
+            Err(e) => warn!("Failed to clean up purged file records: {}", e),
+        }
+
+        match db.cleanup_old_rate_limits().await {
+            Ok(removed) => report.rate_limits_removed += removed.max(0) as u64,
+            Err(e) => warn!("Failed to clean up old rate limits: {}", e),
+        }
+
+        if let Err(e) = db.cleanup_old_quotas(quota::window_start(Utc::now())).await {
+            warn!("Failed to clean up old upload quotas: {}", e);
+        }
+
+        if let Err(e) = db.cleanup_expired_idempotency_keys().await {
+            warn!("Failed to clean up expired idempotency keys: {}", e);
+        }
+    }
+
+    quota::cleanup_quotas(&app_state.quota_storage);
+    idempotency::cleanup_idempotency_keys(&app_state.idempotency_keys);
+    purge_expired_memory_files(app_state, &mut report).await;
+    sessions::cleanup_stale_sessions(app_state).await;
+
+    // Keep the in-memory fallback from growing without bound
+    let rate_limits = rate_limit::cleanup_rate_limits(&app_state.rate_limit_storage, &app_state.config);
+    let evicted = evict_fallback_overflow(app_state).await;
+    let short_codes = prune_dangling_short_codes(app_state);
+    if rate_limits > 0 || evicted > 0 || short_codes > 0 {
+        info!(
+            "Pruned in-memory fallback: {} idle rate limits, {} files over capacity, {} dangling short codes",
+            rate_limits, evicted, short_codes
+        );
+    }
+    report.rate_limits_removed += rate_limits as u64;
+    report.files_evicted = evicted;
+
+    let report = report.finish(started);
+    if report.delete_failures > 0 {
+        warn!("Cleanup couldn't delete the contents of {} files", report.delete_failures);
+    }
+    app_state.cleanup.record(&report);
+    report
+}
+
+/// What `run_cleanup` would remove now, without removing anything. Bytes
+/// are counted in full, even for contents identical uploads share.
+pub async fn preview_cleanup(app_state: &AppState) -> CleanupReport {
+    let started = std::time::Instant::now();
+    let mut report = CleanupReport::new(true);
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_expired_files(max_age_cutoff(&app_state.config, Utc::now())).await {
+            Ok(expired) => {
+                for file in expired {
+                    report.record_removal(file.id, file.file_size.max(0) as u64, ContentsRemoval::Deleted);
+                }
+            }
+            Err(e) => warn!("Failed to find expired files in database: {}", e),
+        }
+
+        match db.count_purged_files(tombstone_retention(&app_state.config)).await {
+            Ok(removed) => report.tombstones_removed += removed,
+            Err(e) => warn!("Failed to count purged file records: {}", e),
+        }
+
+        match db.count_old_rate_limits().await {
+            Ok(removed) => report.rate_limits_removed += removed.max(0) as u64,
+            Err(e) => warn!("Failed to count old rate limits: {}", e),
+        }
+    }
+
+    let now = Utc::now();
+    let tombstone_cutoff = memory_tombstone_cutoff(&app_state.config, now);
+    let created_before = max_age_cutoff(&app_state.config, now);
+    let mut memory_tombstones = 0;
+    for entry in app_state.file_storage.iter() {
+        match entry.purged_at {
+            Some(purged_at) if tombstone_cutoff.is_some_and(|cutoff| purged_at <= cutoff) => {
+                memory_tombstones += 1;
+            }
+            None if memory_file_expired(&entry, now, created_before) => {
+                report.record_removal(*entry.key(), entry.size, ContentsRemoval::Deleted);
+            }
+            _ => {}
+        }
+    }
+    report.tombstones_removed += memory_tombstones as u64;
+    report.rate_limits_removed += rate_limit::idle_rate_limits(&app_state.rate_limit_storage, &app_state.config) as u64;
+    if app_state.config.fallback_capacity > 0 {
+        let live = app_state.file_storage.len().saturating_sub(memory_tombstones);
+        report.files_evicted = live.saturating_sub(app_state.config.fallback_capacity);
+    }
+
+    report.finish(started)
+}
+
+// Spawn the periodic cleanup loop; runs every `cleanup_interval_seconds`
+pub fn spawn_cleanup_task(app_state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(app_state.config.cleanup_interval_seconds));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            run_cleanup(&app_state).await;
+        }
+    })
+}
+
+// Spawn the loop that keeps the memory pool in line with available system memory
+pub fn spawn_memory_pool_task(app_state: AppState) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MEMORY_POOL_REFRESH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The pool was just sized at startup
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            resize_memory_pool(&app_state.config);
+        }
+    })
+}
+
+// Spawn the loop that checks the database and Redis every `database_probe_interval_seconds`.
+// It is the only thing that marks them healthy again; a failed check here or
+// a failed call anywhere marks them unhealthy.
+pub fn spawn_health_probe_task(app_state: AppState) -> Option<tokio::task::JoinHandle<()>> {
+    if app_state.database.is_none() && app_state.redis.is_none() {
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(app_state.config.database_probe_interval_seconds));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if let Some(ref db) = app_state.database {
+                app_state.set_database_healthy(db.health_check().await);
+            }
+            if let Some(ref redis) = app_state.redis {
+                let healthy = redis.health_check().await;
+                let was_healthy = app_state.redis_healthy.swap(healthy, Ordering::AcqRel);
+                match (was_healthy, healthy) {
+                    (true, false) => warn!("Redis marked unhealthy, skipping the cache"),
+                    (false, true) => info!("Redis is healthy again"),
+                    _ => {}
+                }
+            }
+        }
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/drop/{id}",
+    tag = "files",
+    params(("id" = String, Path, description = "File ID or short code"), ("X-Delete-Token" = String, Header, description = "Returned by the upload")),
+    responses(
+        (status = 204, description = "The file was deleted"),
+        (status = 403, description = "Missing or wrong delete token", body = openapi::ErrorResponse),
+        (status = 404, description = "No such file", body = openapi::ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn delete_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    info!("Attempting to delete file with ID: {}", id);
+
+    let Some(uuid) = resolve_id_or_short_code_db(&id, &app_state).await else {
+        warn!("Invalid file ID or short code: {}", id);
+        return StatusCode::NOT_FOUND;
+    };
+
+    let provided_token = headers
+        .get("x-delete-token")
+        .and_then(|v| v.to_str().ok());
+    delete_with_token(&app_state, uuid, provided_token).await
+}
+
+// Delete a file if `provided_token` is its delete token: 204, 403 or 404
+async fn delete_with_token(app_state: &AppState, uuid: Uuid, provided_token: Option<&str>) -> StatusCode {
+    // Try database first
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(uuid).await {
+            Ok(Some(file_mapping)) => {
+                let expected = file_mapping.delete_token.as_deref().unwrap_or_default();
+                if !token_matches(expected, provided_token) {
+                    warn!("Rejected delete for {}: invalid delete token", uuid);
+                    return StatusCode::FORBIDDEN;
+                }
+
+                return match remove_mapped_file(app_state, db.as_ref(), &file_mapping).await {
+                    Ok(true) => {
+                        info!("Deleted file '{}' with ID: {}", file_mapping.filename, uuid);
+                        StatusCode::NO_CONTENT
+                    }
+                    // Lost a race with a concurrent delete
+                    Ok(false) => StatusCode::NOT_FOUND,
+                    Err(e) => {
+                        error!("Failed to delete file mapping from database: {}", e);
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }
+                };
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    // Fallback to in-memory storage
+    let file_data = app_state.file_storage.get(&uuid).map(|file_data| file_data.value().clone());
+
+    let Some(file_data) = file_data else {
+        warn!("File not found for ID: {}", uuid);
+        return StatusCode::NOT_FOUND;
+    };
+
+    if !token_matches(&file_data.delete_token, provided_token) {
+        warn!("Rejected delete for {}: invalid delete token", uuid);
+        return StatusCode::FORBIDDEN;
+    }
+
+    remove_memory_file(app_state, uuid, &file_data).await;
+    info!("Deleted file '{}' with ID: {}", file_data.filename, uuid);
+    StatusCode::NO_CONTENT
+}
+
+// POST /drop/delete - delete many files, each as DELETE /drop/{id} would
+#[utoipa::path(
+    post,
+    path = "/drop/delete",
+    tag = "files",
+    params(("X-Admin-Token" = Option<String>, Header, description = "`DROP_ADMIN_TOKEN`; needed for `ids`")),
+    request_body = BatchDeleteRequest,
+    responses(
+        (status = 200, description = "Every file was deleted", body = BatchDeleteResponse),
+        (status = 207, description = "Some files weren't deleted; see each result", body = BatchDeleteResponse),
+        (status = 400, description = "More than 100 files", body = openapi::ErrorResponse),
+        (status = 403, description = "`ids` with a wrong admin token", body = openapi::ErrorResponse),
+        (status = 404, description = "`ids` while admin endpoints aren't enabled", body = openapi::ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn batch_delete(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<BatchDeleteRequest>,
+) -> Result<(StatusCode, Json<BatchDeleteResponse>), StatusCode> {
+    let count = request.files.len() + request.ids.len();
+    if count > MAX_DELETE_BATCH {
+        warn!("Rejecting delete of {} files (at most {})", count, MAX_DELETE_BATCH);
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !request.ids.is_empty() {
+        admin::authorize(&app_state, &headers)?;
+    }
+
+    let mut results = Vec::with_capacity(count);
+    for item in request.files {
+        let status = match resolve_id_or_short_code_db(&item.id, &app_state).await {
+            Some(uuid) => delete_with_token(&app_state, uuid, Some(&item.delete_token)).await,
+            None => StatusCode::NOT_FOUND,
+        };
+        results.push(DeleteResult::new(item.id, status));
+    }
+    for id in request.ids {
+        let status = match resolve_id_or_short_code_db(&id, &app_state).await {
+            Some(uuid) => match admin::remove_file(&app_state, uuid).await {
+                Ok(Some(_)) => StatusCode::NO_CONTENT,
+                Ok(None) => StatusCode::NOT_FOUND,
+                Err(status) => status,
+            },
+            None => StatusCode::NOT_FOUND,
+        };
+        results.push(DeleteResult::new(id, status));
+    }
+
+    let deleted = results.iter().filter(|result| result.result == DeleteOutcome::Deleted).count();
+    info!("Batch delete removed {} of {} files", deleted, results.len());
+    let status = if deleted == results.len() { StatusCode::OK } else { StatusCode::MULTI_STATUS };
+    Ok((status, Json(BatchDeleteResponse { results })))
+}
+
+/// Remove a file the database knows about: its row (short URLs cascade),
+/// cached lookups and stored bytes, crediting the uploader's quota. Returns
+/// false if the row was already gone.
+pub(crate) async fn remove_mapped_file(app_state: &AppState, db: &dyn MetadataStore, file_mapping: &FileMapping) -> Result<bool> {
+    let uuid = file_mapping.id;
+
+    // Redis would otherwise keep resolving the short codes until they expire
+    let short_codes = db.short_codes_for_file(uuid).await.unwrap_or_else(|e| {
+        warn!("Failed to look up short codes for {}: {}", uuid, e);
+        Vec::new()
+    });
+
+    if !db.delete_file_mapping(uuid).await? {
+        return Ok(false);
+    }
+    let storage_ref = StorageRef::from_mapping(file_mapping, &app_state.config.temp_directory);
+    purge_file_contents(app_state, uuid, storage_ref).await;
+    forget_cached_short_codes(app_state, &short_codes).await;
+
+    let uploader_ip = file_mapping.uploader_ip.as_deref().and_then(|ip| ip.parse::<std::net::IpAddr>().ok());
+    if let (Some(uploader_ip), None) = (uploader_ip, file_mapping.api_key_id) {
+        let size = file_mapping.file_size.max(0) as u64;
+        quota::credit_quota(app_state, uploader_ip, file_mapping.created_at, size).await;
+    }
+    Ok(true)
+}
+
+/// Remove a file held in the in-memory index, crediting the uploader's quota.
+/// Returns the bytes its contents took up.
+pub(crate) async fn remove_memory_file(app_state: &AppState, uuid: Uuid, file_data: &FileData) -> u64 {
+    let size = if file_data.storage.is_some() { file_data.size } else { 0 };
+    purge_file_contents(app_state, uuid, None).await;
+    if let (Some(uploader_ip), None) = (file_data.uploader_ip, file_data.api_key_id) {
+        quota::credit_quota(app_state, uploader_ip, file_data.created_at, size).await;
+    }
+    size
+}
+
+async fn forget_cached_short_codes(app_state: &AppState, short_codes: &[String]) {
+    if short_codes.is_empty() {
+        return;
+    }
+    if let Ok(mut cache) = app_state.short_code_cache.lock() {
+        for short_code in short_codes {
+            cache.remove(short_code);
+        }
+    }
+    if let Some(ref redis) = app_state.redis
+        && app_state.redis_healthy.load(std::sync::atomic::Ordering::Relaxed)
+        && let Err(e) = redis.remove_short_urls(short_codes).await
+    {
+        warn!("Failed to remove cached short codes from Redis: {}", e);
+        app_state.redis_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
 pub fn create_app(app_state: AppState) -> Router {
-    Router::new()
+    let router = Router::new();
+    // API-only deployments leave / unrouted
+    let router = if app_state.config.disable_ui {
+        router
+    } else {
+        router.route("/", get(ui::upload_page))
+    };
+    let router = if app_state.config.disable_docs {
+        router
+    } else {
+        router.merge(openapi::swagger_ui())
+    };
+    let router = router
+        .route("/openapi.json", get(openapi::spec))
         .route("/health", get(health_check))
-        .route("/drop", post(upload_file))
-        .route("/drop/{id}", get(download_file))
+        .route("/healthz", get(health::liveness))
+        .route("/readyz", get(health::readiness))
+        // Upload sizes are enforced while streaming, against the configured limits
+        .route(
+            "/drop",
+            post(upload_file).put(upload_raw).layer(DefaultBodyLimit::disable()),
+        )
+        .route(
+            "/drop/{id}",
+            get(download_file)
+                .head(head_file)
+                .put(upload_raw_named)
+                .delete(delete_file),
+        )
+        // Fixed segments below take precedence over a filename
+        .route("/drop/{id}/{filename}", get(download_file_named).head(head_file_named))
+        .route("/drop/info", post(batch_file_info))
+        .route("/drop/delete", post(batch_delete))
+        .route("/drop/{id}/info", get(file_info))
+        .route("/drop/{id}/preview", get(preview::preview_file))
+        .route("/drop/{id}/aliases", post(aliases::add_alias))
+        .route("/drop/{id}/extend", post(extend::extend_file))
+        .route("/drop/by-hash/{sha256}", get(file_info_by_hash))
+        .route("/drop/bundle", post(bundles::create_bundle))
+        .route("/drop/bundle/{id}", get(bundles::download_bundle))
+        .route("/admin/gc", post(gc::run_orphan_gc))
+        .route("/admin/cleanup", post(cleanup::trigger_cleanup))
+        .route("/admin/cleanup/last", get(cleanup::last_cleanup))
+        .route("/admin/corrupt-files", get(scrub::list_corrupt_files))
+        .route("/admin/files", get(admin::list_files))
+        .route("/admin/mode", post(maintenance::set_mode))
+        .route("/admin/stats", get(stats::get_stats))
+        .route("/admin/files/purge", post(admin::purge_files))
+        .route("/admin/files/{id}", axum::routing::delete(admin::delete_file))
+        .route("/admin/files/{id}/pin", post(admin::pin_file).delete(admin::unpin_file))
+        .route("/admin/api-keys", get(api_keys::list_api_keys).post(api_keys::create_api_key))
+        .route(
+            "/admin/api-keys/{id}",
+            axum::routing::patch(api_keys::update_api_key).delete(api_keys::revoke_api_key),
+        )
+        .route("/my/files", get(api_keys::list_my_files))
+        .route("/my/files/{id}", axum::routing::delete(api_keys::delete_my_file))
+        .route("/drop/sessions", post(sessions::create_session))
+        .route(
+            "/drop/sessions/{session_id}",
+            get(sessions::get_session).patch(sessions::append_chunk),
+        )
+        .route("/drop/sessions/{session_id}/complete", post(sessions::complete_session))
+        .layer(middleware::from_fn_with_state(app_state.clone(), maintenance::reject_writes))
+        .layer(middleware::from_fn(request_id::propagate_request_id))
+        .layer(middleware::from_fn_with_state(app_state.clone(), security_headers::set_security_headers));
+    // Outermost, so preflights are answered before anything else runs
+    let router = match cors::cors_layer(&app_state.config) {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+    // ...except the access log, which sees every request and its final response
+    router
+        .layer(middleware::from_fn_with_state(app_state.clone(), access_log::log_request))
         .with_state(app_state)
 }
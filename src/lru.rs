@@ -0,0 +1,95 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+struct Entry<V> {
+    value: V,
+    inserted_at: Instant,
+    last_used: u64, // Key into `recency`
+}
+
+/// Size-bounded, least-recently-used cache whose entries also expire after a TTL.
+/// A capacity of zero disables caching.
+pub struct LruCache<K, V> {
+    entries: HashMap<K, Entry<V>>,
+    recency: BTreeMap<u64, K>, // Use tick -> key, oldest first
+    tick: u64,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            capacity,
+            ttl,
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Return a copy of a live entry and mark it as most recently used.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let expired = self.entries.get(key)?.inserted_at.elapsed() >= self.ttl;
+        if expired {
+            self.remove(key);
+            return None;
+        }
+
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = tick;
+        self.recency.insert(tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                inserted_at: Instant::now(),
+                last_used: tick,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        Some(entry.value)
+    }
+
+    /// Keep only the entries for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let recency = &mut self.recency;
+        self.entries.retain(|key, entry| {
+            let kept = keep(key, &entry.value);
+            if !kept {
+                recency.remove(&entry.last_used);
+            }
+            kept
+        });
+    }
+}
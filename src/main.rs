@@ -1,70 +1,226 @@
-use color_eyre::eyre::{Context, Result};
-use drop::{AppState, Config, create_app, initialize_memory_pool, database::Database};
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
-use tracing::info;
-use tracing_subscriber;
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{Context, Result, eyre};
+use drop::backup::{self, PrefixRewrite};
+use drop::database::Database;
+use drop::reshard;
+use drop::{Config, Server, config};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// High-performance file sharing service. Options override environment
+/// variables, which override the config file.
+#[derive(Debug, Parser)]
+#[command(name = "drop", version, about)]
+struct Cli {
+    /// TOML config file (default: $DROP_CONFIG)
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on, e.g. 127.0.0.1:8080
+    #[arg(long, value_name = "ADDR")]
+    bind: Option<String>,
+
+    /// Directory for uploaded files
+    #[arg(long, value_name = "DIR")]
+    temp_dir: Option<PathBuf>,
+
+    /// Largest single file accepted, e.g. 512MB or 2GB
+    #[arg(long, value_name = "SIZE", value_parser = size_arg)]
+    max_file_size: Option<u64>,
+
+    /// Largest request accepted, e.g. 10GB
+    #[arg(long, value_name = "SIZE", value_parser = size_arg)]
+    max_total_size: Option<u64>,
+
+    /// Public base URL used in returned links
+    #[arg(long, value_name = "URL")]
+    public_url: Option<String>,
+
+    /// Keep everything in memory even when DATABASE_URL is set
+    #[arg(long)]
+    no_database: bool,
+
+    /// Print the effective configuration, secrets redacted, and exit
+    #[arg(long)]
+    print_config: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Write the metadata of every file, and every short code, to a JSON Lines backup
+    Export {
+        /// Where to write the backup
+        #[arg(long, short, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Load a backup written by `export`, skipping what is already there
+    Import {
+        /// The backup to load
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+
+        /// Move file paths under OLD to NEW; may be repeated, the first match wins
+        #[arg(long, value_name = "OLD=NEW")]
+        rewrite_prefix: Vec<PrefixRewrite>,
+    },
+    /// Move files stored flat in the temp directory into shard directories
+    Reshard,
+}
+
+fn size_arg(value: &str) -> Result<u64, String> {
+    config::parse_size(value).map_err(|e| e.to_string())
+}
+
+impl Cli {
+    // Load the config file (if any) and the environment, then apply the options on top
+    fn load_config(&self) -> Result<Config> {
+        let path = self.config.clone().or_else(|| {
+            std::env::var_os(config::CONFIG_ENV_VAR)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        });
+        let mut config = match path {
+            Some(ref path) => Config::from_file(path)?,
+            None => Config::from_env()?,
+        };
+
+        if let Some(ref bind) = self.bind {
+            config.bind_address = bind.clone();
+        }
+        if let Some(ref temp_dir) = self.temp_dir {
+            config.temp_directory = temp_dir.clone();
+        }
+        if let Some(size) = self.max_file_size {
+            config.max_file_size_limit = usize::try_from(size).context("--max-file-size is too large")?;
+        }
+        if let Some(size) = self.max_total_size {
+            config.max_total_size_per_request = usize::try_from(size).context("--max-total-size is too large")?;
+        }
+        if let Some(ref url) = self.public_url {
+            config.public_base_url = Some(url.trim_end_matches('/').to_string()).filter(|url| !url.is_empty());
+        }
+        if self.no_database {
+            config.database_url = None;
+        }
+        Ok(config)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
 
+    let cli = Cli::parse();
+    let config = cli.load_config()?;
+    if cli.print_config {
+        println!("{:#?}", config.redacted());
+        return Ok(());
+    }
+
     tracing_subscriber::fmt()
         .with_target(false)
         .compact()
         .init();
 
-    info!("Starting drop...💧");
+    if let Some(command) = cli.command {
+        return run_command(command, &config).await;
+    }
 
-    // Load configuration from environment
-    let config = Config::from_env();
+    info!("Starting drop...💧");
     info!(
         "Loaded configuration: bind_address={}, max_file_size={}, temp_directory={:?}",
         config.bind_address, config.max_file_size_limit, config.temp_directory
     );
+    if cli.no_database {
+        info!("Database disabled by --no-database");
+    }
+
+    let handle = Server::builder().config(config).build().await?.serve().await?;
+    handle.shutdown_on(shutdown_signal()).await
+}
 
-    // Initialize memory pool based on system memory
-    initialize_memory_pool();
+// Commands work on the database and temp directory alone; no server is started
+async fn run_command(command: Command, config: &Config) -> Result<()> {
+    let db = match config.database_url {
+        Some(ref database_url) => Some(Database::connect(database_url, &config.pool_settings()).await?),
+        None => None,
+    };
+    let backup_db = || db.as_ref().ok_or_else(|| eyre!("Backups need a database; set DATABASE_URL"));
 
-    // Initialize database connection if configured
-    let (database, database_healthy) = if let Some(ref db_url) = config.database_url {
-        match Database::new(db_url).await {
-            Ok(db) => {
-                info!("Database connected successfully");
-                (Some(db), Arc::new(std::sync::atomic::AtomicBool::new(true)))
+    match command {
+        Command::Export { output } => {
+            let db = backup_db()?;
+            let file = File::create(&output).with_context(|| format!("Failed to create {:?}", output))?;
+            let summary = backup::export(db, &mut BufWriter::new(file)).await?;
+            info!(
+                "Exported {} files and {} short codes to {:?}",
+                summary.files, summary.short_urls, output
+            );
+        }
+        Command::Import { input, rewrite_prefix } => {
+            let db = backup_db()?;
+            let file = File::open(&input).with_context(|| format!("Failed to open {:?}", input))?;
+            let report = backup::import(db, BufReader::new(file), &rewrite_prefix, &config.temp_directory).await?;
+            info!(
+                "Imported {} files ({} already present) and {} short codes ({} skipped)",
+                report.files_imported, report.files_skipped, report.short_urls_imported, report.short_urls_skipped
+            );
+            if !report.missing.is_empty() {
+                warn!("{} imported files have no contents at their path:", report.missing.len());
+                for (id, file_path) in &report.missing {
+                    println!("{}\t{}", id, file_path.display());
+                }
             }
-            Err(e) => {
-                info!("Failed to connect to database, falling back to in-memory storage: {}", e);
-                (None, Arc::new(std::sync::atomic::AtomicBool::new(false)))
+        }
+        // Without a database there are only the files and their sidecars to move
+        Command::Reshard => {
+            let report = reshard::shard_flat_files(config, db.as_ref()).await?;
+            info!(
+                "Moved {} files into shard directories, updating {} database rows",
+                report.files_moved, report.rows_updated
+            );
+            if report.failed > 0 {
+                warn!("{} files could not be moved; run reshard again to retry them", report.failed);
             }
         }
-    } else {
-        info!("No database URL configured, using in-memory storage only");
-        (None, Arc::new(std::sync::atomic::AtomicBool::new(false)))
-    };
+    }
+    Ok(())
+}
 
-    // Create shared state
-    let app_state = AppState {
-        config: config.clone(),
-        file_storage: Arc::new(Mutex::new(HashMap::new())),
-        short_url_storage: Arc::new(Mutex::new(HashMap::new())),
-        rate_limit_storage: Arc::new(Mutex::new(HashMap::new())),
-        database,
-        database_healthy,
+// Resolves on Ctrl+C or, on Unix, SIGTERM; the server then stops taking new
+// connections and lets in-flight requests finish
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
     };
 
-    let app = create_app(app_state);
-
-    let listener = tokio::net::TcpListener::bind(&config.bind_address)
-        .await
-        .with_context(|| format!("Failed to bind to address {}", config.bind_address))?;
-
-    info!("Server running on http://{}", config.bind_address);
-
-    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
-        .await
-        .context("Server failed to start")?;
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    Ok(())
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+    info!("Shutting down");
 }
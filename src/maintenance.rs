@@ -0,0 +1,84 @@
+use axum::{
+    Json,
+    extract::{Request, State, rejection::JsonRejection},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{AppState, admin, openapi::ErrorResponse};
+
+// Maintenance usually takes a while; don't have clients retry too eagerly
+const RETRY_AFTER_SECONDS: u64 = 60;
+
+/// The body of a write refused in read-only mode.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct MaintenanceResponse {
+    error: &'static str,
+    reason: &'static str, // Always "maintenance"
+}
+
+// Requests that would change what is stored. Reads, including POST lookups,
+// and operator endpoints still go through.
+fn is_write(method: &Method, path: &str) -> bool {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return false;
+    }
+    !(path.starts_with("/admin/") || (*method == Method::POST && path == "/drop/info"))
+}
+
+/// Middleware refusing uploads, deletes and other writes with 503 while the
+/// instance is read-only; downloads, info and health are unaffected.
+pub async fn reject_writes(State(app_state): State<AppState>, request: Request, next: Next) -> Response {
+    if app_state.is_read_only() && is_write(request.method(), request.uri().path()) {
+        return maintenance_response();
+    }
+    next.run(request).await
+}
+
+fn maintenance_response() -> Response {
+    let body = MaintenanceResponse {
+        error: "Drop is read-only for maintenance; try again later",
+        reason: "maintenance",
+    };
+    let mut response = (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(RETRY_AFTER_SECONDS));
+    response
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Mode {
+    pub read_only: bool,
+}
+
+// POST /admin/mode - switch read-only mode on or off; requires X-Admin-Token
+#[utoipa::path(
+    post,
+    path = "/admin/mode",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`")),
+    request_body = Mode,
+    responses(
+        (status = 200, description = "The mode now in effect", body = Mode),
+        (status = 400, description = "A malformed request", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request))]
+pub async fn set_mode(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    request: Result<Json<Mode>, JsonRejection>,
+) -> Result<Json<Mode>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    let Json(mode) = request.map_err(|_| StatusCode::BAD_REQUEST)?;
+    app_state.set_read_only(mode.read_only);
+    Ok(Json(Mode {
+        read_only: app_state.is_read_only(),
+    }))
+}
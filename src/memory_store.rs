@@ -0,0 +1,783 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{Result, bail, eyre};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::database::{
+    ApiKey, ApiKeyUsage, Bundle, DailyTraffic, ExpiredFile, FileListQuery, FileMapping, FileSort, KeyClaim,
+    NewFileMapping, PoolStats, SizeBucketCount, StorageTotals, rate_limit_cutoff, tombstone_cutoff,
+};
+use crate::metadata::MetadataStore;
+use crate::rate_limit::{RateLimitAction, RateLimitPolicy, RateLimitStatus};
+
+/// A `MetadataStore` that keeps its records in process, following the same
+/// rules as the database: tombstones, download limits, short codes that only
+/// resolve while their file's row is there. Nothing survives a restart.
+/// Clones share the same records.
+#[derive(Clone)]
+pub struct InMemoryStore {
+    tables: Arc<Mutex<Tables>>,
+    available: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Tables {
+    file_mappings: HashMap<Uuid, FileMapping>,
+    scrubbed_at: HashMap<Uuid, DateTime<Utc>>,
+    short_urls: HashMap<String, Uuid>, // Short code, file
+    blobs: HashMap<(String, i64), Blob>,
+    bundles: HashMap<Uuid, Bundle>,
+    rate_limits: HashMap<(IpAddr, RateLimitAction), Bucket>,
+    quotas: HashMap<IpAddr, (DateTime<Utc>, i64)>, // Window start, bytes used
+    idempotency_keys: HashMap<(String, String), (Option<String>, DateTime<Utc>)>, // Response, expiry
+    api_keys: HashMap<Uuid, (ApiKey, String)>, // The key and the hash of its secret
+    daily_traffic: BTreeMap<String, DailyTraffic>,
+}
+
+// A stored file shared by uploads with the same contents
+struct Blob {
+    file_path: String,
+    ref_count: i32,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+// Whether a file can still be downloaded, as `get_file_mapping` decides it
+fn downloadable(mapping: &FileMapping, now: DateTime<Utc>) -> bool {
+    mapping.purged_at.is_none()
+        && (mapping.pinned || mapping.expires_at.is_none_or(|expires_at| expires_at > now))
+        && mapping.max_downloads.is_none_or(|max_downloads| mapping.access_count < max_downloads)
+}
+
+// Whether `cleanup_expired_files` purges a file
+fn expired(mapping: &FileMapping, now: DateTime<Utc>, created_before: Option<DateTime<Utc>>) -> bool {
+    mapping.purged_at.is_none()
+        && !mapping.pinned
+        && (mapping.expires_at.is_some_and(|expires_at| expires_at < now)
+            || created_before.is_some_and(|created_before| mapping.created_at < created_before))
+}
+
+fn expired_file(mapping: &FileMapping) -> ExpiredFile {
+    ExpiredFile {
+        id: mapping.id,
+        file_path: mapping.file_path.clone(),
+        file_size: mapping.file_size,
+    }
+}
+
+// The row the database writes for a new mapping
+fn new_row(mapping: &NewFileMapping<'_>) -> FileMapping {
+    FileMapping {
+        id: mapping.id,
+        filename: mapping.filename.to_string(),
+        content_type: mapping.content_type.to_string(),
+        file_path: mapping.file_path.map(|path| path.to_string_lossy().to_string()),
+        file_size: mapping.file_size,
+        is_in_memory: mapping.is_in_memory,
+        created_at: mapping.created_at,
+        accessed_at: mapping.created_at,
+        access_count: mapping.access_count,
+        expires_at: mapping.expires_at,
+        delete_token: Some(mapping.delete_token.to_string()),
+        purged_at: None,
+        max_downloads: mapping.max_downloads,
+        content_hash: Some(mapping.content_hash.to_string()),
+        declared_content_type: mapping.declared_content_type.map(str::to_string),
+        detected_content_type: mapping.detected_content_type.map(str::to_string),
+        uploader_ip: mapping.uploader_ip.map(|ip| ip.to_string()),
+        sha256: mapping.sha256.map(str::to_string),
+        encrypted: mapping.encrypted,
+        pinned: mapping.pinned,
+        api_key_id: mapping.api_key_id,
+        corrupt: false,
+        embeddable: mapping.embeddable,
+    }
+}
+
+// A file's key in a listing sorted by `sort`; creation times in nanoseconds, as cursors hold them
+fn sort_key(mapping: &FileMapping, sort: FileSort) -> i64 {
+    match sort {
+        FileSort::Size => mapping.file_size,
+        FileSort::CreatedAt => mapping.created_at.timestamp_nanos_opt().unwrap_or(i64::MAX),
+        FileSort::AccessCount => i64::from(mapping.access_count),
+    }
+}
+
+fn listed(mapping: &FileMapping, list: &FileListQuery) -> bool {
+    mapping.purged_at.is_none()
+        && list.min_size.is_none_or(|min_size| mapping.file_size >= min_size)
+        && list.max_size.is_none_or(|max_size| mapping.file_size <= max_size)
+        && list.created_before.is_none_or(|created_before| mapping.created_at < created_before)
+        && list.created_after.is_none_or(|created_after| mapping.created_at > created_after)
+        && list
+            .content_type_prefix
+            .as_deref()
+            .is_none_or(|prefix| mapping.content_type.starts_with(prefix))
+        && list.is_in_memory.is_none_or(|is_in_memory| mapping.is_in_memory == is_in_memory)
+        && list.pinned.is_none_or(|pinned| mapping.pinned == pinned)
+        && list.api_key_id.is_none_or(|api_key_id| mapping.api_key_id == Some(api_key_id))
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self {
+            tables: Arc::new(Mutex::new(Tables::default())),
+            available: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Make every call fail, as a database that has gone away does, until
+    /// it is made available again. Health checks report it down meanwhile.
+    pub fn set_available(&self, available: bool) {
+        self.available.store(available, Ordering::Relaxed);
+    }
+
+    fn tables(&self) -> Result<MutexGuard<'_, Tables>> {
+        if !self.available.load(Ordering::Relaxed) {
+            bail!("In-memory metadata store is unavailable");
+        }
+        self.tables
+            .lock()
+            .map_err(|_| eyre!("In-memory metadata store is poisoned"))
+    }
+}
+
+impl Tables {
+    fn insert_file_mapping(&mut self, mapping: &NewFileMapping<'_>) -> Result<()> {
+        if self.file_mappings.contains_key(&mapping.id) {
+            bail!("Failed to store file mapping for ID: {}", mapping.id);
+        }
+        self.file_mappings.insert(mapping.id, new_row(mapping));
+        Ok(())
+    }
+
+    // Short codes can only point at files that have a row
+    fn insert_short_url(&mut self, short_code: &str, file_id: Uuid) -> Result<bool> {
+        if !self.file_mappings.contains_key(&file_id) {
+            bail!("Failed to store short URL {}: no file {}", short_code, file_id);
+        }
+        if self.short_urls.contains_key(short_code) {
+            return Ok(false);
+        }
+        self.short_urls.insert(short_code.to_string(), file_id);
+        Ok(true)
+    }
+
+    fn resolve_short_code(&self, short_code: &str) -> Option<Uuid> {
+        self.short_urls
+            .get(short_code)
+            .copied()
+            .filter(|file_id| self.file_mappings.contains_key(file_id))
+    }
+
+    // Live files, for the stats queries
+    fn live_files(&self) -> impl Iterator<Item = &FileMapping> {
+        self.file_mappings.values().filter(|mapping| mapping.purged_at.is_none())
+    }
+}
+
+#[async_trait]
+impl MetadataStore for InMemoryStore {
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    async fn health_check(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    async fn store_file_mapping(&self, mapping: &NewFileMapping<'_>) -> Result<()> {
+        self.tables()?.insert_file_mapping(mapping)
+    }
+
+    async fn store_file_mapping_if_absent(&self, mapping: &NewFileMapping<'_>) -> Result<bool> {
+        let mut tables = self.tables()?;
+        if tables.file_mappings.contains_key(&mapping.id) {
+            return Ok(false);
+        }
+        tables.insert_file_mapping(mapping)?;
+        Ok(true)
+    }
+
+    async fn store_upload(&self, mapping: &NewFileMapping<'_>, short_code: &str) -> Result<bool> {
+        let mut tables = self.tables()?;
+        if tables.short_urls.contains_key(short_code) {
+            return Ok(false);
+        }
+        tables.insert_file_mapping(mapping)?;
+        tables.insert_short_url(short_code, mapping.id)
+    }
+
+    async fn get_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        let now = Utc::now();
+        Ok(self.tables()?.file_mappings.get(&id).filter(|mapping| downloadable(mapping, now)).cloned())
+    }
+
+    async fn claim_download(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        let now = Utc::now();
+        let mut tables = self.tables()?;
+        let Some(mapping) = tables.file_mappings.get_mut(&id).filter(|mapping| downloadable(mapping, now)) else {
+            return Ok(None);
+        };
+        mapping.accessed_at = now;
+        mapping.access_count += 1;
+        Ok(Some(mapping.clone()))
+    }
+
+    async fn record_accesses(&self, counts: &[(Uuid, i32)]) -> Result<()> {
+        let now = Utc::now();
+        let mut tables = self.tables()?;
+        for (id, downloads) in counts {
+            if let Some(mapping) = tables.file_mappings.get_mut(id) {
+                mapping.accessed_at = now;
+                mapping.access_count += downloads;
+            }
+        }
+        Ok(())
+    }
+
+    async fn find_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        Ok(self.tables()?.file_mappings.get(&id).cloned())
+    }
+
+    async fn find_file_mappings(&self, ids: &[Uuid]) -> Result<Vec<FileMapping>> {
+        let tables = self.tables()?;
+        Ok(ids.iter().filter_map(|id| tables.file_mappings.get(id)).cloned().collect())
+    }
+
+    async fn mark_file_purged(&self, id: Uuid) -> Result<()> {
+        if let Some(mapping) = self.tables()?.file_mappings.get_mut(&id) {
+            mapping.purged_at = Some(Utc::now());
+            mapping.file_path = None;
+            mapping.is_in_memory = false;
+        }
+        Ok(())
+    }
+
+    async fn set_file_pinned(&self, id: Uuid, pinned: bool) -> Result<bool> {
+        let mut tables = self.tables()?;
+        let Some(mapping) = tables.file_mappings.get_mut(&id).filter(|mapping| mapping.purged_at.is_none()) else {
+            return Ok(false);
+        };
+        mapping.pinned = pinned;
+        Ok(true)
+    }
+
+    async fn set_file_expiry(&self, id: Uuid, expires_at: DateTime<Utc>) -> Result<bool> {
+        let mut tables = self.tables()?;
+        let Some(mapping) = tables.file_mappings.get_mut(&id).filter(|mapping| mapping.purged_at.is_none()) else {
+            return Ok(false);
+        };
+        mapping.expires_at = Some(expires_at);
+        Ok(true)
+    }
+
+    async fn delete_file_mapping(&self, id: Uuid) -> Result<bool> {
+        let mut tables = self.tables()?;
+        if tables.file_mappings.remove(&id).is_none() {
+            return Ok(false);
+        }
+        tables.scrubbed_at.remove(&id);
+        tables.short_urls.retain(|_, file_id| *file_id != id);
+        Ok(true)
+    }
+
+    async fn list_file_mappings(&self, list: &FileListQuery) -> Result<Vec<FileMapping>> {
+        let tables = self.tables()?;
+        let mut files: Vec<(i64, &FileMapping)> = tables
+            .file_mappings
+            .values()
+            .filter(|mapping| listed(mapping, list))
+            .map(|mapping| (sort_key(mapping, list.sort), mapping))
+            .filter(|(key, mapping)| {
+                list.after.is_none_or(|cursor| {
+                    let position = (*key, mapping.id);
+                    if list.ascending {
+                        position > (cursor.key, cursor.id)
+                    } else {
+                        position < (cursor.key, cursor.id)
+                    }
+                })
+            })
+            .collect();
+        files.sort_by_key(|(key, mapping)| (*key, mapping.id));
+        if !list.ascending {
+            files.reverse();
+        }
+
+        let limit = usize::try_from(list.limit).unwrap_or_default();
+        Ok(files.into_iter().take(limit).map(|(_, mapping)| mapping.clone()).collect())
+    }
+
+    async fn files_to_scrub(&self, limit: i64) -> Result<Vec<FileMapping>> {
+        let tables = self.tables()?;
+        let mut files: Vec<&FileMapping> = tables
+            .file_mappings
+            .values()
+            .filter(|mapping| mapping.purged_at.is_none() && !mapping.is_in_memory && mapping.file_path.is_some())
+            .collect();
+        // None sorts first: files never scrubbed come before all others
+        files.sort_by_key(|mapping| (tables.scrubbed_at.get(&mapping.id), mapping.id));
+
+        let limit = usize::try_from(limit).unwrap_or_default();
+        Ok(files.into_iter().take(limit).cloned().collect())
+    }
+
+    async fn record_scrub(&self, id: Uuid, corrupt: bool) -> Result<()> {
+        let mut tables = self.tables()?;
+        if let Some(mapping) = tables.file_mappings.get_mut(&id) {
+            mapping.corrupt = corrupt;
+            tables.scrubbed_at.insert(id, Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn corrupt_files(&self) -> Result<Vec<FileMapping>> {
+        let tables = self.tables()?;
+        let mut files: Vec<FileMapping> = tables.live_files().filter(|mapping| mapping.corrupt).cloned().collect();
+        files.sort_by_key(|mapping| (mapping.created_at, mapping.id));
+        Ok(files)
+    }
+
+    async fn find_file_by_sha256(&self, sha256: &str) -> Result<Option<FileMapping>> {
+        let now = Utc::now();
+        let tables = self.tables()?;
+        Ok(tables
+            .file_mappings
+            .values()
+            .filter(|mapping| mapping.sha256.as_deref() == Some(sha256) && downloadable(mapping, now))
+            .max_by_key(|mapping| mapping.created_at)
+            .cloned())
+    }
+
+    async fn claim_blob(&self, sha256: &str, file_size: i64, file_path: &str) -> Result<String> {
+        let mut tables = self.tables()?;
+        let blob = tables
+            .blobs
+            .entry((sha256.to_string(), file_size))
+            .and_modify(|blob| blob.ref_count += 1)
+            .or_insert_with(|| Blob {
+                file_path: file_path.to_string(),
+                ref_count: 1,
+            });
+        Ok(blob.file_path.clone())
+    }
+
+    async fn release_blob(&self, file_path: &str) -> Result<bool> {
+        let mut tables = self.tables()?;
+        let Some((key, blob)) = tables.blobs.iter_mut().find(|(_, blob)| blob.file_path == file_path) else {
+            return Ok(true);
+        };
+        blob.ref_count -= 1;
+        if blob.ref_count > 0 {
+            return Ok(false);
+        }
+        let key = key.clone();
+        tables.blobs.remove(&key);
+        Ok(true)
+    }
+
+    async fn is_blob(&self, file_path: &str) -> Result<bool> {
+        Ok(self.tables()?.blobs.values().any(|blob| blob.file_path == file_path))
+    }
+
+    async fn strip_file_path_prefix(&self, prefix: &str) -> Result<u64> {
+        let mut tables = self.tables()?;
+        let tables = &mut *tables;
+        let mapping_paths = tables.file_mappings.values_mut().filter_map(|mapping| mapping.file_path.as_mut());
+        let blob_paths = tables.blobs.values_mut().map(|blob| &mut blob.file_path);
+
+        let mut rows_affected = 0;
+        for file_path in mapping_paths.chain(blob_paths) {
+            if let Some(relative) = file_path.strip_prefix(prefix) {
+                *file_path = relative.to_string();
+                rows_affected += 1;
+            }
+        }
+        Ok(rows_affected)
+    }
+
+    async fn short_codes_for_file(&self, file_id: Uuid) -> Result<Vec<String>> {
+        let tables = self.tables()?;
+        Ok(tables
+            .short_urls
+            .iter()
+            .filter(|(_, id)| **id == file_id)
+            .map(|(short_code, _)| short_code.clone())
+            .collect())
+    }
+
+    async fn store_short_url(&self, short_code: &str, file_id: Uuid) -> Result<bool> {
+        self.tables()?.insert_short_url(short_code, file_id)
+    }
+
+    async fn get_file_id_by_short_code(&self, short_code: &str) -> Result<Option<Uuid>> {
+        Ok(self.tables()?.resolve_short_code(short_code))
+    }
+
+    async fn get_file_ids_by_short_codes(&self, short_codes: &[&str]) -> Result<Vec<(String, Uuid)>> {
+        let tables = self.tables()?;
+        Ok(short_codes
+            .iter()
+            .filter_map(|short_code| Some((short_code.to_string(), tables.resolve_short_code(short_code)?)))
+            .collect())
+    }
+
+    async fn store_bundle(&self, bundle: &Bundle) -> Result<()> {
+        let mut tables = self.tables()?;
+        let taken = tables
+            .bundles
+            .values()
+            .any(|stored| stored.id == bundle.id || stored.short_code == bundle.short_code);
+        if taken {
+            bail!("Failed to store bundle: {}", bundle.id);
+        }
+        tables.bundles.insert(bundle.id, bundle.clone());
+        Ok(())
+    }
+
+    async fn find_bundle(&self, id_or_short_code: &str) -> Result<Option<Bundle>> {
+        let tables = self.tables()?;
+        let bundle = match id_or_short_code.parse::<Uuid>() {
+            Ok(id) => tables.bundles.get(&id),
+            Err(_) => tables.bundles.values().find(|bundle| bundle.short_code == id_or_short_code),
+        };
+        Ok(bundle.cloned())
+    }
+
+    async fn check_rate_limit(
+        &self,
+        client_ip: IpAddr,
+        action: RateLimitAction,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitStatus> {
+        let now = Utc::now();
+        let mut tables = self.tables()?;
+        let Some(bucket) = tables.rate_limits.get_mut(&(client_ip, action)) else {
+            let tokens = f64::from(policy.burst) - 1.0;
+            let bucket = Bucket {
+                tokens,
+                refilled_at: now,
+                updated_at: now,
+            };
+            tables.rate_limits.insert((client_ip, action), bucket);
+            return Ok(policy.status(tokens, true));
+        };
+
+        let elapsed = (now - bucket.refilled_at).to_std().unwrap_or_default();
+        let tokens = policy.refill(bucket.tokens, elapsed);
+        if tokens < 1.0 {
+            return Ok(policy.status(tokens, false));
+        }
+        bucket.tokens = tokens - 1.0;
+        bucket.refilled_at = now;
+        bucket.updated_at = now;
+        Ok(policy.status(bucket.tokens, true))
+    }
+
+    async fn cleanup_old_rate_limits(&self) -> Result<i64> {
+        let cutoff = rate_limit_cutoff();
+        let mut tables = self.tables()?;
+        let before = tables.rate_limits.len();
+        tables.rate_limits.retain(|_, bucket| bucket.updated_at >= cutoff);
+        Ok((before - tables.rate_limits.len()) as i64)
+    }
+
+    async fn count_old_rate_limits(&self) -> Result<i64> {
+        let cutoff = rate_limit_cutoff();
+        let tables = self.tables()?;
+        Ok(tables.rate_limits.values().filter(|bucket| bucket.updated_at < cutoff).count() as i64)
+    }
+
+    async fn quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>) -> Result<i64> {
+        let tables = self.tables()?;
+        Ok(match tables.quotas.get(&client_ip) {
+            Some((window, bytes_used)) if *window == window_start => *bytes_used,
+            _ => 0,
+        })
+    }
+
+    async fn add_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()> {
+        let mut tables = self.tables()?;
+        let usage = tables.quotas.entry(client_ip).or_insert((window_start, 0));
+        if usage.0 != window_start {
+            *usage = (window_start, 0);
+        }
+        usage.1 += bytes;
+        Ok(())
+    }
+
+    async fn credit_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()> {
+        let mut tables = self.tables()?;
+        if let Some((_, bytes_used)) = tables.quotas.get_mut(&client_ip).filter(|(window, _)| *window == window_start) {
+            *bytes_used = (*bytes_used - bytes).max(0);
+        }
+        Ok(())
+    }
+
+    async fn cleanup_old_quotas(&self, current_window: DateTime<Utc>) -> Result<i64> {
+        let mut tables = self.tables()?;
+        let before = tables.quotas.len();
+        tables.quotas.retain(|_, (window, _)| *window >= current_window);
+        Ok((before - tables.quotas.len()) as i64)
+    }
+
+    async fn cleanup_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>> {
+        let now = Utc::now();
+        let mut tables = self.tables()?;
+        let mut purged = Vec::new();
+        for mapping in tables.file_mappings.values_mut() {
+            if expired(mapping, now, created_before) {
+                purged.push(expired_file(mapping));
+                mapping.purged_at = Some(now);
+                mapping.file_path = None;
+                mapping.is_in_memory = false;
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn find_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>> {
+        let now = Utc::now();
+        let tables = self.tables()?;
+        Ok(tables
+            .file_mappings
+            .values()
+            .filter(|mapping| expired(mapping, now, created_before))
+            .map(expired_file)
+            .collect())
+    }
+
+    async fn cleanup_purged_files(&self, retention: Option<chrono::Duration>) -> Result<(u64, Vec<String>)> {
+        let Some(cutoff) = tombstone_cutoff(retention) else {
+            return Ok((0, Vec::new()));
+        };
+        let mut tables = self.tables()?;
+        let tables = &mut *tables;
+        let before = tables.file_mappings.len();
+        tables
+            .file_mappings
+            .retain(|_, mapping| mapping.purged_at.is_none_or(|purged_at| purged_at >= cutoff));
+        let deleted = before - tables.file_mappings.len();
+
+        let mut short_codes = Vec::new();
+        tables.short_urls.retain(|short_code, file_id| {
+            let kept = tables.file_mappings.contains_key(file_id);
+            if !kept {
+                short_codes.push(short_code.clone());
+            }
+            kept
+        });
+        tables.scrubbed_at.retain(|id, _| tables.file_mappings.contains_key(id));
+        Ok((deleted as u64, short_codes))
+    }
+
+    async fn count_purged_files(&self, retention: Option<chrono::Duration>) -> Result<u64> {
+        let Some(cutoff) = tombstone_cutoff(retention) else {
+            return Ok(0);
+        };
+        let tables = self.tables()?;
+        Ok(tables
+            .file_mappings
+            .values()
+            .filter(|mapping| mapping.purged_at.is_some_and(|purged_at| purged_at < cutoff))
+            .count() as u64)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        key: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KeyClaim> {
+        let mut tables = self.tables()?;
+        let id = (scope.to_string(), key.to_string());
+        // A key past its expiry is taken over
+        match tables.idempotency_keys.get(&id) {
+            Some((Some(response), expiry)) if *expiry > now => Ok(KeyClaim::Completed(response.clone())),
+            Some((None, expiry)) if *expiry > now => Ok(KeyClaim::InProgress),
+            _ => {
+                tables.idempotency_keys.insert(id, (None, expires_at));
+                Ok(KeyClaim::Claimed)
+            }
+        }
+    }
+
+    async fn complete_idempotency_key(&self, scope: &str, key: &str, response: &str) -> Result<()> {
+        if let Some(record) = self.tables()?.idempotency_keys.get_mut(&(scope.to_string(), key.to_string())) {
+            record.0 = Some(response.to_string());
+        }
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, scope: &str, key: &str) -> Result<()> {
+        let mut tables = self.tables()?;
+        let id = (scope.to_string(), key.to_string());
+        if tables.idempotency_keys.get(&id).is_some_and(|record| record.0.is_none()) {
+            tables.idempotency_keys.remove(&id);
+        }
+        Ok(())
+    }
+
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<i64> {
+        let now = Utc::now();
+        let mut tables = self.tables()?;
+        let before = tables.idempotency_keys.len();
+        tables.idempotency_keys.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok((before - tables.idempotency_keys.len()) as i64)
+    }
+
+    async fn store_api_key(&self, api_key: &ApiKey, key_hash: &str) -> Result<()> {
+        let mut tables = self.tables()?;
+        let taken = tables
+            .api_keys
+            .values()
+            .any(|(stored, stored_hash)| stored.id == api_key.id || stored_hash == key_hash);
+        if taken {
+            bail!("Failed to store API key: {}", api_key.id);
+        }
+        tables.api_keys.insert(api_key.id, (api_key.clone(), key_hash.to_string()));
+        Ok(())
+    }
+
+    async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        let tables = self.tables()?;
+        Ok(tables
+            .api_keys
+            .values()
+            .find(|(_, stored_hash)| stored_hash == key_hash)
+            .map(|(api_key, _)| api_key.clone()))
+    }
+
+    async fn find_api_key(&self, id: Uuid) -> Result<Option<ApiKey>> {
+        Ok(self.tables()?.api_keys.get(&id).map(|(api_key, _)| api_key.clone()))
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        let tables = self.tables()?;
+        let mut api_keys: Vec<ApiKey> = tables.api_keys.values().map(|(api_key, _)| api_key.clone()).collect();
+        api_keys.sort_by_key(|api_key| (api_key.created_at, api_key.id));
+        Ok(api_keys)
+    }
+
+    async fn update_api_key_limits(&self, api_key: &ApiKey) -> Result<bool> {
+        let mut tables = self.tables()?;
+        let Some((stored, _)) = tables.api_keys.get_mut(&api_key.id).filter(|(stored, _)| stored.revoked_at.is_none())
+        else {
+            return Ok(false);
+        };
+        stored.quota_bytes = api_key.quota_bytes;
+        stored.quota_files = api_key.quota_files;
+        stored.rate_limit_multiplier = api_key.rate_limit_multiplier;
+        Ok(true)
+    }
+
+    async fn revoke_api_key(&self, id: Uuid) -> Result<bool> {
+        let mut tables = self.tables()?;
+        let Some((stored, _)) = tables.api_keys.get_mut(&id).filter(|(stored, _)| stored.revoked_at.is_none()) else {
+            return Ok(false);
+        };
+        stored.revoked_at = Some(Utc::now());
+        Ok(true)
+    }
+
+    async fn api_key_usage(&self, api_key_id: Uuid) -> Result<ApiKeyUsage> {
+        let tables = self.tables()?;
+        Ok(tables
+            .live_files()
+            .filter(|mapping| mapping.api_key_id == Some(api_key_id))
+            .fold(ApiKeyUsage::default(), |usage, mapping| ApiKeyUsage {
+                files: usage.files + 1,
+                bytes: usage.bytes + mapping.file_size,
+            }))
+    }
+
+    async fn get_storage_stats(&self, horizon: chrono::Duration) -> Result<StorageTotals> {
+        let now = Utc::now();
+        let tables = self.tables()?;
+        Ok(tables.live_files().fold(StorageTotals::default(), |mut totals, mapping| {
+            totals.total_files += 1;
+            totals.total_size += mapping.file_size;
+            if mapping.is_in_memory {
+                totals.memory_files += 1;
+                totals.memory_size += mapping.file_size;
+            }
+            let expiring = mapping
+                .expires_at
+                .is_some_and(|expires_at| expires_at > now && expires_at <= now + horizon);
+            if expiring && !mapping.pinned {
+                totals.expiring_files += 1;
+            }
+            totals
+        }))
+    }
+
+    async fn record_traffic(&self, traffic: &[DailyTraffic]) -> Result<()> {
+        let mut tables = self.tables()?;
+        for day in traffic {
+            let total = tables.daily_traffic.entry(day.day.clone()).or_insert_with(|| DailyTraffic {
+                day: day.day.clone(),
+                ..DailyTraffic::default()
+            });
+            total.downloads += day.downloads;
+            total.bytes_served += day.bytes_served;
+        }
+        Ok(())
+    }
+
+    async fn traffic_since(&self, since: &str) -> Result<Vec<DailyTraffic>> {
+        let tables = self.tables()?;
+        Ok(tables.daily_traffic.range(since.to_string()..).map(|(_, day)| day.clone()).collect())
+    }
+
+    async fn uploads_per_day(&self, since: DateTime<Utc>) -> Result<Vec<(String, i64)>> {
+        let tables = self.tables()?;
+        let mut days: BTreeMap<String, i64> = BTreeMap::new();
+        for mapping in tables.file_mappings.values().filter(|mapping| mapping.created_at >= since) {
+            *days.entry(mapping.created_at.format("%Y-%m-%d").to_string()).or_default() += 1;
+        }
+        Ok(days.into_iter().collect())
+    }
+
+    async fn most_downloaded_files(&self, limit: i64) -> Result<Vec<FileMapping>> {
+        let tables = self.tables()?;
+        let mut files: Vec<&FileMapping> = tables.live_files().collect();
+        files.sort_by_key(|mapping| (std::cmp::Reverse(mapping.access_count), mapping.id));
+
+        let limit = usize::try_from(limit).unwrap_or_default();
+        Ok(files.into_iter().take(limit).cloned().collect())
+    }
+
+    async fn file_size_distribution(&self, upper_bounds: &[i64]) -> Result<Vec<SizeBucketCount>> {
+        let tables = self.tables()?;
+        let mut buckets: BTreeMap<i64, SizeBucketCount> = BTreeMap::new();
+        for mapping in tables.live_files() {
+            let bucket = upper_bounds
+                .iter()
+                .position(|bound| mapping.file_size < *bound)
+                .unwrap_or(upper_bounds.len()) as i64;
+            let count = buckets.entry(bucket).or_insert(SizeBucketCount {
+                bucket,
+                ..SizeBucketCount::default()
+            });
+            count.files += 1;
+            count.bytes += mapping.file_size;
+        }
+        Ok(buckets.into_values().collect())
+    }
+}
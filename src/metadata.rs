@@ -0,0 +1,494 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::Result;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::database::{
+    ApiKey, ApiKeyUsage, Bundle, DailyTraffic, Database, ExpiredFile, FileListQuery, FileMapping, KeyClaim,
+    NewFileMapping, PoolStats, SizeBucketCount, StorageTotals,
+};
+use crate::rate_limit::{RateLimitAction, RateLimitPolicy, RateLimitStatus};
+
+/// Where file metadata, short codes and the other records the handlers keep
+/// are stored. `Database` is the real one; `memory_store::InMemoryStore`
+/// keeps the same records in process, for tests or running without one.
+#[async_trait]
+pub trait MetadataStore: Send + Sync {
+    /// Connection pool usage, for stores that have a pool.
+    fn pool_stats(&self) -> Option<PoolStats>;
+
+    /// Whether the store answers at all; false once it has gone away.
+    async fn health_check(&self) -> bool;
+
+    // Files
+
+    /// Record a new file. Fails if a file with the same ID is already there.
+    async fn store_file_mapping(&self, mapping: &NewFileMapping<'_>) -> Result<()>;
+
+    /// Record a file unless one with the same ID is already there, so
+    /// replaying a write twice is harmless. False if it was there.
+    async fn store_file_mapping_if_absent(&self, mapping: &NewFileMapping<'_>) -> Result<bool>;
+
+    /// Record a new upload and its short code together, so a short code
+    /// never points at a file that was not stored. False, storing neither,
+    /// if the short code is already taken.
+    async fn store_upload(&self, mapping: &NewFileMapping<'_>, short_code: &str) -> Result<bool>;
+
+    /// A file that can still be downloaded, without counting a download.
+    /// `None` both for unknown IDs and for files that are expired, purged or
+    /// out of downloads; `find_file_mapping` tells them apart.
+    async fn get_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>>;
+
+    /// Count a download and return the file, or `None` if it can't be
+    /// downloaded any more. Checking and counting is atomic, so no two
+    /// requests get a file's last download.
+    async fn claim_download(&self, id: Uuid) -> Result<Option<FileMapping>>;
+
+    /// Add batched download counts (file ID, downloads) to the files' access
+    /// statistics. Unknown IDs are skipped.
+    async fn record_accesses(&self, counts: &[(Uuid, i32)]) -> Result<()>;
+
+    /// A file's record whatever its state, tombstones included, without
+    /// touching its access statistics.
+    async fn find_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>>;
+
+    /// Files with any of these IDs, in one lookup. IDs with no record are left out.
+    async fn find_file_mappings(&self, ids: &[Uuid]) -> Result<Vec<FileMapping>>;
+
+    /// Mark a file's contents as gone while keeping its record as a tombstone.
+    async fn mark_file_purged(&self, id: Uuid) -> Result<()>;
+
+    /// Pin or unpin a file. False if the file is gone or already purged.
+    async fn set_file_pinned(&self, id: Uuid, pinned: bool) -> Result<bool>;
+
+    /// Move a file's expiry. False if the file is gone or already purged.
+    async fn set_file_expiry(&self, id: Uuid, expires_at: DateTime<Utc>) -> Result<bool>;
+
+    /// Forget a file and its short codes. False if there was no such file.
+    async fn delete_file_mapping(&self, id: Uuid) -> Result<bool>;
+
+    /// One page of the files matching `list`, continuing after its cursor.
+    async fn list_file_mappings(&self, list: &FileListQuery) -> Result<Vec<FileMapping>>;
+
+    // Integrity scrubbing
+
+    /// Live disk-backed files, least recently scrubbed first; files never
+    /// scrubbed come before all others.
+    async fn files_to_scrub(&self, limit: i64) -> Result<Vec<FileMapping>>;
+
+    /// Record the outcome of scrubbing a file now.
+    async fn record_scrub(&self, id: Uuid, corrupt: bool) -> Result<()>;
+
+    /// Live files whose last scrub found them corrupt, oldest first.
+    async fn corrupt_files(&self) -> Result<Vec<FileMapping>>;
+
+    // Stored files shared by identical uploads
+
+    /// The newest downloadable file with these contents, if any.
+    async fn find_file_by_sha256(&self, sha256: &str) -> Result<Option<FileMapping>>;
+
+    /// Take a reference on the stored file holding these contents, recording
+    /// `file_path` as that file if there isn't one yet. Returns the path the
+    /// contents live at; anything other than `file_path` means a duplicate.
+    async fn claim_blob(&self, sha256: &str, file_size: i64, file_path: &str) -> Result<String>;
+
+    /// Drop a reference on the stored file at `file_path`. True if its bytes
+    /// can be removed: the last reference is gone, or it was never shared.
+    async fn release_blob(&self, file_path: &str) -> Result<bool>;
+
+    /// Whether `file_path` holds contents shared through `claim_blob`.
+    async fn is_blob(&self, file_path: &str) -> Result<bool>;
+
+    /// Make file paths recorded whole under `prefix`, which ends in a separator,
+    /// relative to it like the paths recorded now. Returns how many changed.
+    async fn strip_file_path_prefix(&self, prefix: &str) -> Result<u64>;
+
+    // Short codes and bundles
+
+    /// Short codes pointing at `file_id`.
+    async fn short_codes_for_file(&self, file_id: Uuid) -> Result<Vec<String>>;
+
+    /// Point a short code at a file unless the code is taken. False if it
+    /// was, leaving it pointing where it did.
+    async fn store_short_url(&self, short_code: &str, file_id: Uuid) -> Result<bool>;
+
+    /// The file a short code points at. Codes of forgotten files never
+    /// resolve; those of tombstones do, so downloads can answer 410 Gone.
+    async fn get_file_id_by_short_code(&self, short_code: &str) -> Result<Option<Uuid>>;
+
+    /// File IDs for any of these short codes, in one lookup. Unknown codes are left out.
+    async fn get_file_ids_by_short_codes(&self, short_codes: &[&str]) -> Result<Vec<(String, Uuid)>>;
+
+    /// Record a new bundle. Fails if its ID or short code is taken.
+    async fn store_bundle(&self, bundle: &Bundle) -> Result<()>;
+
+    /// A bundle by its ID or short code.
+    async fn find_bundle(&self, id_or_short_code: &str) -> Result<Option<Bundle>>;
+
+    // Rate limits and quotas
+
+    /// Take a token from the client's bucket for `action` (see
+    /// `RateLimitPolicy`) and report how many are left. Concurrent requests
+    /// never both take the last token.
+    async fn check_rate_limit(
+        &self,
+        client_ip: IpAddr,
+        action: RateLimitAction,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitStatus>;
+
+    /// Forget rate limit buckets untouched for 10 minutes. Returns how many went.
+    async fn cleanup_old_rate_limits(&self) -> Result<i64>;
+
+    /// How many buckets `cleanup_old_rate_limits` would forget now.
+    async fn count_old_rate_limits(&self) -> Result<i64>;
+
+    /// Bytes `client_ip` has uploaded in the quota window starting at `window_start`.
+    async fn quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>) -> Result<i64>;
+
+    /// Add `bytes` to `client_ip`'s usage, starting from zero if its usage
+    /// is for an earlier window.
+    async fn add_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()>;
+
+    /// Give `bytes` back to `client_ip`, if its usage is still for `window_start`.
+    async fn credit_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()>;
+
+    /// Forget usage from windows before `current_window`. Returns how many went.
+    async fn cleanup_old_quotas(&self, current_window: DateTime<Utc>) -> Result<i64>;
+
+    // Expiry
+
+    /// Mark expired files, and any created before `created_before` whatever
+    /// their expiry, as purged and return their IDs, disk paths and sizes so
+    /// the caller can remove the contents. The records stay behind as
+    /// tombstones.
+    async fn cleanup_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>>;
+
+    /// The files `cleanup_expired_files` would purge now, without purging them.
+    async fn find_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>>;
+
+    /// Forget tombstones purged longer than `retention` ago, and their short
+    /// codes; none when it reaches back further than dates go. Returns how
+    /// many went and the short codes removed so caches can forget them too.
+    async fn cleanup_purged_files(&self, retention: Option<chrono::Duration>) -> Result<(u64, Vec<String>)>;
+
+    /// How many tombstones `cleanup_purged_files` would forget now.
+    async fn count_purged_files(&self, retention: Option<chrono::Duration>) -> Result<u64>;
+
+    // Idempotency keys
+
+    /// Claim `key` for an upload in `scope` until `expires_at`, or say
+    /// why not: its upload completed, with this response, or is still
+    /// running. A key past its expiry is taken over; only one of several
+    /// concurrent claims succeeds.
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        key: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KeyClaim>;
+
+    /// Record the response of the upload that claimed `key`.
+    async fn complete_idempotency_key(&self, scope: &str, key: &str, response: &str) -> Result<()>;
+
+    /// Forget `key` if its upload never completed.
+    async fn release_idempotency_key(&self, scope: &str, key: &str) -> Result<()>;
+
+    /// Forget keys past their expiry. Returns how many went.
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<i64>;
+
+    // API keys
+
+    /// Store a new API key under the hash of its secret.
+    async fn store_api_key(&self, api_key: &ApiKey, key_hash: &str) -> Result<()>;
+
+    /// The key whose secret hashes to `key_hash`, revoked or not.
+    async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>>;
+
+    /// The key with this ID, revoked or not.
+    async fn find_api_key(&self, id: Uuid) -> Result<Option<ApiKey>>;
+
+    /// Every API key, revoked ones included, oldest first.
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>>;
+
+    /// Replace a key's quotas and rate limit multiplier. False if there is no
+    /// such key or it has been revoked.
+    async fn update_api_key_limits(&self, api_key: &ApiKey) -> Result<bool>;
+
+    /// Stop a key from authenticating. Its files are kept. False if there is
+    /// no such key or it was already revoked.
+    async fn revoke_api_key(&self, id: Uuid) -> Result<bool>;
+
+    /// Files uploaded with `api_key_id` that haven't been purged, and their bytes.
+    async fn api_key_usage(&self, api_key_id: Uuid) -> Result<ApiKeyUsage>;
+
+    // Statistics
+
+    /// Totals over files that haven't been purged, plus how many of them
+    /// expire within `horizon`.
+    async fn get_storage_stats(&self, horizon: chrono::Duration) -> Result<StorageTotals>;
+
+    /// Add downloads and bytes served to their days' totals.
+    async fn record_traffic(&self, traffic: &[DailyTraffic]) -> Result<()>;
+
+    /// Daily traffic from `since` ('YYYY-MM-DD') on, oldest first. Days
+    /// without downloads are left out.
+    async fn traffic_since(&self, since: &str) -> Result<Vec<DailyTraffic>>;
+
+    /// Files uploaded per UTC day from `since` on, tombstones included, as
+    /// ('YYYY-MM-DD', count) oldest first.
+    async fn uploads_per_day(&self, since: DateTime<Utc>) -> Result<Vec<(String, i64)>>;
+
+    /// The `limit` most downloaded files that haven't been purged.
+    async fn most_downloaded_files(&self, limit: i64) -> Result<Vec<FileMapping>>;
+
+    /// Files that haven't been purged, counted into buckets by size: bucket
+    /// `n` holds files smaller than `upper_bounds[n]` (and not in an earlier
+    /// bucket), and the last bucket, `upper_bounds.len()`, everything else.
+    /// Empty buckets are left out.
+    async fn file_size_distribution(&self, upper_bounds: &[i64]) -> Result<Vec<SizeBucketCount>>;
+}
+
+#[async_trait]
+impl MetadataStore for Database {
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(Database::pool_stats(self))
+    }
+
+    async fn health_check(&self) -> bool {
+        Database::health_check(self).await
+    }
+
+    async fn store_file_mapping(&self, mapping: &NewFileMapping<'_>) -> Result<()> {
+        Database::store_file_mapping(self, mapping).await
+    }
+
+    async fn store_file_mapping_if_absent(&self, mapping: &NewFileMapping<'_>) -> Result<bool> {
+        Database::store_file_mapping_if_absent(self, mapping).await
+    }
+
+    async fn store_upload(&self, mapping: &NewFileMapping<'_>, short_code: &str) -> Result<bool> {
+        Database::store_upload(self, mapping, short_code).await
+    }
+
+    async fn get_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        Database::get_file_mapping(self, id).await
+    }
+
+    async fn claim_download(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        Database::claim_download(self, id).await
+    }
+
+    async fn record_accesses(&self, counts: &[(Uuid, i32)]) -> Result<()> {
+        Database::record_accesses(self, counts).await
+    }
+
+    async fn find_file_mapping(&self, id: Uuid) -> Result<Option<FileMapping>> {
+        Database::find_file_mapping(self, id).await
+    }
+
+    async fn find_file_mappings(&self, ids: &[Uuid]) -> Result<Vec<FileMapping>> {
+        Database::find_file_mappings(self, ids).await
+    }
+
+    async fn mark_file_purged(&self, id: Uuid) -> Result<()> {
+        Database::mark_file_purged(self, id).await
+    }
+
+    async fn set_file_pinned(&self, id: Uuid, pinned: bool) -> Result<bool> {
+        Database::set_file_pinned(self, id, pinned).await
+    }
+
+    async fn set_file_expiry(&self, id: Uuid, expires_at: DateTime<Utc>) -> Result<bool> {
+        Database::set_file_expiry(self, id, expires_at).await
+    }
+
+    async fn delete_file_mapping(&self, id: Uuid) -> Result<bool> {
+        Database::delete_file_mapping(self, id).await
+    }
+
+    async fn list_file_mappings(&self, list: &FileListQuery) -> Result<Vec<FileMapping>> {
+        Database::list_file_mappings(self, list).await
+    }
+
+    async fn files_to_scrub(&self, limit: i64) -> Result<Vec<FileMapping>> {
+        Database::files_to_scrub(self, limit).await
+    }
+
+    async fn record_scrub(&self, id: Uuid, corrupt: bool) -> Result<()> {
+        Database::record_scrub(self, id, corrupt).await
+    }
+
+    async fn corrupt_files(&self) -> Result<Vec<FileMapping>> {
+        Database::corrupt_files(self).await
+    }
+
+    async fn find_file_by_sha256(&self, sha256: &str) -> Result<Option<FileMapping>> {
+        Database::find_file_by_sha256(self, sha256).await
+    }
+
+    async fn claim_blob(&self, sha256: &str, file_size: i64, file_path: &str) -> Result<String> {
+        Database::claim_blob(self, sha256, file_size, file_path).await
+    }
+
+    async fn release_blob(&self, file_path: &str) -> Result<bool> {
+        Database::release_blob(self, file_path).await
+    }
+
+    async fn is_blob(&self, file_path: &str) -> Result<bool> {
+        Database::is_blob(self, file_path).await
+    }
+
+    async fn strip_file_path_prefix(&self, prefix: &str) -> Result<u64> {
+        Database::strip_file_path_prefix(self, prefix).await
+    }
+
+    async fn short_codes_for_file(&self, file_id: Uuid) -> Result<Vec<String>> {
+        Database::short_codes_for_file(self, file_id).await
+    }
+
+    async fn store_short_url(&self, short_code: &str, file_id: Uuid) -> Result<bool> {
+        Database::store_short_url(self, short_code, file_id).await
+    }
+
+    async fn get_file_id_by_short_code(&self, short_code: &str) -> Result<Option<Uuid>> {
+        Database::get_file_id_by_short_code(self, short_code).await
+    }
+
+    async fn get_file_ids_by_short_codes(&self, short_codes: &[&str]) -> Result<Vec<(String, Uuid)>> {
+        Database::get_file_ids_by_short_codes(self, short_codes).await
+    }
+
+    async fn store_bundle(&self, bundle: &Bundle) -> Result<()> {
+        Database::store_bundle(self, bundle).await
+    }
+
+    async fn find_bundle(&self, id_or_short_code: &str) -> Result<Option<Bundle>> {
+        Database::find_bundle(self, id_or_short_code).await
+    }
+
+    async fn check_rate_limit(
+        &self,
+        client_ip: IpAddr,
+        action: RateLimitAction,
+        policy: &RateLimitPolicy,
+    ) -> Result<RateLimitStatus> {
+        Database::check_rate_limit(self, client_ip, action, policy).await
+    }
+
+    async fn cleanup_old_rate_limits(&self) -> Result<i64> {
+        Database::cleanup_old_rate_limits(self).await
+    }
+
+    async fn count_old_rate_limits(&self) -> Result<i64> {
+        Database::count_old_rate_limits(self).await
+    }
+
+    async fn quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>) -> Result<i64> {
+        Database::quota_usage(self, client_ip, window_start).await
+    }
+
+    async fn add_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()> {
+        Database::add_quota_usage(self, client_ip, window_start, bytes).await
+    }
+
+    async fn credit_quota_usage(&self, client_ip: IpAddr, window_start: DateTime<Utc>, bytes: i64) -> Result<()> {
+        Database::credit_quota_usage(self, client_ip, window_start, bytes).await
+    }
+
+    async fn cleanup_old_quotas(&self, current_window: DateTime<Utc>) -> Result<i64> {
+        Database::cleanup_old_quotas(self, current_window).await
+    }
+
+    async fn cleanup_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>> {
+        Database::cleanup_expired_files(self, created_before).await
+    }
+
+    async fn find_expired_files(&self, created_before: Option<DateTime<Utc>>) -> Result<Vec<ExpiredFile>> {
+        Database::find_expired_files(self, created_before).await
+    }
+
+    async fn cleanup_purged_files(&self, retention: Option<chrono::Duration>) -> Result<(u64, Vec<String>)> {
+        Database::cleanup_purged_files(self, retention).await
+    }
+
+    async fn count_purged_files(&self, retention: Option<chrono::Duration>) -> Result<u64> {
+        Database::count_purged_files(self, retention).await
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        scope: &str,
+        key: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<KeyClaim> {
+        Database::claim_idempotency_key(self, scope, key, now, expires_at).await
+    }
+
+    async fn complete_idempotency_key(&self, scope: &str, key: &str, response: &str) -> Result<()> {
+        Database::complete_idempotency_key(self, scope, key, response).await
+    }
+
+    async fn release_idempotency_key(&self, scope: &str, key: &str) -> Result<()> {
+        Database::release_idempotency_key(self, scope, key).await
+    }
+
+    async fn cleanup_expired_idempotency_keys(&self) -> Result<i64> {
+        Database::cleanup_expired_idempotency_keys(self).await
+    }
+
+    async fn store_api_key(&self, api_key: &ApiKey, key_hash: &str) -> Result<()> {
+        Database::store_api_key(self, api_key, key_hash).await
+    }
+
+    async fn find_api_key_by_hash(&self, key_hash: &str) -> Result<Option<ApiKey>> {
+        Database::find_api_key_by_hash(self, key_hash).await
+    }
+
+    async fn find_api_key(&self, id: Uuid) -> Result<Option<ApiKey>> {
+        Database::find_api_key(self, id).await
+    }
+
+    async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        Database::list_api_keys(self).await
+    }
+
+    async fn update_api_key_limits(&self, api_key: &ApiKey) -> Result<bool> {
+        Database::update_api_key_limits(self, api_key).await
+    }
+
+    async fn revoke_api_key(&self, id: Uuid) -> Result<bool> {
+        Database::revoke_api_key(self, id).await
+    }
+
+    async fn api_key_usage(&self, api_key_id: Uuid) -> Result<ApiKeyUsage> {
+        Database::api_key_usage(self, api_key_id).await
+    }
+
+    async fn get_storage_stats(&self, horizon: chrono::Duration) -> Result<StorageTotals> {
+        Database::get_storage_stats(self, horizon).await
+    }
+
+    async fn record_traffic(&self, traffic: &[DailyTraffic]) -> Result<()> {
+        Database::record_traffic(self, traffic).await
+    }
+
+    async fn traffic_since(&self, since: &str) -> Result<Vec<DailyTraffic>> {
+        Database::traffic_since(self, since).await
+    }
+
+    async fn uploads_per_day(&self, since: DateTime<Utc>) -> Result<Vec<(String, i64)>> {
+        Database::uploads_per_day(self, since).await
+    }
+
+    async fn most_downloaded_files(&self, limit: i64) -> Result<Vec<FileMapping>> {
+        Database::most_downloaded_files(self, limit).await
+    }
+
+    async fn file_size_distribution(&self, upper_bounds: &[i64]) -> Result<Vec<SizeBucketCount>> {
+        Database::file_size_distribution(self, upper_bounds).await
+    }
+}
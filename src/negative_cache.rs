@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{aliases, lru::LruCache};
+
+/// Recent lookups that found nothing, so repeated guesses at short codes and
+/// IDs answer 404 without another database query. Entries are dropped when a
+/// file or short code with the same name is created here; other instances
+/// sharing the database only see it once the entry expires.
+#[derive(Clone)]
+pub struct NegativeCache {
+    misses: Arc<Mutex<LruCache<String, ()>>>,
+    hits: Arc<AtomicU64>,
+}
+
+impl NegativeCache {
+    /// A capacity of zero disables the cache.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            misses: Arc::new(Mutex::new(LruCache::new(capacity, ttl))),
+            hits: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Whether `key` recently resolved to nothing. Counts a hit if so.
+    pub fn contains(&self, key: &str) -> bool {
+        let found = match self.misses.lock() {
+            Ok(mut misses) => misses.get(&key.to_string()).is_some(),
+            Err(e) => {
+                error!("Failed to acquire lock on negative lookup cache: {}", e);
+                false
+            }
+        };
+        if found {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    pub fn insert(&self, key: &str) {
+        if let Ok(mut misses) = self.misses.lock() {
+            misses.insert(key.to_string(), ());
+        }
+    }
+
+    pub fn remove(&self, key: &str) {
+        if let Ok(mut misses) = self.misses.lock() {
+            misses.remove(&key.to_string());
+        }
+    }
+
+    /// Lookups answered from the cache since startup.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether `input` could name a file at all: a UUID, or a short code or
+/// alias. Anything else is a 404 without looking it up.
+pub fn could_be_file_id(input: &str) -> bool {
+    input.parse::<Uuid>().is_ok() || aliases::is_short_code_syntax(input)
+}
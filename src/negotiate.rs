@@ -0,0 +1,89 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderMap, header, request::Parts},
+    response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::convert::Infallible;
+
+/// The body format a client asked for in its `Accept` header: JSON unless it
+/// prefers `text/plain`. Usable as an extractor by any handler.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResponseFormat {
+    #[default]
+    Json,
+    Text,
+}
+
+impl ResponseFormat {
+    /// Plain text only when it is ranked strictly above JSON, so `*/*` and a
+    /// missing header keep the JSON default.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        if accept.trim().is_empty() {
+            return Self::Json;
+        }
+
+        let json = quality(&accept, "application", "json");
+        let text = quality(&accept, "text", "plain");
+        if text > json { Self::Text } else { Self::Json }
+    }
+
+    /// Build a success response: the body as JSON, or its plain-text form.
+    pub fn respond<T: Serialize + PlainText>(self, body: T) -> Response {
+        match self {
+            Self::Json => Json(body).into_response(),
+            Self::Text => text_response(body.plain_text()),
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// How a response body reads for shell users who asked for `text/plain`.
+pub trait PlainText {
+    fn plain_text(&self) -> String;
+}
+
+/// A `text/plain` response ending in a newline, so it prints cleanly.
+pub fn text_response(mut text: String) -> Response {
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response()
+}
+
+// The q-value the Accept header gives `kind/subtype`, taken from the most
+// specific range that matches it; zero if none does
+fn quality(accept: &str, kind: &str, subtype: &str) -> f32 {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';');
+            let media = params.next()?.trim().to_ascii_lowercase();
+            let specificity = match media.split_once('/')? {
+                ("*", "*") => 0,
+                (range_kind, "*") if range_kind == kind => 1,
+                (range_kind, range_subtype) if range_kind == kind && range_subtype == subtype => 2,
+                _ => return None,
+            };
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((specificity, q.clamp(0.0, 1.0)))
+        })
+        .max_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map_or(0.0, |(_, q)| q)
+}
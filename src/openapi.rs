@@ -0,0 +1,104 @@
+use axum::{Json, Router};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    AppState, admin, aliases, api_keys, bundles, cleanup, extend, gc, health, maintenance, preview, scrub, sessions,
+    stats,
+};
+
+/// The API as served, for `/openapi.json` and Swagger UI.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "drop", description = "File sharing with short URLs"),
+    paths(
+        crate::health_check,
+        health::liveness,
+        health::readiness,
+        crate::upload_file,
+        crate::upload_raw,
+        crate::upload_raw_named,
+        crate::download_file,
+        crate::head_file,
+        crate::download_file_named,
+        crate::head_file_named,
+        crate::delete_file,
+        crate::batch_delete,
+        crate::file_info,
+        crate::file_info_by_hash,
+        crate::batch_file_info,
+        preview::preview_file,
+        aliases::add_alias,
+        extend::extend_file,
+        bundles::create_bundle,
+        bundles::download_bundle,
+        sessions::create_session,
+        sessions::get_session,
+        sessions::append_chunk,
+        sessions::complete_session,
+        gc::run_orphan_gc,
+        cleanup::trigger_cleanup,
+        cleanup::last_cleanup,
+        scrub::list_corrupt_files,
+        admin::list_files,
+        admin::purge_files,
+        admin::delete_file,
+        admin::pin_file,
+        admin::unpin_file,
+        maintenance::set_mode,
+        stats::get_stats,
+        api_keys::create_api_key,
+        api_keys::list_api_keys,
+        api_keys::update_api_key,
+        api_keys::revoke_api_key,
+        api_keys::list_my_files,
+        api_keys::delete_my_file,
+    ),
+    components(schemas(ErrorResponse, crate::InvalidOptionsResponse)),
+    tags(
+        (name = "files", description = "Uploading, downloading and deleting files"),
+        (name = "sessions", description = "Resumable uploads"),
+        (name = "bundles", description = "Several files behind one link"),
+        (name = "admin", description = "Operator endpoints; require X-Admin-Token"),
+        (name = "health", description = "Status and orchestrator probes"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// The body of an error response. Most errors carry only these two fields;
+/// the ones with more (such as a quota or rule rejection) are described on
+/// their endpoints.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    error: String,
+    request_id: String, // As sent in X-Request-Id
+}
+
+/// A multipart upload. Every part with a filename is stored; the other
+/// fields may come before or after the files, or in the query string. A
+/// file part may set its own options as JSON in an `Upload-Options` header.
+#[derive(ToSchema)]
+#[allow(dead_code)] // Only describes the form
+pub struct UploadForm {
+    #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>, // Repeat the part for several files
+    expires_in: Option<String>, // Seconds until the files expire
+    max_downloads: Option<String>,
+    alias: Option<String>,   // Short code for a single-file upload
+    embeddable: Option<String>, // "true" lets other sites link to the files despite DROP_ALLOWED_REFERERS
+    options: Option<String>, // All of the above as one JSON object
+}
+
+// GET /openapi.json
+pub async fn spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI under /docs, reading the spec through a relative URL so it
+/// also works behind a proxy under a path prefix.
+pub fn swagger_ui() -> Router<AppState> {
+    SwaggerUi::new("/docs")
+        .config(utoipa_swagger_ui::Config::from("../openapi.json"))
+        .into()
+}
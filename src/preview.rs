@@ -0,0 +1,224 @@
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use tokio::io::AsyncReadExt;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState, FileInfoResponse, FileMeta, file_data_is_gone, format_http_date, format_size, get_client_ip,
+    concurrency, mapping_is_gone, openapi::ErrorResponse, resolve_id_or_short_code_db, serve_file,
+    rate_limit::{RateLimitAction, check_rate_limit},
+    storage::{StorageBackend, StorageRef},
+    throttle, transfer_rate,
+};
+
+// Sniffed types a browser can show directly without running anything
+const INLINE_PREVIEW_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/bmp",
+    "application/pdf",
+];
+
+// Sniffed markup that is still shown as escaped source text
+const TEXT_PREVIEW_TYPES: &[&str] = &["text/html", "image/svg+xml"];
+
+// The preview page never runs script; styles are inline
+const PREVIEW_PAGE_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'";
+
+// GET /drop/{id}/preview - show a file in the browser instead of downloading it.
+// Read-only: does not count as a download.
+#[utoipa::path(
+    get,
+    path = "/drop/{id}/preview",
+    tag = "files",
+    params(("id" = String, Path, description = "File ID or short code")),
+    responses(
+        (status = 200, description = "A page showing the file; not counted as a download", content_type = "text/html"),
+        (status = 404, description = "No such file", body = ErrorResponse),
+        (status = 410, description = "Expired or out of downloads", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, request_headers))]
+pub async fn preview_file(
+    Path(id): Path<String>,
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request_headers: HeaderMap,
+) -> Response {
+    let client_ip = get_client_ip(&app_state.config, addr, &request_headers);
+    let rate_limit = match check_rate_limit(client_ip, RateLimitAction::Download, &app_state).await {
+        Ok(rate_limit) => rate_limit,
+        Err(response) => return response,
+    };
+
+    let response = render_preview(&id, &app_state, &request_headers).await;
+    let response = throttle::limit_download(&app_state, client_ip, response);
+    let response = transfer_rate::limit_download_idle(&app_state.config, response);
+    (rate_limit, concurrency::track_download(response)).into_response()
+}
+
+async fn render_preview(id: &str, app_state: &AppState, request_headers: &HeaderMap) -> Response {
+    let Some(uuid) = resolve_id_or_short_code_db(id, app_state).await else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let (info, storage_ref) = match find_file(app_state, uuid).await {
+        Ok(found) => found,
+        Err(status) => return status.into_response(),
+    };
+
+    // Showing the contents of a limited file would hand them out uncounted
+    let previewable = info.max_downloads.is_none() && info.size <= app_state.config.preview_max_size;
+    let Some(storage_ref) = storage_ref.filter(|_| previewable) else {
+        return preview_page(&info, None);
+    };
+    let object = match app_state.storage.get(&storage_ref).await {
+        Ok(object) => object,
+        Err(e) => {
+            error!("Failed to open stored file {} for preview: {:?}", uuid, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Only what the contents look like counts, never the declared type
+    match info.detected_content_type.as_deref() {
+        Some(sniffed) if INLINE_PREVIEW_TYPES.contains(&sniffed) => {
+            info!("Previewing {} inline as {}", uuid, sniffed);
+            let meta = FileMeta {
+                content_type: sniffed.to_string(),
+                filename: info.filename.clone(),
+                etag: info.hash.as_ref().map(|hash| format!("\"{}\"", hash)),
+                last_modified: info.created_at,
+                inline: true,
+            };
+            serve_file(&app_state.config, request_headers, &meta, object).await
+        }
+        sniffed if is_text_candidate(sniffed) => {
+            let mut contents = Vec::with_capacity(info.size as usize);
+            let limit = app_state.config.preview_max_size;
+            if let Err(e) = object.reader.take(limit).read_to_end(&mut contents).await {
+                error!("Failed to read stored file {} for preview: {:?}", uuid, e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+            match String::from_utf8(contents) {
+                Ok(text) if !text.contains('\0') => preview_page(&info, Some(&text)),
+                _ => preview_page(&info, None),
+            }
+        }
+        _ => preview_page(&info, None),
+    }
+}
+
+fn is_text_candidate(sniffed: Option<&str>) -> bool {
+    sniffed.is_none_or(|sniffed| TEXT_PREVIEW_TYPES.contains(&sniffed))
+}
+
+// Metadata and storage location of a live file, database first, using the read-only lookup
+async fn find_file(app_state: &AppState, uuid: Uuid) -> Result<(FileInfoResponse, Option<StorageRef>), StatusCode> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(uuid).await {
+            Ok(Some(file_mapping)) => {
+                if mapping_is_gone(&file_mapping) {
+                    return Err(StatusCode::GONE);
+                }
+                let storage_ref = StorageRef::from_mapping(&file_mapping, &app_state.config.temp_directory);
+                return Ok((FileInfoResponse::from_mapping(file_mapping), storage_ref));
+            }
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let lookup = app_state.file_storage.get(&uuid).map(|file_data| file_data.value().clone());
+
+    let file_data = lookup.ok_or(StatusCode::NOT_FOUND)?;
+    if file_data_is_gone(&file_data) {
+        return Err(StatusCode::GONE);
+    }
+    let storage_ref = file_data.storage.clone();
+    Ok((FileInfoResponse::from_file_data(uuid, file_data), storage_ref))
+}
+
+// Everything user-supplied on the page goes through here
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// A metadata card with a download button, plus the text contents if given
+fn preview_page(info: &FileInfoResponse, text: Option<&str>) -> Response {
+    let filename = escape_html(&info.filename);
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        concat!(
+            "<!doctype html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n",
+            "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n",
+            "<title>{filename}</title>\n<style>\n",
+            "  :root {{ color-scheme: light dark; }}\n",
+            "  body {{ font-family: system-ui, sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; }}\n",
+            "  .card {{ border: 1px solid #8884; border-radius: 0.75rem; padding: 1rem 1.5rem; }}\n",
+            "  h1 {{ font-size: 1.3rem; overflow-wrap: anywhere; }}\n",
+            "  dl {{ display: grid; grid-template-columns: max-content 1fr; gap: 0.3rem 1rem; }}\n",
+            "  dd {{ margin: 0; }}\n",
+            "  a.button {{ display: inline-block; padding: 0.5rem 1rem; border-radius: 0.5rem; ",
+            "background: #2f7de1; color: #fff; text-decoration: none; }}\n",
+            "  pre {{ border: 1px solid #8884; border-radius: 0.5rem; padding: 1rem; overflow: auto; }}\n",
+            "</style>\n</head>\n<body>\n<div class=\"card\">\n<h1>{filename}</h1>\n<dl>\n",
+            "<dt>Size</dt><dd>{size}</dd>\n<dt>Type</dt><dd>{content_type}</dd>\n",
+            "<dt>Uploaded</dt><dd>{created_at}</dd>\n",
+        ),
+        filename = filename,
+        size = format_size(info.size as usize),
+        content_type = escape_html(&info.content_type),
+        created_at = format_http_date(info.created_at),
+    );
+    if let Some(expires_at) = info.expires_at {
+        let _ = writeln!(page, "<dt>Expires</dt><dd>{}</dd>", format_http_date(expires_at));
+    }
+    // Relative to /drop/{id}/preview, so it also works under a path prefix
+    let _ = write!(
+        page,
+        "</dl>\n<a class=\"button\" href=\"../{}\">Download</a>\n</div>\n",
+        info.id
+    );
+    if let Some(text) = text {
+        let _ = writeln!(page, "<pre>{}</pre>", escape_html(text));
+    }
+    page.push_str("</body>\n</html>\n");
+
+    (
+        [
+            (header::CONTENT_SECURITY_POLICY, PREVIEW_PAGE_CSP),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        Html(page),
+    )
+        .into_response()
+}
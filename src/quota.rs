@@ -0,0 +1,254 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Days, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tracing::{error, warn};
+
+use crate::database::ApiKey;
+use crate::{AppState, api_keys, format_size};
+
+// Daily upload quotas: IP -> (window start, bytes uploaded) (fallback)
+pub type QuotaStorage = Arc<Mutex<HashMap<IpAddr, (DateTime<Utc>, u64)>>>;
+
+/// Whose quota an upload counts against: the API key it was made with, or
+/// for an anonymous upload the client IP's daily quota.
+#[derive(Clone, Debug)]
+pub enum QuotaOwner {
+    Ip(IpAddr),
+    ApiKey(ApiKey),
+}
+
+impl QuotaOwner {
+    pub fn new(client_ip: IpAddr, api_key: Option<ApiKey>) -> Self {
+        match api_key {
+            Some(api_key) => QuotaOwner::ApiKey(api_key),
+            None => QuotaOwner::Ip(client_ip),
+        }
+    }
+
+    pub fn api_key_id(&self) -> Option<uuid::Uuid> {
+        match self {
+            QuotaOwner::Ip(_) => None,
+            QuotaOwner::ApiKey(api_key) => Some(api_key.id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct QuotaExceededResponse {
+    error: String,
+    quota_bytes: u64,
+    remaining_bytes: u64,
+    resets_at: DateTime<Utc>,
+}
+
+/// Start of the quota window `now` falls in; windows run from midnight UTC.
+pub fn window_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.date_naive()
+        .and_hms_opt(0, 0, 0)
+        .map(|midnight| midnight.and_utc())
+        .unwrap_or(now)
+}
+
+fn window_reset(window_start: DateTime<Utc>) -> DateTime<Utc> {
+    window_start.checked_add_days(Days::new(1)).unwrap_or(window_start)
+}
+
+// Bytes `client_ip` has uploaded in the current window, database first with in-memory fallback
+async fn quota_usage(app_state: &AppState, client_ip: IpAddr, window: DateTime<Utc>) -> u64 {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.quota_usage(client_ip, window).await {
+            Ok(bytes_used) => return bytes_used.max(0) as u64,
+            Err(e) => {
+                warn!("Database quota check failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    match app_state.quota_storage.lock() {
+        Ok(storage) => match storage.get(&client_ip) {
+            Some(&(start, bytes_used)) if start == window => bytes_used,
+            _ => 0,
+        },
+        Err(e) => {
+            error!("Failed to acquire lock on quota storage: {}", e);
+            0
+        }
+    }
+}
+
+/// Bytes `client_ip` may still upload today, or None when no quota is configured.
+pub async fn remaining_quota(app_state: &AppState, client_ip: IpAddr) -> Option<u64> {
+    let quota = app_state.config.quota_per_ip_per_day?;
+    let used = quota_usage(app_state, client_ip, window_start(Utc::now())).await;
+    Some(quota.saturating_sub(used))
+}
+
+/// The 429 sent when an upload would go over `client_ip`'s daily quota.
+pub fn quota_exceeded(app_state: &AppState, client_ip: IpAddr, remaining_bytes: u64) -> Response {
+    let quota_bytes = app_state.config.quota_per_ip_per_day.unwrap_or_default();
+    warn!(
+        "Upload quota exceeded for IP: {} ({} of {} left)",
+        client_ip,
+        format_size(remaining_bytes as usize),
+        format_size(quota_bytes as usize)
+    );
+    let body = QuotaExceededResponse {
+        error: "Daily upload quota exceeded".to_string(),
+        quota_bytes,
+        remaining_bytes,
+        resets_at: window_reset(window_start(Utc::now())),
+    };
+    (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response()
+}
+
+/// Refuse an upload up front when the `projected` bytes the client declared
+/// won't fit in what is left of its quota. Returns the remaining quota so
+/// streaming can stop at it.
+pub async fn check_quota(app_state: &AppState, client_ip: IpAddr, projected: u64) -> Result<Option<u64>, Response> {
+    let Some(remaining) = remaining_quota(app_state, client_ip).await else {
+        return Ok(None);
+    };
+    if remaining == 0 || projected > remaining {
+        return Err(quota_exceeded(app_state, client_ip, remaining));
+    }
+    Ok(Some(remaining))
+}
+
+/// `check_quota` for whichever quota `owner` has: an upload of `files` files
+/// and `projected` bytes.
+pub async fn check_upload_quota(app_state: &AppState, owner: &QuotaOwner, projected: u64, files: usize) -> Result<Option<u64>, Response> {
+    match owner {
+        QuotaOwner::Ip(client_ip) => check_quota(app_state, *client_ip, projected).await,
+        QuotaOwner::ApiKey(api_key) => api_keys::check_quota(app_state, api_key, projected, files).await,
+    }
+}
+
+/// Bytes `owner` may still upload, or None when it has no quota.
+pub async fn remaining_upload_quota(app_state: &AppState, owner: &QuotaOwner) -> Result<Option<u64>, StatusCode> {
+    match owner {
+        QuotaOwner::Ip(client_ip) => Ok(remaining_quota(app_state, *client_ip).await),
+        QuotaOwner::ApiKey(api_key) => api_keys::remaining_quota(app_state, api_key).await,
+    }
+}
+
+/// The 429 sent when an upload would go over `owner`'s quota.
+pub fn upload_quota_exceeded(app_state: &AppState, owner: &QuotaOwner, remaining_bytes: u64) -> Response {
+    match owner {
+        QuotaOwner::Ip(client_ip) => quota_exceeded(app_state, *client_ip, remaining_bytes),
+        QuotaOwner::ApiKey(api_key) => api_keys::quota_exceeded(api_key, Some(remaining_bytes), None),
+    }
+}
+
+/// Cap a file's size limit at what is left of the quota, so streaming stops there.
+pub fn cap_upload_size(max_size: usize, quota_left: Option<u64>) -> usize {
+    quota_left.map_or(max_size, |left| max_size.min(left.try_into().unwrap_or(usize::MAX)))
+}
+
+/// Response for a failed upload stream: a 413 that only happened because of the
+/// quota cap becomes the quota's 429.
+pub fn upload_stream_error(
+    app_state: &AppState,
+    status: StatusCode,
+    owner: &QuotaOwner,
+    quota_left: Option<u64>,
+    max_size: usize,
+) -> Response {
+    match quota_left {
+        Some(left) if status == StatusCode::PAYLOAD_TOO_LARGE && cap_upload_size(max_size, quota_left) < max_size => {
+            upload_quota_exceeded(app_state, owner, left)
+        }
+        _ => status.into_response(),
+    }
+}
+
+/// Charge `bytes` of stored uploads to `client_ip`.
+pub async fn charge_quota(app_state: &AppState, client_ip: IpAddr, bytes: u64) {
+    if app_state.config.quota_per_ip_per_day.is_none() || bytes == 0 {
+        return;
+    }
+    let window = window_start(Utc::now());
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.add_quota_usage(client_ip, window, bytes as i64).await {
+            Ok(()) => return,
+            Err(e) => {
+                warn!("Failed to record quota usage in database, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    match app_state.quota_storage.lock() {
+        Ok(mut storage) => {
+            let entry = storage.entry(client_ip).or_insert((window, 0));
+            if entry.0 != window {
+                *entry = (window, 0);
+            }
+            entry.1 += bytes;
+        }
+        Err(e) => error!("Failed to acquire lock on quota storage: {}", e),
+    }
+}
+
+/// Give back the quota charged for a deleted file uploaded by `client_ip` at
+/// `uploaded_at`. Files from an earlier window are no longer counted.
+pub async fn credit_quota(app_state: &AppState, client_ip: IpAddr, uploaded_at: DateTime<Utc>, bytes: u64) {
+    if app_state.config.quota_per_ip_per_day.is_none() || bytes == 0 {
+        return;
+    }
+    let window = window_start(uploaded_at);
+    if window != window_start(Utc::now()) {
+        return;
+    }
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.credit_quota_usage(client_ip, window, bytes as i64).await {
+            Ok(()) => return,
+            Err(e) => {
+                warn!("Failed to credit quota in database, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    match app_state.quota_storage.lock() {
+        Ok(mut storage) => {
+            if let Some(entry) = storage.get_mut(&client_ip)
+                && entry.0 == window
+            {
+                entry.1 = entry.1.saturating_sub(bytes);
+            }
+        }
+        Err(e) => error!("Failed to acquire lock on quota storage: {}", e),
+    }
+}
+
+/// Drop fallback records from earlier windows.
+pub fn cleanup_quotas(quota_storage: &QuotaStorage) -> usize {
+    let window = window_start(Utc::now());
+    match quota_storage.lock() {
+        Ok(mut storage) => {
+            let before = storage.len();
+            storage.retain(|_, (start, _)| *start == window);
+            before - storage.len()
+        }
+        Err(e) => {
+            error!("Failed to acquire lock on quota storage during cleanup: {}", e);
+            0
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use axum::{
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
+};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::database::ApiKey;
+use crate::{AppState, Config, RateLimitStorage, client_ip::in_ranges};
+
+pub(crate) const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+pub(crate) const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+pub(crate) const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// What a request is being counted against. Uploads and downloads are limited
+/// separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RateLimitAction {
+    Upload,
+    Download,
+}
+
+impl RateLimitAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitAction::Upload => "upload",
+            RateLimitAction::Download => "download",
+        }
+    }
+
+    /// The bucket this action is limited by: `rpm` requests refilled per
+    /// window, with bursts of up to the configured burst (one window's worth by default).
+    pub fn policy(&self, config: &Config) -> RateLimitPolicy {
+        let (rpm, burst) = match self {
+            RateLimitAction::Upload => (config.rate_limit_upload_rpm, config.rate_limit_upload_burst),
+            RateLimitAction::Download => (config.rate_limit_download_rpm, config.rate_limit_download_burst),
+        };
+        RateLimitPolicy {
+            burst: burst.unwrap_or(rpm),
+            refill_per_second: f64::from(rpm) / config.rate_limit_window_seconds.max(1) as f64,
+        }
+    }
+}
+
+impl fmt::Display for RateLimitAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Token-bucket limit: a client's bucket holds up to `burst` tokens, refills
+/// continuously at `refill_per_second`, and each request takes one token.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitPolicy {
+    pub burst: u32,
+    pub refill_per_second: f64,
+}
+
+impl RateLimitPolicy {
+    /// This policy with its burst and refill rate multiplied, as an API key's
+    /// multiplier does.
+    pub fn scaled(&self, multiplier: f64) -> Self {
+        Self {
+            burst: (f64::from(self.burst) * multiplier).round().clamp(0.0, f64::from(u32::MAX)) as u32,
+            refill_per_second: self.refill_per_second * multiplier,
+        }
+    }
+
+    /// Tokens in a bucket that held `tokens` when last refilled `elapsed` ago.
+    pub fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        (tokens + elapsed.as_secs_f64() * self.refill_per_second).min(f64::from(self.burst))
+    }
+
+    /// Whole seconds for the bucket to gain `tokens`, rounded up.
+    pub fn seconds_to_refill(&self, tokens: f64) -> u64 {
+        if tokens <= 0.0 || self.refill_per_second <= 0.0 {
+            return 0;
+        }
+        (tokens / self.refill_per_second).ceil() as u64
+    }
+
+    /// Status of a request that left `tokens` in the bucket. A refused request
+    /// resets when the next token arrives, an allowed one when the bucket is full again.
+    pub fn status(&self, tokens: f64, allowed: bool) -> RateLimitStatus {
+        let reset_after = if allowed {
+            self.seconds_to_refill(f64::from(self.burst) - tokens)
+        } else {
+            self.seconds_to_refill(1.0 - tokens)
+        };
+        RateLimitStatus {
+            limit: self.burst,
+            remaining: tokens.max(0.0).floor() as u32,
+            reset_after,
+            allowed,
+        }
+    }
+}
+
+/// A client's bucket in the in-memory fallback.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(policy: &RateLimitPolicy, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(policy.burst),
+            refilled_at: now,
+        }
+    }
+
+    /// Refill up to `now` and take a token for a request, if there is one.
+    pub fn take(&mut self, policy: &RateLimitPolicy, now: Instant) -> RateLimitStatus {
+        self.tokens = policy.refill(self.tokens, now.saturating_duration_since(self.refilled_at));
+        self.refilled_at = now;
+
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+        policy.status(self.tokens, allowed)
+    }
+}
+
+/// Where a client stands against its rate limit. Returned from handlers to
+/// send the `X-RateLimit-*` headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_after: u64, // Seconds until the limit resets
+    pub allowed: bool,
+}
+
+impl RateLimitStatus {
+
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(X_RATELIMIT_RESET, HeaderValue::from(self.reset_after));
+        if !self.allowed {
+            // Never tell a client to retry immediately while it is still limited
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(self.reset_after.max(1)));
+        }
+    }
+}
+
+impl IntoResponseParts for RateLimitStatus {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        self.insert_headers(res.headers_mut());
+        Ok(res)
+    }
+}
+
+/// Clients exempt from rate limiting unless `DROP_RATE_LIMIT_ALLOWLIST` says otherwise: localhost.
+pub fn default_allowlist() -> Vec<IpNet> {
+    vec![
+        Ipv4Net::new_assert(Ipv4Addr::new(127, 0, 0, 0), 8).into(),
+        Ipv6Net::new_assert(Ipv6Addr::LOCALHOST, 128).into(),
+    ]
+}
+
+/// Whether `client_ip` is on the allowlist.
+pub fn is_exempt(config: &Config, client_ip: IpAddr) -> bool {
+    in_ranges(&config.rate_limit_allowlist, client_ip)
+}
+
+/// Count an `action` request from `client_ip`: Redis first, then the database,
+/// then the in-memory fallback. Fails with a 429 carrying `Retry-After` once
+/// the client is over the limit. Allowlisted clients aren't counted and get no status.
+pub async fn check_rate_limit(
+    client_ip: IpAddr,
+    action: RateLimitAction,
+    app_state: &AppState,
+) -> Result<Option<RateLimitStatus>, Response> {
+    check_key_rate_limit(client_ip, None, action, app_state).await
+}
+
+/// `check_rate_limit` for a request that may carry an API key, whose
+/// multiplier scales the limit.
+pub async fn check_key_rate_limit(
+    client_ip: IpAddr,
+    api_key: Option<&ApiKey>,
+    action: RateLimitAction,
+    app_state: &AppState,
+) -> Result<Option<RateLimitStatus>, Response> {
+    if is_exempt(&app_state.config, client_ip) {
+        return Ok(None);
+    }
+
+    let mut policy = action.policy(&app_state.config);
+    if let Some(api_key) = api_key {
+        policy = policy.scaled(api_key.rate_limit_multiplier);
+    }
+    let status = rate_limit_status(client_ip, action, &policy, app_state).await;
+    if !status.allowed {
+        warn!("Rate limit exceeded for IP: {} ({})", client_ip, action);
+        return Err((StatusCode::TOO_MANY_REQUESTS, status, "Rate limit exceeded").into_response());
+    }
+    Ok(Some(status))
+}
+
+async fn rate_limit_status(
+    client_ip: IpAddr,
+    action: RateLimitAction,
+    policy: &RateLimitPolicy,
+    app_state: &AppState,
+) -> RateLimitStatus {
+    if policy.burst == 0 {
+        // Nothing can ever be taken from an empty bucket
+        return policy.status(0.0, false);
+    }
+
+    // Try Redis first if available and healthy
+    if let Some(ref redis) = app_state.redis
+        && app_state.redis_healthy.load(std::sync::atomic::Ordering::Relaxed)
+    {
+        match redis.check_rate_limit(
+            client_ip,
+            action,
+            policy,
+        ).await {
+            Ok(status) => return status,
+            Err(e) => {
+                warn!("Redis rate limit check failed, falling back to database: {}", e);
+                app_state.redis_healthy.store(false, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Then the database
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.check_rate_limit(
+            client_ip,
+            action,
+            policy,
+        ).await {
+            Ok(status) => return status,
+            Err(e) => {
+                warn!("Database rate limit check failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    // Fallback to in-memory rate limiting
+    check_rate_limit_memory(client_ip, action, &app_state.rate_limit_storage, policy)
+}
+
+// In-memory rate limiting (fallback)
+fn check_rate_limit_memory(
+    client_ip: IpAddr,
+    action: RateLimitAction,
+    rate_storage: &RateLimitStorage,
+    policy: &RateLimitPolicy,
+) -> RateLimitStatus {
+    let now = Instant::now();
+    let mut bucket = rate_storage
+        .entry((client_ip, action))
+        .or_insert_with(|| TokenBucket::new(policy, now));
+    bucket.take(policy, now)
+}
+
+// Buckets idle this long are dropped, as `cleanup_old_rate_limits` does in the database
+const RATE_LIMIT_RETENTION: Duration = Duration::from_secs(10 * 60);
+
+fn rate_limit_retention(config: &Config) -> Duration {
+    RATE_LIMIT_RETENTION.max(Duration::from_secs(config.rate_limit_window_seconds.saturating_mul(3)))
+}
+
+/// Drop fallback buckets that have been idle for a few windows, and at least
+/// as long as the database keeps them. Returns how many were removed.
+pub fn cleanup_rate_limits(rate_storage: &RateLimitStorage, config: &Config) -> usize {
+    let retention = rate_limit_retention(config);
+    let now = Instant::now();
+    let before = rate_storage.len();
+    rate_storage.retain(|_, bucket| now.saturating_duration_since(bucket.refilled_at) < retention);
+    before.saturating_sub(rate_storage.len())
+}
+
+/// How many fallback buckets `cleanup_rate_limits` would drop now.
+pub fn idle_rate_limits(rate_storage: &RateLimitStorage, config: &Config) -> usize {
+    let retention = rate_limit_retention(config);
+    let now = Instant::now();
+    rate_storage
+        .iter()
+        .filter(|bucket| now.saturating_duration_since(bucket.refilled_at) >= retention)
+        .count()
+}
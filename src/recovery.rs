@@ -0,0 +1,590 @@
+use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::database::NewFileMapping;
+use crate::metadata::MetadataStore;
+use crate::storage::{LocalDiskBackend, StorageBackend, StorageRef, recorded_path};
+use crate::{AppState, ContentHasher, FileData, format_size, generate_delete_token, sniff};
+
+// Prefix of every stored upload in the temp directory: file_<uuid>
+pub(crate) const FILE_PREFIX: &str = "file_";
+// Suffix of an upload still being written: file_<uuid>.part
+pub(crate) const PART_SUFFIX: &str = ".part";
+
+/// Metadata written next to each disk-backed file (`file_<uuid>.json`) so the
+/// file can be put back in the index after a restart.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileSidecar {
+    pub short_code: String,
+    pub filename: String,
+    pub content_type: String,
+    #[serde(default)]
+    pub declared_content_type: Option<String>,
+    #[serde(default)]
+    pub detected_content_type: Option<String>,
+    pub delete_token: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub max_downloads: Option<i32>,
+    pub content_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader_ip: Option<IpAddr>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_id: Option<Uuid>,
+    #[serde(default)]
+    pub embeddable: bool,
+}
+
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    file_path.with_extension("json")
+}
+
+pub(crate) async fn write_sidecar(file_path: &Path, sidecar: &FileSidecar) -> std::io::Result<()> {
+    let contents = serde_json::to_vec_pretty(sidecar)?;
+    tokio::fs::write(sidecar_path(file_path), contents).await
+}
+
+// Keep a sidecar's expiry in step with the index, so a restart doesn't undo an extension
+pub(crate) async fn update_sidecar_expiry(file_path: &Path, expires_at: Option<DateTime<Utc>>) -> std::io::Result<()> {
+    update_sidecar(file_path, |sidecar| sidecar.expires_at = expires_at).await
+}
+
+// Likewise for pinning
+pub(crate) async fn update_sidecar_pinned(file_path: &Path, pinned: bool) -> std::io::Result<()> {
+    update_sidecar(file_path, |sidecar| sidecar.pinned = pinned).await
+}
+
+async fn update_sidecar(file_path: &Path, change: impl FnOnce(&mut FileSidecar)) -> std::io::Result<()> {
+    let Some(mut sidecar) = read_sidecar(file_path).await else {
+        return Ok(());
+    };
+    change(&mut sidecar);
+    write_sidecar(file_path, &sidecar).await
+}
+
+async fn read_sidecar(file_path: &Path) -> Option<FileSidecar> {
+    let path = sidecar_path(file_path);
+    let contents = match tokio::fs::read(&path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read sidecar {:?}: {:?}", path, e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&contents) {
+        Ok(sidecar) => Some(sidecar),
+        Err(e) => {
+            warn!("Ignoring malformed sidecar {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Hash stored contents the same way uploads are hashed while streaming
+pub(crate) async fn hash_reader<R: AsyncRead + Unpin>(mut reader: R) -> std::io::Result<ContentHasher> {
+    let mut hasher = ContentHasher::default();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher)
+}
+
+// Rebuild metadata for a file whose sidecar is missing or unreadable
+async fn synthesize_sidecar(
+    disk: &LocalDiskBackend,
+    id: Uuid,
+    path: &Path,
+    short_code: String,
+) -> std::io::Result<FileSidecar> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let filename = id.to_string();
+    // Read through the backend, which decrypts encrypted files
+    let mut object = disk.get(&StorageRef::Disk(path.to_path_buf())).await?;
+    let detected_content_type = sniff::sniff_reader(&mut object.reader).await?;
+    object.reader.seek(SeekFrom::Start(0)).await?;
+    let hasher = hash_reader(&mut object.reader).await?;
+    let created_at = metadata
+        .modified()
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now());
+
+    Ok(FileSidecar {
+        short_code,
+        content_type: sniff::resolve_content_type(&filename, None, detected_content_type),
+        filename,
+        declared_content_type: None,
+        detected_content_type: detected_content_type.map(str::to_string),
+        delete_token: generate_delete_token(),
+        expires_at: None,
+        max_downloads: None,
+        content_hash: hasher.content_hash(),
+        sha256: Some(hasher.sha256()),
+        created_at,
+        uploader_ip: None,
+        pinned: false,
+        api_key_id: None,
+        embeddable: false,
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    pub recovered: usize,
+    pub orphans: usize,
+    pub partial_removed: usize, // Uploads interrupted by the previous run
+}
+
+enum Registered {
+    Known,
+    Recovered,
+}
+
+// Put one recovered file back in the index: the database when it is healthy,
+// otherwise the in-memory fallback
+async fn register_recovered(
+    app_state: &AppState,
+    id: Uuid,
+    path: &Path,
+    sidecar: &FileSidecar,
+) -> Result<Registered, String> {
+    let storage_ref = StorageRef::Disk(path.to_path_buf());
+    let file_size = app_state
+        .storage
+        .size(&storage_ref)
+        .await
+        .map_err(|e| format!("failed to read size: {:?}", e))?;
+    let encrypted = app_state.storage.is_encrypted(&storage_ref).await;
+
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        let file_path = recorded_path(&app_state.config.temp_directory, path);
+        match db.find_file_mapping(id).await {
+            Ok(Some(_)) => return Ok(Registered::Known),
+            // Its own mapping is gone, but duplicate uploads still share the contents
+            Ok(None) if db.is_blob(&file_path.to_string_lossy()).await.unwrap_or(true) => {
+                return Ok(Registered::Known);
+            }
+            Ok(None) => {
+                let stored = db
+                    .store_file_mapping(&NewFileMapping {
+                        id,
+                        filename: &sidecar.filename,
+                        content_type: &sidecar.content_type,
+                        declared_content_type: sidecar.declared_content_type.as_deref(),
+                        detected_content_type: sidecar.detected_content_type.as_deref(),
+                        file_path: Some(&file_path),
+                        file_size: file_size as i64,
+                        is_in_memory: false,
+                        expires_at: sidecar.expires_at,
+                        delete_token: &sidecar.delete_token,
+                        max_downloads: sidecar.max_downloads,
+                        content_hash: &sidecar.content_hash,
+                        sha256: sidecar.sha256.as_deref(),
+                        created_at: sidecar.created_at,
+                        access_count: 0,
+                        uploader_ip: sidecar.uploader_ip,
+                        encrypted,
+                        pinned: sidecar.pinned,
+                        api_key_id: sidecar.api_key_id,
+                        embeddable: sidecar.embeddable,
+                    })
+                    .await;
+                match stored {
+                    Ok(_) => {
+                        match db.store_short_url(&sidecar.short_code, id).await {
+                            Ok(true) => {}
+                            Ok(false) => warn!(
+                                "Short code {} is taken by another file; {} stays reachable by ID",
+                                sidecar.short_code, id
+                            ),
+                            Err(e) => {
+                                warn!("Failed to restore short code {} for {}: {}", sidecar.short_code, id, e)
+                            }
+                        }
+                        return Ok(Registered::Recovered);
+                    }
+                    Err(e) => {
+                        warn!("Failed to restore file mapping in database, falling back to memory: {}", e);
+                        app_state.set_database_healthy(false);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let file_data = FileData {
+        filename: sidecar.filename.clone(),
+        content_type: sidecar.content_type.clone(),
+        declared_content_type: sidecar.declared_content_type.clone(),
+        detected_content_type: sidecar.detected_content_type.clone(),
+        storage: Some(storage_ref),
+        size: file_size,
+        delete_token: sidecar.delete_token.clone(),
+        expires_at: sidecar.expires_at,
+        max_downloads: sidecar.max_downloads,
+        download_count: 0,
+        purged_at: None,
+        content_hash: sidecar.content_hash.clone(),
+        sha256: sidecar.sha256.clone(),
+        created_at: sidecar.created_at,
+        uploader_ip: sidecar.uploader_ip,
+        encrypted,
+        pinned: sidecar.pinned,
+        api_key_id: sidecar.api_key_id,
+        embeddable: sidecar.embeddable,
+    };
+
+    match app_state.file_storage.entry(id) {
+        Entry::Occupied(_) => return Ok(Registered::Known),
+        Entry::Vacant(entry) => {
+            entry.insert(file_data);
+        }
+    }
+    app_state
+        .short_url_storage
+        .entry(sidecar.short_code.clone())
+        .or_insert(id);
+    Ok(Registered::Recovered)
+}
+
+// Shard directories are two hex digits, two levels deep: ab/cd/file_<uuid>
+fn is_shard_directory(name: &str) -> bool {
+    name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// The `file_*` entries (uploads and their sidecars) in the temp directory and
+// its shard directories, by name
+pub(crate) async fn list_stored_files(directory: &Path) -> std::io::Result<HashMap<String, PathBuf>> {
+    let mut files = HashMap::new();
+    let mut pending = vec![(directory.to_path_buf(), 0)];
+    while let Some((directory, depth)) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if file_type.is_dir() && depth < 2 && is_shard_directory(&name) {
+                pending.push((entry.path(), depth + 1));
+            } else if file_type.is_file() && name.starts_with(FILE_PREFIX) {
+                files.insert(name, entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn handle_orphan(app_state: &AppState, path: &Path, reason: &str) {
+    if !app_state.config.clean_orphans {
+        warn!("Found orphaned file {:?} ({}); set DROP_CLEAN_ORPHANS=true to remove it", path, reason);
+        return;
+    }
+    match tokio::fs::remove_file(path).await {
+        Ok(_) => info!("Removed orphaned file {:?} ({})", path, reason),
+        Err(e) => warn!("Failed to remove orphaned file {:?}: {:?}", path, e),
+    }
+}
+
+/// Scan the temp directory for `file_<uuid>` uploads left by a previous run and
+/// put any the index doesn't know about back into it. Download counts are not
+/// persisted, so recovered files start again from zero.
+pub async fn recover_disk_files(app_state: &AppState) -> RecoveryReport {
+    let mut report = RecoveryReport::default();
+    let directory = &app_state.config.temp_directory;
+
+    let names = match list_stored_files(directory).await {
+        Ok(names) => names,
+        Err(e) => {
+            error!("Failed to scan {:?} for stored files: {:?}", directory, e);
+            return report;
+        }
+    };
+
+    for (name, path) in &names {
+        // Nothing is being uploaded yet, so a partial file can only be left over
+        if name.ends_with(PART_SUFFIX) {
+            match tokio::fs::remove_file(path).await {
+                Ok(_) => {
+                    info!("Removed interrupted upload {:?}", path);
+                    report.partial_removed += 1;
+                }
+                Err(e) => warn!("Failed to remove interrupted upload {:?}: {:?}", path, e),
+            }
+            continue;
+        }
+
+        // Sidecars are handled with their file; one without a file is left over
+        if let Some(stem) = name.strip_suffix(".json") {
+            if !names.contains_key(stem) {
+                report.orphans += 1;
+                handle_orphan(app_state, path, "sidecar without a file").await;
+            }
+            continue;
+        }
+
+        let Ok(id) = name[FILE_PREFIX.len()..].parse::<Uuid>() else {
+            report.orphans += 1;
+            handle_orphan(app_state, path, "name is not a file ID").await;
+            continue;
+        };
+
+        let sidecar = match read_sidecar(path).await {
+            Some(sidecar) => sidecar,
+            None => match synthesize_sidecar(&app_state.storage.disk, id, path, app_state.ids.short_code()).await {
+                Ok(sidecar) => {
+                    // Persist the rebuilt metadata so the short code is stable across restarts
+                    if let Err(e) = write_sidecar(path, &sidecar).await {
+                        warn!("Failed to write sidecar for {}: {:?}", id, e);
+                    }
+                    sidecar
+                }
+                Err(e) => {
+                    warn!("Skipping unreadable stored file {:?}: {:?}", path, e);
+                    continue;
+                }
+            },
+        };
+
+        match register_recovered(app_state, id, path, &sidecar).await {
+            Ok(Registered::Recovered) => {
+                info!("Recovered stored file '{}' with ID: {}", sidecar.filename, id);
+                report.recovered += 1;
+            }
+            Ok(Registered::Known) => {}
+            Err(e) => error!("Failed to recover stored file {}: {}", id, e),
+        }
+    }
+
+    // Start disk usage accounting from what is left on disk
+    match app_state.storage.disk.measure_usage().await {
+        Ok(bytes) => info!("Temp directory holds {} of stored files", format_size(bytes as usize)),
+        Err(e) => warn!("Failed to measure disk usage in {:?}: {:?}", directory, e),
+    }
+
+    if report.recovered > 0 || report.orphans > 0 || report.partial_removed > 0 {
+        info!(
+            "Startup recovery: {} files recovered, {} orphans found, {} interrupted uploads removed",
+            report.recovered, report.orphans, report.partial_removed
+        );
+    }
+    report
+}
+
+// Only one replay runs at a time, however often the database flaps
+static REPLAY_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub files: usize,
+    pub short_codes: usize,
+    pub flushed_to_disk: usize,
+}
+
+// Move a fallback file's bytes out of process memory so the database row
+// points at something other instances and restarts can read
+async fn flush_to_disk(app_state: &AppState, id: Uuid, memory_ref: &StorageRef) -> Option<StorageRef> {
+    let object = match app_state.storage.get(memory_ref).await {
+        Ok(object) => object,
+        Err(e) => {
+            warn!("Failed to read in-memory file {} for replay: {:?}", id, e);
+            return None;
+        }
+    };
+    let stream = tokio_util::io::ReaderStream::new(object.reader);
+    match app_state.storage.disk.put(id, stream, usize::MAX).await {
+        Ok((disk_ref, _)) => Some(disk_ref),
+        Err(status) => {
+            warn!("Failed to flush in-memory file {} to disk: {}", id, status);
+            None
+        }
+    }
+}
+
+// Replay one fallback file and its short codes. Returns Err once the database
+// fails, so the caller can stop and leave the rest for the next recovery.
+async fn replay_file(
+    app_state: &AppState,
+    db: &dyn MetadataStore,
+    id: Uuid,
+    mut file_data: FileData,
+    short_codes: &[String],
+    report: &mut ReplayReport,
+) -> Result<(), ()> {
+    let Some(mut storage_ref) = file_data.storage.clone() else {
+        return Ok(());
+    };
+
+    if storage_ref.is_in_memory() {
+        let Some(disk_ref) = flush_to_disk(app_state, id, &storage_ref).await else {
+            return Ok(()); // Leave it in the fallback; still served from memory
+        };
+        if let Some(mut entry) = app_state.file_storage.get_mut(&id) {
+            entry.storage = Some(disk_ref.clone());
+        }
+        if let Err(e) = app_state.storage.delete(&storage_ref).await {
+            warn!("Failed to release in-memory copy of {}: {:?}", id, e);
+        }
+        storage_ref = disk_ref;
+        file_data.storage = Some(storage_ref.clone());
+        report.flushed_to_disk += 1;
+    }
+
+    if let (Some(file_path), Some(short_code)) = (storage_ref.file_path(), short_codes.first()) {
+        let sidecar = FileSidecar {
+            short_code: short_code.clone(),
+            filename: file_data.filename.clone(),
+            content_type: file_data.content_type.clone(),
+            declared_content_type: file_data.declared_content_type.clone(),
+            detected_content_type: file_data.detected_content_type.clone(),
+            delete_token: file_data.delete_token.clone(),
+            expires_at: file_data.expires_at,
+            max_downloads: file_data.max_downloads,
+            content_hash: file_data.content_hash.clone(),
+            sha256: file_data.sha256.clone(),
+            created_at: file_data.created_at,
+            uploader_ip: file_data.uploader_ip,
+            pinned: file_data.pinned,
+            api_key_id: file_data.api_key_id,
+            embeddable: file_data.embeddable,
+        };
+        if let Err(e) = write_sidecar(file_path, &sidecar).await {
+            warn!("Failed to write sidecar for {}: {:?}", id, e);
+        }
+    }
+
+    let stored_path = storage_ref
+        .file_path()
+        .map(|path| recorded_path(&app_state.config.temp_directory, path));
+    let stored = db
+        .store_file_mapping_if_absent(&NewFileMapping {
+            id,
+            filename: &file_data.filename,
+            content_type: &file_data.content_type,
+            declared_content_type: file_data.declared_content_type.as_deref(),
+            detected_content_type: file_data.detected_content_type.as_deref(),
+            file_path: stored_path.as_ref(),
+            file_size: file_data.size as i64,
+            is_in_memory: storage_ref.is_in_memory(),
+            expires_at: file_data.expires_at,
+            delete_token: &file_data.delete_token,
+            max_downloads: file_data.max_downloads,
+            content_hash: &file_data.content_hash,
+            sha256: file_data.sha256.as_deref(),
+            created_at: file_data.created_at,
+            access_count: file_data.download_count,
+            uploader_ip: file_data.uploader_ip,
+            encrypted: file_data.encrypted,
+            pinned: file_data.pinned,
+            api_key_id: file_data.api_key_id,
+            embeddable: file_data.embeddable,
+        })
+        .await;
+    match stored {
+        Ok(true) => report.files += 1,
+        Ok(false) => info!("File {} was already in the database", id),
+        Err(e) => {
+            warn!("Failed to replay file {} into the database: {}", id, e);
+            app_state.set_database_healthy(false);
+            return Err(());
+        }
+    }
+
+    for short_code in short_codes {
+        match db.store_short_url(short_code, id).await {
+            Ok(true) => report.short_codes += 1,
+            Ok(false) => match db.get_file_id_by_short_code(short_code).await {
+                Ok(Some(existing)) if existing != id => warn!(
+                    "Short code {} is taken by another file in the database; {} stays reachable by ID",
+                    short_code, id
+                ),
+                _ => {}
+            },
+            Err(e) => {
+                warn!("Failed to replay short code {}: {}", short_code, e);
+                app_state.set_database_healthy(false);
+                return Err(());
+            }
+        }
+    }
+
+    // The database is the source of truth from here on
+    app_state.file_storage.remove(&id);
+    for short_code in short_codes {
+        app_state.short_url_storage.remove(short_code);
+    }
+    Ok(())
+}
+
+/// Copy files and short codes written to the in-memory fallback during a
+/// database outage into the database, then drop them from the fallback.
+/// Inserts skip rows that already exist, so a repeated replay is harmless.
+pub async fn replay_fallback_writes(app_state: &AppState) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let Some(ref db) = app_state.database else {
+        return report;
+    };
+    if REPLAY_IN_PROGRESS.swap(true, Ordering::AcqRel) {
+        return report;
+    }
+
+    // Snapshot first; no entry is held across the database calls
+    let files: Vec<(Uuid, FileData)> = app_state
+        .file_storage
+        .iter()
+        .filter(|entry| entry.purged_at.is_none())
+        .map(|entry| (*entry.key(), entry.value().clone()))
+        .collect();
+    let mut short_codes: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for entry in app_state.short_url_storage.iter() {
+        short_codes.entry(*entry.value()).or_default().push(entry.key().clone());
+    }
+
+    for (id, file_data) in files {
+        let codes = short_codes.remove(&id).unwrap_or_default();
+        if replay_file(app_state, db.as_ref(), id, file_data, &codes, &mut report).await.is_err() {
+            break;
+        }
+    }
+
+    REPLAY_IN_PROGRESS.store(false, Ordering::Release);
+    if report.files > 0 || report.short_codes > 0 {
+        info!(
+            "Replayed {} files and {} short codes into the database ({} flushed from memory to disk)",
+            report.files, report.short_codes, report.flushed_to_disk
+        );
+    }
+    report
+}
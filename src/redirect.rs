@@ -0,0 +1,82 @@
+use axum::{
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{Result, bail};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState, DownloadParams, FILE_SUBROUTES, encode_path_segment, file_data_is_gone, mapping_is_gone, pretty_url,
+    resolve_id_or_short_code_db,
+};
+
+/// What `GET /drop/{short_code}` answers with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShortCodeMode {
+    #[default]
+    Direct,   // The file itself
+    Redirect, // A 302 to the file's UUID URL, which serves it
+}
+
+/// Parse `direct` or `redirect`.
+pub fn parse_mode(value: &str) -> Result<ShortCodeMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "direct" => Ok(ShortCodeMode::Direct),
+        "redirect" => Ok(ShortCodeMode::Redirect),
+        _ => bail!("expected direct or redirect, got '{}'", value),
+    }
+}
+
+/// In redirect mode, the `302 Found` for a short code of a file that can
+/// still be downloaded, pointing at `/drop/{uuid}/{filename}`. None for UUIDs,
+/// unknown codes and files that are gone, which the download answers as
+/// usual. Nothing is counted; the download the redirect leads to is.
+pub async fn short_code_redirect(app_state: &AppState, id: &str, params: &DownloadParams) -> Option<Response> {
+    if app_state.config.short_code_mode != ShortCodeMode::Redirect || id.parse::<Uuid>().is_ok() {
+        return None;
+    }
+    let uuid = resolve_id_or_short_code_db(id, app_state).await?;
+    let stored_filename = live_filename(app_state, uuid).await?;
+
+    // The name asked for travels in the path, so only the disposition needs the query
+    let filename = params.filename.as_deref().unwrap_or(&stored_filename);
+    let mut location = pretty_url("", &uuid.to_string(), filename);
+    let mut query = Vec::new();
+    if let Some(ref disposition) = params.disposition {
+        query.push(format!("disposition={}", encode_path_segment(disposition)));
+    }
+    // ...unless the name would land on one of the sub-routes
+    if params.filename.is_some() && FILE_SUBROUTES.contains(&filename) {
+        query.push(format!("filename={}", encode_path_segment(filename)));
+    }
+    if !query.is_empty() {
+        location.push('?');
+        location.push_str(&query.join("&"));
+    }
+
+    info!("Redirecting short code {} to {}", id, location);
+    let location = HeaderValue::from_str(&location).ok()?;
+    Some((StatusCode::FOUND, [(header::LOCATION, location)]).into_response())
+}
+
+// The stored name of a file that can still be downloaded, read without counting an access
+async fn live_filename(app_state: &AppState, uuid: Uuid) -> Option<String> {
+    if let Some(ref db) = app_state.database
+        && app_state.database_available()
+    {
+        match db.find_file_mapping(uuid).await {
+            Ok(Some(file_mapping)) => return (!mapping_is_gone(&file_mapping)).then_some(file_mapping.filename),
+            Ok(None) => {
+                // Not in database, try fallback
+            }
+            Err(e) => {
+                warn!("Database file lookup failed, falling back to memory: {}", e);
+                app_state.set_database_healthy(false);
+            }
+        }
+    }
+
+    let file_data = app_state.file_storage.get(&uuid)?;
+    (!file_data_is_gone(&file_data)).then(|| file_data.filename.clone())
+}
@@ -0,0 +1,128 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use serde_json::{Map, Value, json};
+use std::fmt;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::negotiate::{ResponseFormat, text_response};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Longest client-supplied id that is passed through rather than replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+// Error bodies are small; anything bigger is passed along untouched
+const MAX_ERROR_BODY_LEN: usize = 64 * 1024;
+
+/// The id a request is known by in logs and in its response's `X-Request-Id`.
+/// Stored in request extensions by [`propagate_request_id`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// The client's own id if it sent a usable one, otherwise a fresh UUID.
+    pub fn from_header(value: Option<&HeaderValue>) -> Self {
+        value
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .filter(|id| id.chars().all(|c| c.is_ascii_graphic()))
+            .map(|id| Self(id.to_string()))
+            .unwrap_or_else(|| Self(Uuid::new_v4().to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware giving every request a [`RequestId`] and echoing it back in
+/// `X-Request-Id`. Error responses also carry it in their JSON body: bodies
+/// that are already JSON objects gain a `request_id` field, and empty or
+/// plain-text ones are replaced by `{"error": ..., "request_id": ...}`.
+/// Clients that prefer `text/plain` get just the error message instead.
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let request_id = RequestId::from_header(request.headers().get(X_REQUEST_ID));
+    request.extensions_mut().insert(request_id.clone());
+    let format = ResponseFormat::from_headers(request.headers());
+
+    let mut response = next.run(request).await;
+    if response.status().is_client_error() || response.status().is_server_error() {
+        response = with_error_body(response, &request_id, format).await;
+    }
+    if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+async fn with_error_body(response: Response, request_id: &RequestId, format: ResponseFormat) -> Response {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_ascii_lowercase());
+    let is_json = content_type.as_deref().is_some_and(|value| value.starts_with("application/json"));
+    let is_text = content_type.as_deref().is_none_or(|value| value.starts_with("text/plain"));
+    let too_long = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|len| len > MAX_ERROR_BODY_LEN);
+    if (!is_json && !is_text) || too_long {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, MAX_ERROR_BODY_LEN).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read error response body for request {}: {}", request_id, e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let mut body = if is_json {
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(Value::Object(body)) => body,
+            // Not ours to rewrite
+            _ => return Response::from_parts(parts, Body::from(bytes)),
+        }
+    } else {
+        let message = String::from_utf8_lossy(&bytes).trim().to_string();
+        let error = if message.is_empty() {
+            parts.status.canonical_reason().unwrap_or("Error").to_string()
+        } else {
+            message
+        };
+        Map::from_iter([("error".to_string(), json!(error))])
+    };
+
+    // The new body has its own length and type
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_TYPE);
+    match format {
+        ResponseFormat::Json => {
+            body.entry("request_id").or_insert_with(|| json!(request_id.as_str()));
+            (parts, Json(Value::Object(body))).into_response()
+        }
+        ResponseFormat::Text => {
+            let error = match body.get("error") {
+                Some(Value::String(error)) => error.clone(),
+                _ => parts.status.canonical_reason().unwrap_or("Error").to_string(),
+            };
+            (parts, text_response(error)).into_response()
+        }
+    }
+}
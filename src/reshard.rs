@@ -0,0 +1,96 @@
+use color_eyre::eyre::{Context, Result};
+use std::io;
+use std::path::Path;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::Config;
+use crate::database::Database;
+use crate::recovery::{FILE_PREFIX, sidecar_path};
+use crate::storage::{recorded_path, relativize_file_paths, sharded_path};
+
+/// What moving flat files into shard directories did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReshardReport {
+    pub files_moved: u64,
+    pub rows_updated: u64,
+    pub failed: u64, // Left where they were; running again retries them
+}
+
+/// Move the `file_<uuid>` files stored flat in the temp directory by older
+/// versions, with their sidecars, into the shard directories new files are
+/// written to, and point the database rows that refer to them at their new
+/// place. Partial uploads are left alone. Meant to be run while no server is
+/// using the directory.
+pub async fn shard_flat_files(config: &Config, db: Option<&Database>) -> Result<ReshardReport> {
+    let directory = &config.temp_directory;
+    let mut report = ReshardReport::default();
+
+    // Rows still holding whole paths wouldn't match the relative ones below
+    if let Some(db) = db {
+        relativize_file_paths(db, config).await;
+    }
+
+    let mut entries = match tokio::fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(report),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", directory)),
+    };
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("Failed to read {:?}", directory))?
+    {
+        if !entry.file_type().await.is_ok_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        // Sidecars and partial uploads don't parse, and sidecars move with their file
+        let name = entry.file_name();
+        let Some(Ok(id)) = name.to_str().and_then(|name| name.strip_prefix(FILE_PREFIX)).map(str::parse::<Uuid>) else {
+            continue;
+        };
+
+        let from = entry.path();
+        let to = sharded_path(directory, id);
+        if let Err(e) = move_file(&from, &to).await {
+            warn!("Failed to move {:?} to {:?}: {:?}", from, to, e);
+            report.failed += 1;
+            continue;
+        }
+
+        if let Some(db) = db {
+            let old = recorded_path(directory, &from);
+            let new = recorded_path(directory, &to);
+            match db.relocate_file_path(&old.to_string_lossy(), &new.to_string_lossy()).await {
+                Ok(rows) => report.rows_updated += rows,
+                Err(e) => {
+                    // Put it back where its rows still say it is
+                    warn!("Failed to update the rows for {:?}, moving it back: {:?}", from, e);
+                    if let Err(e) = move_file(&to, &from).await {
+                        error!("Failed to move {:?} back to {:?}: {:?}", to, from, e);
+                    }
+                    report.failed += 1;
+                    continue;
+                }
+            }
+        }
+        report.files_moved += 1;
+    }
+
+    Ok(report)
+}
+
+// Move a stored file and its sidecar, if it has one
+async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::rename(from, to).await?;
+    if let Err(e) = tokio::fs::rename(sidecar_path(from), sidecar_path(to)).await {
+        // Recovery rebuilds a missing sidecar; the file itself is in place
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!("Failed to move the sidecar of {:?}: {:?}", from, e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+use crate::database::FileMapping;
+use crate::recovery::hash_reader;
+use crate::storage::{StorageBackend, StorageRef};
+use crate::{AppState, admin, invalidate_cached_file, openapi::ErrorResponse};
+
+// Files found missing or changed since startup
+static CORRUPT_FILES_FOUND: AtomicU64 = AtomicU64::new(0);
+
+/// How many files the scrubber has found corrupt since startup. Files that
+/// were already marked corrupt aren't counted again.
+pub fn corrupt_files_found() -> u64 {
+    CORRUPT_FILES_FOUND.load(Ordering::Relaxed)
+}
+
+/// What scrubbing a file found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Integrity {
+    Intact,
+    Missing,  // Nothing at its path
+    Mismatch, // Its contents no longer hash to what was uploaded
+}
+
+// Re-read a file and compare it with its recorded hash. None if it couldn't be
+// read for some other reason, which says nothing about the file itself.
+async fn check_file(app_state: &AppState, file_mapping: &FileMapping) -> Option<Integrity> {
+    // Only disk-backed files are picked, so there is always a path
+    let storage_ref = StorageRef::from_mapping(file_mapping, &app_state.config.temp_directory)?;
+    let mut object = match app_state.storage.get(&storage_ref).await {
+        Ok(object) => object,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Some(Integrity::Missing),
+        Err(e) => {
+            warn!("Failed to open {:?} for scrubbing: {:?}", storage_ref, e);
+            return None;
+        }
+    };
+    // Encrypted chunks that fail authentication surface as invalid data
+    let hasher = match hash_reader(&mut object.reader).await {
+        Ok(hasher) => hasher,
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => return Some(Integrity::Mismatch),
+        Err(e) => {
+            warn!("Failed to read {:?} for scrubbing: {:?}", storage_ref, e);
+            return None;
+        }
+    };
+
+    let intact = match (&file_mapping.sha256, &file_mapping.content_hash) {
+        (Some(sha256), _) => *sha256 == hasher.sha256(),
+        (None, Some(content_hash)) => *content_hash == hasher.content_hash(),
+        (None, None) => object.len == file_mapping.file_size.max(0) as u64,
+    };
+    Some(if intact { Integrity::Intact } else { Integrity::Mismatch })
+}
+
+/// Check the disk-backed file that has gone longest without a check and
+/// record the outcome. Returns the file and what was found, or None if there
+/// was nothing to check or it couldn't be checked.
+pub async fn scrub_next(app_state: &AppState) -> Option<(Uuid, Integrity)> {
+    let db = app_state.database.as_ref()?;
+    if !app_state.database_available() {
+        return None;
+    }
+
+    let file_mapping = match db.files_to_scrub(1).await {
+        Ok(mut files) => files.pop()?,
+        Err(e) => {
+            warn!("Failed to pick a file to scrub: {}", e);
+            app_state.set_database_healthy(false);
+            return None;
+        }
+    };
+    let id = file_mapping.id;
+    let integrity = check_file(app_state, &file_mapping).await;
+    // Unreadable files keep their state but go to the back of the queue
+    let corrupt = integrity.map_or(file_mapping.corrupt, |integrity| integrity != Integrity::Intact);
+    if let Err(e) = db.record_scrub(id, corrupt).await {
+        warn!("Failed to record scrub of {}: {}", id, e);
+        return None;
+    }
+
+    match (file_mapping.corrupt, integrity) {
+        (false, Some(found)) if found != Integrity::Intact => {
+            CORRUPT_FILES_FOUND.fetch_add(1, Ordering::Relaxed);
+            // Stop serving a cached copy of the mapping
+            invalidate_cached_file(app_state, id);
+            error!(
+                "Stored file '{}' ({}) failed its integrity check: {:?} at {:?}",
+                file_mapping.filename, id, found, file_mapping.file_path
+            );
+        }
+        (true, Some(Integrity::Intact)) => info!("Stored file '{}' ({}) is intact again", file_mapping.filename, id),
+        _ => {}
+    }
+    integrity.map(|integrity| (id, integrity))
+}
+
+// Spawn the integrity scrubbing loop, which checks `scrub_files_per_hour`
+// files an hour. Nothing to do without a database, or when it is zero.
+pub fn spawn_scrub_task(app_state: AppState) -> Option<tokio::task::JoinHandle<()>> {
+    let rate = app_state.config.scrub_files_per_hour;
+    if rate == 0 || app_state.database.is_none() {
+        return None;
+    }
+    info!("Scrubbing up to {} stored files an hour", rate);
+
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(3600.0 / rate as f64));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            scrub_next(&app_state).await;
+        }
+    }))
+}
+
+/// The response to a download of a file marked corrupt.
+pub(crate) fn corrupt_file_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "File contents failed an integrity check and can't be served until they are restored",
+    )
+        .into_response()
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct CorruptFile {
+    pub id: Uuid,
+    pub filename: String,
+    pub file_path: Option<String>, // Where its contents should be
+    pub file_size: i64,
+    pub sha256: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CorruptFile {
+    fn from_mapping(app_state: &AppState, file_mapping: FileMapping) -> Self {
+        let file_path = match StorageRef::from_mapping(&file_mapping, &app_state.config.temp_directory) {
+            Some(StorageRef::Disk(path)) => Some(path.to_string_lossy().into_owned()),
+            _ => None,
+        };
+        Self {
+            id: file_mapping.id,
+            filename: file_mapping.filename,
+            file_path,
+            file_size: file_mapping.file_size,
+            sha256: file_mapping.sha256,
+            created_at: file_mapping.created_at,
+        }
+    }
+}
+
+// GET /admin/corrupt-files - list files that failed their last integrity check; requires X-Admin-Token
+#[utoipa::path(
+    get,
+    path = "/admin/corrupt-files",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`")),
+    responses(
+        (status = 200, description = "Files marked corrupt, oldest first", body = Vec<CorruptFile>),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn list_corrupt_files(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<CorruptFile>>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+
+    // Only files in the database are ever scrubbed
+    let Some(ref db) = app_state.database else {
+        return Ok(Json(Vec::new()));
+    };
+    if !app_state.database_available() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    match db.corrupt_files().await {
+        Ok(files) => Ok(Json(
+            files
+                .into_iter()
+                .map(|file_mapping| CorruptFile::from_mapping(&app_state, file_mapping))
+                .collect(),
+        )),
+        Err(e) => {
+            warn!("Failed to list corrupt files: {}", e);
+            app_state.set_database_healthy(false);
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use axum::Router;
+use color_eyre::eyre::{Context, Result, bail};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{
+    AppState, Config, access::{flush_accesses, spawn_access_flush_task}, cache::RedisStore, create_app,
+    database::Database, gc::spawn_orphan_gc_task, initialize_memory_pool, metadata::MetadataStore,
+    recovery::recover_disk_files, scrub::spawn_scrub_task, spawn_cleanup_task, spawn_health_probe_task,
+    spawn_memory_pool_task, storage::relativize_file_paths, tls::load_tls_config,
+};
+
+// How long in-flight requests get to finish once a TLS server is told to stop
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Builds a [`Server`]. Anything not given is set up from the config the way
+/// the `drop` binary does it.
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Option<Config>,
+    database: Option<Arc<dyn MetadataStore>>,
+    redis: Option<RedisStore>,
+    listener: Option<tokio::net::TcpListener>,
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Use this metadata store, such as an already connected `Database` or
+    /// an `InMemoryStore`, instead of connecting to `database_url`.
+    pub fn database(mut self, database: impl MetadataStore + 'static) -> Self {
+        self.database = Some(Arc::new(database));
+        self
+    }
+
+    /// Use this Redis store instead of connecting to `redis_url`.
+    pub fn redis(mut self, redis: RedisStore) -> Self {
+        self.redis = Some(redis);
+        self
+    }
+
+    /// Serve on an already bound listener instead of binding `bind_address`.
+    pub fn listener(mut self, listener: tokio::net::TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Validate the config, size the memory pool, connect to the database and
+    /// Redis and put files left on disk by a previous run back in the index.
+    /// Fails if a configured database is unreachable and not optional.
+    pub async fn build(self) -> Result<Server> {
+        let config = self.config.unwrap_or_default();
+        config.validate()?;
+
+        // Initialize memory pool based on system memory
+        initialize_memory_pool(&config);
+
+        let database = match self.database {
+            Some(database) => Some(database),
+            None => connect_database(&config).await?.map(|database| Arc::new(database) as Arc<dyn MetadataStore>),
+        };
+        if let Some(ref database) = database {
+            relativize_file_paths(database.as_ref(), &config).await;
+        }
+        let redis = match self.redis {
+            Some(redis) => Some(redis),
+            None => connect_redis(&config).await,
+        };
+
+        let app_state = AppState::new(config, database, redis)?;
+
+        // Put files left on disk by a previous run back in the index
+        recover_disk_files(&app_state).await;
+
+        Ok(Server {
+            app_state,
+            listener: self.listener,
+        })
+    }
+}
+
+async fn connect_database(config: &Config) -> Result<Option<Database>> {
+    let Some(ref db_url) = config.database_url else {
+        info!("No database URL configured, using in-memory storage only");
+        return Ok(None);
+    };
+    match Database::connect(db_url, &config.pool_settings()).await {
+        Ok(db) => {
+            info!("Database connected successfully");
+            Ok(Some(db))
+        }
+        Err(e) if config.database_optional => {
+            warn!("Failed to connect to database, falling back to in-memory storage: {:?}", e);
+            Ok(None)
+        }
+        // A configured database is expected to be there; don't silently lose persistence
+        Err(e) => Err(e).context("Database is unreachable (set DROP_DB_OPTIONAL=true to start without it)"),
+    }
+}
+
+// Redis only fronts the database, so failing to reach it is not fatal
+async fn connect_redis(config: &Config) -> Option<RedisStore> {
+    let Some(ref redis_url) = config.redis_url else {
+        info!("No Redis URL configured, skipping cache layer");
+        return None;
+    };
+    match RedisStore::new(redis_url).await {
+        Ok(redis) => Some(redis),
+        Err(e) => {
+            info!("Failed to connect to Redis, continuing without it: {}", e);
+            None
+        }
+    }
+}
+
+/// A drop server, set up and ready to serve.
+pub struct Server {
+    app_state: AppState,
+    listener: Option<tokio::net::TcpListener>,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn app_state(&self) -> &AppState {
+        &self.app_state
+    }
+
+    /// The routes on their own, to nest in another application. The caller
+    /// serves them and runs no background tasks; see [`Server::serve`].
+    pub fn router(&self) -> Router {
+        create_app(self.app_state.clone())
+    }
+
+    /// Start the background tasks and accept connections, over TLS when a
+    /// certificate is configured. Fails if the certificate can't be used or
+    /// the address can't be bound.
+    pub async fn serve(self) -> Result<ServerHandle> {
+        let Server { app_state, listener } = self;
+        let config = &app_state.config;
+
+        // Fail before binding if the certificate can't be used
+        let tls_config = load_tls_config(config).await?;
+
+        let listener = match listener {
+            Some(listener) => listener,
+            None => tokio::net::TcpListener::bind(&config.bind_address)
+                .await
+                .with_context(|| format!("Failed to bind to address {}", config.bind_address))?,
+        };
+        let local_addr = listener.local_addr().context("Failed to read the bound address")?;
+
+        let mut tasks = vec![
+            // Periodically purge expired files and stale bookkeeping
+            spawn_cleanup_task(app_state.clone()),
+            // Follow changes in available system memory
+            spawn_memory_pool_task(app_state.clone()),
+            // Periodically remove temp files nothing refers to any more
+            spawn_orphan_gc_task(app_state.clone()),
+            // Write download counts to the database in batches
+            spawn_access_flush_task(app_state.clone()),
+        ];
+        // Keep the database and Redis health flags current
+        tasks.extend(spawn_health_probe_task(app_state.clone()));
+        // Re-hash stored files at `scrub_files_per_hour`, if enabled
+        tasks.extend(spawn_scrub_task(app_state.clone()));
+
+        let app = create_app(app_state.clone()).into_make_service_with_connect_info::<SocketAddr>();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = match tls_config {
+            None => {
+                info!("Server running on http://{}", local_addr);
+                tokio::spawn(async move {
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(stop_requested(stopped))
+                        .await
+                        .context("Server failed")
+                })
+            }
+            Some(tls_config) => {
+                #[cfg(unix)]
+                if let (Some(cert), Some(key)) = (&config.tls_cert_path, &config.tls_key_path) {
+                    tasks.push(crate::tls::spawn_tls_reload_task(tls_config.clone(), cert.clone(), key.clone())?);
+                }
+                info!("Server running on https://{}", local_addr);
+
+                let handle = axum_server::Handle::new();
+                let shutdown = handle.clone();
+                tokio::spawn(async move {
+                    stop_requested(stopped).await;
+                    shutdown.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+                });
+                let listener = listener.into_std()?;
+                tokio::spawn(async move {
+                    axum_server::from_tcp_rustls(listener, tls_config)
+                        .handle(handle)
+                        .serve(app)
+                        .await
+                        .context("Server failed")
+                })
+            }
+        };
+
+        Ok(ServerHandle {
+            app_state,
+            local_addr,
+            stop,
+            server,
+            tasks,
+        })
+    }
+}
+
+// Resolves once the handle asks the server to stop; never if it was dropped instead
+async fn stop_requested(stopped: oneshot::Receiver<()>) {
+    if stopped.await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// A running server. Dropping the handle leaves it running; call
+/// [`ServerHandle::shutdown`] to stop it.
+pub struct ServerHandle {
+    app_state: AppState,
+    local_addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    server: JoinHandle<Result<()>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// The address actually bound, e.g. the port picked for `127.0.0.1:0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn app_state(&self) -> &AppState {
+        &self.app_state
+    }
+
+    /// Stop the server: report not ready for `shutdown_drain_seconds`, stop
+    /// accepting connections, let in-flight requests finish, then stop the
+    /// background tasks and write out pending download counts.
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown_on(std::future::ready(())).await
+    }
+
+    /// Serve until `signal` resolves, then shut down as [`ServerHandle::shutdown`]
+    /// does. Returns early, with its error, if the server stops on its own.
+    pub async fn shutdown_on<F>(self, signal: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let ServerHandle {
+            app_state,
+            stop,
+            mut server,
+            tasks,
+            ..
+        } = self;
+
+        let result = tokio::select! {
+            _ = signal => {
+                app_state.readiness.start_draining();
+                tokio::time::sleep(Duration::from_secs(app_state.config.shutdown_drain_seconds)).await;
+                let _ = stop.send(());
+                server.await
+            }
+            result = &mut server => result,
+        };
+        for task in tasks {
+            task.abort();
+        }
+        flush_accesses(&app_state).await;
+
+        match result {
+            Ok(result) => result,
+            Err(e) => bail!("Server task failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use axum::{
+    Json,
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, instrument, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    AppState, PendingUpload, api_keys, UploadBatchResponse, UploadInspector, UploadOptions, UploadParams, UploadRejectedResponse,
+    check_disk_space, check_pin_allowed, concurrency, declared_content_length, enforce_upload_policy, ensure_temp_directory, format_size, get_client_ip,
+    negotiate::ResponseFormat, openapi::ErrorResponse, public_base_url, quota, quota::{QuotaExceededResponse, QuotaOwner},
+    register_uploads, sanitize_filename,
+    rate_limit::{RateLimitAction, RateLimitStatus, check_key_rate_limit},
+    storage::append_stream_to_file,
+    transfer_rate,
+};
+
+// Resumable upload sessions: session_id -> progress (in-memory only)
+pub type UploadSessionStorage = Arc<Mutex<HashMap<Uuid, UploadSession>>>;
+
+// Header carrying the byte offset a chunk starts at (and the new offset in responses)
+pub(crate) const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+
+#[derive(Clone)]
+pub struct UploadSession {
+    pub filename: String,
+    pub content_type: Option<String>, // As declared when the session was created
+    pub expected_size: Option<usize>,
+    pub received: usize,
+    pub file_path: PathBuf,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_downloads: Option<i32>,
+    pub pinned: bool,
+    pub embeddable: bool,
+    pub updated_at: DateTime<Utc>,
+    pub busy: bool, // A chunk is currently being appended
+    pub(crate) inspector: UploadInspector, // Digests and sniffed head of the bytes acknowledged so far
+    pub client_ip: IpAddr,
+    pub quota_owner: QuotaOwner, // Whose quota the upload counts against
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct CreateSessionRequest {
+    filename: Option<String>,
+    content_type: Option<String>,
+    expected_size: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SessionResponse {
+    session_id: Uuid,
+    offset: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_size: Option<usize>,
+    expires_at: DateTime<Utc>,
+}
+
+fn sessions_directory(app_state: &AppState) -> PathBuf {
+    app_state.config.temp_directory.join("sessions")
+}
+
+// Capped at a year so timestamp arithmetic can't overflow
+fn session_ttl(app_state: &AppState) -> chrono::Duration {
+    const MAX_TTL_SECONDS: u64 = 365 * 24 * 60 * 60;
+    chrono::Duration::seconds(app_state.config.upload_session_ttl_seconds.min(MAX_TTL_SECONDS) as i64)
+}
+
+fn session_response(app_state: &AppState, session_id: Uuid, session: &UploadSession) -> SessionResponse {
+    SessionResponse {
+        session_id,
+        offset: session.received,
+        expected_size: session.expected_size,
+        expires_at: session.updated_at + session_ttl(app_state),
+    }
+}
+
+fn offset_response(status: StatusCode, offset: usize) -> Response {
+    let mut response = status.into_response();
+    response
+        .headers_mut()
+        .insert(UPLOAD_OFFSET_HEADER, HeaderValue::from(offset));
+    response
+}
+
+// POST /drop/sessions - start a resumable upload
+#[utoipa::path(
+    post,
+    path = "/drop/sessions",
+    tag = "sessions",
+    params(UploadParams, ("Authorization" = Option<String>, Header, description = "`Bearer <key>` to upload with an API key")),
+    request_body = CreateSessionRequest,
+    responses(
+        (status = 201, description = "The session was started", body = SessionResponse),
+        (status = 400, description = "An invalid parameter", body = ErrorResponse),
+        (status = 401, description = "An unknown or revoked API key, or none when anonymous uploads are off", body = ErrorResponse),
+        (status = 413, description = "The expected size is over the size limit", body = ErrorResponse),
+        (status = 429, description = "Rate limit, daily quota or API key quota exceeded", body = QuotaExceededResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, request), fields(client_ip))]
+pub async fn create_session(
+    State(app_state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    Json(request): Json<CreateSessionRequest>,
+) -> Result<(StatusCode, Option<RateLimitStatus>, Json<SessionResponse>), Response> {
+    // Only session creation is rate limited; a large upload may need many chunks
+    let client_ip = get_client_ip(&app_state.config, addr, &headers);
+    let api_key = api_keys::upload_key(&app_state, &headers).await?;
+    let rate_limit = check_key_rate_limit(client_ip, api_key.as_ref(), RateLimitAction::Upload, &app_state).await?;
+    let quota_owner = QuotaOwner::new(client_ip, api_key);
+
+    let options = UploadOptions::from_params(&params).map_err(IntoResponse::into_response)?;
+    check_pin_allowed(&app_state, &headers, options.pinned).map_err(IntoResponse::into_response)?;
+
+    if let Some(expected_size) = request.expected_size
+        && expected_size > app_state.config.max_file_size_limit
+    {
+        warn!(
+            "Rejecting upload session: expected size {} exceeds limit of {}",
+            format_size(expected_size),
+            format_size(app_state.config.max_file_size_limit)
+        );
+        return Err(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+    }
+
+    let expected_size = request.expected_size.unwrap_or_default() as u64;
+    check_disk_space(&app_state, expected_size).map_err(IntoResponse::into_response)?;
+    quota::check_upload_quota(&app_state, &quota_owner, expected_size, 1).await?;
+
+    let sessions_dir = sessions_directory(&app_state);
+    ensure_temp_directory(&sessions_dir)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let session_id = app_state.ids.uuid();
+    let file_path = sessions_dir.join(format!("session_{}", session_id));
+    if let Err(e) = tokio::fs::File::create(&file_path).await {
+        error!("Failed to create upload session file: {:?}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    let session = UploadSession {
+        filename: sanitize_filename(request.filename.as_deref().unwrap_or("unknown")),
+        content_type: request.content_type.filter(|v| !v.is_empty()),
+        expected_size: request.expected_size,
+        received: 0,
+        file_path,
+        expires_at: options.expires_at,
+        max_downloads: options.max_downloads,
+        pinned: options.pinned,
+        embeddable: options.embeddable,
+        updated_at: Utc::now(),
+        busy: false,
+        inspector: UploadInspector::default(),
+        client_ip,
+        quota_owner,
+    };
+
+    let response = session_response(&app_state, session_id, &session);
+    let stored = match app_state.upload_sessions.lock() {
+        Ok(mut sessions) => {
+            sessions.insert(session_id, session);
+            true
+        }
+        Err(e) => {
+            error!("Failed to acquire lock on upload sessions: {}", e);
+            false
+        }
+    };
+    if !stored {
+        let _ = tokio::fs::remove_file(sessions_dir.join(format!("session_{}", session_id))).await;
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    }
+
+    info!("Created upload session: {}", session_id);
+    Ok((StatusCode::CREATED, rate_limit, Json(response)))
+}
+
+// GET /drop/sessions/{session_id} - report progress so clients can resume
+#[utoipa::path(
+    get,
+    path = "/drop/sessions/{session_id}",
+    tag = "sessions",
+    params(("session_id" = Uuid, Path, description = "Returned when the session was created")),
+    responses(
+        (status = 200, description = "Bytes received so far", body = SessionResponse),
+        (status = 404, description = "No such session, or it expired", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state))]
+pub async fn get_session(
+    Path(session_id): Path<Uuid>,
+    State(app_state): State<AppState>,
+) -> Response {
+    let sessions = match app_state.upload_sessions.lock() {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            error!("Failed to acquire lock on upload sessions: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match sessions.get(&session_id) {
+        Some(session) => {
+            let offset = session.received;
+            let mut response = Json(session_response(&app_state, session_id, session)).into_response();
+            response
+                .headers_mut()
+                .insert(UPLOAD_OFFSET_HEADER, HeaderValue::from(offset));
+            response
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// PATCH /drop/sessions/{session_id} - append a chunk at the declared Upload-Offset
+#[utoipa::path(
+    patch,
+    path = "/drop/sessions/{session_id}",
+    tag = "sessions",
+    params(("session_id" = Uuid, Path, description = "Returned when the session was created"), ("Upload-Offset" = u64, Header, description = "Bytes the server already has")),
+    request_body(content = String, content_type = "application/octet-stream"),
+    responses(
+        (status = 204, description = "The chunk was appended; the new offset is in `Upload-Offset`"),
+        (status = 400, description = "Missing or invalid `Upload-Offset`", body = ErrorResponse),
+        (status = 404, description = "No such session, or it expired", body = ErrorResponse),
+        (status = 409, description = "The offset doesn't match; the server's is in `Upload-Offset`"),
+        (status = 413, description = "The upload is over the size limit", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers, body))]
+pub async fn append_chunk(
+    Path(session_id): Path<Uuid>,
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    let Some(offset) = headers
+        .get(UPLOAD_OFFSET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<usize>().ok())
+    else {
+        warn!("Missing or invalid Upload-Offset header for session {}", session_id);
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let chunk_size = declared_content_length(&headers).unwrap_or_default();
+    if let Err(shortfall) = check_disk_space(&app_state, chunk_size as u64) {
+        return shortfall.into_response();
+    }
+
+    // Before claiming the session, so a 503 leaves it free for the retry
+    let slot = match concurrency::acquire_upload_slot(&app_state).await {
+        Ok(slot) => slot,
+        Err(response) => return response,
+    };
+
+    // Claim the session so concurrent chunks can't interleave
+    let (file_path, received, expected_size, mut inspector, quota_owner) = {
+        let mut sessions = match app_state.upload_sessions.lock() {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                error!("Failed to acquire lock on upload sessions: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if session.busy || offset != session.received {
+            warn!(
+                "Rejecting chunk for session {}: offset {} (expected {})",
+                session_id, offset, session.received
+            );
+            return offset_response(StatusCode::CONFLICT, session.received);
+        }
+        session.busy = true;
+        (
+            session.file_path.clone(),
+            session.received,
+            session.expected_size,
+            session.inspector.clone(),
+            session.quota_owner.clone(),
+        )
+    };
+
+    // The completed file must respect the per-file limit across all chunks
+    let mut max_size = app_state.config.max_file_size_limit.saturating_sub(received);
+    if let Some(expected_size) = expected_size {
+        max_size = max_size.min(expected_size.saturating_sub(received));
+    }
+
+    // The session's bytes are only charged once it completes
+    let quota_left = quota::remaining_upload_quota(&app_state, &quota_owner)
+        .await
+        .map(|left| left.map(|remaining| remaining.saturating_sub(received as u64)));
+    let limit = quota::cap_upload_size(max_size, quota_left.unwrap_or_default());
+
+    let result = match quota_left {
+        Ok(_) => append_to_session_file(&app_state, &file_path, received, body, limit, &mut inspector).await,
+        Err(status) => Err(status),
+    };
+    drop(slot);
+
+    let mut sessions = match app_state.upload_sessions.lock() {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            error!("Failed to acquire lock on upload sessions: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Some(session) = sessions.get_mut(&session_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    session.busy = false;
+
+    match result {
+        Ok(written) => {
+            session.received += written;
+            session.inspector = inspector;
+            session.updated_at = Utc::now();
+            info!(
+                "Appended {} to upload session {} (now {})",
+                format_size(written),
+                session_id,
+                format_size(session.received)
+            );
+            offset_response(StatusCode::NO_CONTENT, session.received)
+        }
+        // Only the quota can have lowered the limit
+        Err(StatusCode::PAYLOAD_TOO_LARGE) if limit < max_size => {
+            quota::upload_quota_exceeded(&app_state, &quota_owner, quota_left.ok().flatten().unwrap_or_default())
+        }
+        Err(status) => offset_response(status, session.received),
+    }
+}
+
+// Append a request body to a session file, rolling back to `received` bytes on failure
+async fn append_to_session_file(
+    app_state: &AppState,
+    file_path: &PathBuf,
+    received: usize,
+    body: Body,
+    max_size: usize,
+    inspector: &mut UploadInspector,
+) -> Result<usize, StatusCode> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(file_path)
+        .await
+        .map_err(|e| {
+            error!("Failed to open upload session file: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let (stream, watch) = transfer_rate::watch_upload(&app_state.config, body.into_data_stream());
+    let stream = stream.inspect(|chunk| {
+        if let Ok(bytes) = chunk {
+            inspector.update(bytes);
+        }
+    });
+    let written = append_stream_to_file(stream, &mut file, max_size, app_state.config.io_buffer_size)
+        .await
+        .map_err(|status| watch.status(status));
+    if written.is_err() {
+        // Roll back to the last acknowledged offset so the client can retry the chunk
+        if let Err(e) = file.set_len(received as u64).await {
+            error!("Failed to roll back upload session file: {:?}", e);
+        }
+    }
+    written
+}
+
+// POST /drop/sessions/{session_id}/complete - turn the session into a normal file
+#[utoipa::path(
+    post,
+    path = "/drop/sessions/{session_id}/complete",
+    tag = "sessions",
+    params(("session_id" = Uuid, Path, description = "Returned when the session was created")),
+    responses(
+        (status = 200, description = "The file was stored", body = UploadBatchResponse),
+        (status = 404, description = "No such session, or it expired", body = ErrorResponse),
+        (status = 409, description = "Fewer bytes than the expected size; the server's offset is in `Upload-Offset`"),
+        (status = 415, description = "The file's type or extension is refused", body = UploadRejectedResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn complete_session(
+    Path(session_id): Path<Uuid>,
+    State(app_state): State<AppState>,
+    format: ResponseFormat,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let session = {
+        let mut sessions = app_state.upload_sessions.lock().map_err(|e| {
+            error!("Failed to acquire lock on upload sessions: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })?;
+        let Some(session) = sessions.get(&session_id) else {
+            return Err(StatusCode::NOT_FOUND.into_response());
+        };
+        let incomplete = session
+            .expected_size
+            .is_some_and(|expected_size| expected_size != session.received);
+        if session.busy || incomplete {
+            warn!("Upload session {} is not ready to complete", session_id);
+            return Err(offset_response(StatusCode::CONFLICT, session.received));
+        }
+        sessions.remove(&session_id).ok_or_else(|| StatusCode::NOT_FOUND.into_response())?
+    };
+
+    ensure_temp_directory(&app_state.config.temp_directory)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let id = app_state.ids.uuid();
+    let storage_ref = match app_state
+        .storage
+        .disk
+        .adopt(id, &session.file_path, session.received)
+        .await
+    {
+        Ok(storage_ref) => storage_ref,
+        Err(status) => {
+            error!("Failed to finalize upload session {}: {}", session_id, status);
+            let _ = tokio::fs::remove_file(&session.file_path).await;
+            return Err(status.into_response());
+        }
+    };
+
+    let streamed = session.inspector.finish(storage_ref, session.received);
+    let pending = vec![PendingUpload::new(id, session.filename, session.content_type, streamed)];
+    enforce_upload_policy(&app_state, &pending).await?;
+    let options = UploadOptions {
+        expires_at: session.expires_at,
+        max_downloads: session.max_downloads,
+        pinned: session.pinned,
+        embeddable: session.embeddable,
+        client_ip: Some(session.client_ip),
+        api_key_id: session.quota_owner.api_key_id(),
+    };
+
+    info!("Completed upload session {} as file {}", session_id, id);
+    let base_url = public_base_url(&app_state.config, &headers);
+    register_uploads(&app_state, pending, options, &base_url)
+        .await
+        .map(|files| format.respond(UploadBatchResponse { files }))
+}
+
+// Drop sessions that have seen no activity within the configured TTL
+pub(crate) async fn cleanup_stale_sessions(app_state: &AppState) {
+    let cutoff = Utc::now() - session_ttl(app_state);
+
+    let stale: Vec<(Uuid, PathBuf)> = match app_state.upload_sessions.lock() {
+        Ok(mut sessions) => {
+            let stale_ids: Vec<Uuid> = sessions
+                .iter()
+                .filter(|(_, session)| !session.busy && session.updated_at < cutoff)
+                .map(|(session_id, _)| *session_id)
+                .collect();
+            stale_ids
+                .into_iter()
+                .filter_map(|session_id| {
+                    sessions
+                        .remove(&session_id)
+                        .map(|session| (session_id, session.file_path))
+                })
+                .collect()
+        }
+        Err(e) => {
+            error!("Failed to acquire lock on upload sessions during cleanup: {}", e);
+            return;
+        }
+    };
+
+    for (session_id, file_path) in &stale {
+        if let Err(e) = tokio::fs::remove_file(file_path).await
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove stale upload session {}: {:?}", session_id, e);
+        }
+    }
+
+    if !stale.is_empty() {
+        info!("Cleaned up {} stale upload sessions", stale.len());
+    }
+}
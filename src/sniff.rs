@@ -0,0 +1,187 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{info, warn};
+
+// How much of the start of a file is inspected for magic bytes
+pub const SNIFF_LEN: usize = 4096;
+
+// Declared types that say nothing about the contents
+const GENERIC_CONTENT_TYPES: &[&str] = &[
+    "application/octet-stream",
+    "application/binary",
+    "application/unknown",
+    "application/x-unknown",
+    "binary/octet-stream",
+];
+
+// Declared types that are consistent with a sniffed type without being equal to it
+const CONTENT_TYPE_ALIASES: &[(&str, &[&str])] = &[
+    ("image/jpeg", &["image/jpg", "image/pjpeg"]),
+    ("image/x-icon", &["image/vnd.microsoft.icon"]),
+    ("image/svg+xml", &["application/xml", "text/xml"]),
+    ("audio/mpeg", &["audio/mp3"]),
+    ("audio/wav", &["audio/x-wav", "audio/wave", "audio/vnd.wave"]),
+    ("video/mp4", &["video/x-m4v", "video/quicktime", "audio/mp4", "audio/x-m4a"]),
+    ("video/webm", &["video/x-matroska", "audio/webm"]),
+    ("application/gzip", &["application/x-gzip", "application/x-gtar", "application/x-compressed-tar"]),
+    ("application/x-msdownload", &["application/vnd.microsoft.portable-executable", "application/x-dosexec"]),
+    ("text/html", &["application/xhtml+xml"]),
+    (
+        "application/zip",
+        &[
+            "application/x-zip-compressed",
+            "application/java-archive",
+            "application/epub+zip",
+            "application/vnd.android.package-archive",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "application/vnd.oasis.opendocument.text",
+            "application/vnd.oasis.opendocument.spreadsheet",
+            "application/vnd.oasis.opendocument.presentation",
+        ],
+    ),
+];
+
+// Fixed signatures at the start of the file: (magic bytes, content type)
+const MAGIC_PREFIXES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\x00", "image/tiff"),
+    (b"MM\x00*", "image/tiff"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"8BPS", "image/vnd.adobe.photoshop"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\x00", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (b"\x00asm", "application/wasm"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"MZ", "application/x-msdownload"),
+    (b"ID3", "audio/mpeg"),
+    (b"\xff\xfb", "audio/mpeg"),
+    (b"\xff\xf3", "audio/mpeg"),
+    (b"\xff\xf2", "audio/mpeg"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+];
+
+// Detect a content type from the first bytes of a file, if it has a known signature
+pub fn sniff_content_type(head: &[u8]) -> Option<&'static str> {
+    // RIFF containers carry their format at offset 8
+    if head.len() >= 12 && head.starts_with(b"RIFF") {
+        return match &head[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+
+    // ISO base media files (MP4, MOV, HEIC, AVIF) have an `ftyp` box at offset 4
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some(match &head[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        });
+    }
+
+    // "BM" alone is too common at the start of text, so also require the zeroed reserved field
+    if head.len() >= 14 && head.starts_with(b"BM") && head[6..10] == [0, 0, 0, 0] {
+        return Some("image/bmp");
+    }
+
+    // Matroska and WebM share an EBML header; WebM names itself as the doctype
+    if head.starts_with(b"\x1a\x45\xdf\xa3") {
+        let is_webm = head.windows(4).any(|window| window == b"webm");
+        return Some(if is_webm { "video/webm" } else { "video/x-matroska" });
+    }
+
+    if let Some((_, content_type)) = MAGIC_PREFIXES
+        .iter()
+        .find(|(magic, _)| head.starts_with(magic))
+    {
+        return Some(*content_type);
+    }
+
+    sniff_markup(head)
+}
+
+// Recognise HTML and SVG documents, which have no fixed signature
+fn sniff_markup(head: &[u8]) -> Option<&'static str> {
+    let text = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let start = text.iter().position(|b| !b.is_ascii_whitespace())?;
+    let text = String::from_utf8_lossy(&text[start..]).to_ascii_lowercase();
+
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        Some("text/html")
+    } else if text.starts_with("<svg") || (text.starts_with("<?xml") && text.contains("<svg")) {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+// Detect the type of whatever `reader` starts with, reading at most `SNIFF_LEN` bytes
+pub async fn sniff_reader<R: AsyncRead + Unpin>(reader: R) -> std::io::Result<Option<&'static str>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head).await?;
+    Ok(sniff_content_type(&head))
+}
+
+// The type without parameters, lowercased: "Text/HTML; charset=utf-8" -> "text/html"
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn is_generic(content_type: &str) -> bool {
+    let essence = essence(content_type);
+    essence.is_empty() || GENERIC_CONTENT_TYPES.contains(&essence.as_str())
+}
+
+fn is_consistent(declared: &str, detected: &str) -> bool {
+    let declared = essence(declared);
+    declared == detected
+        || CONTENT_TYPE_ALIASES
+            .iter()
+            .any(|(sniffed, aliases)| *sniffed == detected && aliases.contains(&declared.as_str()))
+}
+
+// Pick the type a file is served with. A specific declared type wins unless the
+// contents are clearly something else; generic or missing types defer to sniffing.
+pub fn resolve_content_type(filename: &str, declared: Option<&str>, detected: Option<&str>) -> String {
+    match (declared.filter(|declared| !is_generic(declared)), detected) {
+        (Some(declared), Some(detected)) if !is_consistent(declared, detected) => {
+            warn!(
+                "Content type mismatch for '{}': declared {}, detected {}; using detected type",
+                filename, declared, detected
+            );
+            detected.to_string()
+        }
+        (Some(declared), _) => declared.to_string(),
+        (None, Some(detected)) => {
+            info!("Detected content type {} for '{}'", detected, filename);
+            detected.to_string()
+        }
+        (None, None) => declared
+            .filter(|declared| !declared.trim().is_empty())
+            .unwrap_or("application/octet-stream") // Standard fallback for binary data
+            .to_string(),
+    }
+}
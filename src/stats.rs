@@ -0,0 +1,298 @@
+use axum::{
+    Json,
+    extract::{Query, State, rejection::QueryRejection},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{instrument, warn};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::database::DailyTraffic;
+use crate::metadata::MetadataStore;
+use crate::openapi::ErrorResponse;
+use crate::{ALLOCATED_MEMORY, AppState, DiskStats, MEMORY_POOL, admin};
+
+// How long the aggregates behind /admin/stats are reused
+const STATS_CACHE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_DAYS: u32 = 30;
+const MAX_DAYS: u32 = 365;
+const TOP_FILES: usize = 10;
+// Exclusive upper bounds of the file size buckets; the last bucket has none
+const SIZE_BUCKET_BOUNDS: [i64; 5] = [
+    64 * 1024,
+    1024 * 1024,
+    10 * 1024 * 1024,
+    100 * 1024 * 1024,
+    1024 * 1024 * 1024,
+];
+
+/// The aggregates behind `/admin/stats`, gathered at most once a minute for
+/// each window asked about.
+#[derive(Clone, Debug, Default)]
+pub struct StatsCache {
+    aggregates: Arc<Mutex<HashMap<u32, (Instant, Aggregates)>>>,
+}
+
+#[derive(Clone, Debug)]
+struct Aggregates {
+    generated_at: DateTime<Utc>,
+    daily: Vec<DayStats>,
+    top_files: Vec<TopFile>,
+    size_distribution: Vec<SizeBucket>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsParams {
+    days: Option<u32>, // Days of daily figures, today included; 30 by default, at most 365
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct StatsResponse {
+    generated_at: DateTime<Utc>, // When the aggregates were gathered; memory and disk are current
+    days: u32,
+    daily: Vec<DayStats>,
+    uploads: i64,      // Over the whole window
+    downloads: i64,    // ...
+    bytes_served: i64, // ...
+    total_files: i64,
+    total_bytes_stored: i64,
+    top_files: Vec<TopFile>,
+    size_distribution: Vec<SizeBucket>,
+    memory: MemoryUsage,
+    disk: DiskStats,
+}
+
+/// One UTC day; every day of the window is listed, quiet ones with zeros.
+#[derive(Clone, Debug, Default, Serialize, ToSchema)]
+pub struct DayStats {
+    date: NaiveDate,
+    uploads: i64,
+    downloads: i64,    // Full downloads; range requests only add bytes
+    bytes_served: i64, // Bytes of download bodies sent
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct TopFile {
+    id: Uuid,
+    filename: String,
+    access_count: i64,
+    file_size: i64,
+}
+
+/// Files of at least `min_bytes` and under `max_bytes`; the last bucket has no upper bound.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SizeBucket {
+    min_bytes: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_bytes: Option<i64>,
+    files: i64,
+    bytes: i64,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct MemoryUsage {
+    allocated_bytes: usize, // Held by files in the memory pool
+    pool_bytes: usize,
+}
+
+fn empty_buckets() -> Vec<SizeBucket> {
+    let mut min_bytes = 0;
+    let mut buckets = Vec::with_capacity(SIZE_BUCKET_BOUNDS.len() + 1);
+    for bound in SIZE_BUCKET_BOUNDS {
+        buckets.push(SizeBucket { min_bytes, max_bytes: Some(bound), files: 0, bytes: 0 });
+        min_bytes = bound;
+    }
+    buckets.push(SizeBucket { min_bytes, max_bytes: None, files: 0, bytes: 0 });
+    buckets
+}
+
+fn bucket_of(size: i64) -> usize {
+    SIZE_BUCKET_BOUNDS
+        .iter()
+        .position(|bound| size < *bound)
+        .unwrap_or(SIZE_BUCKET_BOUNDS.len())
+}
+
+// Every day from `first` to today, with the figures known for each
+fn fill_days(first: NaiveDate, uploads: &BTreeMap<NaiveDate, i64>, traffic: &[DailyTraffic]) -> Vec<DayStats> {
+    let mut days: BTreeMap<NaiveDate, DayStats> = first
+        .iter_days()
+        .take_while(|date| *date <= Utc::now().date_naive())
+        .map(|date| (date, DayStats { date, ..DayStats::default() }))
+        .collect();
+    for (date, count) in uploads {
+        if let Some(day) = days.get_mut(date) {
+            day.uploads += count;
+        }
+    }
+    for entry in traffic {
+        let Ok(date) = entry.day.parse::<NaiveDate>() else {
+            continue;
+        };
+        if let Some(day) = days.get_mut(&date) {
+            day.downloads += entry.downloads;
+            day.bytes_served += entry.bytes_served;
+        }
+    }
+    days.into_values().collect()
+}
+
+async fn gather_from_database(app_state: &AppState, db: &dyn MetadataStore, first: NaiveDate) -> color_eyre::Result<Aggregates> {
+    let since = first.and_time(NaiveTime::MIN).and_utc();
+    let uploads: BTreeMap<NaiveDate, i64> = db
+        .uploads_per_day(since)
+        .await?
+        .into_iter()
+        .filter_map(|(day, count)| Some((day.parse::<NaiveDate>().ok()?, count)))
+        .collect();
+    // Recent downloads may not have been written yet
+    let mut traffic = db.traffic_since(&first.to_string()).await?;
+    traffic.extend(app_state.access_counts.pending_traffic());
+
+    let top_files = db
+        .most_downloaded_files(TOP_FILES as i64)
+        .await?
+        .into_iter()
+        .map(|file_mapping| TopFile {
+            id: file_mapping.id,
+            filename: file_mapping.filename,
+            access_count: file_mapping.access_count.into(),
+            file_size: file_mapping.file_size,
+        })
+        .collect();
+
+    let mut size_distribution = empty_buckets();
+    for count in db.file_size_distribution(&SIZE_BUCKET_BOUNDS).await? {
+        if let Some(bucket) = usize::try_from(count.bucket).ok().and_then(|n| size_distribution.get_mut(n)) {
+            bucket.files = count.files;
+            bucket.bytes = count.bytes;
+        }
+    }
+
+    Ok(Aggregates {
+        generated_at: Utc::now(),
+        daily: fill_days(first, &uploads, &traffic),
+        top_files,
+        size_distribution,
+    })
+}
+
+// The same figures from the in-memory fallback; traffic since startup
+fn gather_from_memory(app_state: &AppState, first: NaiveDate) -> Aggregates {
+    let mut uploads = BTreeMap::new();
+    let mut top_files = Vec::new();
+    let mut size_distribution = empty_buckets();
+    for entry in app_state.file_storage.iter() {
+        *uploads.entry(entry.created_at.date_naive()).or_default() += 1;
+        // Tombstones hold nothing
+        if entry.purged_at.is_some() {
+            continue;
+        }
+        let size = entry.size as i64;
+        let bucket = &mut size_distribution[bucket_of(size)];
+        bucket.files += 1;
+        bucket.bytes += size;
+        top_files.push(TopFile {
+            id: *entry.key(),
+            filename: entry.filename.clone(),
+            access_count: entry.download_count.into(),
+            file_size: size,
+        });
+    }
+    top_files.sort_by(|a, b| b.access_count.cmp(&a.access_count).then(a.id.cmp(&b.id)));
+    top_files.truncate(TOP_FILES);
+
+    Aggregates {
+        generated_at: Utc::now(),
+        daily: fill_days(first, &uploads, &app_state.access_counts.pending_traffic()),
+        top_files,
+        size_distribution,
+    }
+}
+
+async fn aggregates(app_state: &AppState, days: u32) -> Result<Aggregates, StatusCode> {
+    // Callers arriving while the figures are gathered wait for them
+    let mut cache = app_state.stats_cache.aggregates.lock().await;
+    if let Some((gathered_at, aggregates)) = cache.get(&days)
+        && gathered_at.elapsed() < STATS_CACHE_TTL
+    {
+        return Ok(aggregates.clone());
+    }
+
+    let today = Utc::now().date_naive();
+    let first = today - chrono::Duration::days(i64::from(days) - 1);
+    let aggregates = match app_state.database {
+        Some(ref db) => {
+            if !app_state.database_available() {
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+            gather_from_database(app_state, db.as_ref(), first).await.map_err(|e| {
+                warn!("Failed to gather stats: {}", e);
+                app_state.set_database_healthy(false);
+                StatusCode::SERVICE_UNAVAILABLE
+            })?
+        }
+        None => gather_from_memory(app_state, first),
+    };
+
+    cache.retain(|_, (gathered_at, _)| gathered_at.elapsed() < STATS_CACHE_TTL);
+    cache.insert(days, (Instant::now(), aggregates.clone()));
+    Ok(aggregates)
+}
+
+// GET /admin/stats - usage figures for dashboards; requires X-Admin-Token
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "admin",
+    params(("X-Admin-Token" = String, Header, description = "`DROP_ADMIN_TOKEN`"), StatsParams),
+    responses(
+        (status = 200, description = "Daily figures, totals and current utilization", body = StatsResponse),
+        (status = 400, description = "`days` isn't between 1 and 365", body = ErrorResponse),
+        (status = 403, description = "Wrong admin token", body = ErrorResponse),
+        (status = 404, description = "Admin endpoints aren't enabled", body = ErrorResponse),
+        (status = 503, description = "The database is unavailable", body = ErrorResponse),
+    ),
+)]
+#[instrument(skip(app_state, headers))]
+pub async fn get_stats(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    admin::authorize(&app_state, &headers)?;
+    let Query(params) = params.map_err(|_| StatusCode::BAD_REQUEST)?;
+    let days = params.days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let aggregates = aggregates(&app_state, days).await?;
+    let daily = &aggregates.daily;
+    let buckets = &aggregates.size_distribution;
+    Ok(Json(StatsResponse {
+        generated_at: aggregates.generated_at,
+        days,
+        uploads: daily.iter().map(|day| day.uploads).sum(),
+        downloads: daily.iter().map(|day| day.downloads).sum(),
+        bytes_served: daily.iter().map(|day| day.bytes_served).sum(),
+        total_files: buckets.iter().map(|bucket| bucket.files).sum(),
+        total_bytes_stored: buckets.iter().map(|bucket| bucket.bytes).sum(),
+        memory: MemoryUsage {
+            allocated_bytes: ALLOCATED_MEMORY.load(Ordering::Acquire),
+            pool_bytes: MEMORY_POOL.load(Ordering::Acquire),
+        },
+        disk: app_state.health_cache.disk(|| async { DiskStats::collect(&app_state) }).await,
+        daily: aggregates.daily,
+        top_files: aggregates.top_files,
+        size_distribution: aggregates.size_distribution,
+    }))
+}
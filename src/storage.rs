@@ -0,0 +1,677 @@
+use axum::http::StatusCode;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use sysinfo::Disks;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWriteExt, BufWriter};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::database::FileMapping;
+use crate::encryption::{self, Keyring};
+use crate::metadata::MetadataStore;
+use crate::recovery::{PART_SUFFIX, list_stored_files, sidecar_path};
+use crate::{deallocate_memory, ensure_temp_directory, format_size, try_allocate_memory};
+
+/// Handle to a stored file's bytes, as recorded in the file index. Disk paths
+/// include the temp directory but serialize as just the file's name, so saved
+/// state doesn't depend on where the directory is mounted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageRef {
+    Memory(Uuid),
+    Disk(#[serde(serialize_with = "serialize_file_name")] PathBuf),
+}
+
+fn serialize_file_name<S: serde::Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    serializer.serialize_str(&name.to_string_lossy())
+}
+
+impl StorageRef {
+    /// Rebuild the handle from a database row's location columns. Relative
+    /// paths are resolved against `temp_directory`.
+    pub fn from_mapping(file_mapping: &FileMapping, temp_directory: &Path) -> Option<Self> {
+        if file_mapping.is_in_memory {
+            return Some(Self::Memory(file_mapping.id));
+        }
+        file_mapping
+            .file_path
+            .as_ref()
+            .map(|file_path| Self::Disk(resolve_recorded_path(temp_directory, file_path)))
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        matches!(self, Self::Memory(_))
+    }
+
+    pub fn file_path(&self) -> Option<&PathBuf> {
+        match self {
+            Self::Disk(path) => Some(path),
+            Self::Memory(_) => None,
+        }
+    }
+}
+
+/// How a stored file's path is written to the database: relative to the temp
+/// directory, so the directory can be moved without rewriting every row.
+/// Paths outside it are recorded whole.
+pub fn recorded_path(temp_directory: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(temp_directory).map_or_else(|_| path.to_path_buf(), Path::to_path_buf)
+}
+
+/// The path a recorded one refers to. Absolute paths, which rows written by
+/// older versions hold, are used as they are.
+pub fn resolve_recorded_path(temp_directory: &Path, recorded: &str) -> PathBuf {
+    temp_directory.join(recorded)
+}
+
+// Rows written before file paths were recorded relative to the temp directory
+// hold them whole, under the temp directory as it is now or as it was then
+pub(crate) async fn relativize_file_paths(database: &dyn MetadataStore, config: &Config) {
+    for directory in std::iter::once(&config.temp_directory).chain(&config.old_temp_directory) {
+        let mut prefix = directory.to_string_lossy().into_owned();
+        if !prefix.ends_with(MAIN_SEPARATOR) {
+            prefix.push(MAIN_SEPARATOR);
+        }
+        match database.strip_file_path_prefix(&prefix).await {
+            Ok(0) => {}
+            Ok(rows) => info!("Made {} file paths under {} relative to the temp directory", rows, directory.display()),
+            Err(e) => warn!("Failed to make file paths under {} relative: {:?}", directory.display(), e),
+        }
+    }
+}
+
+pub trait ObjectReader: AsyncRead + AsyncSeek + Send + Unpin {}
+impl<T: AsyncRead + AsyncSeek + Send + Unpin> ObjectReader for T {}
+
+/// An opened stored file: a seekable reader over its bytes and their length.
+pub struct StoredObject {
+    pub reader: Box<dyn ObjectReader>,
+    pub len: u64,
+    pub contents: Option<Bytes>, // The whole file, when it is already in memory
+}
+
+impl StoredObject {
+    /// An object over bytes already in memory. The reader and `contents`
+    /// share the buffer, so nothing is copied.
+    pub fn in_memory(contents: Bytes) -> Self {
+        Self {
+            reader: Box::new(io::Cursor::new(contents.clone())),
+            len: contents.len() as u64,
+            contents: Some(contents),
+        }
+    }
+}
+
+/// Somewhere file bytes can be written, read back and removed.
+pub trait StorageBackend: Send + Sync {
+    /// Store a stream of body chunks for file `id`, failing with 413 once more
+    /// than `max_size` bytes arrive. Returns the handle and the stored size.
+    fn put<S, E>(
+        &self,
+        id: Uuid,
+        stream: S,
+        max_size: usize,
+    ) -> impl Future<Output = Result<(StorageRef, usize), StatusCode>> + Send
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send,
+        E: std::fmt::Debug + Send;
+
+    fn get(&self, storage_ref: &StorageRef) -> impl Future<Output = io::Result<StoredObject>> + Send;
+
+    fn delete(&self, storage_ref: &StorageRef) -> impl Future<Output = io::Result<()>> + Send;
+
+    fn size(&self, storage_ref: &StorageRef) -> impl Future<Output = io::Result<u64>> + Send;
+}
+
+fn wrong_backend(storage_ref: &StorageRef) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("storage reference {:?} belongs to another backend", storage_ref),
+    )
+}
+
+/// Default for how much is buffered between the network and disk.
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 512 * 1024;
+
+// Write a stream of body chunks to an open file through a `buffer_size`
+// buffer, failing as soon as a chunk would take it past `max_size` bytes
+pub(crate) async fn append_stream_to_file<S, E>(
+    field: S,
+    file: &mut tokio::fs::File,
+    max_size: usize,
+    buffer_size: usize,
+) -> Result<usize, StatusCode>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: std::fmt::Debug,
+{
+    let mut field = std::pin::pin!(field);
+    let mut total_size = 0usize;
+    let mut writer = BufWriter::with_capacity(buffer_size, file);
+
+    while let Some(chunk) = field.next().await.transpose().map_err(|e| {
+        error!("Failed to read chunk during streaming: {:?}", e);
+        StatusCode::BAD_REQUEST
+    })? {
+        total_size += chunk.len();
+
+        // Check size limit during streaming, before the chunk is written
+        if total_size > max_size {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        writer.write_all(&chunk).await.map_err(|e| {
+            error!("Failed to write chunk to disk: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    writer.flush().await.map_err(|e| {
+        error!("Failed to flush file to disk: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(total_size)
+}
+
+/// Where the file for `id` lives under `directory` in the sharded layout:
+/// `ab/cd/file_<uuid>` for an ID starting `abcd`, so that no one directory
+/// ends up holding every file.
+pub fn sharded_path(directory: &Path, id: Uuid) -> PathBuf {
+    let hex = id.simple().to_string();
+    directory.join(&hex[..2]).join(&hex[2..4]).join(format!("file_{}", id))
+}
+
+// Create the shard directories a finished file is about to be moved into
+async fn ensure_shard_directory(file_path: &Path) -> Result<(), StatusCode> {
+    let Some(parent) = file_path.parent() else {
+        return Ok(());
+    };
+    tokio::fs::create_dir_all(parent).await.map_err(|e| {
+        error!("Failed to create shard directory {:?}: {:?}", parent, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// Fsync a complete file written elsewhere before it is moved into place
+async fn sync_file(path: &Path) -> Result<(), StatusCode> {
+    let synced = match tokio::fs::File::open(path).await {
+        Ok(file) => file.sync_all().await,
+        Err(e) => Err(e),
+    };
+    synced.map_err(|e| {
+        error!("Failed to sync {:?} to disk: {:?}", path, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+// Fsync a directory's entries. Only Unix lets a directory be opened for this;
+// elsewhere renames are left to the filesystem.
+async fn sync_directory(directory: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    tokio::fs::File::open(directory).await?.sync_all().await?;
+    #[cfg(not(unix))]
+    let _ = directory;
+    Ok(())
+}
+
+/// Files under a local directory, one `file_<uuid>` per upload, sharded into
+/// subdirectories by ID. Files stored flat by older versions are still read
+/// through the paths recorded for them.
+#[derive(Clone, Debug)]
+pub struct LocalDiskBackend {
+    directory: PathBuf,
+    bytes_stored: Arc<AtomicU64>, // Total size of the files written under `directory`
+    keyring: Option<Keyring>, // Encrypts new files and decrypts encrypted ones
+    io_buffer_size: usize,
+    sync_min_size: Option<u64>, // Files at least this large are fsynced before they count as stored
+}
+
+impl LocalDiskBackend {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            bytes_stored: Arc::new(AtomicU64::new(0)),
+            keyring: None,
+            io_buffer_size: DEFAULT_IO_BUFFER_SIZE,
+            sync_min_size: None,
+        }
+    }
+
+    /// Fsync files of at least `min_size` bytes, and the directories they are
+    /// moved into, before `put` or `adopt` returns, so a stored file survives
+    /// a power loss. None leaves writing back to the OS.
+    pub fn with_durable_writes(self, sync_min_size: Option<u64>) -> Self {
+        Self { sync_min_size, ..self }
+    }
+
+    // Whether files of `size` bytes are fsynced before they count as stored
+    fn syncs(&self, size: u64) -> bool {
+        self.sync_min_size.is_some_and(|min_size| size >= min_size)
+    }
+
+    /// The smallest file that is fsynced, or None if none are.
+    pub fn sync_min_size(&self) -> Option<u64> {
+        self.sync_min_size
+    }
+
+    // Fsync the directories from a file's own up to the temp directory, so the
+    // rename that put it there and any shard directories just created survive
+    async fn sync_directories(&self, file_path: &Path) -> Result<(), StatusCode> {
+        for directory in file_path.ancestors().skip(1) {
+            if let Err(e) = sync_directory(directory).await {
+                error!("Failed to sync directory {:?}: {:?}", directory, e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            if directory == self.directory {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffer this much between incoming chunks and the file.
+    pub fn with_io_buffer_size(self, io_buffer_size: usize) -> Self {
+        Self { io_buffer_size, ..self }
+    }
+
+    /// Encrypt files written from now on with the keyring's active key, if it
+    /// has one, and decrypt files written under any of its keys.
+    pub fn with_keyring(self, keyring: Option<Keyring>) -> Self {
+        Self { keyring, ..self }
+    }
+
+    /// Whether the stored file was written encrypted.
+    pub async fn is_encrypted(&self, storage_ref: &StorageRef) -> io::Result<bool> {
+        let StorageRef::Disk(path) = storage_ref else {
+            return Err(wrong_backend(storage_ref));
+        };
+        encryption::is_encrypted(path).await
+    }
+
+    /// Take over a complete file written elsewhere in the temp directory as
+    /// the file for `id`, encrypting it on the way in when new files are.
+    pub(crate) async fn adopt(&self, id: Uuid, source: &Path, size: usize) -> Result<StorageRef, StatusCode> {
+        if self.keyring.as_ref().is_some_and(Keyring::encrypts) {
+            let file = tokio::fs::File::open(source).await.map_err(|e| {
+                error!("Failed to open {:?} for encryption: {:?}", source, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            let (storage_ref, _) = self.put(id, tokio_util::io::ReaderStream::new(file), size).await?;
+            if let Err(e) = tokio::fs::remove_file(source).await {
+                warn!("Failed to remove {:?} after encrypting it: {:?}", source, e);
+            }
+            return Ok(storage_ref);
+        }
+
+        let durable = self.syncs(size as u64);
+        if durable {
+            sync_file(source).await?;
+        }
+        let file_path = self.path_for(id);
+        ensure_shard_directory(&file_path).await?;
+        tokio::fs::rename(source, &file_path).await.map_err(|e| {
+            error!("Failed to move {:?} into place: {:?}", source, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        self.record_stored(size as u64);
+        if durable {
+            self.sync_directories(&file_path).await?;
+        }
+        Ok(StorageRef::Disk(file_path))
+    }
+
+    /// Where the file for `id` lives (or will live) on disk.
+    pub fn path_for(&self, id: Uuid) -> PathBuf {
+        sharded_path(&self.directory, id)
+    }
+
+    /// Where the file for `id` is written before it is complete. Partial
+    /// files stay at the top of the directory, so shard directories are only
+    /// created for files that finish; they aren't removed once emptied.
+    pub fn part_path_for(&self, id: Uuid) -> PathBuf {
+        self.directory.join(format!("file_{}{}", id, PART_SUFFIX))
+    }
+
+    /// Bytes of file contents currently stored on disk by this instance.
+    pub fn bytes_stored(&self) -> u64 {
+        self.bytes_stored.load(Ordering::Acquire)
+    }
+
+    /// Count a file that was moved into the directory rather than written by `put`.
+    pub(crate) fn record_stored(&self, size: u64) {
+        self.bytes_stored.fetch_add(size, Ordering::AcqRel);
+    }
+
+    fn record_removed(&self, size: u64) {
+        // Files that predate the last measurement may not have been counted
+        let _ = self
+            .bytes_stored
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |stored| Some(stored.saturating_sub(size)));
+    }
+
+    /// Recount `bytes_stored` from the files already in the directory, e.g. at startup.
+    pub async fn measure_usage(&self) -> io::Result<u64> {
+        let mut total = 0;
+        for (name, path) in list_stored_files(&self.directory).await? {
+            if name.ends_with(".json") || name.ends_with(PART_SUFFIX) {
+                continue;
+            }
+            if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                total += metadata.len();
+            }
+        }
+        self.bytes_stored.store(total, Ordering::Release);
+        Ok(total)
+    }
+
+    /// Free space on the filesystem holding the directory, or None if it can't be determined.
+    pub fn available_space(&self) -> Option<u64> {
+        // The directory may not exist yet; its nearest existing ancestor is on the same disk
+        let directory = self
+            .directory
+            .ancestors()
+            .find_map(|dir| std::fs::canonicalize(dir).ok())?;
+        let disks = Disks::new_with_refreshed_list();
+        disks
+            .list()
+            .iter()
+            .filter(|disk| directory.starts_with(disk.mount_point()))
+            .max_by_key(|disk| disk.mount_point().as_os_str().len())
+            .map(|disk| disk.available_space())
+    }
+}
+
+// Removes an unfinished upload when dropped, including when the upload
+// future is dropped because the client went away
+struct PartFile(PathBuf);
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove partial upload {:?}: {:?}", self.0, e);
+        }
+    }
+}
+
+impl StorageBackend for LocalDiskBackend {
+    async fn put<S, E>(&self, id: Uuid, stream: S, max_size: usize) -> Result<(StorageRef, usize), StatusCode>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send,
+        E: std::fmt::Debug + Send,
+    {
+        // Create temp directory if it doesn't exist
+        ensure_temp_directory(&self.directory).await?;
+
+        // Write under a temporary name so a half-written file is never served
+        let file_path = self.path_for(id);
+        let part = PartFile(self.part_path_for(id));
+        let mut file = tokio::fs::File::create(&part.0).await.map_err(|e| {
+            error!("Failed to create file for streaming: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        let written = match self.keyring {
+            Some(ref keyring) if keyring.encrypts() => {
+                encryption::append_encrypted_stream(stream, &mut file, max_size, self.io_buffer_size, keyring).await
+            }
+            _ => append_stream_to_file(stream, &mut file, max_size, self.io_buffer_size).await,
+        };
+        // On error the guard removes the partial file
+        let size = written?;
+        // Count what is on disk, which for encrypted files is a little more
+        let stored = file.metadata().await.map_or(size as u64, |metadata| metadata.len());
+        let durable = self.syncs(size as u64);
+        if durable {
+            file.sync_all().await.map_err(|e| {
+                error!("Failed to sync {:?} to disk: {:?}", part.0, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        }
+        drop(file);
+        ensure_shard_directory(&file_path).await?;
+        tokio::fs::rename(&part.0, &file_path).await.map_err(|e| {
+            error!("Failed to move {:?} into place: {:?}", part.0, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        std::mem::forget(part);
+        self.record_stored(stored);
+        if durable {
+            self.sync_directories(&file_path).await?;
+        }
+        Ok((StorageRef::Disk(file_path), size))
+    }
+
+    async fn get(&self, storage_ref: &StorageRef) -> io::Result<StoredObject> {
+        let StorageRef::Disk(path) = storage_ref else {
+            return Err(wrong_backend(storage_ref));
+        };
+        encryption::open(path, self.keyring.as_ref()).await
+    }
+
+    async fn delete(&self, storage_ref: &StorageRef) -> io::Result<()> {
+        let StorageRef::Disk(path) = storage_ref else {
+            return Err(wrong_backend(storage_ref));
+        };
+        let size = tokio::fs::metadata(path).await.map(|metadata| metadata.len()).ok();
+        // The metadata sidecar goes with the file, if one was written
+        for path in [path.clone(), sidecar_path(path)] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(size) = size {
+            self.record_removed(size);
+        }
+        Ok(())
+    }
+
+    async fn size(&self, storage_ref: &StorageRef) -> io::Result<u64> {
+        let StorageRef::Disk(path) = storage_ref else {
+            return Err(wrong_backend(storage_ref));
+        };
+        // Encrypted files are larger on disk than their contents
+        Ok(encryption::open(path, self.keyring.as_ref()).await?.len)
+    }
+}
+
+/// Small files held in process memory, accounted against the memory pool.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBackend {
+    objects: Arc<Mutex<HashMap<Uuid, Bytes>>>,
+}
+
+impl MemoryBackend {
+    fn object(&self, storage_ref: &StorageRef) -> io::Result<Bytes> {
+        let StorageRef::Memory(id) = storage_ref else {
+            return Err(wrong_backend(storage_ref));
+        };
+        let objects = self
+            .objects
+            .lock()
+            .map_err(|e| io::Error::other(format!("memory storage lock poisoned: {}", e)))?;
+        objects
+            .get(id)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no in-memory file {}", id)))
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    // Fails with 507 when the memory pool can't hold the file
+    async fn put<S, E>(&self, id: Uuid, stream: S, max_size: usize) -> Result<(StorageRef, usize), StatusCode>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send,
+        E: std::fmt::Debug + Send,
+    {
+        let mut stream = std::pin::pin!(stream);
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await.transpose().map_err(|e| {
+            error!("Failed to read chunk into memory: {:?}", e);
+            StatusCode::BAD_REQUEST
+        })? {
+            if data.len() + chunk.len() > max_size {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        let size = data.len();
+        if !try_allocate_memory(size) {
+            return Err(StatusCode::INSUFFICIENT_STORAGE);
+        }
+
+        match self.objects.lock() {
+            Ok(mut objects) => {
+                if let Some(previous) = objects.insert(id, Bytes::from(data)) {
+                    deallocate_memory(previous.len());
+                }
+            }
+            Err(e) => {
+                error!("Failed to acquire lock on memory storage: {}", e);
+                deallocate_memory(size);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        Ok((StorageRef::Memory(id), size))
+    }
+
+    async fn get(&self, storage_ref: &StorageRef) -> io::Result<StoredObject> {
+        self.object(storage_ref).map(StoredObject::in_memory)
+    }
+
+    async fn delete(&self, storage_ref: &StorageRef) -> io::Result<()> {
+        let StorageRef::Memory(id) = storage_ref else {
+            return Err(wrong_backend(storage_ref));
+        };
+        let removed = self
+            .objects
+            .lock()
+            .map_err(|e| io::Error::other(format!("memory storage lock poisoned: {}", e)))?
+            .remove(id);
+        if let Some(data) = removed {
+            deallocate_memory(data.len());
+        }
+        Ok(())
+    }
+
+    async fn size(&self, storage_ref: &StorageRef) -> io::Result<u64> {
+        self.object(storage_ref).map(|data| data.len() as u64)
+    }
+}
+
+/// The storage used by the handlers: uploads land on local disk, and small
+/// files are then moved into memory when the pool has room.
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    pub memory: MemoryBackend,
+    pub disk: LocalDiskBackend,
+}
+
+impl FileStore {
+    pub fn new(temp_directory: PathBuf) -> Self {
+        Self {
+            memory: MemoryBackend::default(),
+            disk: LocalDiskBackend::new(temp_directory),
+        }
+    }
+
+    /// The store for `config`: files under its temp directory, encrypted when
+    /// an encryption key is set.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            memory: MemoryBackend::default(),
+            disk: LocalDiskBackend::new(config.temp_directory.clone())
+                .with_keyring(Keyring::from_config(config))
+                .with_io_buffer_size(config.io_buffer_size)
+                .with_durable_writes(config.durable_writes.then_some(config.durable_writes_min_size)),
+        }
+    }
+
+    /// Whether the stored file is encrypted at rest; files in memory never are.
+    pub async fn is_encrypted(&self, storage_ref: &StorageRef) -> bool {
+        match storage_ref {
+            StorageRef::Memory(_) => false,
+            StorageRef::Disk(_) => self.disk.is_encrypted(storage_ref).await.unwrap_or_else(|e| {
+                warn!("Failed to check whether {:?} is encrypted: {:?}", storage_ref, e);
+                false
+            }),
+        }
+    }
+
+    /// Decide where a freshly uploaded file lives: files under `stream_threshold`
+    /// move into memory if the pool has room, everything else stays on disk.
+    pub async fn settle(&self, id: Uuid, storage_ref: StorageRef, size: usize, stream_threshold: usize) -> StorageRef {
+        if size >= stream_threshold || storage_ref.is_in_memory() {
+            info!("Keeping file {} on disk (size: {})", id, format_size(size));
+            return storage_ref;
+        }
+
+        let object = match self.disk.get(&storage_ref).await {
+            Ok(object) => object,
+            Err(e) => {
+                error!("Failed to read file into memory: {:?}", e);
+                return storage_ref;
+            }
+        };
+        let stream = tokio_util::io::ReaderStream::new(object.reader);
+        match self.memory.put(id, stream, size).await {
+            Ok((memory_ref, _)) => {
+                info!("Moved file {} to memory pool (size: {})", id, format_size(size));
+                // Delete the temporary file since we have it in memory
+                if let Err(e) = self.disk.delete(&storage_ref).await {
+                    warn!("Failed to remove temporary file: {:?}", e);
+                }
+                memory_ref
+            }
+            Err(status) => {
+                info!("Keeping file {} on disk (memory pool: {})", id, status);
+                storage_ref
+            }
+        }
+    }
+}
+
+impl StorageBackend for FileStore {
+    async fn put<S, E>(&self, id: Uuid, stream: S, max_size: usize) -> Result<(StorageRef, usize), StatusCode>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send,
+        E: std::fmt::Debug + Send,
+    {
+        // Always stream to disk first for large file support
+        self.disk.put(id, stream, max_size).await
+    }
+
+    async fn get(&self, storage_ref: &StorageRef) -> io::Result<StoredObject> {
+        match storage_ref {
+            StorageRef::Memory(_) => self.memory.get(storage_ref).await,
+            StorageRef::Disk(_) => self.disk.get(storage_ref).await,
+        }
+    }
+
+    async fn delete(&self, storage_ref: &StorageRef) -> io::Result<()> {
+        match storage_ref {
+            StorageRef::Memory(_) => self.memory.delete(storage_ref).await,
+            StorageRef::Disk(_) => self.disk.delete(storage_ref).await,
+        }
+    }
+
+    async fn size(&self, storage_ref: &StorageRef) -> io::Result<u64> {
+        match storage_ref {
+            StorageRef::Memory(_) => self.memory.size(storage_ref).await,
+            StorageRef::Disk(_) => self.disk.size(storage_ref).await,
+        }
+    }
+}
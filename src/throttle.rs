@@ -0,0 +1,92 @@
+use axum::{body::Body, response::Response};
+use futures_util::StreamExt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{AppState, Config, rate_limit};
+
+/// A budget of bytes per second, refilled continuously. A chunk may take the
+/// bucket into debt; whoever sends next waits it off, so downloads sharing a
+/// bucket split the rate between them.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second as f64;
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    // Take `amount` bytes and say how long to wait before sending them.
+    // At most one second's worth builds up while idle.
+    fn reserve(&self, amount: usize) -> Duration {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.rate);
+        state.refilled_at = now;
+        state.tokens -= amount as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        }
+    }
+}
+
+/// The bucket behind `AppState::download_bandwidth`, if total download
+/// bandwidth is capped.
+pub fn download_bandwidth(config: &Config) -> Option<Arc<TokenBucket>> {
+    config.max_total_download_bandwidth.map(|rate| Arc::new(TokenBucket::new(rate)))
+}
+
+/// Send the body of a successful download no faster than the per-download
+/// and total limits allow. Allowlisted clients and error responses are
+/// never slowed down.
+pub fn limit_download(app_state: &AppState, client_ip: IpAddr, response: Response) -> Response {
+    let buckets: Vec<Arc<TokenBucket>> = app_state
+        .config
+        .max_bandwidth_per_download
+        .map(|rate| Arc::new(TokenBucket::new(rate)))
+        .into_iter()
+        .chain(app_state.download_bandwidth.clone())
+        .collect();
+    if buckets.is_empty() || !response.status().is_success() || rate_limit::is_exempt(&app_state.config, client_ip) {
+        return response;
+    }
+
+    response.map(|body| {
+        Body::from_stream(body.into_data_stream().then(move |chunk| {
+            let buckets = buckets.clone();
+            async move {
+                if let Ok(ref bytes) = chunk {
+                    let wait = buckets
+                        .iter()
+                        .map(|bucket| bucket.reserve(bytes.len()))
+                        .max()
+                        .unwrap_or_default();
+                    if !wait.is_zero() {
+                        tokio::time::sleep(wait).await;
+                    }
+                }
+                chunk
+            }
+        }))
+    })
+}
@@ -0,0 +1,47 @@
+use axum_server::tls_rustls::RustlsConfig;
+use color_eyre::eyre::{Context, Result};
+use tracing::{error, info};
+
+use crate::Config;
+
+/// Load the configured certificate and key, or None when TLS is off.
+pub async fn load_tls_config(config: &Config) -> Result<Option<RustlsConfig>> {
+    let (Some(cert), Some(key)) = (&config.tls_cert_path, &config.tls_key_path) else {
+        return Ok(None);
+    };
+
+    // sqlx brings its own rustls, so the crypto provider has to be chosen explicitly
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tls_config = RustlsConfig::from_pem_file(cert, key)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to load TLS certificate {} and key {}",
+                cert.display(),
+                key.display()
+            )
+        })?;
+    Ok(Some(tls_config))
+}
+
+/// Reload the certificate and key on SIGHUP, so renewed certificates are
+/// picked up without a restart. A failed reload keeps the current ones.
+#[cfg(unix)]
+pub fn spawn_tls_reload_task(
+    tls_config: RustlsConfig,
+    cert: std::path::PathBuf,
+    key: std::path::PathBuf,
+) -> Result<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+    Ok(tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            match tls_config.reload_from_pem_file(&cert, &key).await {
+                Ok(()) => info!("Reloaded TLS certificate from {}", cert.display()),
+                Err(e) => error!("Failed to reload TLS certificate, keeping the current one: {}", e),
+            }
+        }
+    }))
+}
@@ -0,0 +1,161 @@
+use axum::{body::Body, http::StatusCode, response::Response};
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt, stream};
+use std::io;
+use std::pin::Pin;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::warn;
+
+use crate::Config;
+
+/// Why a watched upload body failed.
+#[derive(Debug)]
+pub enum WatchError<E> {
+    Body(E),
+    TooSlow,
+}
+
+/// Watches an upload body for the minimum transfer rate. Once a window ends
+/// with fewer bytes than the rate asks for, the body fails, so whatever is
+/// storing it stops and removes what it wrote.
+#[derive(Clone, Debug, Default)]
+pub struct UploadWatch {
+    too_slow: Arc<AtomicBool>,
+}
+
+impl UploadWatch {
+    /// The status to answer a failed upload with: 408 if this watch cut it
+    /// off, otherwise the storage's own.
+    pub fn status(&self, status: StatusCode) -> StatusCode {
+        if self.too_slow.load(Ordering::Relaxed) {
+            StatusCode::REQUEST_TIMEOUT
+        } else {
+            status
+        }
+    }
+}
+
+// What the watched stream carries between polls
+struct Window<S> {
+    stream: Pin<Box<S>>,
+    started: Instant,
+    bytes: u64,
+    done: bool,
+}
+
+/// Wrap an upload body so it fails once its average rate over a
+/// `min_upload_rate_window_seconds` window drops below `min_upload_rate`.
+/// A client that sends nothing at all is cut off at the end of the window
+/// too. Without a minimum rate the body passes through untouched.
+pub fn watch_upload<S, E>(
+    config: &Config,
+    body: S,
+) -> (impl Stream<Item = Result<Bytes, WatchError<E>>> + Send, UploadWatch)
+where
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Send,
+{
+    let watch = UploadWatch::default();
+    let minimum = config
+        .min_upload_rate
+        .map(|rate| (rate, Duration::from_secs(config.min_upload_rate_window_seconds)));
+    let too_slow = watch.too_slow.clone();
+
+    let state = Window {
+        stream: Box::pin(body),
+        started: Instant::now(),
+        bytes: 0,
+        done: false,
+    };
+    let watched = stream::unfold(state, move |mut state| {
+        let too_slow = too_slow.clone();
+        async move {
+            if state.done {
+                return None;
+            }
+            let Some((rate, window)) = minimum else {
+                let chunk = state.stream.next().await?;
+                return Some((chunk.map_err(WatchError::Body), state));
+            };
+
+            loop {
+                let deadline = state.started + window;
+                let next = match tokio::time::timeout_at(deadline, state.stream.next()).await {
+                    Ok(None) => return None,
+                    Ok(Some(chunk)) => Some(chunk),
+                    Err(_) => None, // The window ended while waiting
+                };
+                if let Some(Ok(ref chunk)) = next {
+                    state.bytes += chunk.len() as u64;
+                }
+                if Instant::now() >= deadline {
+                    // Full windows only, so a short upload is never judged on a fraction of one
+                    let required = rate.saturating_mul(window.as_secs());
+                    if state.bytes < required {
+                        warn!(
+                            "Aborting upload: {} bytes in {:?}, below the minimum of {} bytes/s",
+                            state.bytes, window, rate
+                        );
+                        too_slow.store(true, Ordering::Relaxed);
+                        state.done = true;
+                        return Some((Err(WatchError::TooSlow), state));
+                    }
+                    state.started = Instant::now();
+                    state.bytes = 0;
+                }
+                if let Some(chunk) = next {
+                    return Some((chunk.map_err(WatchError::Body), state));
+                }
+            }
+        }
+    });
+    (watched, watch)
+}
+
+/// Cut a download off once its client has read nothing for
+/// `download_idle_timeout`, closing the file behind it. The body is read
+/// ahead by one chunk, so only time spent waiting on the client counts,
+/// never time spent reading the file or throttling.
+pub fn limit_download_idle(config: &Config, response: Response) -> Response {
+    let Some(idle) = config.download_idle_timeout else {
+        return response;
+    };
+    if !response.status().is_success() {
+        return response;
+    }
+
+    response.map(|body| {
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let stalled = Arc::new(AtomicBool::new(false));
+        let stalled_writer = stalled.clone();
+        tokio::spawn(async move {
+            let mut body = body.into_data_stream();
+            while let Some(chunk) = body.next().await {
+                match sender.send_timeout(chunk, idle).await {
+                    Ok(()) => {}
+                    Err(tokio::sync::mpsc::error::SendTimeoutError::Timeout(_)) => {
+                        warn!("Closing download: client read nothing for {:?}", idle);
+                        stalled_writer.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                    // The client went away
+                    Err(tokio::sync::mpsc::error::SendTimeoutError::Closed(_)) => return,
+                }
+            }
+        });
+
+        // A cut-off body ends in an error, so the client never takes it for the whole file
+        let cut_off = stream::once(async move { stalled }).filter_map(|stalled| async move {
+            stalled
+                .load(Ordering::Relaxed)
+                .then(|| Err(axum::Error::new(io::Error::new(io::ErrorKind::TimedOut, "download stalled"))))
+        });
+        Body::from_stream(ReceiverStream::new(receiver).chain(cut_off))
+    })
+}
@@ -0,0 +1,13 @@
+use axum::{
+    http::header,
+    response::{Html, IntoResponse},
+};
+
+// Self-contained page (inline styles and script, relative URLs) so it works
+// under a proxy path prefix and needs nothing besides the binary
+const UPLOAD_PAGE: &str = include_str!("ui/index.html");
+
+// GET / - drag-and-drop upload page, unless `disable_ui` is set
+pub async fn upload_page() -> impl IntoResponse {
+    ([(header::CACHE_CONTROL, "no-cache")], Html(UPLOAD_PAGE))
+}
@@ -0,0 +1,199 @@
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use crate::aliases;
+
+/// Form fields, and keys of the `options` JSON field, that set upload options.
+pub const OPTION_FIELDS: &[&str] = &[
+    "expires_in",
+    "max_downloads",
+    "alias",
+    "password",
+    "disposition",
+    "pinned",
+    "embeddable",
+];
+/// Form field carrying every option as one JSON object.
+pub const OPTIONS_FIELD: &str = "options";
+/// Header on a file part whose JSON options override the request's for that file.
+pub const PART_OPTIONS_HEADER: &str = "upload-options";
+
+const MAX_PASSWORD_LEN: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// Upload options as a client asked for them, validated but not yet applied.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestedOptions {
+    pub expires_in: Option<u64>, // Seconds
+    pub max_downloads: Option<i32>,
+    pub alias: Option<String>,
+    pub password: Option<String>,
+    pub disposition: Option<Disposition>,
+    pub pinned: Option<bool>, // Requires the admin token
+    pub embeddable: Option<bool>, // Downloads skip the referer check
+}
+
+impl RequestedOptions {
+    /// These options, with every one `overrides` sets replaced.
+    pub fn overridden_by(&self, overrides: &RequestedOptions) -> RequestedOptions {
+        RequestedOptions {
+            expires_in: overrides.expires_in.or(self.expires_in),
+            max_downloads: overrides.max_downloads.or(self.max_downloads),
+            alias: overrides.alias.clone().or_else(|| self.alias.clone()),
+            password: overrides.password.clone().or_else(|| self.password.clone()),
+            disposition: overrides.disposition.or(self.disposition),
+            pinned: overrides.pinned.or(self.pinned),
+            embeddable: overrides.embeddable.or(self.embeddable),
+        }
+    }
+}
+
+/// One option that couldn't be used, and why.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct InvalidOption {
+    pub field: String,
+    pub reason: String,
+}
+
+impl InvalidOption {
+    pub fn new(field: &str, reason: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Collects options field by field, keeping every problem rather than
+/// stopping at the first, so a client can fix them all at once.
+#[derive(Debug, Default)]
+pub struct OptionsParser {
+    options: RequestedOptions,
+    errors: Vec<InvalidOption>,
+}
+
+impl OptionsParser {
+    /// Whether a form field of this name is read as an option.
+    pub fn is_option(name: &str) -> bool {
+        name == OPTIONS_FIELD || OPTION_FIELDS.contains(&name)
+    }
+
+    /// Read one form field: a single option, or `options` holding JSON.
+    pub fn field(&mut self, name: &str, value: &str) {
+        if name == OPTIONS_FIELD {
+            self.json(value);
+            return;
+        }
+        if let Err(reason) = self.set(name, value.trim()) {
+            self.errors.push(InvalidOption::new(name, reason));
+        }
+    }
+
+    /// Read a JSON object of options. Unknown keys are errors, so a typo
+    /// isn't silently ignored.
+    pub fn json(&mut self, value: &str) {
+        let object = match serde_json::from_str::<Map<String, Value>>(value) {
+            Ok(object) => object,
+            Err(_) => {
+                self.errors.push(InvalidOption::new(OPTIONS_FIELD, "must be a JSON object"));
+                return;
+            }
+        };
+        for (name, value) in object {
+            let result = if !OPTION_FIELDS.contains(&name.as_str()) {
+                Err("is not an upload option".to_string())
+            } else {
+                match value {
+                    Value::String(value) => self.set(&name, value.trim()),
+                    Value::Number(value) => self.set(&name, &value.to_string()),
+                    Value::Bool(value) => self.set(&name, &value.to_string()),
+                    _ => Err("must be a string, a number or a boolean".to_string()),
+                }
+            };
+            if let Err(reason) = result {
+                self.errors.push(InvalidOption::new(&name, reason));
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<RequestedOptions, Vec<InvalidOption>> {
+        if self.errors.is_empty() {
+            Ok(self.options)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "expires_in" => self.options.expires_in = Some(parse_expires_in(value)?),
+            "max_downloads" => {
+                let max = value.parse::<i32>().ok().filter(|max| *max > 0);
+                self.options.max_downloads = Some(max.ok_or("must be a positive whole number")?);
+            }
+            "alias" => {
+                if let Some(problem) = aliases::alias_problem(value) {
+                    return Err(problem.to_string());
+                }
+                self.options.alias = Some(value.to_string());
+            }
+            "password" => {
+                if value.is_empty() || value.chars().count() > MAX_PASSWORD_LEN {
+                    return Err(format!("must be 1 to {} characters long", MAX_PASSWORD_LEN));
+                }
+                self.options.password = Some(value.to_string());
+            }
+            "disposition" => {
+                self.options.disposition = Some(match value.to_ascii_lowercase().as_str() {
+                    "inline" => Disposition::Inline,
+                    "attachment" => Disposition::Attachment,
+                    _ => return Err("must be inline or attachment".to_string()),
+                });
+            }
+            "pinned" => self.options.pinned = Some(parse_bool(value)?),
+            "embeddable" => self.options.embeddable = Some(parse_bool(value)?),
+            _ => return Err("is not an upload option".to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// Parse a whole set of options from one JSON object, as in a file part's
+/// `Upload-Options` header.
+pub fn parse_json(value: &str) -> Result<RequestedOptions, Vec<InvalidOption>> {
+    let mut parser = OptionsParser::default();
+    parser.json(value);
+    parser.finish()
+}
+
+/// Parse a `pinned` or `embeddable` value: true or false, case-insensitively.
+pub fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err("must be true or false".to_string()),
+    }
+}
+
+// Seconds from now; must land on a representable time
+fn parse_expires_in(value: &str) -> Result<u64, String> {
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|secs| *secs > 0)
+        .filter(|secs| {
+            i64::try_from(*secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                .is_some()
+        })
+        .ok_or_else(|| "must be a positive number of seconds".to_string())
+}
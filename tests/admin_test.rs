@@ -0,0 +1,713 @@
+//! The admin API: API keys, listings, deletes, maintenance mode and stats.
+
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{
+    create_test_client, file_form, post_form, put_file, spawn_server, sqlite_app_state, test_app_state, upload,
+    upload_raw, uploaded_file,
+};
+
+#[tokio::test]
+async fn test_api_keys() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    app_state.config.anonymous_uploads = false;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+    let create_key = |body: Value| {
+        client
+            .post(&format!("{}/admin/api-keys", base_url))
+            .header("X-Admin-Token", "test-admin-token")
+            .json(&body)
+            .send()
+    };
+    let upload = |key: Option<String>| {
+        let mut request = post_form(&client, &base_url, "", file_form("keyed.txt", "keyed"));
+        if let Some(key) = key {
+            request = request.bearer_auth(key);
+        }
+        request.send()
+    };
+
+    let response = create_key(serde_json::json!({"name": ""})).await.expect("Create request failed");
+    assert_eq!(response.status(), 400, "A key needs a name");
+    let response = create_key(serde_json::json!({"name": "ci", "quota_files": 1}))
+        .await
+        .expect("Create request failed");
+    assert_eq!(response.status(), 201);
+    let created: Value = response.json().await.expect("Failed to parse key");
+    let key = created["key"].as_str().expect("No key secret").to_string();
+    let key_id = created["id"].as_str().expect("No key ID").to_string();
+    let response = create_key(serde_json::json!({"name": "other"})).await.expect("Create request failed");
+    let other: Value = response.json().await.expect("Failed to parse key");
+    let other_key = other["key"].as_str().expect("No key secret").to_string();
+
+    // Anonymous uploads are turned off, and made-up keys don't pass
+    let response = upload(None).await.expect("Upload request failed");
+    assert_eq!(response.status(), 401);
+    let response = upload(Some("drop_not-a-real-key".to_string())).await.expect("Upload request failed");
+    assert_eq!(response.status(), 401);
+    let response = upload(Some(format!("drop_{}", "0".repeat(64)))).await.expect("Upload request failed");
+    assert_eq!(response.status(), 401);
+
+    let response = upload(Some(key.clone())).await.expect("Upload request failed");
+    let id = uploaded_file(response).await["id"].as_str().expect("No file ID").to_string();
+    let response = upload(Some(key.clone())).await.expect("Upload request failed");
+    assert_eq!(response.status(), 429, "The key's file quota is used up");
+    let response = upload(Some(other_key.clone())).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Other keys have their own quota");
+
+    let response = client
+        .get(&format!("{}/my/files", base_url))
+        .bearer_auth(&key)
+        .send()
+        .await
+        .expect("List request failed");
+    assert_eq!(response.status(), 200);
+    let listing: Value = response.json().await.expect("Failed to parse file list");
+    assert_eq!(listing["files"].as_array().expect("No file list").len(), 1);
+    assert_eq!(listing["files"][0]["id"], id.as_str());
+
+    let response = client
+        .delete(&format!("{}/my/files/{}", base_url, id))
+        .bearer_auth(&other_key)
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 404, "Keys can't delete each other's files");
+    let response = client
+        .delete(&format!("{}/my/files/{}", base_url, id))
+        .bearer_auth(&key)
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 204);
+    let response = upload(Some(key.clone())).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Deleting a file frees its share of the quota");
+
+    // Lifting the quota, then revoking the key
+    let response = client
+        .patch(&format!("{}/admin/api-keys/{}", base_url, key_id))
+        .header("X-Admin-Token", "test-admin-token")
+        .json(&serde_json::json!({"quota_files": null}))
+        .send()
+        .await
+        .expect("Update request failed");
+    assert_eq!(response.status(), 200);
+    let updated: Value = response.json().await.expect("Failed to parse key");
+    assert!(updated["quota_files"].is_null());
+    assert_eq!(updated["used_files"], 1);
+    let response = upload(Some(key.clone())).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "The key has no quota left to hit");
+
+    let response = client
+        .delete(&format!("{}/admin/api-keys/{}", base_url, key_id))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("Revoke request failed");
+    assert_eq!(response.status(), 204);
+    let response = upload(Some(key)).await.expect("Upload request failed");
+    assert_eq!(response.status(), 401, "A revoked key no longer authenticates");
+}
+
+#[tokio::test]
+async fn test_other_authorization_leaves_uploads_anonymous() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(sqlite_app_state(dir.path()).await).await;
+    let client = create_test_client();
+    let upload = || post_form(&client, &base_url, "", file_form("proxied.txt", "behind a proxy"));
+
+    // Credentials meant for a proxy in front aren't API keys
+    let response = upload().basic_auth("deploy", Some("hunter2")).send().await.expect("Upload request failed");
+    assert_eq!(response.status(), 201, "Basic credentials should leave the upload anonymous");
+    let response = upload().bearer_auth("proxy-session-token").send().await.expect("Upload request failed");
+    assert_eq!(response.status(), 201, "Bearer tokens that aren't API keys should be ignored");
+
+    // What looks like an API key has to be one
+    let response = upload().bearer_auth("drop_truncated").send().await.expect("Upload request failed");
+    assert_eq!(response.status(), 401);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+}
+
+#[tokio::test]
+async fn test_admin_orphan_collection() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    app_state.config.orphan_max_age_seconds = 0;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let test_content = "Referenced, so never collected.";
+    let file = upload(&client, &base_url, "", "kept.txt", test_content).await;
+    let file_id = file["id"].as_str().expect("No file ID").to_string();
+
+    // Nothing refers to this one
+    let orphan_content = b"left behind by a crashed upload";
+    let orphan = dir.path().join("files").join(format!("file_{}", uuid::Uuid::new_v4()));
+    std::fs::write(&orphan, orphan_content).expect("Failed to write orphan");
+
+    let response = client
+        .post(&format!("{}/admin/gc", base_url))
+        .header("X-Admin-Token", "wrong-token")
+        .send()
+        .await
+        .expect("GC request failed");
+    assert_eq!(response.status(), 403, "A wrong admin token should be rejected");
+    assert!(orphan.exists(), "Rejected request must not remove anything");
+
+    let report: Value = client
+        .post(&format!("{}/admin/gc", base_url))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("GC request failed")
+        .json()
+        .await
+        .expect("Failed to parse GC report");
+    assert_eq!(report["files_removed"], 1, "Only the orphan should be removed");
+    assert_eq!(report["bytes_reclaimed"], orphan_content.len(), "Reclaimed bytes should match the orphan");
+    assert!(!orphan.exists(), "Orphan should be gone");
+
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file_id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.status().is_success(), "Referenced file should survive collection");
+    assert_eq!(response.text().await.expect("No body"), test_content);
+
+    println!("✅ Orphan collection test passed");
+}
+
+#[tokio::test]
+async fn test_read_only_mode() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let upload = || put_file(&client, &base_url, "notes.txt", "still readable").send();
+    let set_mode = |read_only: bool| {
+        client
+            .post(&format!("{}/admin/mode", base_url))
+            .header("X-Admin-Token", "test-admin-token")
+            .json(&serde_json::json!({ "read_only": read_only }))
+            .send()
+    };
+
+    let file = uploaded_file(upload().await.expect("Upload request failed")).await;
+    let file_id = file["id"].as_str().expect("No file ID in response");
+
+    let response = set_mode(true).await.expect("Mode request failed");
+    assert_eq!(response.status(), 200);
+    let mode: Value = response.json().await.expect("Failed to parse mode response");
+    assert_eq!(mode["read_only"], true);
+
+    // Writes are refused
+    let response = upload().await.expect("Upload request failed");
+    assert_eq!(response.status(), 503, "Uploads should be refused while read-only");
+    assert!(response.headers().contains_key("retry-after"), "Refusals should carry Retry-After");
+    let body: Value = response.json().await.expect("Failed to parse error response");
+    assert_eq!(body["reason"], "maintenance");
+    assert!(body["request_id"].is_string(), "The error should carry the request ID");
+
+    let response = client
+        .delete(&format!("{}/drop/{}", base_url, file_id))
+        .header("X-Delete-Token", file["delete_token"].as_str().expect("No delete token"))
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 503, "Deletes should be refused while read-only");
+
+    let response = client
+        .post(&format!("{}/drop/{}/aliases", base_url, file_id))
+        .json(&serde_json::json!({ "alias": "maintenance-notes" }))
+        .send()
+        .await
+        .expect("Alias request failed");
+    assert_eq!(response.status(), 503, "Aliases should be refused while read-only");
+
+    // Reads keep working
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file_id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.text().await.expect("No body"), "still readable");
+    let response = client
+        .get(&format!("{}/drop/{}/info", base_url, file_id))
+        .send()
+        .await
+        .expect("Info request failed");
+    assert_eq!(response.status(), 200, "Info should still be served");
+    let health: Value = client
+        .get(&format!("{}/health", base_url))
+        .send()
+        .await
+        .expect("Health request failed")
+        .json()
+        .await
+        .expect("Failed to parse health response");
+    assert_eq!(health["read_only"], true, "Health should report the mode");
+
+    // Clearing the mode lets writes through again
+    let response = set_mode(false).await.expect("Mode request failed");
+    assert_eq!(response.status(), 200);
+    let response = upload().await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed after maintenance");
+}
+
+#[tokio::test]
+async fn test_admin_stats() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+
+    let mut ids = Vec::new();
+    for content in ["a".repeat(100), "b".repeat(100 * 1024)] {
+        let response = client
+            .put(&format!("{}/drop/stats.txt", base_url))
+            .body(content)
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        ids.push(upload["files"][0]["id"].as_str().expect("No file ID in response").to_string());
+    }
+
+    // Two full downloads of the small file and part of the large one
+    for _ in 0..2 {
+        let response = client
+            .get(&format!("{}/drop/{}", base_url, ids[0]))
+            .send()
+            .await
+            .expect("Download request failed");
+        assert_eq!(response.bytes().await.expect("No body").len(), 100);
+    }
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, ids[1]))
+        .header("Range", "bytes=0-999")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await.expect("No body").len(), 1000);
+
+    // Bodies are counted once the server is done with them
+    let served = || {
+        app_state
+            .access_counts
+            .pending_traffic()
+            .iter()
+            .map(|day| day.bytes_served)
+            .sum::<i64>()
+    };
+    for _ in 0..50 {
+        if served() >= 1200 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    drop::access::flush_accesses(&app_state).await;
+
+    let get_stats = |query: &'static str| {
+        client
+            .get(&format!("{}/admin/stats{}", base_url, query))
+            .header("X-Admin-Token", "test-admin-token")
+            .send()
+    };
+    let response = get_stats("?days=7").await.expect("Stats request failed");
+    assert_eq!(response.status(), 200);
+    let stats: Value = response.json().await.expect("Failed to parse stats");
+
+    let daily = stats["daily"].as_array().expect("Expected daily figures");
+    assert_eq!(daily.len(), 7, "Every day of the window should be listed");
+    let today = chrono::Utc::now().date_naive().to_string();
+    assert_eq!(daily[6]["date"], today.as_str(), "Days should be ISO-8601 dates, ending today");
+    assert_eq!(daily[6]["uploads"], 2);
+    assert_eq!(daily[6]["downloads"], 2, "Range requests shouldn't count as downloads");
+    assert_eq!(daily[6]["bytes_served"], 1200);
+    assert_eq!(stats["uploads"], 2);
+    assert_eq!(stats["bytes_served"], 1200);
+
+    assert_eq!(stats["total_files"], 2);
+    assert_eq!(stats["total_bytes_stored"], 100 + 100 * 1024);
+    assert_eq!(stats["top_files"][0]["id"], ids[0].as_str(), "The most downloaded file should be first");
+    assert_eq!(stats["top_files"][0]["access_count"], 2);
+    let buckets = stats["size_distribution"].as_array().expect("Expected size buckets");
+    assert_eq!(buckets[0]["files"], 1, "The small file should be in the smallest bucket");
+    assert_eq!(buckets[1]["files"], 1, "The large file should be in the next bucket");
+    assert!(buckets.last().expect("No buckets")["max_bytes"].is_null());
+    assert!(stats["memory"]["pool_bytes"].is_u64());
+    assert!(stats["disk"]["stored_bytes"].is_u64());
+
+    // The aggregates are reused for a while
+    let again: Value = get_stats("?days=7")
+        .await
+        .expect("Stats request failed")
+        .json()
+        .await
+        .expect("Failed to parse stats");
+    assert_eq!(again["generated_at"], stats["generated_at"]);
+
+    let response = get_stats("?days=0").await.expect("Stats request failed");
+    assert_eq!(response.status(), 400, "An empty window should be refused");
+}
+
+#[tokio::test]
+async fn test_stats_count_partial_download() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.stream_threshold = 0; // Served from disk in many chunks
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+
+    let size = 32 * 1024 * 1024;
+    let file = upload_raw(&client, &base_url, "partial.bin", vec![b'p'; size]).await;
+    let mut response = client
+        .get(&format!("{}/drop/{}", base_url, file["id"].as_str().expect("No file ID")))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    let first = response.chunk().await.expect("Failed to read body").expect("Empty body");
+    assert!(!first.is_empty());
+    drop(response);
+
+    // The bytes are added once the server notices the client went away
+    let traffic = || app_state.access_counts.pending_traffic();
+    for _ in 0..100 {
+        if traffic().iter().any(|day| day.bytes_served > 0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let traffic = traffic();
+    assert_eq!(traffic.len(), 1);
+    assert_eq!(traffic[0].downloads, 1, "A full response counts as a download even if cut short");
+    assert!(traffic[0].bytes_served >= first.len() as i64, "The bytes read should be counted");
+    assert!(
+        traffic[0].bytes_served < size as i64,
+        "Only the bytes sent before the client went away should be counted, got {}",
+        traffic[0].bytes_served
+    );
+}
+
+/// Upload a spread of files to `base_url` and page through them with the admin listing
+async fn check_admin_file_listing(base_url: &str) {
+    let client = create_test_client();
+    for size in [10, 20, 30, 40] {
+        let response = client
+            .put(&format!("{}/drop/text-{}.txt", base_url, size))
+            .body("t".repeat(size))
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+    }
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.resize(50, 0);
+    let response = client
+        .put(&format!("{}/drop/pixel.png", base_url))
+        .body(png)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+
+    let list = |query: String| {
+        let client = client.clone();
+        async move {
+            client
+                .get(&format!("{}/admin/files?{}", base_url, query))
+                .header("X-Admin-Token", "test-admin-token")
+                .send()
+                .await
+                .expect("Listing request failed")
+        }
+    };
+    let sizes = |page: &Value| -> Vec<u64> {
+        page["files"]
+            .as_array()
+            .expect("No files in listing")
+            .iter()
+            .map(|file| file["size"].as_u64().expect("No size"))
+            .collect()
+    };
+
+    let response = client
+        .get(&format!("{}/admin/files", base_url))
+        .send()
+        .await
+        .expect("Listing request failed");
+    assert_eq!(response.status(), 403, "Listing requires the admin token");
+
+    // Largest first, two at a time, following the cursor to the end
+    let mut seen = Vec::new();
+    let mut query = "sort=size&limit=2".to_string();
+    loop {
+        let response = list(query.clone()).await;
+        assert!(response.status().is_success(), "Listing should succeed");
+        let page: Value = response.json().await.expect("Failed to parse listing");
+        seen.extend(sizes(&page));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("sort=size&limit=2&cursor={}", cursor),
+            None => break,
+        }
+    }
+    assert_eq!(seen, vec![50, 40, 30, 20, 10], "Pages should cover every file once, largest first");
+
+    let page: Value = list("sort=size&order=asc&min_size=15&max_size=45".to_string())
+        .await
+        .json()
+        .await
+        .expect("Failed to parse listing");
+    assert_eq!(sizes(&page), vec![20, 30, 40]);
+    assert!(page["next_cursor"].is_null(), "A partial page is the last one");
+
+    let page: Value = list("content_type=image/".to_string())
+        .await
+        .json()
+        .await
+        .expect("Failed to parse listing");
+    assert_eq!(sizes(&page), vec![50]);
+    assert_eq!(page["files"][0]["filename"], "pixel.png");
+
+    let page: Value = list("in_memory=true".to_string())
+        .await
+        .json()
+        .await
+        .expect("Failed to parse listing");
+    assert_eq!(sizes(&page), Vec::<u64>::new(), "Test uploads are kept on disk");
+
+    assert_eq!(list("sort=filename".to_string()).await.status(), 400);
+    assert_eq!(list("sort=size&cursor=created_at.1.nope".to_string()).await.status(), 400);
+}
+
+async fn check_admin_delete_and_purge(base_url: &str) {
+    let client = create_test_client();
+    let mut ids = Vec::new();
+    let mut short_codes = Vec::new();
+    for size in [100, 200, 300] {
+        let response = client
+            .put(&format!("{}/drop/purge-{}.txt", base_url, size))
+            .body("p".repeat(size))
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        let upload = &upload["files"][0];
+        ids.push(upload["id"].as_str().expect("No file ID in response").to_string());
+        short_codes.push(
+            upload["short_url"]
+                .as_str()
+                .expect("No short URL in response")
+                .rsplit('/')
+                .next()
+                .expect("Invalid short URL format")
+                .to_string(),
+        );
+    }
+    let status_of = |path: String| {
+        let client = client.clone();
+        async move {
+            client
+                .get(&format!("{}{}", base_url, path))
+                .send()
+                .await
+                .expect("Download request failed")
+                .status()
+        }
+    };
+    let purge = |body: Value, query: &'static str| {
+        let client = client.clone();
+        async move {
+            let response = client
+                .post(&format!("{}/admin/files/purge{}", base_url, query))
+                .header("X-Admin-Token", "test-admin-token")
+                .json(&body)
+                .send()
+                .await
+                .expect("Purge request failed");
+            assert!(response.status().is_success(), "Purge should succeed");
+            response.json::<Value>().await.expect("Failed to parse purge report")
+        }
+    };
+
+    let response = client
+        .delete(&format!("{}/admin/files/{}", base_url, ids[0]))
+        .header("X-Admin-Token", "wrong-token")
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 403, "Deleting requires the admin token");
+
+    // Delete by short code, no delete token needed
+    let response = client
+        .delete(&format!("{}/admin/files/{}", base_url, short_codes[0]))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 204, "Admin delete should succeed");
+    assert_eq!(status_of(format!("/drop/{}", ids[0])).await, 404, "Deleted file should be gone");
+    assert_eq!(status_of(format!("/drop/{}", short_codes[0])).await, 404, "Short code should no longer resolve");
+    let response = client
+        .delete(&format!("{}/admin/files/{}", base_url, ids[0]))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 404, "A file can only be deleted once");
+
+    let response = client
+        .post(&format!("{}/admin/files/purge", base_url))
+        .header("X-Admin-Token", "test-admin-token")
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .expect("Purge request failed");
+    assert_eq!(response.status(), 400, "A purge needs a filter");
+
+    // A dry run reports without deleting
+    let report = purge(serde_json::json!({ "older_than_days": 0 }), "?dry_run=true").await;
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["files_removed"], 2);
+    assert_eq!(report["bytes_reclaimed"], 500);
+    assert_eq!(status_of(format!("/drop/{}", ids[1])).await, 200, "Dry run shouldn't delete");
+
+    let report = purge(serde_json::json!({ "ids": [ids[0], ids[1]] }), "").await;
+    assert_eq!(report["dry_run"], false);
+    assert_eq!(report["files_removed"], 1, "Already deleted files aren't counted");
+    assert_eq!(report["bytes_reclaimed"], 200);
+    assert_eq!(report["ids"], serde_json::json!([ids[1]]));
+    assert_eq!(status_of(format!("/drop/{}", ids[1])).await, 404, "Purged file should be gone");
+    assert_eq!(status_of(format!("/drop/{}", ids[2])).await, 200, "Unlisted file should remain");
+
+    // Old enough to purge, but nothing is that old
+    let report = purge(serde_json::json!({ "older_than_days": 1, "ids": [ids[2]] }), "").await;
+    assert_eq!(report["files_removed"], 0);
+
+    let report = purge(serde_json::json!({ "older_than_days": 0 }), "").await;
+    assert_eq!(report["files_removed"], 1);
+    assert_eq!(report["bytes_reclaimed"], 300);
+    assert_eq!(status_of(format!("/drop/{}", ids[2])).await, 404, "Purged file should be gone");
+}
+
+#[tokio::test]
+async fn test_admin_delete_and_purge() {
+    // In-memory index
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    check_admin_delete_and_purge(&spawn_server(app_state).await).await;
+
+    // Database index
+    let sqlite_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(sqlite_dir.path()).await;
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    check_admin_delete_and_purge(&spawn_server(app_state).await).await;
+
+    println!("✅ Admin delete and purge test passed");
+}
+
+#[tokio::test]
+async fn test_batch_delete() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+    let mut uploads = Vec::new();
+    for name in ["one.txt", "two.txt"] {
+        let response = client
+            .put(&format!("{}/drop/{}", base_url, name))
+            .body("delete me")
+            .send()
+            .await
+            .expect("Upload request failed");
+        let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+        let file = &upload_response["files"][0];
+        uploads.push((
+            file["id"].as_str().expect("No file ID").to_string(),
+            file["delete_token"].as_str().expect("No delete token").to_string(),
+        ));
+    }
+    let (ref first_id, ref first_token) = uploads[0];
+    let (ref second_id, _) = uploads[1];
+    let unknown_id = uuid::Uuid::new_v4().to_string();
+
+    let response = client
+        .post(&format!("{}/drop/delete", base_url))
+        .json(&serde_json::json!({ "files": [
+            { "id": first_id, "delete_token": first_token },
+            { "id": second_id, "delete_token": "not-the-token" },
+            { "id": unknown_id, "delete_token": first_token },
+        ]}))
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 207, "Not every file was deleted");
+    let report: Value = response.json().await.expect("Failed to parse delete response");
+    let results: Vec<&str> = report["results"]
+        .as_array()
+        .expect("No results")
+        .iter()
+        .map(|result| result["result"].as_str().unwrap())
+        .collect();
+    assert_eq!(results, ["deleted", "forbidden", "not_found"]);
+    assert!(!app_state.file_storage.contains_key(&first_id.parse().unwrap()));
+    assert!(app_state.file_storage.contains_key(&second_id.parse().unwrap()));
+
+    // Bare IDs need the admin token
+    let delete_ids = |admin_token: &'static str| {
+        client
+            .post(&format!("{}/drop/delete", base_url))
+            .header("X-Admin-Token", admin_token)
+            .json(&serde_json::json!({ "ids": [second_id] }))
+            .send()
+    };
+    let response = delete_ids("wrong-token").await.expect("Delete request failed");
+    assert_eq!(response.status(), 403);
+    let response = delete_ids("test-admin-token").await.expect("Delete request failed");
+    assert_eq!(response.status(), 200, "Every file was deleted");
+    assert!(app_state.file_storage.is_empty());
+    assert!(app_state.short_url_storage.is_empty(), "Short codes go with their files");
+
+    let ids = vec![unknown_id; 101];
+    let response = client
+        .post(&format!("{}/drop/delete", base_url))
+        .header("X-Admin-Token", "test-admin-token")
+        .json(&serde_json::json!({ "ids": ids }))
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 400, "At most 100 files per request");
+}
+
+#[tokio::test]
+async fn test_admin_file_listing() {
+    // In-memory index
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    check_admin_file_listing(&spawn_server(app_state).await).await;
+
+    // Database index
+    let sqlite_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(sqlite_dir.path()).await;
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    check_admin_file_listing(&spawn_server(app_state).await).await;
+
+    println!("✅ Admin file listing test passed");
+}
@@ -0,0 +1,85 @@
+//! Client IP resolution behind reverse proxies.
+
+use axum::http::{HeaderMap, HeaderValue};
+use drop::client_ip::{get_client_ip, parse_ip_ranges};
+use std::net::{IpAddr, SocketAddr};
+
+fn config() -> drop::Config {
+    drop::Config {
+        trusted_proxies: parse_ip_ranges("10.0.0.0/8, fd00::/8").expect("Valid proxy ranges"),
+        ..drop::Config::default()
+    }
+}
+
+fn peer(ip: &str) -> SocketAddr {
+    SocketAddr::new(ip.parse().expect("Valid peer IP"), 40000)
+}
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.append(*name, HeaderValue::from_str(value).expect("Valid header value"));
+    }
+    headers
+}
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().expect("Valid IP")
+}
+
+#[test]
+fn test_multi_hop_forwarded_for() {
+    // client, proxy1, proxy2 - the peer is a third proxy
+    let request = headers(&[("x-forwarded-for", "203.0.113.5, 10.0.0.2, 10.0.0.3")]);
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.4"), &request), ip("203.0.113.5"));
+
+    // Hops split across several headers read in order
+    let request = headers(&[("x-forwarded-for", "203.0.113.5"), ("x-forwarded-for", "10.0.0.2, 10.0.0.3")]);
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.4"), &request), ip("203.0.113.5"));
+}
+
+#[test]
+fn test_forged_hops_left_of_the_client_are_ignored() {
+    // The client prepended its own entry; the first proxy appended the real address
+    let request = headers(&[("x-forwarded-for", "198.51.100.1, 203.0.113.5, 10.0.0.2")]);
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.3"), &request), ip("203.0.113.5"));
+}
+
+#[test]
+fn test_untrusted_peer_headers_ignored() {
+    let request = headers(&[("x-forwarded-for", "203.0.113.5"), ("x-real-ip", "203.0.113.6")]);
+    assert_eq!(get_client_ip(&config(), peer("192.0.2.10"), &request), ip("192.0.2.10"));
+
+    // Nothing is trusted by default
+    assert_eq!(
+        get_client_ip(&drop::Config::default(), peer("10.0.0.4"), &request),
+        ip("10.0.0.4")
+    );
+}
+
+#[test]
+fn test_real_ip_and_fallbacks() {
+    // X-Real-IP when there is no X-Forwarded-For
+    let request = headers(&[("x-real-ip", "203.0.113.6")]);
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.4"), &request), ip("203.0.113.6"));
+
+    // A trusted peer without either header is the client
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.4"), &HeaderMap::new()), ip("10.0.0.4"));
+
+    // Only trusted hops: the furthest one is the client
+    let request = headers(&[("x-forwarded-for", "10.9.9.9, 10.0.0.2")]);
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.4"), &request), ip("10.9.9.9"));
+
+    // A garbled hop stops the walk before anything it could hide
+    let request = headers(&[("x-forwarded-for", "203.0.113.5, unknown, 10.0.0.2")]);
+    assert_eq!(get_client_ip(&config(), peer("10.0.0.4"), &request), ip("10.0.0.2"));
+}
+
+#[test]
+fn test_forwarded_hops_with_ports_and_v6() {
+    let request = headers(&[("x-forwarded-for", "[2001:db8::7]:51234, fd00::2")]);
+    assert_eq!(get_client_ip(&config(), peer("fd00::3"), &request), ip("2001:db8::7"));
+
+    let request = headers(&[("x-forwarded-for", "203.0.113.5:51234")]);
+    assert_eq!(get_client_ip(&config(), peer("::ffff:10.0.0.4"), &request), ip("203.0.113.5"));
+}
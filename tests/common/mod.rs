@@ -0,0 +1,140 @@
+//! Helpers for the test binaries that run the app in process. Each binary
+//! uses its own subset of them.
+#![allow(dead_code)]
+
+use drop::metadata::MetadataStore;
+use reqwest::{Client, RequestBuilder, multipart};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Helper function to create a test client with appropriate timeouts
+pub fn create_test_client() -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to create HTTP client")
+}
+
+/// Build the state for an in-process server storing files under `dir`
+pub fn test_app_state(dir: &std::path::Path, database: Option<drop::database::Database>) -> drop::AppState {
+    store_app_state(dir, database.map(|database| Arc::new(database) as Arc<dyn MetadataStore>))
+}
+
+/// URL of the SQLite database kept in `dir`
+pub fn sqlite_url(dir: &std::path::Path) -> String {
+    format!("sqlite:{}", dir.join("drop.db").display())
+}
+
+/// Open the SQLite database in `dir`, creating it on first use
+pub async fn open_sqlite(dir: &std::path::Path) -> drop::database::Database {
+    drop::database::Database::new(&sqlite_url(dir))
+        .await
+        .expect("Failed to open SQLite database")
+}
+
+/// `test_app_state` backed by the SQLite database in `dir`
+pub async fn sqlite_app_state(dir: &std::path::Path) -> drop::AppState {
+    test_app_state(dir, Some(open_sqlite(dir).await))
+}
+
+/// `test_app_state` over any metadata store, such as an `InMemoryStore`
+pub fn store_app_state(dir: &std::path::Path, store: Option<Arc<dyn MetadataStore>>) -> drop::AppState {
+    let config = drop::Config {
+        temp_directory: dir.join("files"),
+        ..drop::Config::default()
+    };
+    drop::AppState::new(config, store, None).expect("Failed to build app state")
+}
+
+/// Serve `app_state` on an ephemeral port and return its base URL
+pub async fn spawn_server(app_state: drop::AppState) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind test listener");
+    let addr = listener.local_addr().expect("No local address");
+    let app = drop::create_app(app_state);
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+            .await
+            .expect("Test server failed");
+    });
+
+    format!("http://{}", addr)
+}
+
+/// A form with one `file` part named `filename`
+pub fn file_form(filename: &str, content: impl Into<Vec<u8>>) -> multipart::Form {
+    let part = multipart::Part::bytes(content.into()).file_name(filename.to_string());
+    multipart::Form::new().part("file", part)
+}
+
+/// Multipart upload of `form` to `/drop{query}`, ready for extra headers
+pub fn post_form(client: &Client, base_url: &str, query: &str, form: multipart::Form) -> RequestBuilder {
+    client.post(format!("{}/drop{}", base_url, query)).multipart(form)
+}
+
+/// Raw upload of `body` to `/drop/{path}`, where `path` is a filename and optional query
+pub fn put_file(client: &Client, base_url: &str, path: &str, body: impl Into<reqwest::Body>) -> RequestBuilder {
+    client.put(format!("{}/drop/{}", base_url, path)).body(body)
+}
+
+/// Assert an upload succeeded and return the entry for its first file
+pub async fn uploaded_file(response: reqwest::Response) -> Value {
+    assert!(response.status().is_success(), "Upload should succeed, got {}", response.status());
+    let uploaded: Value = response.json().await.expect("Failed to parse upload response");
+    uploaded["files"][0].clone()
+}
+
+/// Upload `content` as `filename` through the form endpoint and return its file entry
+pub async fn upload(
+    client: &Client,
+    base_url: &str,
+    query: &str,
+    filename: &str,
+    content: impl Into<Vec<u8>>,
+) -> Value {
+    let response = post_form(client, base_url, query, file_form(filename, content))
+        .send()
+        .await
+        .expect("Upload request failed");
+    uploaded_file(response).await
+}
+
+/// Upload `body` with a raw PUT to `/drop/{path}` and return its file entry
+pub async fn upload_raw(client: &Client, base_url: &str, path: &str, body: impl Into<reqwest::Body>) -> Value {
+    let response = put_file(client, base_url, path, body)
+        .send()
+        .await
+        .expect("Upload request failed");
+    uploaded_file(response).await
+}
+
+/// Start an in-process server backed by an SQLite file in `dir` and return its base URL
+pub async fn spawn_sqlite_server(dir: &std::path::Path) -> String {
+    let mut app_state = sqlite_app_state(dir).await;
+    app_state.config.database_url = Some(sqlite_url(dir));
+    spawn_server(app_state).await
+}
+
+/// Names of the stored uploads (not sidecars) in the test temp directory
+pub fn stored_files(dir: &std::path::Path) -> Vec<String> {
+    fn walk(directory: &std::path::Path, names: &mut Vec<String>) {
+        for entry in std::fs::read_dir(directory).into_iter().flatten().flatten() {
+            let path = entry.path();
+            match entry.file_name().into_string() {
+                _ if path.is_dir() => walk(&path, names),
+                Ok(name) if name.starts_with("file_") && !name.ends_with(".json") => names.push(name),
+                _ => {}
+            }
+        }
+    }
+    let mut names = Vec::new();
+    walk(&dir.join("files"), &mut names);
+    names
+}
+
+/// Where the file `id` is stored in the temp directory of `test_app_state(dir, ..)`
+pub fn stored_path(dir: &std::path::Path, id: &str) -> std::path::PathBuf {
+    drop::storage::sharded_path(&dir.join("files"), id.parse().expect("Invalid file ID"))
+}
@@ -0,0 +1,326 @@
+//! Loading and validating configuration. These tests assume no `DROP_*`
+//! variables are set, since the environment overrides the file.
+
+use drop::Config;
+
+fn write_config(dir: &std::path::Path, contents: &str) -> std::path::PathBuf {
+    let path = dir.join("drop.toml");
+    std::fs::write(&path, contents).expect("Failed to write config file");
+    path
+}
+
+#[test]
+fn test_from_file_reads_settings() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(
+        dir.path(),
+        r#"
+        bind_address = "127.0.0.1:4000"
+        max_file_size_mb = 64
+        memory_pool_ratio = 0.25
+        blocked_extensions = [".EXE", "scr"]
+        rate_limit_allowlist = []
+        clean_orphans = true
+        max_disk_usage_gb = 0
+        public_url = "https://files.example.com/"
+        "#,
+    );
+
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.bind_address, "127.0.0.1:4000");
+    assert_eq!(config.max_file_size_limit, 64 * 1024 * 1024);
+    assert_eq!(config.memory_pool_ratio, 0.25);
+    assert_eq!(config.blocked_extensions, vec!["exe", "scr"]);
+    assert!(config.rate_limit_allowlist.is_empty(), "An empty list replaces the default");
+    assert!(config.clean_orphans);
+    assert_eq!(config.max_disk_usage, None, "0 means no cap");
+    assert_eq!(config.public_base_url.as_deref(), Some("https://files.example.com"));
+    assert_eq!(config.rate_limit_download_rpm, 300, "Unset keys keep their defaults");
+}
+
+#[test]
+fn test_from_file_rejects_unknown_keys_and_bad_values() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(
+        dir.path(),
+        r#"
+        max_file_size_gbb = 5
+        rate_limit_upload_rpm = "lots"
+        cleanup_interval_secs = 0
+        db_optional = "maybe"
+        "#,
+    );
+
+    let error = format!("{:#}", Config::from_file(&path).expect_err("Config should be rejected"));
+    for key in ["max_file_size_gbb", "rate_limit_upload_rpm", "cleanup_interval_secs", "db_optional"] {
+        assert!(error.contains(key), "Error should name {}: {}", key, error);
+    }
+}
+
+#[test]
+fn test_from_file_rejects_malformed_toml() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(dir.path(), "max_file_size_gb = ");
+    assert!(Config::from_file(&path).is_err());
+    assert!(Config::from_file(dir.path().join("missing.toml")).is_err());
+}
+
+#[test]
+fn test_validate_reports_every_violation() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let not_a_dir = dir.path().join("file");
+    std::fs::write(&not_a_dir, b"").expect("Failed to write file");
+
+    let config = Config {
+        min_file_size_limit: 100,
+        max_file_size_limit: 10,
+        max_total_size_per_request: 5,
+        temp_directory: not_a_dir.join("files"),
+        ..Config::default()
+    };
+    let error = config.validate().expect_err("Config should be invalid").to_string();
+    assert!(error.contains("min_file_size_mb"), "{}", error);
+    assert!(error.contains("max_total_size_gb"), "{}", error);
+    assert!(error.contains("temp_dir"), "{}", error);
+}
+
+#[test]
+fn test_validate_accepts_defaults() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let config = Config {
+        temp_directory: dir.path().join("files"),
+        ..Config::default()
+    };
+    config.validate().expect("Defaults should be valid");
+    assert!(dir.path().join("files").is_dir(), "The temp directory is created");
+    assert_eq!(std::fs::read_dir(dir.path().join("files")).unwrap().count(), 0, "No probe file is left behind");
+}
+
+#[test]
+fn test_parse_size() {
+    use drop::config::parse_size;
+
+    assert_eq!(parse_size("1024").unwrap(), 1024);
+    assert_eq!(parse_size("64KB").unwrap(), 64 * 1024);
+    assert_eq!(parse_size("512mb").unwrap(), 512 * 1024 * 1024);
+    assert_eq!(parse_size("2GB").unwrap(), 2 * 1024 * 1024 * 1024);
+    assert_eq!(parse_size("2 GiB").unwrap(), 2 * 1024 * 1024 * 1024);
+    assert!(parse_size("2.5GB").is_err(), "Fractions aren't supported");
+    assert!(parse_size("GB").is_err());
+    assert!(parse_size("10XB").is_err());
+    assert!(parse_size("99999999999TB").is_err(), "Overflow is an error");
+}
+
+#[test]
+fn test_parse_duration() {
+    use drop::config::parse_duration;
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+    assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+    assert_eq!(parse_duration("12h").unwrap(), Duration::from_secs(12 * 60 * 60));
+    assert_eq!(parse_duration("7D").unwrap(), Duration::from_secs(7 * 24 * 60 * 60));
+    assert_eq!(parse_duration("2 w").unwrap(), Duration::from_secs(14 * 24 * 60 * 60));
+    assert!(parse_duration("1.5h").is_err(), "Fractions aren't supported");
+    assert!(parse_duration("h").is_err());
+    assert!(parse_duration("3y").is_err());
+    assert!(parse_duration("99999999999999999w").is_err(), "Overflow is an error");
+}
+
+#[test]
+fn test_retention_settings() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    assert_eq!(Config::default().default_ttl, None);
+    assert_eq!(Config::default().max_ttl, None);
+
+    let path = write_config(dir.path(), "default_ttl = \"12h\"\nmax_ttl = \"7d\"");
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.default_ttl, Some(std::time::Duration::from_secs(12 * 60 * 60)));
+    assert_eq!(config.max_ttl, Some(std::time::Duration::from_secs(7 * 24 * 60 * 60)));
+
+    // 0 keeps files until they are deleted
+    let path = write_config(dir.path(), "default_ttl = \"0\"\nmax_ttl = 0");
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.default_ttl, None);
+    assert_eq!(config.max_ttl, None);
+
+    let path = write_config(dir.path(), "max_ttl = \"a week\"");
+    assert!(Config::from_file(&path).is_err(), "Unparseable durations should be rejected");
+}
+
+#[test]
+fn test_redacted_hides_secrets() {
+    let config = Config {
+        admin_token: Some("hunter2".to_string()),
+        database_url: Some("postgresql://drop_user:s3cret@db:5432/drop".to_string()),
+        redis_url: Some("redis://:r3dis@cache:6379/0".to_string()),
+        ..Config::default()
+    };
+    let redacted = config.redacted();
+    assert_eq!(redacted.admin_token.as_deref(), Some("<redacted>"));
+    assert_eq!(
+        redacted.database_url.as_deref(),
+        Some("postgresql://drop_user:<redacted>@db:5432/drop")
+    );
+    assert_eq!(redacted.redis_url.as_deref(), Some("redis://:<redacted>@cache:6379/0"));
+
+    let printed = format!("{:?}", redacted);
+    for secret in ["hunter2", "s3cret", "r3dis"] {
+        assert!(!printed.contains(secret), "{} leaked: {}", secret, printed);
+    }
+
+    // Nothing to hide
+    let config = Config {
+        database_url: Some("sqlite:./drop.db".to_string()),
+        ..Config::default()
+    };
+    assert_eq!(config.redacted().database_url.as_deref(), Some("sqlite:./drop.db"));
+}
+
+#[test]
+fn test_encryption_keys() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(
+        dir.path(),
+        r#"
+        encryption_key = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8="
+        encryption_old_keys = ["ZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXp7fH1+f4CBgoM="]
+        "#,
+    );
+    let config = Config::from_file(&path).expect("Config should load");
+    assert!(config.encryption_key.is_some());
+    assert_eq!(config.encryption_old_keys.len(), 1, "Keys keep their case and are not lowercased");
+
+    let printed = format!("{:?}", config.redacted());
+    assert!(!printed.contains("AAECAwQF"), "Key leaked: {}", printed);
+    assert!(!printed.contains("0, 1, 2, 3"), "Key bytes leaked: {}", printed);
+
+    // Keys must be base64 and exactly 32 bytes
+    for key in ["not base64!", "AAAAAAAAAAAAAAAAAAAAAA=="] {
+        let path = write_config(dir.path(), &format!("encryption_key = \"{}\"", key));
+        let error = format!("{:#}", Config::from_file(&path).expect_err("Key should be rejected"));
+        assert!(error.contains("encryption_key"), "{}", error);
+    }
+}
+
+#[test]
+fn test_validate_requires_tls_cert_and_key_together() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let cert = dir.path().join("cert.pem");
+    std::fs::write(&cert, b"certificate").expect("Failed to write certificate");
+
+    let config = Config {
+        temp_directory: dir.path().join("files"),
+        tls_cert_path: Some(cert.clone()),
+        ..Config::default()
+    };
+    let error = config.validate().expect_err("A certificate needs a key").to_string();
+    assert!(error.contains("tls_key"), "{}", error);
+    assert!(!config.tls_enabled());
+
+    let config = Config {
+        tls_key_path: Some(dir.path().join("missing-key.pem")),
+        ..config
+    };
+    let error = config.validate().expect_err("The key must be readable").to_string();
+    assert!(error.contains("missing-key.pem"), "{}", error);
+    assert!(config.tls_enabled());
+}
+
+#[test]
+fn test_cors_allowed_origins() {
+    use drop::cors::AllowedOrigins;
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(
+        dir.path(),
+        r#"cors_allowed_origins = ["https://app.example.com/", "http://localhost:5173"]"#,
+    );
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(
+        config.cors_allowed_origins,
+        Some(AllowedOrigins::List(vec![
+            "https://app.example.com".parse().unwrap(),
+            "http://localhost:5173".parse().unwrap(),
+        ]))
+    );
+    assert_eq!(Config::default().cors_allowed_origins, None, "CORS is off by default");
+
+    let path = write_config(dir.path(), r#"cors_allowed_origins = "*""#);
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.cors_allowed_origins, Some(AllowedOrigins::Any));
+
+    for origins in ["*, https://app.example.com", "app.example.com"] {
+        let path = write_config(dir.path(), &format!("cors_allowed_origins = \"{}\"", origins));
+        let error = format!("{:#}", Config::from_file(&path).expect_err("Origins should be rejected"));
+        assert!(error.contains("cors_allowed_origins"), "{}", error);
+    }
+}
+
+#[test]
+fn test_slow_transfer_limits() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(
+        dir.path(),
+        r#"
+        min_upload_rate_kbps = 8
+        min_upload_rate_window_secs = 10
+        download_idle_timeout_secs = 120
+        "#,
+    );
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.min_upload_rate, Some(8 * 1024));
+    assert_eq!(config.min_upload_rate_window_seconds, 10);
+    assert_eq!(config.download_idle_timeout, Some(std::time::Duration::from_secs(120)));
+
+    // 0 turns either check off for legitimately slow links
+    let path = write_config(dir.path(), "min_upload_rate_kbps = 0\ndownload_idle_timeout_secs = 0");
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.min_upload_rate, None);
+    assert_eq!(config.download_idle_timeout, None);
+
+    let path = write_config(dir.path(), "min_upload_rate_window_secs = 0");
+    assert!(Config::from_file(&path).is_err(), "A zero window should be rejected");
+}
+
+#[test]
+fn test_access_log_settings() {
+    use drop::access_log::AccessLogFormat;
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(
+        dir.path(),
+        r#"
+        access_log_format = "Combined"
+        access_log_path = "/var/log/drop/access.log"
+        slow_request_ms = 2500
+        "#,
+    );
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.access_log_format, Some(AccessLogFormat::Combined));
+    assert_eq!(config.access_log_path, Some(std::path::PathBuf::from("/var/log/drop/access.log")));
+    assert_eq!(config.slow_request_threshold, Some(std::time::Duration::from_millis(2500)));
+
+    let path = write_config(dir.path(), "access_log_format = \"off\"\naccess_log_path = \"-\"\nslow_request_ms = 0");
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.access_log_format, None);
+    assert_eq!(config.access_log_path, None, "'-' means stdout");
+    assert_eq!(config.slow_request_threshold, None);
+
+    let path = write_config(dir.path(), "access_log_format = \"apache\"");
+    assert!(Config::from_file(&path).is_err(), "Unknown formats should be rejected");
+}
+
+#[test]
+fn test_short_code_mode() {
+    use drop::redirect::ShortCodeMode;
+
+    assert_eq!(Config::default().short_code_mode, ShortCodeMode::Direct);
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let path = write_config(dir.path(), "short_code_mode = \"Redirect\"");
+    let config = Config::from_file(&path).expect("Config should load");
+    assert_eq!(config.short_code_mode, ShortCodeMode::Redirect);
+
+    let path = write_config(dir.path(), "short_code_mode = \"proxy\"");
+    assert!(Config::from_file(&path).is_err(), "Unknown modes should be rejected");
+}
@@ -0,0 +1,104 @@
+//! The `active_uploads` and `active_downloads` counters in `/health`. They
+//! are process-wide, so these tests live in their own test binary where
+//! nothing else moves them.
+
+use reqwest::{Client, multipart};
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{create_test_client, file_form, post_form, spawn_server};
+
+/// State with upload limits small enough to trip in a test
+fn test_app_state(dir: &std::path::Path) -> drop::AppState {
+    let config = drop::Config {
+        temp_directory: dir.join("files"),
+        max_file_size_limit: 4 * 1024 * 1024,
+        max_total_size_per_request: 5 * 1024 * 1024,
+        ..drop::Config::default()
+    };
+    drop::AppState::new(config, None, None).expect("Failed to build app state")
+}
+
+/// Wait for both counters to drop back to zero, returning the last readings
+async fn settled_counts(client: &Client, base_url: &str) -> (u64, u64) {
+    let mut counts = (u64::MAX, u64::MAX);
+    for _ in 0..50 {
+        let health: Value = client
+            .get(&format!("{}/health", base_url))
+            .send()
+            .await
+            .expect("Health request failed")
+            .json()
+            .await
+            .expect("Failed to parse health response");
+        counts = (
+            health["active_uploads"].as_u64().expect("No active_uploads"),
+            health["active_downloads"].as_u64().expect("No active_downloads"),
+        );
+        if counts == (0, 0) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    counts
+}
+
+#[tokio::test]
+async fn test_counters_return_to_zero() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path())).await;
+    let client = create_test_client();
+    let upload = |form: multipart::Form| post_form(&client, &base_url, "", form).send();
+
+    // A successful upload
+    let big = "x".repeat(3 * 1024 * 1024);
+    let uploaded = common::upload(&client, &base_url, "", "big.txt", big.clone()).await;
+    let file_id = uploaded["id"].as_str().expect("No file ID").to_string();
+
+    // A file over the size limit
+    let response = upload(file_form("huge.txt", "y".repeat(5 * 1024 * 1024)))
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 413, "Oversized file should be rejected");
+
+    // Files that together go over the per-request limit
+    let form = multipart::Form::new()
+        .part("file", multipart::Part::text(big.clone()).file_name("one.txt"))
+        .part("file", multipart::Part::text(big.clone()).file_name("two.txt"));
+    let response = upload(form).await.expect("Upload request failed");
+    assert_eq!(response.status(), 413, "Oversized request should be rejected");
+
+    // A form without any file, and a body that isn't valid multipart. An empty
+    // form would be sent as an empty body, which is malformed too
+    let response = upload(multipart::Form::new().text("comment", "No file here"))
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 422, "A form without files should be rejected");
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .header("content-type", "multipart/form-data; boundary=nothing")
+        .body("this is not multipart")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 400, "A malformed body should be rejected");
+
+    // A complete download, a missing file, and a download abandoned unread
+    let url = format!("{}/drop/{}", base_url, file_id);
+    let body = client.get(&url).send().await.expect("Download request failed").bytes().await;
+    assert_eq!(body.expect("No body").len(), big.len());
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 404);
+    let response = client.get(&url).send().await.expect("Download request failed");
+    assert!(response.status().is_success());
+    std::mem::drop(response);
+
+    assert_eq!(settled_counts(&client, &base_url).await, (0, 0), "Counters should return to zero");
+
+    println!("✅ Connection counter test passed");
+}
@@ -0,0 +1,347 @@
+//! Serving files: referer checks, named links, previews and compression.
+
+use serde_json::Value;
+
+mod common;
+use common::{create_test_client, put_file, spawn_server, test_app_state, upload, upload_raw, uploaded_file};
+
+#[tokio::test]
+async fn test_allowed_referers() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.allowed_referers = vec!["example.com".to_string(), "*.example.com".to_string()];
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let upload = |query: &'static str| {
+        let path = format!("picture.txt{}", query);
+        let (client, base_url) = (&client, &base_url);
+        async move { upload_raw(client, base_url, &path, "linked content").await }
+    };
+    let file_id = upload("").await["id"].as_str().expect("No file ID in response").to_string();
+    let embeddable_id = upload("?embeddable=true").await["id"].as_str().expect("No file ID in response").to_string();
+    let download = |id: &str, header: Option<(&'static str, &'static str)>| {
+        let mut request = client.get(&format!("{}/drop/{}", base_url, id));
+        if let Some((name, value)) = header {
+            request = request.header(name, value);
+        }
+        request.send()
+    };
+
+    // Listed sites, this instance and requests naming no site are served
+    for header in [
+        Some(("Referer", "https://example.com/gallery")),
+        Some(("Referer", "https://cdn.example.com:8443/page?x=1")),
+        Some(("Origin", "https://www.example.com")),
+        None,
+    ] {
+        let response = download(&file_id, header).await.expect("Download request failed");
+        assert_eq!(response.status(), 200, "{:?} should be allowed", header);
+    }
+    let own_referer = format!("{}/", base_url);
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file_id))
+        .header("Referer", own_referer)
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200, "Links from this instance should be allowed");
+
+    // Other sites are refused before anything is sent
+    for header in [
+        ("Referer", "https://elsewhere.example.net/post"),
+        ("Referer", "https://notexample.com/"),
+        ("Origin", "null"),
+    ] {
+        let response = download(&file_id, Some(header)).await.expect("Download request failed");
+        assert_eq!(response.status(), 403, "{:?} should be refused", header);
+        let body: Value = response.json().await.expect("Failed to parse error response");
+        assert!(body["error"].is_string(), "The refusal should explain itself");
+    }
+
+    // Embeddable files, HEAD and info are never checked
+    let hotlink = Some(("Referer", "https://elsewhere.example.net/post"));
+    let response = download(&embeddable_id, hotlink).await.expect("Download request failed");
+    assert_eq!(response.text().await.expect("No body"), "linked content");
+    let response = client
+        .head(&format!("{}/drop/{}", base_url, file_id))
+        .header("Referer", "https://elsewhere.example.net/post")
+        .send()
+        .await
+        .expect("HEAD request failed");
+    assert_eq!(response.status(), 200, "HEAD should not be checked");
+    let response = client
+        .get(&format!("{}/drop/{}/info", base_url, file_id))
+        .header("Referer", "https://elsewhere.example.net/post")
+        .send()
+        .await
+        .expect("Info request failed");
+    assert_eq!(response.status(), 200, "Info should not be checked");
+}
+
+#[tokio::test]
+async fn test_block_empty_referer() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.allowed_referers = vec!["example.com".to_string()];
+    app_state.config.block_empty_referer = true;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let uploaded: Value = client
+        .put(&format!("{}/drop/picture.txt", base_url))
+        .body("linked content")
+        .send()
+        .await
+        .expect("Upload request failed")
+        .json()
+        .await
+        .expect("Failed to parse upload response");
+    let file_id = uploaded["files"][0]["id"].as_str().expect("No file ID in response");
+
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file_id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 403, "Downloads naming no site should be refused");
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file_id))
+        .header("Referer", "https://example.com/")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200, "Listed sites should still be allowed");
+}
+
+#[tokio::test]
+async fn test_download_with_filename_segment() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let file = upload(&client, &base_url, "", "Q3 report.txt", "quarterly numbers").await;
+    let pretty_url = file["pretty_url"].as_str().expect("No pretty URL in response");
+    assert_eq!(pretty_url, format!("{}/Q3%20report.txt", file["short_url"].as_str().unwrap()));
+
+    let disposition = |response: &reqwest::Response| {
+        response.headers()["content-disposition"].to_str().unwrap().to_string()
+    };
+
+    let response = client.get(pretty_url).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(disposition(&response), "attachment; filename=\"Q3 report.txt\"");
+    assert_eq!(response.text().await.unwrap(), "quarterly numbers");
+
+    // The ID decides the file; the segment only names it
+    let id = file["id"].as_str().unwrap();
+    let response = client
+        .get(&format!("{}/drop/{}/renamed.txt", base_url, id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(disposition(&response), "attachment; filename=\"renamed.txt\"");
+    assert_eq!(response.text().await.unwrap(), "quarterly numbers");
+    let response = client
+        .head(&format!("{}/drop/{}/renamed.txt", base_url, id))
+        .send()
+        .await
+        .expect("HEAD request failed");
+    assert_eq!(disposition(&response), "attachment; filename=\"renamed.txt\"");
+
+    // Nothing left after sanitizing: the stored name
+    let response = client
+        .get(&format!("{}/drop/{}/%3F%2A", base_url, id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(disposition(&response), "attachment; filename=\"Q3 report.txt\"");
+
+    // The query still wins, and the fixed sub-routes still answer
+    let response = client
+        .get(&format!("{}/drop/{}/renamed.txt?filename=chosen.txt", base_url, id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(disposition(&response), "attachment; filename=\"chosen.txt\"");
+    let response = client
+        .get(&format!("{}/drop/{}/info", base_url, id))
+        .send()
+        .await
+        .expect("Info request failed");
+    let info: Value = response.json().await.expect("Info should still be JSON");
+    assert_eq!(info["filename"], "Q3 report.txt");
+
+    let response = client.get(&format!("{}/drop/{}", base_url, id)).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 200, "The plain form should keep working");
+
+    println!("✅ Filename segment download test passed");
+}
+
+#[tokio::test]
+async fn test_file_preview() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.preview_max_size = 1024;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+    let upload = |name: &str, contents: Vec<u8>, query: &str| {
+        let request = put_file(&client, &base_url, &format!("{}{}", name, query), contents);
+        async move {
+            let file = uploaded_file(request.send().await.expect("Upload request failed")).await;
+            file["id"].as_str().unwrap().to_string()
+        }
+    };
+    let preview = |id: String| client.get(format!("{}/drop/{}/preview", base_url, id)).send();
+
+    // Text is escaped, never rendered as markup - even when it is HTML
+    let id = upload("page.html", b"<script>alert('hi')</script>".to_vec(), "").await;
+    let response = preview(id).await.expect("Preview request failed");
+    assert_eq!(response.status(), 200);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+    assert!(response.headers().contains_key("content-security-policy"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains("&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;"));
+    assert!(!page.contains("<script>"));
+
+    // Images are served inline with their sniffed type, whatever was declared
+    let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR".to_vec();
+    let response = client
+        .put(format!("{}/drop/shot.txt", base_url))
+        .header("Content-Type", "text/plain")
+        .body(png.clone())
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let response = preview(upload_response["files"][0]["id"].as_str().unwrap().to_string())
+        .await
+        .expect("Preview request failed");
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert!(response.headers()["content-disposition"].to_str().unwrap().starts_with("inline"));
+    assert_eq!(response.bytes().await.unwrap().as_ref(), png.as_slice());
+
+    // Binary, oversized and download-limited files only get the metadata card
+    for (name, contents, query) in [
+        ("blob.bin", vec![0u8, 159, 146, 150], ""),
+        ("big.txt", vec![b'a'; 2048], ""),
+        ("once.txt", b"secret".to_vec(), "?max_downloads=1"),
+    ] {
+        let id = upload(name, contents, query).await;
+        let response = preview(id.clone()).await.expect("Preview request failed");
+        assert_eq!(response.status(), 200);
+        let page = response.text().await.unwrap();
+        assert!(page.contains(name) && page.contains("Download"), "Expected a card for {}", name);
+        assert!(!page.contains("<pre>"), "No contents expected for {}", name);
+        assert!(!page.contains("secret"));
+        // ...and previewing never uses up a download
+        let response = client.get(format!("{}/drop/{}", base_url, id)).send().await.expect("Download failed");
+        assert_eq!(response.status(), 200);
+    }
+
+    println!("✅ File preview test passed");
+}
+
+#[tokio::test]
+async fn test_download_compression() {
+    use tokio::io::AsyncReadExt;
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let contents = "SELECT * FROM drops;\n".repeat(500);
+    let response = client
+        .put(&format!("{}/drop/dump.sql", base_url))
+        .header("Content-Type", "text/plain")
+        .body(contents.clone())
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let url = upload["files"][0]["full_url"].as_str().unwrap().to_string();
+
+    let response = client
+        .get(&url)
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-encoding"], "gzip");
+    assert_eq!(response.headers()["vary"], "accept-encoding");
+    assert!(response.headers().get("content-length").is_none());
+    assert!(response.headers()["etag"].to_str().unwrap().starts_with("W/"));
+    let compressed = response.bytes().await.unwrap();
+    assert!(compressed.len() < contents.len(), "Body should be smaller than the file");
+    let mut decoded = String::new();
+    async_compression::tokio::bufread::GzipDecoder::new(&compressed[..])
+        .read_to_string(&mut decoded)
+        .await
+        .expect("Body should be valid gzip");
+    assert_eq!(decoded, contents);
+
+    // zstd is preferred when both are acceptable
+    let response = client
+        .get(&url)
+        .header("Accept-Encoding", "gzip, zstd")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.headers()["content-encoding"], "zstd");
+
+    // Range requests and clients without Accept-Encoding get the stored bytes
+    let response = client
+        .get(&url)
+        .header("Accept-Encoding", "gzip")
+        .header("Range", "bytes=0-5")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 206);
+    assert!(response.headers().get("content-encoding").is_none());
+    assert_eq!(response.text().await.unwrap(), "SELECT");
+    let response = client.get(&url).send().await.expect("Download request failed");
+    assert!(response.headers().get("content-encoding").is_none());
+    assert_eq!(response.headers()["content-length"], contents.len().to_string().as_str());
+
+    // Already compressed types are never compressed again
+    let jpeg = [b"\xff\xd8\xff\xe0".as_slice(), &[0u8; 4096]].concat();
+    let response = client
+        .put(&format!("{}/drop/photo.jpg", base_url))
+        .body(jpeg)
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let response = client
+        .get(upload["files"][0]["full_url"].as_str().unwrap())
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.headers().get("content-encoding").is_none());
+
+    // ...and the feature can be turned off
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.disable_compression = true;
+    let base_url = spawn_server(app_state).await;
+    let response = client
+        .put(&format!("{}/drop/dump.sql", base_url))
+        .header("Content-Type", "text/plain")
+        .body(contents)
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let response = client
+        .get(upload["files"][0]["full_url"].as_str().unwrap())
+        .header("Accept-Encoding", "gzip")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.headers().get("content-encoding").is_none());
+
+    println!("✅ Download compression test passed");
+}
@@ -0,0 +1,356 @@
+//! Expiry, retention, pinning and what is left behind once a file is gone.
+
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{
+    create_test_client, file_form, open_sqlite, post_form, spawn_server, sqlite_app_state, test_app_state, upload,
+    uploaded_file,
+};
+
+#[tokio::test]
+async fn test_short_code_of_expired_file_stops_resolving() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let app_state = test_app_state(dir.path(), Some(database.clone()));
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+
+    let file = upload(&client, &base_url, "?expires_in=1", "expiring.txt", "gone soon").await;
+    let file_id: uuid::Uuid = file["id"].as_str().expect("No file ID").parse().expect("Invalid file ID");
+    let short_url = file["short_url"].as_str().expect("No short URL").to_string();
+    let short_code = short_url.rsplit('/').next().expect("No short code").to_string();
+
+    // Expired and purged: the tombstone still answers 410 by short code
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    drop::run_cleanup(&app_state).await;
+    let response = client.get(&short_url).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 410, "An expired file's short code should answer 410");
+
+    // Once the tombstone is gone its short code goes with it
+    assert!(database.delete_file_mapping(file_id).await.unwrap());
+    assert_eq!(database.get_file_id_by_short_code(&short_code).await.unwrap(), None);
+    assert!(database.short_codes_for_file(file_id).await.unwrap().is_empty(), "Short codes should cascade");
+    let response = client.get(&short_url).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 404, "The short code should no longer resolve");
+}
+
+#[tokio::test]
+async fn test_admin_cleanup() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let mut app_state = test_app_state(dir.path(), Some(database.clone()));
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+    let cleanup = |query: &'static str| {
+        let client = client.clone();
+        let base_url = base_url.clone();
+        async move {
+            client
+                .post(&format!("{}/admin/cleanup{}", base_url, query))
+                .header("X-Admin-Token", "test-admin-token")
+                .send()
+                .await
+                .expect("Cleanup request failed")
+        }
+    };
+    let last = || async {
+        client
+            .get(&format!("{}/admin/cleanup/last", base_url))
+            .header("X-Admin-Token", "test-admin-token")
+            .send()
+            .await
+            .expect("Last cleanup request failed")
+    };
+
+    assert_eq!(last().await.status(), 204, "No pass has run yet");
+
+    let file = upload(&client, &base_url, "?expires_in=1", "expiring.txt", "gone soon").await;
+    let file_id = file["id"].as_str().expect("No file ID").to_string();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    // A dry run reports the expired file and leaves it, and isn't kept as the last pass
+    let report: Value = cleanup("?dry_run=true").await.json().await.expect("Failed to parse cleanup report");
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["files_removed"], 1);
+    assert_eq!(report["bytes_reclaimed"], 9);
+    assert_eq!(report["ids"], serde_json::json!([file_id]));
+    let mapping = database.find_file_mapping(file_id.parse().unwrap()).await.unwrap().expect("File should remain");
+    assert!(mapping.purged_at.is_none(), "A dry run shouldn't purge anything");
+    assert_eq!(last().await.status(), 204, "Dry runs aren't recorded");
+
+    // A real pass removes it
+    let report: Value = cleanup("").await.json().await.expect("Failed to parse cleanup report");
+    assert_eq!(report["dry_run"], false);
+    assert_eq!(report["ids"], serde_json::json!([file_id]));
+    assert_eq!(report["bytes_reclaimed"], 9);
+    assert_eq!(report["delete_failures"], 0);
+    let mapping = database.find_file_mapping(file_id.parse().unwrap()).await.unwrap().expect("Tombstone should remain");
+    assert!(mapping.purged_at.is_some(), "The file should be purged");
+
+    let response = last().await;
+    assert_eq!(response.status(), 200);
+    let last_report: Value = response.json().await.expect("Failed to parse cleanup report");
+    assert_eq!(last_report, report, "The last pass should be the one just run");
+
+    // Nothing left to do
+    let report: Value = cleanup("").await.json().await.expect("Failed to parse cleanup report");
+    assert_eq!(report["files_removed"], 0);
+
+    let response = client.post(&format!("{}/admin/cleanup", base_url)).send().await.expect("Cleanup request failed");
+    assert_eq!(response.status(), 403, "The admin token is required");
+}
+
+#[tokio::test]
+async fn test_retention_policy() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.default_ttl = Some(Duration::from_secs(60 * 60));
+    app_state.config.max_ttl = Some(Duration::from_secs(2 * 60 * 60));
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+    let upload = |query: &'static str| upload(&client, &base_url, query, "retained.txt", "retained");
+    let hours_left = |file: &Value| {
+        let expires_at: chrono::DateTime<chrono::Utc> =
+            file["expires_at"].as_str().expect("No expiry").parse().expect("Invalid expiry");
+        (expires_at - chrono::Utc::now()).num_minutes() as f64 / 60.0
+    };
+
+    // No expiry asked for gets the default, a longer one is cut to the maximum,
+    // and a shorter one is kept
+    assert!((hours_left(&upload("").await) - 1.0).abs() < 0.1, "The default TTL should apply");
+    assert!((hours_left(&upload("?expires_in=86400").await) - 2.0).abs() < 0.1, "The maximum TTL should cap expiry");
+    let short = upload("?expires_in=600").await;
+    assert!(hours_left(&short) < 0.2, "Shorter expiries should be kept");
+
+    // Lowering the maximum purges files already older than it
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let mut strict = app_state.clone();
+    strict.config.max_ttl = Some(Duration::from_secs(1));
+    let report = drop::run_cleanup(&strict).await;
+    assert_eq!(report.files_removed, 3, "Every file is past the new maximum");
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, short["id"].as_str().expect("No file ID")))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 410, "A file past the maximum age should be gone");
+}
+
+#[tokio::test]
+async fn test_extend_expiry() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.max_ttl = Some(Duration::from_secs(7 * 24 * 60 * 60));
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+    let upload = |query: &'static str| {
+        let (client, base_url) = (&client, &base_url);
+        async move {
+            let file = upload(client, base_url, query, "extend.txt", "extend me").await;
+            (
+                file["id"].as_str().expect("No file ID").to_string(),
+                file["delete_token"].as_str().expect("No delete token").to_string(),
+            )
+        }
+    };
+    let extend = |id: &str, token: &str, body: Value| {
+        client
+            .post(&format!("{}/drop/{}/extend", base_url, id))
+            .header("X-Delete-Token", token)
+            .json(&body)
+            .send()
+    };
+    let hours_left = |response: &Value| {
+        let expires_at: chrono::DateTime<chrono::Utc> =
+            response["expires_at"].as_str().expect("No expiry").parse().expect("Invalid expiry");
+        (expires_at - chrono::Utc::now()).num_minutes() as f64 / 60.0
+    };
+
+    let (id, token) = upload("?expires_in=3600").await;
+    let response = extend(&id, &token, serde_json::json!({ "extend_by": "48h" })).await.expect("Extend request failed");
+    assert_eq!(response.status(), 200);
+    let extended: Value = response.json().await.expect("Failed to parse extend response");
+    assert!((hours_left(&extended) - 49.0).abs() < 0.1, "48 hours should be added to the expiry");
+
+    // Capped at DROP_MAX_TTL after the upload
+    let response = extend(&id, &token, serde_json::json!({ "extend_by": "30d" })).await.expect("Extend request failed");
+    let extended: Value = response.json().await.expect("Failed to parse extend response");
+    assert!((hours_left(&extended) - 7.0 * 24.0).abs() < 0.1, "The maximum TTL should cap the extension");
+
+    let response = extend(&id, "wrong-token", serde_json::json!({ "extend_by": "1h" })).await.expect("Extend request failed");
+    assert_eq!(response.status(), 403);
+    for body in [serde_json::json!({}), serde_json::json!({ "extend_by": "soon" }), serde_json::json!({ "expires_at": "2000-01-01T00:00:00Z" })] {
+        let response = extend(&id, &token, body).await.expect("Extend request failed");
+        assert_eq!(response.status(), 400);
+    }
+
+    // Uploads without an expiry get DROP_MAX_TTL, which extending can't pass
+    let (id, token) = upload("").await;
+    let response = extend(&id, &token, serde_json::json!({ "extend_by": "1h" })).await.expect("Extend request failed");
+    assert_eq!(response.status(), 200);
+    let extended: Value = response.json().await.expect("Failed to parse extend response");
+    assert!((hours_left(&extended) - 7.0 * 24.0).abs() < 0.1, "The maximum TTL should still cap the expiry");
+
+    // An expired file comes back only when asked to
+    let (id, token) = upload("?expires_in=1").await;
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let response = extend(&id, &token, serde_json::json!({ "extend_by": "1h" })).await.expect("Extend request failed");
+    assert_eq!(response.status(), 410);
+    let response = extend(&id, &token, serde_json::json!({ "extend_by": "1h", "resurrect": true }))
+        .await
+        .expect("Extend request failed");
+    assert_eq!(response.status(), 200);
+    let response = client.get(&format!("{}/drop/{}", base_url, id)).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 200, "A resurrected file should download again");
+    assert_eq!(response.text().await.expect("Failed to read body"), "extend me");
+}
+
+#[tokio::test]
+async fn test_extend_file_without_expiry() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(sqlite_app_state(dir.path()).await).await;
+    let client = create_test_client();
+
+    // Without DROP_MAX_TTL a file uploaded without an expiry keeps none
+    let file = upload(&client, &base_url, "", "forever.txt", "never expires").await;
+    assert!(file["expires_at"].is_null(), "The upload should have no expiry");
+    let response = client
+        .post(&format!("{}/drop/{}/extend", base_url, file["id"].as_str().expect("No file ID")))
+        .header("X-Delete-Token", file["delete_token"].as_str().expect("No delete token"))
+        .json(&serde_json::json!({ "extend_by": "1h" }))
+        .send()
+        .await
+        .expect("Extend request failed");
+    assert_eq!(response.status(), 200, "Extending a file that never expires should succeed");
+    let extended: Value = response.json().await.expect("Failed to parse extend response");
+    assert!(extended["expires_at"].is_null(), "The file should still never expire");
+}
+
+#[tokio::test]
+async fn test_pinned_files() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+    let upload = |admin_token: Option<&'static str>| {
+        let form = file_form("pinned.txt", "keep me");
+        let mut request = post_form(&client, &base_url, "?expires_in=1&pinned=true", form);
+        if let Some(admin_token) = admin_token {
+            request = request.header("X-Admin-Token", admin_token);
+        }
+        request.send()
+    };
+    let download = |id: &str| client.get(&format!("{}/drop/{}", base_url, id)).send();
+
+    // Only admins may pin at upload
+    let response = upload(None).await.expect("Upload request failed");
+    assert_eq!(response.status(), 403);
+    let response = upload(Some("wrong-token")).await.expect("Upload request failed");
+    assert_eq!(response.status(), 403);
+
+    let response = upload(Some("test-admin-token")).await.expect("Upload request failed");
+    let id = uploaded_file(response).await["id"].as_str().expect("No file ID").to_string();
+
+    // Past its expiry, but pinned
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let report = drop::run_cleanup(&app_state).await;
+    assert_eq!(report.files_removed, 0, "Cleanup should skip pinned files");
+    let response = download(&id).await.expect("Download request failed");
+    assert_eq!(response.status(), 200, "A pinned file should outlive its expiry");
+
+    let response = client
+        .get(&format!("{}/admin/files?pinned=true", base_url))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("List request failed");
+    let listing: Value = response.json().await.expect("Failed to parse file list");
+    assert_eq!(listing["files"].as_array().expect("No file list").len(), 1);
+    assert_eq!(listing["files"][0]["id"], id.as_str());
+
+    let response = client
+        .delete(&format!("{}/admin/files/{}/pin", base_url, id))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("Unpin request failed");
+    assert_eq!(response.status(), 200);
+    let unpinned: Value = response.json().await.expect("Failed to parse unpin response");
+    assert_eq!(unpinned["pinned"], false);
+
+    let response = download(&id).await.expect("Download request failed");
+    assert_eq!(response.status(), 410, "An unpinned file should expire as usual");
+    let report = drop::run_cleanup(&app_state).await;
+    assert_eq!(report.files_removed, 1, "Cleanup should purge the unpinned file");
+}
+
+#[tokio::test]
+async fn test_gone_files_explain_why() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let app_state = test_app_state(dir.path(), None);
+    let file_storage = app_state.file_storage.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+    let upload = |query: &'static str| upload(&client, &base_url, query, "gone.txt", "short-lived");
+
+    let consumed = upload("?max_downloads=1").await;
+    let short_url = consumed["short_url"].as_str().unwrap();
+    assert_eq!(client.get(short_url).send().await.unwrap().status(), 200);
+    let response = client.get(short_url).send().await.unwrap();
+    assert_eq!(response.status(), 410);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["reason"], "consumed");
+    assert_eq!(body["max_downloads"], 1);
+    assert_eq!(body["download_count"], 1);
+    assert!(body["gone_at"].is_string());
+    assert!(body["request_id"].is_string());
+
+    let expired = upload("?expires_in=3600").await;
+    let id: uuid::Uuid = expired["id"].as_str().unwrap().parse().unwrap();
+    file_storage.get_mut(&id).unwrap().expires_at = Some(chrono::Utc::now() - chrono::Duration::seconds(1));
+    let response = client.get(&format!("{}/drop/{}/info", base_url, id)).send().await.unwrap();
+    assert_eq!(response.status(), 410);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["reason"], "expired");
+    assert!(body["expires_at"].is_string());
+
+    let deleted = upload("").await;
+    let id: uuid::Uuid = deleted["id"].as_str().unwrap().parse().unwrap();
+    file_storage.get_mut(&id).unwrap().purged_at = Some(chrono::Utc::now());
+    let response = client.get(deleted["short_url"].as_str().unwrap()).send().await.unwrap();
+    assert_eq!(response.status(), 410);
+    assert_eq!(response.json::<Value>().await.unwrap()["reason"], "deleted");
+
+    let response = client.get(&format!("{}/drop/{}", base_url, uuid::Uuid::new_v4())).send().await.unwrap();
+    assert_eq!(response.status(), 404, "Unknown IDs stay 404");
+
+    println!("✅ Gone file explanation test passed");
+}
+
+#[tokio::test]
+async fn test_tombstones_kept_for_retention() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+
+    let file = upload(&client, &base_url, "?max_downloads=1", "once.txt", "once").await;
+    let short_url = file["short_url"].as_str().unwrap().to_string();
+    assert_eq!(client.get(&short_url).send().await.unwrap().status(), 200);
+
+    // Within the retention the tombstone answers, through the short code too
+    drop::run_cleanup(&app_state).await;
+    let response = client.get(&short_url).send().await.unwrap();
+    assert_eq!(response.status(), 410);
+    assert_eq!(response.json::<Value>().await.unwrap()["reason"], "consumed");
+
+    app_state.config.tombstone_retention_seconds = 0;
+    drop::run_cleanup(&app_state).await;
+    assert_eq!(client.get(&short_url).send().await.unwrap().status(), 404);
+
+    println!("✅ Tombstone retention test passed");
+}
@@ -0,0 +1,133 @@
+//! Concurrent reads of the in-memory fallback: latency of 100 simultaneous
+//! downloads of one small memory-resident file, and the same lookups against
+//! the single-mutex map the fallback used to be. Latencies are printed; for
+//! meaningful numbers run
+//! `cargo test --release --test fallback_load_test -- --nocapture`.
+
+use dashmap::DashMap;
+use reqwest::{Client, multipart};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+mod common;
+use common::{spawn_server, test_app_state};
+
+const CONCURRENT_DOWNLOADS: usize = 100;
+const ROUNDS: usize = 20;
+
+// The p50 and p99 of `latencies`
+fn percentiles(latencies: &mut [Duration]) -> (Duration, Duration) {
+    latencies.sort_unstable();
+    let at = |share: f64| latencies[((latencies.len() - 1) as f64 * share).round() as usize];
+    (at(0.5), at(0.99))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_concurrent_in_memory_downloads() {
+    drop::initialize_memory_pool(&drop::Config::default());
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let app_state = test_app_state(dir.path(), None);
+    let file_storage = app_state.file_storage.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = Client::builder()
+        .pool_max_idle_per_host(CONCURRENT_DOWNLOADS)
+        .build()
+        .expect("Failed to create HTTP client");
+
+    let contents = "small and hot".repeat(64);
+    let part = multipart::Part::text(contents.clone()).file_name("hot.txt");
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .multipart(multipart::Form::new().part("file", part))
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let url = format!("{}/drop/{}", base_url, upload["files"][0]["id"].as_str().unwrap());
+    assert_eq!(file_storage.len(), 1, "Without a database the file lives in the fallback");
+
+    let mut latencies = Vec::with_capacity(CONCURRENT_DOWNLOADS * ROUNDS);
+    for _ in 0..ROUNDS {
+        let downloads = (0..CONCURRENT_DOWNLOADS).map(|_| {
+            let client = client.clone();
+            let url = url.clone();
+            tokio::spawn(async move {
+                let started = Instant::now();
+                let response = client.get(&url).send().await.expect("Download request failed");
+                assert!(response.status().is_success());
+                let body = response.bytes().await.expect("Failed to read body");
+                (started.elapsed(), body.len())
+            })
+        });
+        for download in futures_util::future::join_all(downloads).await {
+            let (latency, len) = download.expect("Download task failed");
+            assert_eq!(len, contents.len());
+            latencies.push(latency);
+        }
+    }
+
+    let (p50, p99) = percentiles(&mut latencies);
+    println!(
+        "{} concurrent downloads x {} rounds: p50 {:?}, p99 {:?}",
+        CONCURRENT_DOWNLOADS, ROUNDS, p50, p99
+    );
+}
+
+// What a download does to the fallback map: look the file up, count the
+// download and copy the entry out
+fn lookup_latencies<F>(lookup: F) -> Vec<Duration>
+where
+    F: Fn(&str) + Send + Sync + 'static,
+{
+    let lookup = Arc::new(lookup);
+    let keys: Vec<String> = (0..CONCURRENT_DOWNLOADS).map(|i| format!("file-{}", i % 4)).collect();
+    let threads: Vec<_> = keys
+        .into_iter()
+        .map(|key| {
+            let lookup = lookup.clone();
+            std::thread::spawn(move || {
+                (0..ROUNDS * 50)
+                    .map(|_| {
+                        let started = Instant::now();
+                        lookup(&key);
+                        started.elapsed()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    threads.into_iter().flat_map(|thread| thread.join().expect("Lookup thread failed")).collect()
+}
+
+#[test]
+fn test_fallback_map_lookup_latency() {
+    let entry = || (0u64, vec![0u8; 1024]);
+
+    let mutex: Arc<Mutex<HashMap<String, (u64, Vec<u8>)>>> = Arc::new(Mutex::new(HashMap::new()));
+    let dashmap: Arc<DashMap<String, (u64, Vec<u8>)>> = Arc::new(DashMap::new());
+    for i in 0..4 {
+        mutex.lock().unwrap().insert(format!("file-{}", i), entry());
+        dashmap.insert(format!("file-{}", i), entry());
+    }
+
+    let mut before = lookup_latencies(move |key| {
+        let mut storage = mutex.lock().unwrap();
+        if let Some(file_data) = storage.get_mut(key) {
+            file_data.0 += 1;
+            std::hint::black_box(file_data.clone());
+        }
+    });
+    let mut after = lookup_latencies(move |key| {
+        if let Some(mut file_data) = dashmap.get_mut(key) {
+            file_data.0 += 1;
+            std::hint::black_box(file_data.clone());
+        }
+    });
+
+    let (before_p50, before_p99) = percentiles(&mut before);
+    let (after_p50, after_p99) = percentiles(&mut after);
+    println!("Mutex<HashMap>: p50 {:?}, p99 {:?}", before_p50, before_p99);
+    println!("DashMap:        p50 {:?}, p99 {:?}", after_p50, after_p99);
+}
@@ -0,0 +1,397 @@
+//! Cross-cutting HTTP behaviour: health probes, the OpenAPI spec, CORS,
+//! request IDs, security headers and the access log.
+
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{create_test_client, put_file, spawn_server, test_app_state, upload_raw};
+
+#[tokio::test]
+async fn test_liveness_and_readiness() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let app_state = test_app_state(dir.path(), None);
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+    let get = |path: &'static str| client.get(&format!("{}{}", base_url, path)).send();
+
+    let response = get("/healthz").await.expect("Liveness request failed");
+    assert_eq!(response.status(), 200);
+
+    let response = get("/readyz").await.expect("Readiness request failed");
+    assert_eq!(response.status(), 200);
+    let ready: Value = response.json().await.expect("Failed to parse readiness response");
+    assert_eq!(ready["status"], "ready");
+    assert_eq!(ready["database"], "not_configured");
+
+    // Draining stops traffic but the process is still alive
+    app_state.readiness.start_draining();
+    let response = get("/readyz").await.expect("Readiness request failed");
+    assert_eq!(response.status(), 503, "A draining instance should not be ready");
+    let not_ready: Value = response.json().await.expect("Failed to parse readiness response");
+    assert_eq!(not_ready["reasons"][0], "draining");
+    let response = get("/healthz").await.expect("Liveness request failed");
+    assert_eq!(response.status(), 200, "Draining should not fail liveness");
+
+    // A temp directory that can't be created, because a file is in the way
+    let blocker = dir.path().join("blocker");
+    std::fs::write(&blocker, "not a directory").expect("Failed to create blocking file");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.temp_directory = blocker.join("files");
+    let base_url = spawn_server(app_state).await;
+    let response = client
+        .get(&format!("{}/readyz", base_url))
+        .send()
+        .await
+        .expect("Readiness request failed");
+    assert_eq!(response.status(), 503, "An unwritable temp directory should fail readiness");
+    let not_ready: Value = response.json().await.expect("Failed to parse readiness response");
+    assert_eq!(not_ready["reasons"][0], "temp_directory_not_writable");
+
+    println!("✅ Liveness and readiness test passed");
+}
+
+#[tokio::test]
+async fn test_openapi_spec_and_docs() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let response = client
+        .get(&format!("{}/openapi.json", base_url))
+        .send()
+        .await
+        .expect("Spec request failed");
+    assert_eq!(response.status(), 200);
+    let spec = response.text().await.expect("No spec body");
+    oas3::from_json(&spec).expect("The spec should be a valid OpenAPI document");
+
+    let spec: Value = serde_json::from_str(&spec).expect("Failed to parse spec");
+    let upload = &spec["paths"]["/drop"]["post"];
+    assert!(upload["requestBody"]["content"]["multipart/form-data"].is_object(), "Uploads are multipart");
+    for status in ["400", "413", "415", "429", "503", "507"] {
+        assert!(upload["responses"][status].is_object(), "Upload should document {}", status);
+    }
+    let download = &spec["paths"]["/drop/{id}"]["get"];
+    assert_eq!(download["parameters"][0]["name"], "id");
+    for status in ["200", "404", "410", "429"] {
+        assert!(download["responses"][status].is_object(), "Download should document {}", status);
+    }
+    let upload_response = &spec["components"]["schemas"]["UploadResponse"]["properties"];
+    assert!(upload_response["short_url"].is_object() && upload_response["full_url"].is_object());
+    assert!(spec["components"]["schemas"]["HealthResponse"].is_object());
+    assert!(spec["components"]["schemas"]["ErrorResponse"].is_object());
+
+    let response = client
+        .get(&format!("{}/docs/", base_url))
+        .send()
+        .await
+        .expect("Docs request failed");
+    assert_eq!(response.status(), 200, "Swagger UI should be served");
+
+    // The spec stays available when the UI is turned off
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.disable_docs = true;
+    let base_url = spawn_server(app_state).await;
+    let response = client.get(&format!("{}/docs/", base_url)).send().await.expect("Docs request failed");
+    assert_eq!(response.status(), 404, "Swagger UI should be disabled");
+    let response = client.get(&format!("{}/openapi.json", base_url)).send().await.expect("Spec request failed");
+    assert_eq!(response.status(), 200);
+
+    println!("✅ OpenAPI spec test passed");
+}
+
+#[tokio::test]
+async fn test_cors_preflight_and_exposed_headers() {
+    const ORIGIN: &str = "https://app.example.com";
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let client = create_test_client();
+
+    // Without configured origins nothing changes
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let response = client
+        .get(&format!("{}/health", base_url))
+        .header("origin", ORIGIN)
+        .send()
+        .await
+        .expect("Health request failed");
+    assert!(!response.headers().contains_key("access-control-allow-origin"), "CORS should be off by default");
+
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.cors_allowed_origins = drop::cors::parse_origins(ORIGIN).expect("Valid origin");
+    app_state.config.rate_limit_upload_rpm = 1;
+    app_state.config.rate_limit_allowlist = Vec::new(); // Test clients connect from localhost
+    let base_url = spawn_server(app_state).await;
+    let preflight = |origin: &'static str| {
+        client
+            .request(reqwest::Method::OPTIONS, &format!("{}/drop/spa.txt", base_url))
+            .header("origin", origin)
+            .header("access-control-request-method", "PUT")
+            .header("access-control-request-headers", "x-delete-token, content-type")
+            .send()
+    };
+
+    // Preflights never use up the single upload allowed
+    for _ in 0..3 {
+        let response = preflight(ORIGIN).await.expect("Preflight failed");
+        assert!(response.status().is_success(), "Preflight should be answered");
+        assert_eq!(response.headers()["access-control-allow-origin"], ORIGIN);
+        let methods = response.headers()["access-control-allow-methods"].to_str().unwrap().to_string();
+        assert!(methods.contains("PUT"), "PUT should be allowed: {}", methods);
+        let headers = response.headers()["access-control-allow-headers"].to_str().unwrap().to_ascii_lowercase();
+        assert!(headers.contains("x-delete-token"), "X-Delete-Token should be allowed: {}", headers);
+    }
+    let response = preflight("https://evil.example.com").await.expect("Preflight failed");
+    assert!(
+        !response.headers().contains_key("access-control-allow-origin"),
+        "Other origins should not be allowed"
+    );
+
+    let upload = || put_file(&client, &base_url, "spa.txt", "from the browser").header("origin", ORIGIN).send();
+    let response = upload().await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload after preflights should succeed");
+    assert_eq!(response.headers()["access-control-allow-origin"], ORIGIN);
+    let exposed = response.headers()["access-control-expose-headers"].to_str().unwrap().to_ascii_lowercase();
+    for header in ["x-ratelimit-remaining", "retry-after", "x-request-id"] {
+        assert!(exposed.contains(header), "{} should be exposed: {}", header, exposed);
+    }
+
+    // Errors carry the headers too, so scripts can read why
+    let response = upload().await.expect("Upload request failed");
+    assert_eq!(response.status(), 429);
+    assert_eq!(response.headers()["access-control-allow-origin"], ORIGIN);
+
+    println!("✅ CORS test passed");
+}
+
+#[tokio::test]
+async fn test_request_id_propagation() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.blocked_extensions = vec!["exe".to_string()];
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    // A client's own id is echoed back
+    let response = client
+        .put(&format!("{}/drop/request-id.txt", base_url))
+        .header("X-Request-Id", "support-ticket-42")
+        .body("hello")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    assert_eq!(response.headers()["x-request-id"], "support-ticket-42");
+
+    // Otherwise one is generated, and errors repeat it in the body
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, uuid::Uuid::new_v4()))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 404);
+    let request_id = response.headers()["x-request-id"]
+        .to_str()
+        .expect("Invalid request id")
+        .to_string();
+    assert!(request_id.parse::<uuid::Uuid>().is_ok(), "Generated request id should be a UUID");
+    let body: Value = response.json().await.expect("Error body should be JSON");
+    assert_eq!(body["request_id"], request_id.as_str());
+    assert_eq!(body["error"], "Not Found");
+
+    // Errors that already have a JSON body gain the id
+    let response = client
+        .put(&format!("{}/drop/setup.exe", base_url))
+        .header("X-Request-Id", "blocked-upload")
+        .body("hello")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 415);
+    assert_eq!(response.headers()["x-request-id"], "blocked-upload");
+    let body: Value = response.json().await.expect("Error body should be JSON");
+    assert_eq!(body["request_id"], "blocked-upload");
+    assert_eq!(body["rule"], "DROP_BLOCKED_EXTENSIONS");
+
+    println!("✅ Request ID propagation test passed");
+}
+
+#[tokio::test]
+async fn test_security_headers() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+    let get = |path: String| client.get(format!("{}{}", base_url, path)).send();
+
+    let response = get("/".to_string()).await.expect("Upload page request failed");
+    let headers = response.headers();
+    assert_eq!(headers["x-content-type-options"], "nosniff");
+    assert_eq!(headers["x-frame-options"], "DENY");
+    assert_eq!(headers["referrer-policy"], "no-referrer");
+    assert!(headers["content-security-policy"].to_str().unwrap().contains("frame-ancestors 'none'"));
+    assert!(!headers.contains_key("strict-transport-security"), "HSTS only applies over HTTPS");
+
+    let response = get("/health".to_string()).await.expect("Health request failed");
+    assert_eq!(response.headers()["x-frame-options"], "DENY");
+    assert!(!response.headers().contains_key("content-security-policy"), "Only HTML gets a page CSP");
+
+    // Uploaded HTML must not be able to run script in our origin
+    let response = client
+        .put(format!("{}/drop/page.html", base_url))
+        .header("Content-Type", "text/html")
+        .body("<script>alert(document.cookie)</script>")
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let file_id = upload["files"][0]["id"].as_str().unwrap().to_string();
+    let response = get(format!("/drop/{}", file_id)).await.expect("Download request failed");
+    assert_eq!(response.headers()["content-security-policy"], "sandbox");
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+
+    // Behind a TLS-terminating proxy HSTS is sent; with the layer off, only
+    // the download's own protection remains
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.behind_tls_proxy = true;
+    let base_url = spawn_server(app_state).await;
+    let response = client.get(format!("{}/health", base_url)).send().await.expect("Health request failed");
+    assert!(response.headers().contains_key("strict-transport-security"));
+
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.security_headers = false;
+    let base_url = spawn_server(app_state).await;
+    let response = client.get(format!("{}/", base_url)).send().await.expect("Upload page request failed");
+    assert!(!response.headers().contains_key("x-frame-options"), "The layer should be off");
+
+    println!("✅ Security headers test passed");
+}
+
+#[tokio::test]
+async fn test_access_log() {
+    use drop::access_log::{AccessLog, AccessLogFormat};
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let log_path = dir.path().join("access.log");
+    let read_log = || async {
+        // Lines are written as response bodies finish
+        for _ in 0..50 {
+            let contents = std::fs::read_to_string(&log_path).unwrap_or_default();
+            if contents.lines().count() >= 2 {
+                return contents;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Access log lines were not written");
+    };
+
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.access_log_format = Some(AccessLogFormat::Json);
+    app_state.config.access_log_path = Some(log_path.clone());
+    app_state.access_log = AccessLog::open(&app_state.config).expect("Failed to open access log");
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let response = client
+        .put(&format!("{}/drop/logged.txt", base_url))
+        .body("secret contents")
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let id = upload["files"][0]["id"].as_str().unwrap().to_string();
+    let response = client
+        .get(&format!("{}/drop/{}?token=abc", base_url, id))
+        .header("User-Agent", "access-log-test")
+        .header("X-Request-Id", "logged-request")
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.text().await.unwrap(), "secret contents");
+
+    let contents = read_log().await;
+    assert!(!contents.contains("secret contents"), "Bodies must not be logged");
+    let lines: Vec<Value> = contents
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("Each line should be JSON"))
+        .collect();
+    assert_eq!(lines[0]["method"], "PUT");
+    assert_eq!(lines[0]["status"], 201);
+    let download = &lines[1];
+    assert_eq!(download["method"], "GET");
+    assert_eq!(download["path"], format!("/drop/{}", id), "The query string is left out");
+    assert_eq!(download["status"], 200);
+    assert_eq!(download["bytes"], "secret contents".len());
+    assert_eq!(download["client_ip"], "127.0.0.1");
+    assert_eq!(download["user_agent"], "access-log-test");
+    assert_eq!(download["request_id"], "logged-request");
+    assert!(download["duration_ms"].is_u64());
+
+    // The combined format, as log pipelines built for Apache expect
+    std::fs::remove_file(&log_path).expect("Failed to remove access log");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.access_log_format = Some(AccessLogFormat::Combined);
+    app_state.config.access_log_path = Some(log_path.clone());
+    app_state.access_log = AccessLog::open(&app_state.config).expect("Failed to open access log");
+    let base_url = spawn_server(app_state).await;
+    for path in ["/drop/missing", "/healthz"] {
+        client
+            .get(&format!("{}{}", base_url, path))
+            .header("User-Agent", "access-log-test")
+            .send()
+            .await
+            .expect("Request failed")
+            .bytes()
+            .await
+            .expect("Failed to read body");
+    }
+    let contents = read_log().await;
+    let line = contents.lines().next().unwrap();
+    assert!(line.starts_with("127.0.0.1 - - ["), "{}", line);
+    assert!(line.contains("\"GET /drop/missing HTTP/1.1\" 404 "), "{}", line);
+    assert!(line.contains("\"-\" \"access-log-test\""), "{}", line);
+    let bytes: u64 = line
+        .split("\" 404 ")
+        .nth(1)
+        .and_then(|rest| rest.split(' ').next())
+        .and_then(|bytes| bytes.parse().ok())
+        .expect("No byte count in the combined line");
+    assert!(bytes > 0, "The error body's size should be logged: {}", line);
+
+    println!("✅ Access log test passed");
+}
+
+#[tokio::test]
+async fn test_access_log_counts_streamed_bytes() {
+    use drop::access_log::{AccessLog, AccessLogFormat};
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let log_path = dir.path().join("access.log");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.stream_threshold = 0; // Served from disk in many chunks
+    app_state.config.access_log_format = Some(AccessLogFormat::Json);
+    app_state.config.access_log_path = Some(log_path.clone());
+    app_state.access_log = AccessLog::open(&app_state.config).expect("Failed to open access log");
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let contents = vec![b'z'; 1024 * 1024];
+    let file = upload_raw(&client, &base_url, "streamed.bin", contents.clone()).await;
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file["id"].as_str().expect("No file ID")))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.bytes().await.expect("Failed to read body").len(), contents.len());
+
+    let mut download = Value::Null;
+    for _ in 0..50 {
+        let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+        if let Some(line) = log.lines().nth(1) {
+            download = serde_json::from_str(line).expect("Each line should be JSON");
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(download["method"], "GET", "The download should be logged");
+    assert_eq!(download["bytes"], contents.len(), "Every streamed chunk should be counted");
+}
@@ -1,14 +1,12 @@
-use reqwest::{Client, multipart};
+//! End-to-end tests against the server started by `docker-compose up -d`. The
+//! tests that run the app in process live in the other `*_test.rs` files.
+
+use reqwest::multipart;
 use serde_json::Value;
 use std::time::Duration;
 
-/// Helper function to create a test client with appropriate timeouts
-fn create_test_client() -> Client {
-    Client::builder()
-        .timeout(Duration::from_secs(30))
-        .build()
-        .expect("Failed to create HTTP client")
-}
+mod common;
+use common::{create_test_client, post_form, uploaded_file};
 
 /// Constants for the Docker test environment
 const DOCKER_BASE_URL: &str = "http://localhost:3000";
@@ -39,7 +37,7 @@ async fn wait_for_services() -> Result<(), Box<dyn std::error::Error>> {
     Err("Docker services did not become ready in time".into())
 }
 
-/// Upload a test file and return the response JSON
+/// Upload a test file and return the response JSON for that file
 async fn upload_test_file(
     filename: &str,
     content: &str,
@@ -60,7 +58,7 @@ async fn upload_test_file(
     }
 
     let json: Value = response.json().await?;
-    Ok(json)
+    Ok(json["files"][0].clone())
 }
 
 /// Download a file by ID or short code
@@ -115,8 +113,10 @@ async fn test_docker_services_health() {
     // Verify required fields exist
     assert!(health["status"].is_string(), "Health status should be present");
     assert!(health["database"].is_string(), "Database status should be present");
+    assert!(health["redis"].is_string(), "Redis status should be present");
     assert!(health["memory_pool"].is_string(), "Memory pool info should be present");
-    assert!(health["active_connections"].is_number(), "Active connections should be present");
+    assert!(health["active_uploads"].is_number(), "Active uploads should be present");
+    assert!(health["active_downloads"].is_number(), "Active downloads should be present");
 }
 
 #[tokio::test]
@@ -394,3 +394,683 @@ async fn test_filename_sanitization() {
         println!("✅ Sanitized filename test passed for: {}", problematic_filename);
     }
 }
+
+#[tokio::test]
+async fn test_delete_with_token() {
+    setup_test().await.expect("Failed to setup test");
+
+    let upload_response = upload_test_file("delete_me.txt", "This file will be deleted.")
+        .await
+        .expect("Failed to upload test file");
+
+    let file_id = upload_response["id"]
+        .as_str()
+        .expect("No file ID in response");
+    let delete_token = upload_response["delete_token"]
+        .as_str()
+        .expect("No delete token in response");
+
+    let client = create_test_client();
+    let delete_url = format!("{}/drop/{}", DOCKER_BASE_URL, file_id);
+
+    // Missing and wrong tokens are rejected
+    let response = client.delete(&delete_url).send().await.expect("Request failed");
+    assert_eq!(response.status(), 403, "Expected 403 without a delete token");
+
+    let response = client
+        .delete(&delete_url)
+        .header("X-Delete-Token", "not-the-token")
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 403, "Expected 403 with a wrong delete token");
+
+    // Correct token deletes the file
+    let response = client
+        .delete(&delete_url)
+        .header("X-Delete-Token", delete_token)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 204, "Expected 204 for a successful delete");
+
+    // File is gone and a repeat delete is a 404
+    assert!(download_test_file(file_id).await.is_err(), "Deleted file should not be downloadable");
+
+    let response = client
+        .delete(&delete_url)
+        .header("X-Delete-Token", delete_token)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 404, "Expected 404 for a repeat delete");
+
+    println!("✅ Delete with token test passed");
+}
+
+#[tokio::test]
+async fn test_expiring_upload_returns_gone() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let part = multipart::Part::text("This file expires quickly.").file_name("expiring.txt");
+    let form = multipart::Form::new().part("file", part);
+
+    let response = client
+        .post(&format!("{}/drop?expires_in=1", DOCKER_BASE_URL))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload with expires_in should succeed");
+
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let upload_response = &upload_response["files"][0];
+    let file_id = upload_response["id"]
+        .as_str()
+        .expect("No file ID in response");
+    assert!(upload_response["expires_at"].is_string(), "Expiry should be reported");
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let response = client
+        .get(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 410, "Expected 410 for an expired file");
+
+    println!("✅ Expiring upload test passed");
+}
+
+#[tokio::test]
+async fn test_max_downloads_consumes_file() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let content = "Burn after reading.";
+    let part = multipart::Part::text(content).file_name("one_time.txt");
+    let form = multipart::Form::new().text("max_downloads", "1").part("file", part);
+
+    let response = client
+        .post(&format!("{}/drop", DOCKER_BASE_URL))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload with max_downloads should succeed");
+
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let upload_response = &upload_response["files"][0];
+    let file_id = upload_response["id"]
+        .as_str()
+        .expect("No file ID in response");
+
+    let downloaded_content = download_test_file(file_id)
+        .await
+        .expect("First download should succeed");
+    assert_eq!(downloaded_content, content, "Downloaded content doesn't match");
+
+    let response = client
+        .get(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 410, "Expected 410 once downloads are used up");
+
+    println!("✅ Max downloads test passed");
+}
+
+#[tokio::test]
+async fn test_multi_file_upload() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let files = [
+        ("multi_file1.txt", "Content of multi file 1"),
+        ("multi_file2.txt", "Content of multi file 2"),
+        ("multi_file3.txt", "Content of multi file 3"),
+    ];
+
+    let mut form = multipart::Form::new();
+    for (filename, content) in &files {
+        form = form.part("file", multipart::Part::text(*content).file_name(*filename));
+    }
+
+    let response = client
+        .post(&format!("{}/drop", DOCKER_BASE_URL))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Multi-file upload should succeed");
+
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let uploaded = upload_response["files"]
+        .as_array()
+        .expect("No files array in response");
+    assert_eq!(uploaded.len(), files.len(), "Every file should be stored");
+
+    for ((_, content), entry) in files.iter().zip(uploaded) {
+        assert_eq!(entry["size"].as_u64(), Some(content.len() as u64), "Size mismatch");
+        let file_id = entry["id"].as_str().expect("No file ID in response");
+        let downloaded_content = download_test_file(file_id)
+            .await
+            .expect("Failed to download file");
+        assert_eq!(downloaded_content, *content, "File content mismatch for ID: {}", file_id);
+    }
+
+    println!("✅ Multi-file upload test passed");
+}
+
+/// Requires the server to run with a small per-file limit, e.g.
+/// `DROP_MAX_FILE_SIZE_MB=1 docker-compose up -d`, and the same variable set for the test.
+#[tokio::test]
+async fn test_multi_file_upload_rejects_oversized_part() {
+    let Some(limit_mb) = std::env::var("DROP_MAX_FILE_SIZE_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|mb| *mb <= 16)
+    else {
+        println!("⏭️ Skipping: set DROP_MAX_FILE_SIZE_MB (<= 16) to match the server limit");
+        return;
+    };
+
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let oversized = "B".repeat(limit_mb * 1024 * 1024 + 1);
+    let form = multipart::Form::new()
+        .part("file", multipart::Part::text("first").file_name("first.txt"))
+        .part("file", multipart::Part::text(oversized).file_name("too_big.txt"))
+        .part("file", multipart::Part::text("third").file_name("third.txt"));
+
+    let response = client
+        .post(&format!("{}/drop", DOCKER_BASE_URL))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 413, "Expected 413 when one part exceeds the per-file limit");
+
+    println!("✅ Oversized part rejection test passed");
+}
+
+#[tokio::test]
+async fn test_raw_put_upload() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let content = "Uploaded with a raw PUT body.";
+
+    let response = client
+        .put(&format!("{}/drop/raw_put.txt", DOCKER_BASE_URL))
+        .header("Content-Type", "text/plain")
+        .body(content)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Raw PUT upload should succeed");
+
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let upload_response = &upload_response["files"][0];
+    assert_eq!(upload_response["filename"], "raw_put.txt", "Filename should come from the path");
+
+    let file_id = upload_response["id"]
+        .as_str()
+        .expect("No file ID in response");
+    let downloaded_content = download_test_file(file_id)
+        .await
+        .expect("Failed to download file");
+    assert_eq!(downloaded_content, content, "Downloaded content doesn't match");
+
+    println!("✅ Raw PUT upload test passed");
+}
+
+#[tokio::test]
+async fn test_resumable_upload_session() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let chunks = ["first chunk, ", "second chunk"];
+    let expected_size: usize = chunks.iter().map(|c| c.len()).sum();
+
+    let response = client
+        .post(&format!("{}/drop/sessions", DOCKER_BASE_URL))
+        .json(&serde_json::json!({ "filename": "resumable.txt", "expected_size": expected_size }))
+        .send()
+        .await
+        .expect("Failed to create session");
+    assert_eq!(response.status(), 201, "Session creation should return 201");
+    let session: Value = response.json().await.expect("Failed to parse session response");
+    let session_id = session["session_id"].as_str().expect("No session ID in response");
+    let session_url = format!("{}/drop/sessions/{}", DOCKER_BASE_URL, session_id);
+
+    // Out-of-order chunk is rejected
+    let response = client
+        .patch(&session_url)
+        .header("Upload-Offset", "5")
+        .body(chunks[1])
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 409, "Expected 409 for an out-of-order offset");
+
+    let mut offset = 0;
+    for chunk in chunks {
+        let response = client
+            .patch(&session_url)
+            .header("Upload-Offset", offset.to_string())
+            .body(chunk)
+            .send()
+            .await
+            .expect("Failed to append chunk");
+        assert_eq!(response.status(), 204, "Chunk append should return 204");
+        offset += chunk.len();
+    }
+
+    let response = client
+        .post(&format!("{}/complete", session_url))
+        .send()
+        .await
+        .expect("Failed to complete session");
+    assert!(response.status().is_success(), "Completing the session should succeed");
+
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let file_id = upload_response["files"][0]["id"]
+        .as_str()
+        .expect("No file ID in response");
+    let downloaded_content = download_test_file(file_id)
+        .await
+        .expect("Failed to download file");
+    assert_eq!(downloaded_content, chunks.concat(), "Downloaded content doesn't match");
+
+    println!("✅ Resumable upload session test passed");
+}
+
+#[tokio::test]
+async fn test_head_does_not_count_as_download() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let content = "HEAD requests must not consume downloads.";
+    let part = multipart::Part::text(content).file_name("head_test.txt");
+    let form = multipart::Form::new().text("max_downloads", "1").part("file", part);
+
+    let response = client
+        .post(&format!("{}/drop", DOCKER_BASE_URL))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let file_id = upload_response["files"][0]["id"]
+        .as_str()
+        .expect("No file ID in response");
+
+    for _ in 0..2 {
+        let response = client
+            .head(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+            .send()
+            .await
+            .expect("HEAD request failed");
+        assert_eq!(response.status(), 200, "HEAD should succeed");
+        assert_eq!(
+            response.headers()["content-length"].to_str().unwrap(),
+            content.len().to_string(),
+            "HEAD should report the file size"
+        );
+    }
+
+    let downloaded_content = download_test_file(file_id)
+        .await
+        .expect("Download after HEAD should still succeed");
+    assert_eq!(downloaded_content, content, "Downloaded content doesn't match");
+
+    println!("✅ HEAD request test passed");
+}
+
+#[tokio::test]
+async fn test_etag_conditional_and_range_requests() {
+    setup_test().await.expect("Failed to setup test");
+
+    let content = "0123456789abcdefghij";
+    let upload_response = upload_test_file("etag_test.txt", content)
+        .await
+        .expect("Failed to upload test file");
+    let file_id = upload_response["id"]
+        .as_str()
+        .expect("No file ID in response");
+    let hash = upload_response["hash"]
+        .as_str()
+        .expect("No hash in response");
+
+    let client = create_test_client();
+    let url = format!("{}/drop/{}", DOCKER_BASE_URL, file_id);
+
+    let response = client.get(&url).send().await.expect("Request failed");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    assert_eq!(etag, format!("\"{}\"", hash), "ETag should be the quoted upload hash");
+    assert!(response.headers().contains_key("last-modified"), "Last-Modified should be set");
+
+    let response = client
+        .get(&url)
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 304, "Matching If-None-Match should return 304");
+
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=5-9")
+        .header("If-Range", &etag)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 206, "Range request should return 206");
+    assert_eq!(response.text().await.unwrap(), "56789", "Partial content mismatch");
+
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=5-9")
+        .header("If-Range", "\"stale-etag\"")
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 200, "Stale If-Range should return the full file");
+    assert_eq!(response.text().await.unwrap(), content, "Full content mismatch");
+
+    println!("✅ ETag and range request test passed");
+}
+
+#[tokio::test]
+async fn test_download_sets_content_length() {
+    setup_test().await.expect("Failed to setup test");
+
+    // Large enough to be kept on disk when the memory pool is busy, small enough to be quick
+    let content = "C".repeat(256 * 1024);
+    let upload_response = upload_test_file("content_length_test.txt", &content)
+        .await
+        .expect("Failed to upload test file");
+    let file_id = upload_response["id"]
+        .as_str()
+        .expect("No file ID in response");
+
+    let client = create_test_client();
+    let response = client
+        .get(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+        .send()
+        .await
+        .expect("Request failed");
+
+    assert_eq!(
+        response.content_length(),
+        Some(content.len() as u64),
+        "Download should carry an explicit Content-Length"
+    );
+    assert!(
+        !response.headers().contains_key("transfer-encoding"),
+        "Download should not use chunked transfer encoding"
+    );
+
+    println!("✅ Content-Length test passed");
+}
+
+#[tokio::test]
+async fn test_inline_disposition_and_filename_override() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let upload = |filename: &'static str, content_type: &'static str| {
+        let part = multipart::Part::text("disposition test").file_name(filename).mime_str(content_type).unwrap();
+        let request = post_form(&client, DOCKER_BASE_URL, "", multipart::Form::new().part("file", part));
+        async move {
+            let file = uploaded_file(request.send().await.expect("Upload request failed")).await;
+            file["id"].as_str().unwrap().to_string()
+        }
+    };
+    let disposition = |response: &reqwest::Response| {
+        response.headers()["content-disposition"].to_str().unwrap().to_string()
+    };
+
+    let text_id = upload("notes.txt", "text/plain").await;
+    let html_id = upload("page.html", "text/html").await;
+
+    let response = client
+        .get(&format!("{}/drop/{}", DOCKER_BASE_URL, text_id))
+        .send()
+        .await
+        .expect("Request failed");
+    assert!(disposition(&response).starts_with("attachment"), "Default should stay attachment");
+
+    let response = client
+        .get(&format!("{}/drop/{}?disposition=inline&filename=../renamed.txt", DOCKER_BASE_URL, text_id))
+        .send()
+        .await
+        .expect("Request failed");
+    let value = disposition(&response);
+    assert!(value.starts_with("inline"), "Plain text may be served inline: {}", value);
+    assert!(value.contains("renamed.txt") && !value.contains('/'), "Override should be sanitized: {}", value);
+
+    let response = client
+        .get(&format!("{}/drop/{}?disposition=inline", DOCKER_BASE_URL, html_id))
+        .send()
+        .await
+        .expect("Request failed");
+    assert!(disposition(&response).starts_with("attachment"), "HTML must never be inline");
+
+    println!("✅ Disposition test passed");
+}
+
+/// Percent-decode the RFC 5987 `filename*` parameter of a Content-Disposition header
+fn decode_filename_star(disposition: &str) -> Option<String> {
+    let encoded = disposition.split("filename*=UTF-8''").nth(1)?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[tokio::test]
+async fn test_unicode_filenames() {
+    setup_test().await.expect("Failed to setup test");
+
+    let long_name = format!("{}.txt", "日本語ファイル名".repeat(38)); // 300+ multibyte characters
+    let filenames = vec![
+        "résumé 2024.pdf".to_string(),
+        "party 🎉🎈.txt".to_string(),
+        "报告 最终版.docx".to_string(),
+        long_name.clone(),
+    ];
+
+    let client = create_test_client();
+    for filename in &filenames {
+        let upload_response = upload_test_file(filename, "unicode filename test")
+            .await
+            .expect("Upload with a unicode filename should not fail");
+        let file_id = upload_response["id"]
+            .as_str()
+            .expect("No file ID in response");
+
+        let response = client
+            .get(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+            .send()
+            .await
+            .expect("Request failed");
+        assert!(response.status().is_success(), "Download should succeed for {}", filename);
+
+        let disposition = response.headers()["content-disposition"]
+            .to_str()
+            .expect("Content-Disposition must be ASCII")
+            .to_string();
+        let decoded = decode_filename_star(&disposition).expect("filename* should be present");
+
+        if filename == &long_name {
+            assert!(decoded.chars().count() <= 203, "Long filename should be truncated");
+            assert!(decoded.starts_with("日本語"), "Truncation should keep whole characters");
+        } else {
+            assert_eq!(&decoded, filename, "filename* should round-trip the original name");
+        }
+    }
+
+    println!("✅ Unicode filename test passed");
+}
+
+#[tokio::test]
+async fn test_content_type_sniffing_and_info() {
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x01\x00\x00\x00\x01".to_vec();
+
+    // (declared type, expected served type) for the same PNG contents
+    let cases = [
+        ("application/octet-stream", "image/png"),
+        ("text/html", "image/png"),
+        ("image/png", "image/png"),
+    ];
+
+    for (declared, expected) in cases {
+        let response = client
+            .put(&format!("{}/drop/pixel.bin", DOCKER_BASE_URL))
+            .header("Content-Type", declared)
+            .body(png.clone())
+            .send()
+            .await
+            .expect("Raw upload failed");
+        assert!(response.status().is_success(), "Raw upload should succeed");
+        let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+        let file_id = upload_response["files"][0]["id"]
+            .as_str()
+            .expect("No file ID in response")
+            .to_string();
+
+        let response = client
+            .get(&format!("{}/drop/{}/info", DOCKER_BASE_URL, file_id))
+            .send()
+            .await
+            .expect("Info request failed");
+        assert_eq!(response.status(), 200, "Info endpoint should return 200");
+        let info: Value = response.json().await.expect("Failed to parse info response");
+        assert_eq!(info["content_type"], expected, "Served type for declared {}", declared);
+        assert_eq!(info["declared_content_type"], declared);
+        assert_eq!(info["detected_content_type"], "image/png");
+        assert_eq!(info["download_count"], 0, "Info must not count as a download");
+
+        let response = client
+            .get(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+            .send()
+            .await
+            .expect("Download failed");
+        assert_eq!(response.headers()["content-type"], expected);
+    }
+
+    // Plain text has no signature, so a specific declared type is kept
+    let response = client
+        .put(&format!("{}/drop/notes.txt", DOCKER_BASE_URL))
+        .header("Content-Type", "text/plain")
+        .body("just some notes")
+        .send()
+        .await
+        .expect("Raw upload failed");
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let file_id = upload_response["files"][0]["id"]
+        .as_str()
+        .expect("No file ID in response");
+    let info: Value = client
+        .get(&format!("{}/drop/{}/info", DOCKER_BASE_URL, file_id))
+        .send()
+        .await
+        .expect("Info request failed")
+        .json()
+        .await
+        .expect("Failed to parse info response");
+    assert_eq!(info["content_type"], "text/plain");
+    assert!(info["detected_content_type"].is_null(), "Plain text should not be detected");
+
+    println!("✅ Content type sniffing test passed");
+}
+
+/// Requires the server to block the `exe` extension, e.g.
+/// `DROP_BLOCKED_EXTENSIONS=exe docker-compose up -d`, and the same variable set for the test.
+#[tokio::test]
+async fn test_blocked_extension_is_rejected() {
+    let blocks_exe = std::env::var("DROP_BLOCKED_EXTENSIONS")
+        .map(|v| v.split(',').any(|ext| ext.trim().trim_start_matches('.').eq_ignore_ascii_case("exe")))
+        .unwrap_or(false);
+    if !blocks_exe {
+        println!("⏭️ Skipping: set DROP_BLOCKED_EXTENSIONS to include exe to match the server");
+        return;
+    }
+
+    setup_test().await.expect("Failed to setup test");
+
+    let client = create_test_client();
+    let form = multipart::Form::new()
+        .part("file", multipart::Part::text("fine").file_name("fine.txt"))
+        .part("file", multipart::Part::bytes(b"MZ\x90\x00".to_vec()).file_name("setup.exe"));
+
+    let response = client
+        .post(&format!("{}/drop", DOCKER_BASE_URL))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 415, "Expected 415 for a blocked extension");
+
+    let body: Value = response.json().await.expect("Failed to parse rejection body");
+    assert_eq!(body["rule"], "DROP_BLOCKED_EXTENSIONS");
+    assert_eq!(body["value"], "exe");
+    assert_eq!(body["filename"], "setup.exe");
+
+    println!("✅ Blocked extension test passed");
+}
+
+#[tokio::test]
+async fn test_cached_file_is_invalidated_on_delete() {
+    setup_test().await.expect("Failed to setup test");
+
+    let test_content = "Hot file served from the lookup cache.";
+    let upload_response = upload_test_file("hot_file.txt", test_content)
+        .await
+        .expect("Failed to upload test file");
+    let file_id = upload_response["id"].as_str().expect("No file ID in response");
+    let short_code = upload_response["short_url"]
+        .as_str()
+        .and_then(|url| url.rsplit('/').next())
+        .expect("No short URL in response");
+    let delete_token = upload_response["delete_token"]
+        .as_str()
+        .expect("No delete token in response");
+
+    // Repeated downloads warm the caches and keep serving the same contents
+    for _ in 0..3 {
+        let downloaded = download_test_file(short_code).await.expect("Failed to download file");
+        assert_eq!(downloaded, test_content, "Cached download doesn't match");
+    }
+
+    let client = create_test_client();
+    let response = client
+        .delete(&format!("{}/drop/{}", DOCKER_BASE_URL, file_id))
+        .header("X-Delete-Token", delete_token)
+        .send()
+        .await
+        .expect("Request failed");
+    assert_eq!(response.status(), 204, "Expected 204 for a successful delete");
+
+    assert!(download_test_file(short_code).await.is_err(), "Deleted file should not be served by short code");
+    assert!(download_test_file(file_id).await.is_err(), "Deleted file should not be served by ID");
+
+    println!("✅ Lookup cache invalidation test passed");
+}
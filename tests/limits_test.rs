@@ -0,0 +1,539 @@
+//! Rate limits, quotas, concurrency and timeouts on uploads and downloads.
+
+use reqwest::multipart;
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{create_test_client, post_form, put_file, spawn_server, sqlite_app_state, stored_files, test_app_state};
+
+#[tokio::test]
+async fn test_upload_quota_per_ip() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.quota_per_ip_per_day = Some(1024);
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let put = |content: String| {
+        client
+            .put(&format!("{}/drop/quota.txt", base_url))
+            .body(content)
+            .send()
+    };
+
+    let response = put("a".repeat(600)).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload within the quota should succeed");
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let file = &upload_response["files"][0];
+
+    // The declared size no longer fits
+    let response = put("b".repeat(600)).await.expect("Upload request failed");
+    assert_eq!(response.status(), 429, "Upload past the quota should be refused");
+    let body: Value = response.json().await.expect("Failed to parse quota response");
+    assert_eq!(body["quota_bytes"], 1024);
+    assert_eq!(body["remaining_bytes"], 424);
+    assert!(body["resets_at"].is_string(), "Quota response should say when it resets");
+
+    // Deleting the first file gives its bytes back
+    let response = client
+        .delete(&format!("{}/drop/{}", base_url, file["id"].as_str().expect("No file ID")))
+        .header("X-Delete-Token", file["delete_token"].as_str().expect("No delete token"))
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 204);
+
+    let response = put("b".repeat(600)).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Deleting should credit the quota back");
+
+    println!("✅ Upload quota test passed");
+}
+
+#[tokio::test]
+async fn test_upload_quota_enforced_while_streaming() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.quota_per_ip_per_day = Some(1024);
+    let base_url = spawn_server(app_state).await;
+
+    // A chunked body has no Content-Length to check up front
+    let mut stream = tokio::net::TcpStream::connect(base_url.trim_start_matches("http://"))
+        .await
+        .expect("Failed to connect");
+    let chunk = "c".repeat(512);
+    let mut request = String::from(
+        "PUT /drop/chunked.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+    );
+    for _ in 0..4 {
+        request.push_str(&format!("{:x}\r\n{}\r\n", chunk.len(), chunk));
+    }
+    request.push_str("0\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.expect("Failed to send request");
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.expect("Failed to read response");
+    assert!(
+        response.starts_with("HTTP/1.1 429"),
+        "Streaming past the quota should be refused: {}",
+        response.lines().next().unwrap_or_default()
+    );
+    assert!(response.contains("Daily upload quota exceeded"));
+
+    // Nothing from the refused upload is left on disk
+    let mut entries = tokio::fs::read_dir(dir.path().join("files")).await.expect("No temp directory");
+    assert!(entries.next_entry().await.expect("Failed to list temp directory").is_none());
+
+    println!("✅ Streaming quota test passed");
+}
+
+#[tokio::test]
+async fn test_rate_limit_headers() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.rate_limit_upload_rpm = 2;
+    app_state.config.rate_limit_allowlist = Vec::new(); // Test clients connect from localhost
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let put = || {
+        client
+            .put(&format!("{}/drop/limited.txt", base_url))
+            .body("rate limited")
+            .send()
+    };
+    let header = |response: &reqwest::Response, name: &str| -> u64 {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_else(|| panic!("Missing {} header", name))
+    };
+
+    // Below the limit: the headers count down
+    let response = put().await.expect("Upload request failed");
+    assert!(response.status().is_success(), "First upload should succeed");
+    assert_eq!(header(&response, "x-ratelimit-limit"), 2);
+    assert_eq!(header(&response, "x-ratelimit-remaining"), 1);
+    let reset = header(&response, "x-ratelimit-reset");
+    assert!(reset > 0 && reset <= 60, "Reset should fall within the window, got {}", reset);
+    assert!(response.headers().get("retry-after").is_none(), "Allowed requests need no Retry-After");
+
+    let response = put().await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Second upload should succeed");
+    assert_eq!(header(&response, "x-ratelimit-remaining"), 0);
+
+    // Over the limit: 429 with Retry-After
+    let response = put().await.expect("Upload request failed");
+    assert_eq!(response.status(), 429, "Third upload should be rate limited");
+    assert_eq!(header(&response, "x-ratelimit-limit"), 2);
+    assert_eq!(header(&response, "x-ratelimit-remaining"), 0);
+    let retry_after = header(&response, "retry-after");
+    assert!(retry_after >= 1 && retry_after <= 60, "Retry-After should fall within the window, got {}", retry_after);
+
+    println!("✅ Rate limit headers test passed");
+}
+
+/// Exhaust the download limit of `app_state`'s server and check uploads and health checks are unaffected
+async fn exceed_download_limit(app_state: drop::AppState) {
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let response = client
+        .put(&format!("{}/drop/download-limit.txt", base_url))
+        .body("download me")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    let upload_response: Value = response.json().await.expect("Failed to parse upload response");
+    let download_url = format!("{}/drop/{}", base_url, upload_response["files"][0]["id"].as_str().expect("No file ID"));
+
+    for remaining in ["1", "0"] {
+        let response = client.get(&download_url).send().await.expect("Download request failed");
+        assert!(response.status().is_success(), "Download within the limit should succeed");
+        assert_eq!(response.headers()["x-ratelimit-limit"], "2");
+        assert_eq!(response.headers()["x-ratelimit-remaining"], remaining);
+    }
+
+    let response = client.get(&download_url).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 429, "Download past the limit should be refused");
+    assert!(response.headers().contains_key("retry-after"), "429 should carry Retry-After");
+
+    // Uploads count against their own limit
+    let response = client
+        .put(&format!("{}/drop/after-downloads.txt", base_url))
+        .body("still allowed")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Uploads should not share the download limit");
+    assert_eq!(response.headers()["x-ratelimit-limit"], "60");
+    let remaining: u32 = response.headers()["x-ratelimit-remaining"]
+        .to_str()
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .expect("Missing x-ratelimit-remaining header");
+    assert!(remaining >= 58, "Only the two uploads should count against the upload limit, got {}", remaining);
+
+    // Health checks are never limited
+    let response = client
+        .get(&format!("{}/health", base_url))
+        .send()
+        .await
+        .expect("Health request failed");
+    assert_eq!(response.status(), 200);
+    assert!(!response.headers().contains_key("x-ratelimit-limit"), "Health checks should not be rate limited");
+}
+
+/// Fire 100 simultaneous downloads and count how many got past the limiter
+async fn parallel_requests_allowed(base_url: &str) -> usize {
+    let client = create_test_client();
+    let url = format!("{}/drop/{}", base_url, uuid::Uuid::new_v4());
+    let requests = (0..100).map(|_| client.get(&url).send());
+    futures_util::future::join_all(requests)
+        .await
+        .into_iter()
+        .map(|response| response.expect("Download request failed").status())
+        .filter(|status| *status != 429)
+        .count()
+}
+
+#[tokio::test]
+async fn test_rate_limit_holds_under_concurrency() {
+    // A burst of 10 that doesn't refill while the test runs
+    let limited = |app_state: &mut drop::AppState| {
+        app_state.config.rate_limit_download_rpm = 1;
+        app_state.config.rate_limit_download_burst = Some(10);
+        app_state.config.rate_limit_window_seconds = 3600;
+        app_state.config.rate_limit_allowlist = Vec::new(); // Test clients connect from localhost
+    };
+
+    // In-memory limiter
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    limited(&mut app_state);
+    let base_url = spawn_server(app_state).await;
+    assert_eq!(parallel_requests_allowed(&base_url).await, 10, "Exactly the burst should get through");
+
+    // Database limiter: the take is a single upsert, so no two requests share a token
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    limited(&mut app_state);
+    let base_url = spawn_server(app_state.clone()).await;
+    assert_eq!(parallel_requests_allowed(&base_url).await, 10, "Exactly the burst should get through");
+    assert!(app_state.database_available(), "The database should have handled every check");
+}
+
+#[tokio::test]
+async fn test_download_rate_limit_separate_from_uploads() {
+    // In-memory limiter
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.rate_limit_download_rpm = 2;
+    app_state.config.rate_limit_allowlist = Vec::new(); // Test clients connect from localhost
+    exceed_download_limit(app_state).await;
+
+    // Database limiter
+    let sqlite_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(sqlite_dir.path()).await;
+    app_state.config.rate_limit_download_rpm = 2;
+    app_state.config.rate_limit_allowlist = Vec::new(); // Test clients connect from localhost
+    exceed_download_limit(app_state).await;
+
+    println!("✅ Download rate limit test passed");
+}
+
+#[tokio::test]
+async fn test_allowlisted_client_not_rate_limited() {
+    // Localhost is on the default allowlist
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.rate_limit_upload_rpm = 1;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    for _ in 0..3 {
+        let response = client
+            .put(&format!("{}/drop/allowlisted.txt", base_url))
+            .body("exempt")
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Allowlisted uploads should never be limited");
+        assert!(
+            !response.headers().contains_key("x-ratelimit-limit"),
+            "Allowlisted clients aren't counted, so get no rate limit headers"
+        );
+    }
+
+    println!("✅ Rate limit allowlist test passed");
+}
+
+#[tokio::test]
+async fn test_rate_limit_per_forwarded_client() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.rate_limit_upload_rpm = 1;
+    app_state.config.rate_limit_allowlist = Vec::new();
+    app_state.config.trusted_proxies = drop::client_ip::parse_ip_ranges("127.0.0.1").expect("Valid proxy range");
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let upload_as = |forwarded_for: &'static str| {
+        client
+            .put(&format!("{}/drop/proxied.txt", base_url))
+            .header("X-Forwarded-For", forwarded_for)
+            .body("behind a proxy")
+            .send()
+    };
+
+    // Each forwarded client gets its own bucket behind the shared proxy
+    let response = upload_as("203.0.113.5, 127.0.0.1").await.expect("Upload request failed");
+    assert!(response.status().is_success(), "First client's upload should succeed");
+    let response = upload_as("203.0.113.6").await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Second client has its own limit");
+    let response = upload_as("198.51.100.1, 203.0.113.5").await.expect("Upload request failed");
+    assert_eq!(response.status(), 429, "A forged leading hop shouldn't escape the limit");
+
+    println!("✅ Forwarded client rate limit test passed");
+}
+
+#[tokio::test]
+async fn test_forwarded_for_ignored_from_untrusted_peer() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.rate_limit_upload_rpm = 1;
+    app_state.config.rate_limit_allowlist = Vec::new();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    for (forwarded_for, allowed) in [("203.0.113.5", true), ("203.0.113.6", false)] {
+        let response = client
+            .put(&format!("{}/drop/spoofed.txt", base_url))
+            .header("X-Forwarded-For", forwarded_for)
+            .body("not behind a proxy")
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert_eq!(response.status().is_success(), allowed, "Spoofed X-Forwarded-For must not reset the limit");
+    }
+
+    println!("✅ Untrusted X-Forwarded-For test passed");
+}
+
+#[tokio::test]
+async fn test_upload_concurrency_limit() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.max_concurrent_uploads = 1;
+    app_state.config.upload_queue_timeout_ms = 100;
+    app_state.upload_permits = drop::concurrency::upload_permits(&app_state.config);
+    let permits = app_state.upload_permits.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+    let upload = |name: &'static str| {
+        let request = put_file(&client, &base_url, name, "concurrent");
+        async move { request.send().await.expect("Upload request failed") }
+    };
+
+    // Slots are given back after every upload
+    for name in ["first.txt", "second.txt"] {
+        assert!(upload(name).await.status().is_success(), "Upload should succeed");
+    }
+
+    // With the only slot taken, uploads give up after the queue timeout
+    let held = permits.clone().try_acquire_owned().expect("Slot should be free");
+    let response = upload("queued.txt").await;
+    assert_eq!(response.status(), 503, "Upload should be rejected while every slot is busy");
+    assert!(response.headers().contains_key("retry-after"), "503 should carry Retry-After");
+
+    drop(held);
+    assert!(upload("retried.txt").await.status().is_success(), "Upload should succeed once a slot frees up");
+    assert_eq!(permits.available_permits(), 1, "No slot should leak");
+}
+
+#[tokio::test]
+async fn test_download_bandwidth_limit() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let client = create_test_client();
+    let contents = vec![7u8; 192 * 1024];
+
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.max_bandwidth_per_download = Some(64 * 1024);
+    app_state.config.rate_limit_allowlist = Vec::new(); // Test clients connect from localhost
+    let base_url = spawn_server(app_state).await;
+    let response = client
+        .put(&format!("{}/drop/big.bin", base_url))
+        .body(contents.clone())
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let id = upload["files"][0]["id"].as_str().unwrap().to_string();
+
+    // One second's worth goes out at once, the rest at 64KB/s
+    let started = std::time::Instant::now();
+    let response = client.get(&format!("{}/drop/{}", base_url, id)).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await.unwrap().len(), contents.len());
+    assert!(started.elapsed() >= Duration::from_millis(1900), "Took {:?}", started.elapsed());
+
+    // Allowlisted clients are never throttled
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.max_bandwidth_per_download = Some(64 * 1024);
+    let base_url = spawn_server(app_state).await;
+    let response = client
+        .put(&format!("{}/drop/big.bin", base_url))
+        .body(contents.clone())
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let started = std::time::Instant::now();
+    let response = client
+        .get(upload["files"][0]["full_url"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.bytes().await.unwrap().len(), contents.len());
+    assert!(started.elapsed() < Duration::from_millis(1500), "Took {:?}", started.elapsed());
+
+    println!("✅ Download bandwidth limit test passed");
+}
+
+#[tokio::test]
+async fn test_multipart_part_limits() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.max_files_per_request = 2;
+    app_state.config.max_multipart_parts = 4;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+    let upload = |form: multipart::Form| post_form(&client, &base_url, "", form).send();
+    let file = |name: &str| multipart::Part::text("contents").file_name(name.to_string());
+    let stored_files = || stored_files(dir.path()).len();
+
+    // A form field without a filename is metadata, not a file called "unknown"
+    let form = multipart::Form::new().text("note", "ignored").part("file", file("kept.txt"));
+    let response = upload(form).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Unknown form fields should be tolerated");
+    let uploaded: Value = response.json().await.expect("Failed to parse upload response");
+    assert_eq!(uploaded["files"].as_array().unwrap().len(), 1, "Only the file part should be stored");
+    assert_eq!(uploaded["files"][0]["filename"], "kept.txt");
+    let stored = stored_files();
+
+    let form = (0..3).fold(multipart::Form::new(), |form, i| form.part("file", file(&format!("{}.txt", i))));
+    let response = upload(form).await.expect("Upload request failed");
+    assert_eq!(response.status(), 413);
+    let error: Value = response.json().await.expect("Error should be JSON");
+    assert_eq!(error["error"], "Too many files");
+    assert_eq!(error["limit"], 2);
+
+    let form = (0..4).fold(multipart::Form::new(), |form, i| form.text(format!("field{}", i), "x"));
+    let response = upload(form.part("file", file("late.txt"))).await.expect("Upload request failed");
+    assert_eq!(response.status(), 413);
+    let error: Value = response.json().await.expect("Error should be JSON");
+    assert_eq!(error["error"], "Too many parts");
+
+    let form = multipart::Form::new().text("note", "x".repeat(2048)).part("file", file("a.txt"));
+    let response = upload(form).await.expect("Upload request failed");
+    assert_eq!(response.status(), 422, "An oversized form field should be refused");
+
+    // Parts stored before a limit was hit are cleaned up
+    for _ in 0..50 {
+        if stored_files() == stored {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(stored_files(), stored, "Refused uploads should leave no files behind");
+
+    println!("✅ Multipart limits test passed");
+}
+
+#[tokio::test]
+async fn test_slow_upload_is_cut_off() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.min_upload_rate = Some(1024);
+    app_state.config.min_upload_rate_window_seconds = 1;
+    let base_url = spawn_server(app_state).await;
+
+    // A few bytes, then nothing: well under 1KB/s
+    let mut stream = tokio::net::TcpStream::connect(base_url.trim_start_matches("http://"))
+        .await
+        .expect("Failed to connect");
+    let request = "PUT /drop/slow.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+                   a\r\ntrickle...\r\n";
+    stream.write_all(request.as_bytes()).await.expect("Failed to send request");
+
+    let mut response = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), stream.read_to_end(&mut response))
+        .await
+        .expect("The slow upload should have been cut off")
+        .expect("Failed to read response");
+    let response = String::from_utf8_lossy(&response);
+    assert!(
+        response.starts_with("HTTP/1.1 408"),
+        "A trickling upload should get a 408: {}",
+        response.lines().next().unwrap_or_default()
+    );
+
+    // The partial file is removed
+    let mut entries = tokio::fs::read_dir(dir.path().join("files")).await.expect("No temp directory");
+    assert!(entries.next_entry().await.expect("Failed to list temp directory").is_none());
+
+    // A fast upload is unaffected
+    let response = create_test_client()
+        .put(&format!("{}/drop/fast.txt", base_url))
+        .body("quick")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "A fast upload should succeed");
+
+    println!("✅ Slow upload test passed");
+}
+
+#[tokio::test]
+async fn test_stalled_download_is_closed() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.download_idle_timeout = Some(Duration::from_secs(1));
+    let base_url = spawn_server(app_state).await;
+
+    // Bigger than the socket buffers, so the server has to wait on the reader
+    let size = 64 * 1024 * 1024;
+    let response = create_test_client()
+        .put(&format!("{}/drop/large.bin", base_url))
+        .body(vec![0u8; size])
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let id = upload["files"][0]["id"].as_str().unwrap().to_string();
+
+    let mut stream = tokio::net::TcpStream::connect(base_url.trim_start_matches("http://"))
+        .await
+        .expect("Failed to connect");
+    let request = format!("GET /drop/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", id);
+    stream.write_all(request.as_bytes()).await.expect("Failed to send request");
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    // What was already sent arrives, then the connection ends short of the file
+    let mut received = Vec::new();
+    let _ = tokio::time::timeout(Duration::from_secs(10), stream.read_to_end(&mut received))
+        .await
+        .expect("The stalled download should have been closed");
+    assert!(received.len() < size, "A stalled download should not be completed");
+
+    println!("✅ Stalled download test passed");
+}
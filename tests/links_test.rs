@@ -0,0 +1,545 @@
+//! Short codes, aliases and bundles, and resolving them.
+
+use reqwest::{Client, multipart};
+use serde_json::Value;
+
+mod common;
+use common::{
+    create_test_client, open_sqlite, put_file, spawn_server, spawn_sqlite_server, sqlite_app_state, test_app_state,
+    upload, uploaded_file,
+};
+
+#[tokio::test]
+async fn test_batch_file_info() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_sqlite_server(dir.path()).await;
+    let client = create_test_client();
+    let upload = |query: &'static str| upload(&client, &base_url, query, "batched.txt", "batched");
+    let batch_info = |ids: Vec<String>| {
+        client
+            .post(&format!("{}/drop/info", base_url))
+            .json(&serde_json::json!({ "ids": ids }))
+            .send()
+    };
+
+    let file = upload("").await;
+    let file_id = file["id"].as_str().expect("No file ID").to_string();
+    let short_code = file["short_url"]
+        .as_str()
+        .and_then(|url| url.rsplit('/').next())
+        .expect("No short URL in response")
+        .to_string();
+    let used_id = upload("?max_downloads=1").await["id"].as_str().expect("No file ID").to_string();
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, used_id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.status().is_success());
+
+    let unknown_id = uuid::Uuid::new_v4().to_string();
+    let ids = vec![short_code.clone(), file_id.clone(), used_id.clone(), unknown_id.clone(), "zzzzzzzz".to_string()];
+    let response = batch_info(ids).await.expect("Info request failed");
+    assert_eq!(response.status(), 200);
+    let info: Value = response.json().await.expect("Failed to parse info response");
+    let files = &info["files"];
+    assert_eq!(files[&short_code]["id"], file_id.as_str(), "Short codes resolve like downloads");
+    assert_eq!(files[&file_id]["id"], file_id.as_str());
+    assert_eq!(files[&file_id]["download_count"], 0);
+    assert_eq!(files[&used_id]["reason"], "consumed");
+    assert_eq!(files[&unknown_id]["error"], "Not Found");
+    assert_eq!(files["zzzzzzzz"]["error"], "Not Found");
+
+    // Asking again counted nothing
+    let response = client
+        .get(&format!("{}/drop/{}/info", base_url, file_id))
+        .send()
+        .await
+        .expect("Info request failed");
+    let single: Value = response.json().await.expect("Failed to parse info response");
+    assert_eq!(single["download_count"], 0, "Batch lookups aren't downloads");
+
+    let response = batch_info(vec![file_id; 101]).await.expect("Info request failed");
+    assert_eq!(response.status(), 400, "At most 100 IDs per request");
+}
+
+/// (name, contents) of each entry in a tar archive
+fn tar_entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 512 <= archive.len() && archive[offset] != 0 {
+        let header = &archive[offset..offset + 512];
+        let name = String::from_utf8_lossy(header[..100].split(|&b| b == 0).next().unwrap()).to_string();
+        let size = std::str::from_utf8(&header[124..136])
+            .ok()
+            .and_then(|size| usize::from_str_radix(size.trim_matches(|c: char| c == '\0' || c == ' '), 8).ok())
+            .expect("Invalid tar entry size");
+        let data = offset + 512;
+        entries.push((name, archive[data..data + size].to_vec()));
+        offset = data + size.div_ceil(512) * 512;
+    }
+    entries
+}
+
+async fn check_bundles(base_url: &str) {
+    let client = create_test_client();
+    let mut files = Vec::new();
+    for (name, contents) in [("notes.txt", "first file"), ("notes.txt", "same name"), ("data.csv", "a,b\n1,2\n")] {
+        let response = client
+            .put(&format!("{}/drop/{}", base_url, name))
+            .body(contents)
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        files.push(upload["files"][0].clone());
+    }
+    let short_code = |file: &Value| file["short_url"].as_str().unwrap().rsplit('/').next().unwrap().to_string();
+    let create = |files: Value| {
+        client
+            .post(&format!("{}/drop/bundle", base_url))
+            .json(&serde_json::json!({ "files": files }))
+            .send()
+    };
+
+    // Members may be named by ID or short code
+    let response = create(serde_json::json!([files[0]["id"], short_code(&files[1]), files[2]["id"]]))
+        .await
+        .expect("Bundle request failed");
+    assert_eq!(response.status(), 201);
+    let bundle: Value = response.json().await.expect("Failed to parse bundle response");
+    assert_eq!(bundle["files"], 3);
+    assert_eq!(bundle["size"], 27);
+    let bundle_url = bundle["short_url"].as_str().expect("No bundle URL").to_string();
+    assert!(bundle_url.contains("/drop/bundle/"), "{}", bundle_url);
+
+    // Unknown files and empty lists are refused
+    let response = create(serde_json::json!([files[0]["id"], "nosuchcode"])).await.expect("Bundle request failed");
+    assert_eq!(response.status(), 404);
+    let response = create(serde_json::json!([])).await.expect("Bundle request failed");
+    assert_eq!(response.status(), 400);
+
+    // Zip by default, stored uncompressed
+    let response = client.get(&bundle_url).send().await.expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "application/zip");
+    let archive = response.bytes().await.expect("No body");
+    assert!(archive.starts_with(b"PK\x03\x04"), "Expected a zip archive");
+    for needle in ["notes.txt", "notes (2).txt", "data.csv", "first file", "same name"] {
+        assert!(
+            archive.windows(needle.len()).any(|window| window == needle.as_bytes()),
+            "Archive should contain {}",
+            needle
+        );
+    }
+
+    // Tar on request
+    let response = client
+        .get(&format!("{}?format=tar", bundle_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.headers()["content-type"], "application/x-tar");
+    let entries = tar_entries(&response.bytes().await.expect("No body"));
+    let expected: Vec<(String, Vec<u8>)> = [("notes.txt", "first file"), ("notes (2).txt", "same name"), ("data.csv", "a,b\n1,2\n")]
+        .into_iter()
+        .map(|(name, contents)| (name.to_string(), contents.as_bytes().to_vec()))
+        .collect();
+    assert_eq!(entries, expected);
+
+    // A deleted member is listed as missing, or fails a strict download
+    let response = client
+        .delete(&format!("{}/drop/{}", base_url, files[1]["id"].as_str().unwrap()))
+        .header("X-Delete-Token", files[1]["delete_token"].as_str().unwrap())
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 204);
+    let response = client
+        .get(&format!("{}?format=tar", bundle_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    let entries = tar_entries(&response.bytes().await.expect("No body"));
+    let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["notes.txt", "data.csv", "MISSING.txt"]);
+    let note = String::from_utf8_lossy(&entries[2].1).to_string();
+    assert!(note.contains(files[1]["id"].as_str().unwrap()), "{}", note);
+    let response = client
+        .get(&format!("{}?strict=true", bundle_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 404);
+
+    let response = client
+        .get(&format!("{}/drop/bundle/nosuchbundle", base_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_bundle_archives() {
+    // In-memory index
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    check_bundles(&spawn_server(test_app_state(dir.path(), None)).await).await;
+
+    // Database index
+    let sqlite_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    check_bundles(&spawn_server(sqlite_app_state(sqlite_dir.path()).await).await).await;
+
+    println!("✅ Bundle archive test passed");
+}
+
+#[tokio::test]
+async fn test_bundle_size_limit() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.max_bundle_size = 10;
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let response = client
+        .put(&format!("{}/drop/big.txt", base_url))
+        .body("more than ten bytes")
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let response = client
+        .post(&format!("{}/drop/bundle", base_url))
+        .json(&serde_json::json!({ "files": [upload["files"][0]["id"]] }))
+        .send()
+        .await
+        .expect("Bundle request failed");
+    assert_eq!(response.status(), 413);
+}
+
+async fn check_aliases(base_url: &str) {
+    let client = create_test_client();
+    let upload_with_alias = |alias: &str, contents: &'static str| {
+        let form = multipart::Form::new()
+            .text("alias", alias.to_string())
+            .part("file", multipart::Part::text(contents).file_name("report.txt"));
+        client.post(&format!("{}/drop", base_url)).multipart(form).send()
+    };
+
+    let response = upload_with_alias("q3-report", "quarterly numbers").await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let file = &upload["files"][0];
+    assert_eq!(file["short_url"].as_str(), Some(format!("{}/drop/q3-report", base_url).as_str()));
+    let response = client
+        .get(&format!("{}/drop/q3-report", base_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "quarterly numbers");
+
+    // Taken aliases conflict, whether sent as a form field or in the query string
+    let response = upload_with_alias("q3-report", "other numbers").await.expect("Upload request failed");
+    assert_eq!(response.status(), 409);
+    let error: Value = response.json().await.expect("Failed to parse error response");
+    assert_eq!(error["alias"], "q3-report");
+    let response = client
+        .put(&format!("{}/drop/other.txt?alias=q3-report", base_url))
+        .body("other numbers")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 409);
+
+    // Invalid and reserved aliases are rejected, as is an alias for several files
+    for alias in ["ab", "Has-Caps", "bundle", "550e8400-e29b-41d4-a716-446655440000"] {
+        let response = upload_with_alias(alias, "contents").await.expect("Upload request failed");
+        assert_eq!(response.status(), 422, "Alias '{}' should be rejected", alias);
+        let error: Value = response.json().await.expect("Failed to parse error response");
+        assert_eq!(error["fields"][0]["field"], "alias");
+        let response = client
+            .put(&format!("{}/drop/other.txt?alias={}", base_url, alias))
+            .body("contents")
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert_eq!(response.status(), 400, "Alias '{}' should be rejected", alias);
+    }
+    let form = multipart::Form::new()
+        .text("alias", "two-files")
+        .part("file", multipart::Part::text("one").file_name("one.txt"))
+        .part("file", multipart::Part::text("two").file_name("two.txt"));
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 400);
+
+    // More aliases need the delete token
+    let add_alias = |alias: &str, token: &str| {
+        client
+            .post(&format!("{}/drop/q3-report/aliases", base_url))
+            .header("X-Delete-Token", token)
+            .json(&serde_json::json!({ "alias": alias }))
+            .send()
+    };
+    let token = file["delete_token"].as_str().unwrap();
+    assert_eq!(add_alias("latest", "wrong-token").await.expect("Request failed").status(), 403);
+    let response = add_alias("latest", token).await.expect("Request failed");
+    assert_eq!(response.status(), 201);
+    let added: Value = response.json().await.expect("Failed to parse alias response");
+    assert_eq!(added["short_url"].as_str(), Some(format!("{}/drop/latest", base_url).as_str()));
+    assert_eq!(add_alias("latest", token).await.expect("Request failed").status(), 409);
+    let response = client
+        .get(&format!("{}/drop/latest/info", base_url))
+        .send()
+        .await
+        .expect("Info request failed");
+    assert_eq!(response.status(), 200);
+    let info: Value = response.json().await.expect("Failed to parse info response");
+    assert_eq!(info["id"], file["id"]);
+}
+
+#[tokio::test]
+async fn test_upload_aliases() {
+    // In-memory index
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    check_aliases(&spawn_server(test_app_state(dir.path(), None)).await).await;
+
+    // Database index
+    let sqlite_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    check_aliases(&spawn_server(sqlite_app_state(sqlite_dir.path()).await).await).await;
+
+    println!("✅ Upload alias test passed");
+}
+
+#[tokio::test]
+async fn test_misses_are_cached_until_created() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let app_state = sqlite_app_state(dir.path()).await;
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+    let status = |path: String| {
+        let client = client.clone();
+        async move { client.get(&path).send().await.expect("Download request failed").status() }
+    };
+
+    // Neither a UUID nor a short code: never looked up, so never cached either
+    assert_eq!(status(format!("{}/drop/NOT.a.code", base_url)).await, 404);
+    assert_eq!(status(format!("{}/drop/NOT.a.code", base_url)).await, 404);
+    assert_eq!(app_state.negative_cache.hits(), 0);
+
+    // The first miss asks the database, the next ones don't
+    let unknown_id = uuid::Uuid::new_v4();
+    for path in ["later-alias".to_string(), unknown_id.to_string()] {
+        assert_eq!(status(format!("{}/drop/{}", base_url, path)).await, 404);
+        assert_eq!(status(format!("{}/drop/{}", base_url, path)).await, 404);
+    }
+    assert_eq!(app_state.negative_cache.hits(), 2);
+    let health: Value = client
+        .get(&format!("{}/health", base_url))
+        .send()
+        .await
+        .expect("Health request failed")
+        .json()
+        .await
+        .expect("Failed to parse health response");
+    assert_eq!(health["negative_cache_hits"], 2);
+
+    // Claiming the short code forgets the miss
+    let form = multipart::Form::new()
+        .text("alias", "later-alias")
+        .part("file", multipart::Part::text("here now").file_name("later.txt"));
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    let response = client
+        .get(&format!("{}/drop/later-alias", base_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "here now");
+}
+
+#[tokio::test]
+async fn test_short_code_collision_keeps_existing_link() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let base_url = spawn_server(test_app_state(dir.path(), Some(database.clone()))).await;
+    let client = create_test_client();
+
+    let mut files = Vec::new();
+    for name in ["first.txt", "second.txt"] {
+        let response = client
+            .put(&format!("{}/drop/{}", base_url, name))
+            .body(name)
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        let id: uuid::Uuid = upload["files"][0]["id"].as_str().and_then(|id| id.parse().ok()).expect("No file ID");
+        files.push(id);
+    }
+
+    // A taken code is left pointing at its original file
+    assert!(database.store_short_url("taken01", files[0]).await.expect("Insert failed"));
+    assert!(!database.store_short_url("taken01", files[1]).await.expect("Insert failed"));
+    assert_eq!(database.get_file_id_by_short_code("taken01").await.expect("Lookup failed"), Some(files[0]));
+    let response = client
+        .get(&format!("{}/drop/taken01", base_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.text().await.unwrap(), "first.txt");
+
+    // The in-memory fallback refuses it the same way
+    let memory_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let app_state = test_app_state(memory_dir.path(), None);
+    let original = uuid::Uuid::new_v4();
+    app_state.short_url_storage.insert("taken02".to_string(), original);
+    let short_url_storage = app_state.short_url_storage.clone();
+    let base_url = spawn_server(app_state).await;
+    let response = client
+        .put(&format!("{}/drop/other.txt?alias=taken02", base_url))
+        .body("someone else's file")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 409);
+    assert_eq!(short_url_storage.get("taken02").map(|file_id| *file_id), Some(original));
+
+    println!("✅ Short code collision test passed");
+}
+
+/// Upload `content` as a raw body and return the short code it was given, or the status
+async fn upload_for_short_code(client: &Client, base_url: &str, content: &str) -> Result<String, u16> {
+    let response = put_file(client, base_url, "generated.txt", content.to_string())
+        .send()
+        .await
+        .expect("Upload request failed");
+    if !response.status().is_success() {
+        return Err(response.status().as_u16());
+    }
+    let file = uploaded_file(response).await;
+    let short_url = file["short_url"].as_str().expect("No short URL in response");
+    Ok(short_url.rsplit('/').next().expect("Invalid short URL format").to_string())
+}
+
+#[tokio::test]
+async fn test_generated_short_code_collisions_retry() {
+    let client = create_test_client();
+
+    for with_database in [false, true] {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let database = if with_database {
+            Some(open_sqlite(dir.path()).await)
+        } else {
+            None
+        };
+        let mut app_state = test_app_state(dir.path(), database);
+        // The second upload is handed the first one's code twice before a free one
+        app_state.ids = std::sync::Arc::new(
+            drop::ids::SeededIds::new(7).with_short_codes(["dupe0001", "dupe0001", "dupe0001", "free0002"]),
+        );
+        let base_url = spawn_server(app_state).await;
+
+        assert_eq!(upload_for_short_code(&client, &base_url, "first").await, Ok("dupe0001".to_string()));
+        assert_eq!(upload_for_short_code(&client, &base_url, "second").await, Ok("free0002".to_string()));
+
+        let response = client
+            .get(&format!("{}/drop/dupe0001", base_url))
+            .send()
+            .await
+            .expect("Download request failed");
+        assert_eq!(response.text().await.unwrap(), "first", "A retried collision must not take over the code");
+    }
+
+    // A generator that never stops colliding gives up instead of looping
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.ids = std::sync::Arc::new(drop::ids::SeededIds::new(7).with_short_codes(vec!["stuck001"; 10]));
+    let base_url = spawn_server(app_state).await;
+    assert_eq!(upload_for_short_code(&client, &base_url, "first").await, Ok("stuck001".to_string()));
+    assert_eq!(upload_for_short_code(&client, &base_url, "second").await, Err(500));
+
+    println!("✅ Generated short code collision test passed");
+}
+
+#[test]
+fn test_seeded_ids_repeat_per_seed() {
+    use drop::ids::{IdGenerator, SeededIds};
+
+    let sequence = |seed| {
+        let ids = SeededIds::new(seed);
+        (0..4).map(|_| (ids.uuid(), ids.short_code())).collect::<Vec<_>>()
+    };
+    assert_eq!(sequence(42), sequence(42), "The same seed should give the same IDs");
+    assert_ne!(sequence(42), sequence(43), "Different seeds should give different IDs");
+
+    let ids = SeededIds::new(42);
+    let uuid = ids.uuid();
+    assert_eq!(uuid.get_version_num(), 4, "Seeded IDs should still be valid v4 UUIDs");
+    let short_code = ids.short_code();
+    assert_eq!(short_code.len(), 8);
+    assert!(short_code.chars().all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+}
+
+#[tokio::test]
+async fn test_short_code_redirect_mode() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.short_code_mode = drop::redirect::ShortCodeMode::Redirect;
+    let base_url = spawn_server(app_state).await;
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Failed to create HTTP client");
+
+    let file = upload(&client, &base_url, "?max_downloads=1", "note.txt", "read me once").await;
+    let short_url = file["short_url"].as_str().unwrap();
+    let id = file["id"].as_str().unwrap();
+
+    // Redirects don't use up the single download
+    for _ in 0..2 {
+        let response = client.get(short_url).send().await.expect("Short link request failed");
+        assert_eq!(response.status(), 302);
+        assert_eq!(response.headers()["location"], format!("/drop/{}/note.txt", id));
+    }
+    let response = client
+        .get(&format!("{}?disposition=inline", file["pretty_url"].as_str().unwrap()))
+        .send()
+        .await
+        .expect("Pretty link request failed");
+    assert_eq!(response.status(), 302);
+    assert_eq!(response.headers()["location"], format!("/drop/{}/note.txt?disposition=inline", id));
+
+    let response = client
+        .get(&format!("{}/drop/{}/note.txt", base_url, id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await.unwrap(), "read me once");
+
+    // Once the file is gone the short code answers for itself
+    let response = client.get(short_url).send().await.expect("Short link request failed");
+    assert_eq!(response.status(), 410);
+    let response = client
+        .get(&format!("{}/drop/not-a-code", base_url))
+        .send()
+        .await
+        .expect("Short link request failed");
+    assert_eq!(response.status(), 404);
+
+    println!("✅ Short code redirect mode test passed");
+}
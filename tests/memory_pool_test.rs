@@ -0,0 +1,198 @@
+//! Tests that need the memory pool switched on. The pool is process-wide, so
+//! they live in their own test binary to keep small uploads in the other
+//! test binaries on disk.
+
+use drop::memory_store::InMemoryStore;
+use drop::metadata::MetadataStore;
+use reqwest::Client;
+use std::sync::Arc;
+
+mod common;
+use common::{
+    create_test_client, open_sqlite, spawn_server, sqlite_app_state, store_app_state, test_app_state, upload,
+};
+
+// Tests here share the pool counters; run them one at a time
+static POOL_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+#[tokio::test]
+async fn test_in_memory_file_readable_with_database() {
+    let _pool = POOL_LOCK.lock().await;
+    drop::initialize_memory_pool(&drop::Config::default());
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let base_url = spawn_server(test_app_state(dir.path(), Some(database.clone()))).await;
+    let client = create_test_client();
+
+    let test_content = "Small enough for the memory pool.";
+    let file = upload(&client, &base_url, "", "memory.txt", test_content).await;
+    let file_id: uuid::Uuid = file["id"]
+        .as_str()
+        .and_then(|id| id.parse().ok())
+        .expect("No file ID in response");
+
+    // The database recorded the file as held in memory...
+    let mapping = database
+        .find_file_mapping(file_id)
+        .await
+        .expect("Lookup failed")
+        .expect("Upload should be recorded in the database");
+    assert!(mapping.is_in_memory, "Small upload should be kept in memory");
+
+    // ...and the bytes can still be served
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file_id))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.status().is_success(), "In-memory file should download");
+    assert_eq!(response.text().await.expect("No body"), test_content);
+
+    println!("✅ In-memory download with database test passed");
+}
+
+/// Upload and delete `count` small files, checking each one lands in the pool
+async fn upload_and_delete(client: &Client, base_url: &str, count: usize) {
+    for i in 0..count {
+        let before = drop::allocated_memory();
+        let file = upload(client, base_url, "", &format!("pool-{}.txt", i), "x".repeat(1024)).await;
+        assert!(drop::allocated_memory() > before, "Upload {} should be held in the memory pool", i);
+
+        let file_id = file["id"].as_str().expect("No file ID in response");
+        let response = client
+            .delete(&format!("{}/drop/{}", base_url, file_id))
+            .header("X-Delete-Token", file["delete_token"].as_str().expect("No delete token"))
+            .send()
+            .await
+            .expect("Delete request failed");
+        assert!(response.status().is_success(), "Delete should succeed");
+    }
+}
+
+#[tokio::test]
+async fn test_memory_pool_released_on_delete() {
+    let _pool = POOL_LOCK.lock().await;
+    drop::initialize_memory_pool(&drop::Config::default());
+    let client = create_test_client();
+    let starting = drop::allocated_memory();
+
+    // In-memory index
+    let memory_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(memory_dir.path(), None)).await;
+    upload_and_delete(&client, &base_url, 10).await;
+    assert_eq!(drop::allocated_memory(), starting, "Deleted files should release their pool memory");
+
+    // Database index
+    let sqlite_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(sqlite_app_state(sqlite_dir.path()).await).await;
+    upload_and_delete(&client, &base_url, 10).await;
+    assert_eq!(drop::allocated_memory(), starting, "Deleted files should release their pool memory");
+
+    println!("✅ Memory pool release test passed");
+}
+
+#[tokio::test]
+async fn test_memory_pool_follows_config_ratio() {
+    let _pool = POOL_LOCK.lock().await;
+    let config = drop::Config::default();
+    drop::initialize_memory_pool(&config);
+    let initial = drop::memory_pool_size();
+
+    // A much smaller share shrinks the pool...
+    let small = drop::Config {
+        memory_pool_ratio: config.memory_pool_ratio / 10.0,
+        ..drop::Config::default()
+    };
+    let resized = drop::resize_memory_pool(&small).expect("Pool should shrink");
+    assert!(resized < initial, "Pool should shrink with a smaller ratio");
+    assert!(resized >= drop::allocated_memory(), "Pool should never drop below allocated memory");
+
+    // ...and going back restores it, while a repeat resize is absorbed by the hysteresis
+    drop::resize_memory_pool(&config).expect("Pool should grow back");
+    assert!(drop::resize_memory_pool(&config).is_none(), "Unchanged memory should not resize the pool");
+
+    println!("✅ Memory pool resize test passed");
+}
+
+#[tokio::test]
+async fn test_in_memory_downloads_share_the_stored_buffer() {
+    let _pool = POOL_LOCK.lock().await;
+    drop::initialize_memory_pool(&drop::Config::default());
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let test_content = "0123456789abcdefghij";
+    let file = upload(&client, &base_url, "", "shared.txt", test_content).await;
+    let url = format!("{}/drop/{}", base_url, file["id"].as_str().expect("No file ID in response"));
+    let allocated = drop::allocated_memory();
+
+    // Whole downloads and ranges are both served from the one stored copy...
+    for _ in 0..5 {
+        let response = client.get(&url).send().await.expect("Download request failed");
+        assert!(response.status().is_success(), "In-memory file should download");
+        assert_eq!(response.text().await.expect("No body"), test_content);
+
+        let response = client
+            .get(&url)
+            .header("Range", "bytes=5-9")
+            .send()
+            .await
+            .expect("Range request failed");
+        assert_eq!(response.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get("content-range").and_then(|value| value.to_str().ok()),
+            Some("bytes 5-9/20")
+        );
+        assert_eq!(response.text().await.expect("No body"), "56789");
+    }
+
+    // ...so serving it never charges the pool again
+    assert_eq!(drop::allocated_memory(), allocated, "Downloads should not allocate pool memory");
+
+    println!("✅ Shared in-memory buffer test passed");
+}
+
+#[tokio::test]
+async fn test_in_memory_mapping_without_contents_is_not_found() {
+    let _pool = POOL_LOCK.lock().await;
+    drop::initialize_memory_pool(&drop::Config::default());
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let store = InMemoryStore::new();
+    let app_state = store_app_state(dir.path(), Some(Arc::new(store.clone())));
+    let storage = app_state.storage.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let file = upload(&client, &base_url, "", "vanishing.txt", "Gone from memory").await;
+    let file_id: uuid::Uuid = file["id"]
+        .as_str()
+        .and_then(|id| id.parse().ok())
+        .expect("No file ID in response");
+    let url = format!("{}/drop/{}", base_url, file_id);
+
+    // Served once, so the mapping is cached too...
+    let response = client.get(&url).send().await.expect("Download request failed");
+    assert!(response.status().is_success(), "In-memory file should download");
+
+    // ...then the bytes disappear while the store still says they are in memory
+    let mapping = store
+        .find_file_mapping(file_id)
+        .await
+        .expect("Lookup failed")
+        .expect("Upload should be recorded in the store");
+    assert!(mapping.is_in_memory, "Small upload should be kept in memory");
+    drop::storage::StorageBackend::delete(&storage, &drop::storage::StorageRef::Memory(file_id))
+        .await
+        .expect("Failed to remove in-memory contents");
+
+    for _ in 0..2 {
+        let response = client.get(&url).send().await.expect("Download request failed");
+        assert_eq!(response.status(), 404, "Missing contents should be a 404, cached or not");
+    }
+
+    println!("✅ Missing in-memory contents test passed");
+}
@@ -0,0 +1,175 @@
+//! `InMemoryStore` against the database it stands in for: the same calls
+//! must give the same answers from both.
+
+use chrono::{Duration, Utc};
+use drop::database::{FileListQuery, FileSort, KeyClaim, NewFileMapping};
+use drop::memory_store::InMemoryStore;
+use drop::metadata::MetadataStore;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+mod common;
+use common::open_sqlite;
+
+fn new_mapping(id: Uuid, file_size: i64) -> NewFileMapping<'static> {
+    NewFileMapping {
+        id,
+        filename: "file.txt",
+        content_type: "text/plain",
+        declared_content_type: None,
+        detected_content_type: None,
+        file_path: None,
+        file_size,
+        is_in_memory: true,
+        expires_at: None,
+        delete_token: "token",
+        max_downloads: None,
+        content_hash: "hash",
+        sha256: None,
+        created_at: Utc::now(),
+        access_count: 0,
+        uploader_ip: None,
+        encrypted: false,
+        pinned: false,
+        api_key_id: None,
+        embeddable: false,
+    }
+}
+
+// Uploads, downloads, expiry and short codes, checked the same way against either store
+async fn check_files(store: &dyn MetadataStore) {
+    let (first, second, limited, expired) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    assert!(store.store_upload(&new_mapping(first, 10), "first").await.unwrap());
+    assert!(
+        !store.store_upload(&new_mapping(second, 20), "first").await.unwrap(),
+        "A taken short code stores nothing"
+    );
+    assert!(store.find_file_mapping(second).await.unwrap().is_none());
+    assert!(store.store_upload(&new_mapping(second, 20), "second").await.unwrap());
+    assert!(store.store_file_mapping(&new_mapping(second, 20)).await.is_err(), "IDs are unique");
+    assert!(!store.store_file_mapping_if_absent(&new_mapping(second, 20)).await.unwrap());
+
+    // The last download is only handed out once
+    let mapping = NewFileMapping {
+        max_downloads: Some(1),
+        ..new_mapping(limited, 30)
+    };
+    store.store_file_mapping(&mapping).await.unwrap();
+    assert_eq!(store.claim_download(limited).await.unwrap().map(|m| m.access_count), Some(1));
+    assert!(store.claim_download(limited).await.unwrap().is_none());
+    assert!(store.get_file_mapping(limited).await.unwrap().is_none());
+    assert!(store.find_file_mapping(limited).await.unwrap().is_some());
+
+    // Expired files become tombstones that still resolve by short code
+    let mapping = NewFileMapping {
+        expires_at: Some(Utc::now() - Duration::seconds(1)),
+        ..new_mapping(expired, 40)
+    };
+    store.store_file_mapping(&mapping).await.unwrap();
+    assert!(store.store_short_url("expired", expired).await.unwrap());
+    assert!(!store.store_short_url("expired", first).await.unwrap());
+    assert_eq!(store.find_expired_files(None).await.unwrap().len(), 1);
+    let purged = store.cleanup_expired_files(None).await.unwrap();
+    assert_eq!(purged.iter().map(|file| file.id).collect::<Vec<_>>(), vec![expired]);
+    let tombstone = store.find_file_mapping(expired).await.unwrap().expect("Tombstone kept");
+    assert!(tombstone.purged_at.is_some() && !tombstone.is_in_memory);
+    assert_eq!(store.get_file_id_by_short_code("expired").await.unwrap(), Some(expired));
+    assert!(!store.set_file_expiry(expired, Utc::now()).await.unwrap(), "Tombstones keep their expiry");
+
+    // Tombstones past their retention go, with their short codes
+    assert_eq!(store.count_purged_files(Some(Duration::days(7))).await.unwrap(), 0);
+    assert_eq!(store.count_purged_files(Some(Duration::seconds(-60))).await.unwrap(), 1);
+    let (deleted, short_codes) = store.cleanup_purged_files(Some(Duration::seconds(-60))).await.unwrap();
+    assert_eq!((deleted, short_codes), (1, vec!["expired".to_string()]));
+    assert!(store.get_file_id_by_short_code("expired").await.unwrap().is_none());
+
+    let stats = store.get_storage_stats(Duration::days(1)).await.unwrap();
+    assert_eq!((stats.total_files, stats.total_size, stats.memory_files), (3, 60, 3));
+
+    let list = FileListQuery {
+        sort: FileSort::Size,
+        ascending: false,
+        limit: 2,
+        ..FileListQuery::default()
+    };
+    let sizes: Vec<i64> = store.list_file_mappings(&list).await.unwrap().iter().map(|m| m.file_size).collect();
+    assert_eq!(sizes, vec![30, 20]);
+
+    assert_eq!(store.short_codes_for_file(second).await.unwrap(), vec!["second".to_string()]);
+    let mut resolved = store.get_file_ids_by_short_codes(&["first", "second", "missing"]).await.unwrap();
+    resolved.sort();
+    assert_eq!(resolved, vec![("first".to_string(), first), ("second".to_string(), second)]);
+    assert!(store.delete_file_mapping(first).await.unwrap());
+    assert!(!store.delete_file_mapping(first).await.unwrap());
+    assert!(store.get_file_id_by_short_code("first").await.unwrap().is_none());
+}
+
+// Shared stored files, idempotency keys and quotas
+async fn check_bookkeeping(store: &dyn MetadataStore) {
+    assert_eq!(store.claim_blob("abc", 5, "one").await.unwrap(), "one");
+    assert_eq!(store.claim_blob("abc", 5, "two").await.unwrap(), "one");
+    assert!(store.is_blob("one").await.unwrap());
+    assert!(!store.release_blob("one").await.unwrap());
+    assert!(store.release_blob("one").await.unwrap());
+    assert!(!store.is_blob("one").await.unwrap());
+    assert!(store.release_blob("never-shared").await.unwrap());
+
+    let ip: IpAddr = "192.0.2.1".parse().unwrap();
+    let now = Utc::now();
+    let later = now + Duration::hours(1);
+    let scope = "ip:192.0.2.1";
+    assert_eq!(store.claim_idempotency_key(scope, "key", now, later).await.unwrap(), KeyClaim::Claimed);
+    assert_eq!(store.claim_idempotency_key(scope, "key", now, later).await.unwrap(), KeyClaim::InProgress);
+    store.complete_idempotency_key(scope, "key", "[]").await.unwrap();
+    store.release_idempotency_key(scope, "key").await.unwrap();
+    assert_eq!(
+        store.claim_idempotency_key(scope, "key", now, later).await.unwrap(),
+        KeyClaim::Completed("[]".to_string())
+    );
+    assert_eq!(
+        store.claim_idempotency_key(scope, "key", later, later + Duration::hours(1)).await.unwrap(),
+        KeyClaim::Claimed,
+        "An expired key is taken over"
+    );
+
+    let window = now - Duration::hours(1);
+    store.add_quota_usage(ip, window, 100).await.unwrap();
+    store.add_quota_usage(ip, window, 50).await.unwrap();
+    store.credit_quota_usage(ip, window, 30).await.unwrap();
+    assert_eq!(store.quota_usage(ip, window).await.unwrap(), 120);
+    store.add_quota_usage(ip, now, 10).await.unwrap();
+    assert_eq!(store.quota_usage(ip, window).await.unwrap(), 0, "A new window starts from zero");
+    assert_eq!(store.quota_usage(ip, now).await.unwrap(), 10);
+    assert_eq!(store.cleanup_old_quotas(later).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_in_memory_store_matches_database() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let in_memory = InMemoryStore::new();
+
+    for store in [&database as &dyn MetadataStore, &in_memory] {
+        check_files(store).await;
+        check_bookkeeping(store).await;
+    }
+
+    println!("✅ In-memory store parity test passed");
+}
+
+#[tokio::test]
+async fn test_unavailable_in_memory_store_fails_every_call() {
+    let store = InMemoryStore::new();
+    store.store_file_mapping(&new_mapping(Uuid::new_v4(), 1)).await.unwrap();
+
+    store.set_available(false);
+    assert!(!store.health_check().await);
+    assert!(store.find_file_mapping(Uuid::new_v4()).await.is_err());
+    assert!(store.get_storage_stats(Duration::days(1)).await.is_err());
+
+    store.set_available(true);
+    assert!(store.health_check().await);
+    assert_eq!(store.get_storage_stats(Duration::days(1)).await.unwrap().total_files, 1);
+
+    println!("✅ Unavailable in-memory store test passed");
+}
@@ -0,0 +1,102 @@
+//! Token-bucket behaviour of the rate limiter, driven with explicit instants.
+
+use drop::rate_limit::{RateLimitPolicy, TokenBucket};
+use std::time::{Duration, Instant};
+
+// The default upload limit: 60 requests a minute, bursting to 60
+const POLICY: RateLimitPolicy = RateLimitPolicy {
+    burst: 60,
+    refill_per_second: 1.0,
+};
+
+/// Send `count` requests at `now` and return how many were allowed
+fn burst(bucket: &mut TokenBucket, now: Instant, count: usize) -> usize {
+    (0..count).filter(|_| bucket.take(&POLICY, now).allowed).count()
+}
+
+#[test]
+fn test_burst_is_not_allowed_twice_at_a_boundary() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&POLICY, start);
+
+    // A full burst at 0:59 empties the bucket...
+    assert_eq!(burst(&mut bucket, start + Duration::from_secs(59), 61), 60);
+
+    // ...so at 1:01 only the two seconds of refill are available, not a fresh 60
+    assert_eq!(burst(&mut bucket, start + Duration::from_secs(61), 61), 2);
+}
+
+#[test]
+fn test_steady_rate_is_allowed() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&POLICY, start);
+    assert_eq!(burst(&mut bucket, start, 60), 60);
+
+    // One request a second matches the refill rate and is never refused
+    for second in 1..=120 {
+        let status = bucket.take(&POLICY, start + Duration::from_secs(second));
+        assert!(status.allowed, "Request at {}s should be allowed", second);
+    }
+}
+
+#[test]
+fn test_status_reports_tokens_and_reset() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&POLICY, start);
+
+    let status = bucket.take(&POLICY, start);
+    assert!(status.allowed);
+    assert_eq!(status.limit, 60);
+    assert_eq!(status.remaining, 59);
+    assert_eq!(status.reset_after, 1, "One token short of full");
+
+    burst(&mut bucket, start, 59);
+    let status = bucket.take(&POLICY, start + Duration::from_millis(500));
+    assert!(!status.allowed, "Empty bucket should refuse");
+    assert_eq!(status.remaining, 0);
+    assert_eq!(status.reset_after, 1, "Half a token away from the next request");
+}
+
+#[test]
+fn test_idle_bucket_refills_only_to_burst() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(&POLICY, start);
+    burst(&mut bucket, start, 60);
+
+    // An hour idle refills the bucket but never beyond the burst
+    assert_eq!(burst(&mut bucket, start + Duration::from_secs(3600), 100), 60);
+}
+
+#[test]
+fn test_parse_allowlist() {
+    use drop::{client_ip::parse_ip_ranges, rate_limit::is_exempt};
+
+    let allowlist = parse_ip_ranges(" 10.1.0.0/16, 192.168.1.5 ,2001:db8::/32,, fd00::1 ").expect("Valid allowlist");
+    assert_eq!(allowlist.len(), 4);
+
+    let config = drop::Config {
+        rate_limit_allowlist: allowlist,
+        ..drop::Config::default()
+    };
+    let exempt = |ip: &str| is_exempt(&config, ip.parse().expect("Valid IP"));
+    assert!(exempt("10.1.200.3"), "Address inside a v4 block");
+    assert!(exempt("192.168.1.5"), "Single v4 address");
+    assert!(exempt("2001:db8:1::42"), "Address inside a v6 block");
+    assert!(exempt("fd00::1"), "Single v6 address");
+    assert!(exempt("::ffff:10.1.0.9"), "IPv4-mapped address matches its v4 block");
+    assert!(!exempt("10.2.0.1"));
+    assert!(!exempt("192.168.1.6"));
+    assert!(!exempt("127.0.0.1"), "Setting the allowlist replaces the localhost default");
+
+    assert!(parse_ip_ranges("10.0.0.0/8, backup-host").is_err(), "Hostnames are rejected");
+    assert!(parse_ip_ranges("10.0.0.0/33").is_err(), "Out of range prefixes are rejected");
+    assert!(parse_ip_ranges("").expect("Empty allowlist").is_empty());
+}
+
+#[test]
+fn test_localhost_exempt_by_default() {
+    let config = drop::Config::default();
+    assert!(drop::rate_limit::is_exempt(&config, "127.0.0.1".parse().unwrap()));
+    assert!(drop::rate_limit::is_exempt(&config, "::1".parse().unwrap()));
+    assert!(!drop::rate_limit::is_exempt(&config, "203.0.113.7".parse().unwrap()));
+}
@@ -0,0 +1,76 @@
+//! Tests for the embeddable `Server`. Building one sizes the process-wide
+//! memory pool, so they live in their own test binary.
+
+mod common;
+use common::{create_test_client, open_sqlite, upload};
+
+fn test_config(dir: &std::path::Path) -> drop::Config {
+    drop::Config {
+        bind_address: "127.0.0.1:0".to_string(),
+        temp_directory: dir.join("files"),
+        ..drop::Config::default()
+    }
+}
+
+#[tokio::test]
+async fn test_server_serves_until_shutdown() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let handle = drop::Server::builder()
+        .config(test_config(dir.path()))
+        .build()
+        .await
+        .expect("Failed to build server")
+        .serve()
+        .await
+        .expect("Failed to start server");
+    let base_url = format!("http://{}", handle.local_addr());
+    assert_ne!(handle.local_addr().port(), 0, "The handle should report the port actually bound");
+    let client = create_test_client();
+
+    // Served straight away, no waiting for the listener
+    let file = upload(&client, &base_url, "", "embedded.txt", "Served by an embedded server").await;
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, file["id"].as_str().expect("No file ID in response")))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.text().await.expect("No body"), "Served by an embedded server");
+
+    handle.shutdown().await.expect("Server should shut down cleanly");
+    assert!(
+        client.get(&format!("{}/health", base_url)).send().await.is_err(),
+        "A shut down server should refuse connections"
+    );
+
+    println!("✅ Embedded server start/stop test passed");
+}
+
+#[tokio::test]
+async fn test_server_uses_given_database() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let handle = drop::Server::builder()
+        .config(test_config(dir.path()))
+        .database(database.clone())
+        .build()
+        .await
+        .expect("Failed to build server")
+        .serve()
+        .await
+        .expect("Failed to start server");
+    let base_url = format!("http://{}", handle.local_addr());
+
+    let file = upload(&create_test_client(), &base_url, "", "embedded.txt", "Recorded in the given database").await;
+    let file_id: uuid::Uuid = file["id"]
+        .as_str()
+        .and_then(|id| id.parse().ok())
+        .expect("No file ID in response");
+    assert!(
+        database.find_file_mapping(file_id).await.expect("Lookup failed").is_some(),
+        "Upload should be recorded in the database the server was given"
+    );
+    assert!(handle.app_state().file_storage.is_empty(), "Nothing should fall back to memory");
+
+    handle.shutdown().await.expect("Server should shut down cleanly");
+    println!("✅ Embedded server database test passed");
+}
@@ -0,0 +1,587 @@
+//! Files on disk: layout, recovery after a restart, integrity, shared
+//! contents and encryption at rest.
+
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{
+    create_test_client, file_form, open_sqlite, post_form, spawn_server, sqlite_app_state, stored_files, stored_path,
+    test_app_state, upload, upload_raw,
+};
+
+#[tokio::test]
+async fn test_reshard_moves_flat_files() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+
+    // A file stored flat, with its whole path recorded, as older versions did
+    let app_state = test_app_state(dir.path(), Some(database.clone()));
+    let file_id = uuid::Uuid::new_v4();
+    let flat_path = app_state.config.temp_directory.join(format!("file_{}", file_id));
+    std::fs::create_dir_all(&app_state.config.temp_directory).unwrap();
+    std::fs::write(&flat_path, "stored flat").unwrap();
+    std::fs::write(flat_path.with_extension("json"), "{}").unwrap();
+    let new_mapping = drop::database::NewFileMapping {
+        id: file_id,
+        filename: "flat.txt",
+        content_type: "text/plain",
+        declared_content_type: None,
+        detected_content_type: None,
+        file_path: Some(&flat_path),
+        file_size: 11,
+        is_in_memory: false,
+        expires_at: None,
+        delete_token: "token",
+        max_downloads: None,
+        content_hash: "hash",
+        sha256: None,
+        created_at: chrono::Utc::now(),
+        access_count: 0,
+        uploader_ip: None,
+        encrypted: false,
+        pinned: false,
+        api_key_id: None,
+        embeddable: false,
+    };
+    assert!(database.store_upload(&new_mapping, "flat1234").await.unwrap());
+
+    let config = app_state.config.clone();
+    let report = drop::reshard::shard_flat_files(&config, Some(&database)).await.expect("Reshard failed");
+    assert_eq!((report.files_moved, report.rows_updated, report.failed), (1, 1, 0));
+    let sharded = stored_path(dir.path(), &file_id.to_string());
+    assert!(sharded.exists() && !flat_path.exists(), "The file should be moved into its shard directory");
+    assert!(sharded.with_extension("json").exists(), "The sidecar moves with its file");
+
+    let base_url = spawn_server(app_state).await;
+    let response = create_test_client()
+        .get(&format!("{}/drop/flat1234", base_url))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.status().is_success(), "The moved file should still be served");
+    assert_eq!(response.text().await.expect("No body"), "stored flat");
+
+    // Nothing is left to move
+    let report = drop::reshard::shard_flat_files(&config, Some(&database)).await.expect("Reshard failed");
+    assert_eq!(report, drop::reshard::ReshardReport::default());
+}
+
+#[tokio::test]
+async fn test_storage_totals_follow_writes() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let day = chrono::Duration::hours(24);
+    let mapping = |id: uuid::Uuid, file_size: i64, is_in_memory: bool, expires_in: Option<chrono::Duration>| {
+        drop::database::NewFileMapping {
+            id,
+            filename: "counted.txt",
+            content_type: "text/plain",
+            declared_content_type: None,
+            detected_content_type: None,
+            file_path: None,
+            file_size,
+            is_in_memory,
+            expires_at: expires_in.map(|expires_in| chrono::Utc::now() + expires_in),
+            delete_token: "token",
+            max_downloads: None,
+            content_hash: "hash",
+            sha256: None,
+            created_at: chrono::Utc::now(),
+            access_count: 0,
+            uploader_ip: None,
+            encrypted: false,
+            pinned: false,
+            api_key_id: None,
+            embeddable: false,
+        }
+    };
+
+    let (memory_id, disk_id, later_id) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+    database.store_file_mapping(&mapping(memory_id, 100, true, Some(chrono::Duration::hours(1)))).await.unwrap();
+    database.store_file_mapping(&mapping(disk_id, 1000, false, None)).await.unwrap();
+    database.store_file_mapping(&mapping(later_id, 10, false, Some(chrono::Duration::days(7)))).await.unwrap();
+    let totals = database.get_storage_stats(day).await.unwrap();
+    assert_eq!(totals.total_files, 3);
+    assert_eq!(totals.total_size, 1110);
+    assert_eq!((totals.memory_files, totals.memory_size), (1, 100));
+    assert_eq!(totals.expiring_files, 1, "Only the file expiring within a day counts");
+
+    // Purged files and deleted ones leave the totals; deleting a tombstone changes nothing
+    database.mark_file_purged(memory_id).await.unwrap();
+    let totals = database.get_storage_stats(day).await.unwrap();
+    assert_eq!((totals.total_files, totals.total_size), (2, 1010));
+    assert_eq!((totals.memory_files, totals.memory_size, totals.expiring_files), (0, 0, 0));
+    assert!(database.delete_file_mapping(memory_id).await.unwrap());
+    assert!(database.delete_file_mapping(disk_id).await.unwrap());
+    let totals = database.get_storage_stats(day).await.unwrap();
+    assert_eq!((totals.total_files, totals.total_size), (1, 10));
+}
+
+#[tokio::test]
+async fn test_disk_files_recovered_after_restart() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let test_content = "Still here after a restart.";
+    let file = upload(&client, &base_url, "", "restart.txt", test_content).await;
+    let file_id = file["id"].as_str().expect("No file ID in response").to_string();
+    let short_code = file["short_url"]
+        .as_str()
+        .and_then(|url| url.rsplit('/').next())
+        .expect("No short URL in response")
+        .to_string();
+
+    // An unparseable leftover is reported but kept unless DROP_CLEAN_ORPHANS is set
+    let orphan = dir.path().join("files").join("file_not-a-uuid");
+    std::fs::write(&orphan, b"leftover").expect("Failed to write orphan");
+    // An upload interrupted by the restart is never served, just removed
+    let partial = dir.path().join("files").join(format!("file_{}.part", uuid::Uuid::new_v4()));
+    std::fs::write(&partial, b"half an upl").expect("Failed to write partial upload");
+
+    // A fresh process with an empty index rebuilds it from the temp directory
+    let app_state = test_app_state(dir.path(), None);
+    let report = drop::recovery::recover_disk_files(&app_state).await;
+    assert_eq!(report.recovered, 1, "The uploaded file should be recovered");
+    assert_eq!(report.orphans, 1, "The unparseable file should be reported");
+    assert!(orphan.exists(), "Orphans are only deleted when DROP_CLEAN_ORPHANS is set");
+    assert_eq!(report.partial_removed, 1, "The interrupted upload should be removed");
+    assert!(!partial.exists(), "Partial uploads are removed at startup");
+    let restarted_url = spawn_server(app_state).await;
+
+    for identifier in [&file_id, &short_code] {
+        let response = client
+            .get(&format!("{}/drop/{}", restarted_url, identifier))
+            .send()
+            .await
+            .expect("Download request failed");
+        assert!(response.status().is_success(), "Recovered file should be served by {}", identifier);
+        let content_disposition = response
+            .headers()
+            .get("content-disposition")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        assert!(content_disposition.contains("restart.txt"), "Filename should come from the sidecar");
+        assert_eq!(response.text().await.expect("No body"), test_content);
+    }
+
+    // The original delete token still works, and removes the sidecar with the file
+    let response = client
+        .delete(&format!("{}/drop/{}", restarted_url, file_id))
+        .header("X-Delete-Token", file["delete_token"].as_str().expect("No delete token"))
+        .send()
+        .await
+        .expect("Delete request failed");
+    assert_eq!(response.status(), 204, "Expected 204 for a successful delete");
+    let sidecar = stored_path(dir.path(), &file_id).with_extension("json");
+    assert!(!sidecar.exists(), "Sidecar should be removed with the file");
+
+    println!("✅ Startup recovery test passed");
+}
+
+#[tokio::test]
+async fn test_client_disconnect_mid_upload_leaves_no_files() {
+    use tokio::io::AsyncWriteExt;
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let addr = base_url.trim_start_matches("http://");
+
+    // One complete part, then half of a second before the connection drops
+    let boundary = "drop-disconnect-test";
+    let body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"first.txt\"\r\n\r\n\
+         complete file\r\n\
+         --{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"second.txt\"\r\n\r\n\
+         {half}",
+        b = boundary,
+        half = "x".repeat(256 * 1024),
+    );
+    let head = format!(
+        "POST /drop HTTP/1.1\r\nHost: {}\r\nContent-Type: multipart/form-data; boundary={}\r\nContent-Length: {}\r\n\r\n",
+        addr,
+        boundary,
+        body.len() + 1024 * 1024
+    );
+    let mut stream = tokio::net::TcpStream::connect(addr).await.expect("Failed to connect");
+    stream.write_all(head.as_bytes()).await.expect("Failed to send headers");
+    stream.write_all(body.as_bytes()).await.expect("Failed to send body");
+    stream.flush().await.expect("Failed to flush");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    drop(stream);
+
+    // Neither the finished first part nor the partial second one is kept;
+    // the first part's shard directory may be left behind, empty, as shard
+    // directories are once their files go
+    let mut leftovers = Vec::new();
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        leftovers = stored_files(dir.path());
+        if leftovers.is_empty() {
+            break;
+        }
+    }
+    assert!(leftovers.is_empty(), "Abandoned upload left files behind: {:?}", leftovers);
+    fn walk(directory: &std::path::Path, found: &mut Vec<std::path::PathBuf>) {
+        for entry in std::fs::read_dir(directory).into_iter().flatten().flatten() {
+            found.push(entry.path());
+            if entry.path().is_dir() {
+                walk(&entry.path(), found);
+            }
+        }
+    }
+    let mut remaining = Vec::new();
+    walk(&dir.path().join("files"), &mut remaining);
+    remaining.sort();
+    match remaining.as_slice() {
+        [] => {}
+        [shard, nested] => assert!(
+            shard.is_dir() && nested.parent() == Some(shard.as_path()) && nested.read_dir().unwrap().next().is_none(),
+            "Only the first part's empty shard directory should remain: {:?}",
+            remaining
+        ),
+        _ => panic!("Abandoned upload left more than a shard directory behind: {:?}", remaining),
+    }
+
+    println!("✅ Client disconnect cleanup test passed");
+}
+
+#[tokio::test]
+async fn test_durable_writes() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.stream_threshold = 0; // Keep everything on disk
+    app_state.config.durable_writes = true;
+    app_state.config.durable_writes_min_size = 1024;
+    app_state.storage = drop::storage::FileStore::from_config(&app_state.config);
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let health: Value = client
+        .get(&format!("{}/health", base_url))
+        .send()
+        .await
+        .expect("Health request failed")
+        .json()
+        .await
+        .expect("Failed to parse health response");
+    assert_eq!(health["disk"]["durable_writes"], true, "Health should report the durability mode");
+    assert_eq!(health["disk"]["durable_writes_min_bytes"], 1024);
+
+    // Files on either side of the threshold are stored and served alike
+    for content in ["small".to_string(), "large".repeat(1024)] {
+        let response = client
+            .put(&format!("{}/drop/durable.txt", base_url))
+            .body(content.clone())
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        let file_id = upload["files"][0]["id"].as_str().expect("No file ID in response");
+        assert!(stored_path(dir.path(), file_id).exists(), "The file should be on disk");
+
+        let response = client
+            .get(&format!("{}/drop/{}", base_url, file_id))
+            .send()
+            .await
+            .expect("Download request failed");
+        assert_eq!(response.text().await.expect("No body"), content);
+    }
+}
+
+#[tokio::test]
+async fn test_scrub_marks_corrupt_files() {
+    use drop::scrub::{Integrity, scrub_next};
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.stream_threshold = 0; // Keep everything on disk
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let base_url = spawn_server(app_state.clone()).await;
+    let client = create_test_client();
+
+    let mut ids = Vec::new();
+    for content in ["changed on disk", "deleted from disk"] {
+        let response = client
+            .put(&format!("{}/drop/scrub.txt", base_url))
+            .body(content)
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        ids.push(upload["files"][0]["id"].as_str().expect("No file ID in response").to_string());
+    }
+    let changed = stored_path(dir.path(), &ids[0]);
+    std::fs::write(&changed, "CHANGED ON DISK").expect("Failed to overwrite the file");
+    std::fs::remove_file(stored_path(dir.path(), &ids[1])).expect("Failed to delete the file");
+
+    let mut found = Vec::new();
+    for _ in 0..2 {
+        let (id, integrity) = scrub_next(&app_state).await.expect("A file should be scrubbed");
+        found.push((id.to_string(), integrity));
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut expected = vec![(ids[0].clone(), Integrity::Mismatch), (ids[1].clone(), Integrity::Missing)];
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(found, expected, "Both files should fail their check");
+
+    for id in &ids {
+        let response = client
+            .get(&format!("{}/drop/{}", base_url, id))
+            .send()
+            .await
+            .expect("Download request failed");
+        assert_eq!(response.status(), 503, "Corrupt files shouldn't be served");
+        let body: Value = response.json().await.expect("Failed to parse error response");
+        assert!(body["error"].as_str().unwrap_or_default().contains("integrity check"));
+    }
+
+    let corrupt: Value = client
+        .get(&format!("{}/admin/corrupt-files", base_url))
+        .header("X-Admin-Token", "test-admin-token")
+        .send()
+        .await
+        .expect("Admin request failed")
+        .json()
+        .await
+        .expect("Failed to parse corrupt file list");
+    let listed: Vec<&str> = corrupt
+        .as_array()
+        .expect("Expected a list")
+        .iter()
+        .map(|file| file["id"].as_str().expect("No file ID"))
+        .collect();
+    assert_eq!(listed.len(), 2, "Both files should be listed");
+    assert!(ids.iter().all(|id| listed.contains(&id.as_str())));
+
+    let health: Value = client
+        .get(&format!("{}/health", base_url))
+        .send()
+        .await
+        .expect("Health request failed")
+        .json()
+        .await
+        .expect("Failed to parse health response");
+    assert!(health["corrupt_files_found"].as_u64().unwrap_or_default() >= 2);
+
+    // Restored contents pass their next check and are served again
+    std::fs::write(&changed, "changed on disk").expect("Failed to restore the file");
+    for _ in 0..2 {
+        scrub_next(&app_state).await.expect("A file should be scrubbed");
+    }
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, ids[0]))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.status(), 200, "A restored file should be served");
+    assert_eq!(response.text().await.expect("No body"), "changed on disk");
+}
+
+#[tokio::test]
+async fn test_upload_refused_past_disk_usage_cap() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.max_disk_usage = Some(1024);
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let upload = |content: String| post_form(&client, &base_url, "", file_form("disk.txt", content)).send();
+
+    // The first upload fits under the cap
+    let response = upload("a".repeat(512)).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload under the cap should succeed");
+
+    let health: Value = client
+        .get(&format!("{}/health", base_url))
+        .send()
+        .await
+        .expect("Health request failed")
+        .json()
+        .await
+        .expect("Failed to parse health response");
+    assert_eq!(health["disk"]["stored_bytes"], 512, "Health should report bytes stored on disk");
+    assert_eq!(health["disk"]["headroom_bytes"], 512, "Health should report the remaining headroom");
+
+    // The second would take the instance past it
+    let response = upload("b".repeat(512)).await.expect("Upload request failed");
+    assert_eq!(response.status(), 507, "Upload past the cap should be refused");
+    let body: Value = response.json().await.expect("Failed to parse error response");
+    assert_eq!(body["error"], "Disk usage limit reached");
+    assert_eq!(body["available_bytes"], 512);
+
+    println!("✅ Disk usage cap test passed");
+}
+
+#[tokio::test]
+async fn test_identical_uploads_share_contents() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = sqlite_app_state(dir.path()).await;
+    app_state.config.stream_threshold = 0; // Keep everything on disk
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let test_content = "The same large ISO, uploaded twice.";
+    let mut files = Vec::new();
+    for name in ["first.iso", "second.iso"] {
+        let response = client
+            .put(&format!("{}/drop/{}", base_url, name))
+            .body(test_content)
+            .send()
+            .await
+            .expect("Upload request failed");
+        assert!(response.status().is_success(), "Upload should succeed");
+        let upload: Value = response.json().await.expect("Failed to parse upload response");
+        files.push(upload["files"][0].clone());
+    }
+    let digest = files[0]["sha256"].as_str().expect("No SHA-256 in response").to_string();
+    assert_eq!(digest.len(), 64, "SHA-256 should be 64 hex digits");
+    assert_eq!(files[1]["sha256"], files[0]["sha256"]);
+    assert_ne!(files[1]["id"], files[0]["id"], "Each upload gets its own file");
+    assert_ne!(files[1]["short_url"], files[0]["short_url"]);
+    assert_eq!(stored_files(dir.path()).len(), 1, "Identical contents are stored once");
+
+    // Lookup by hash finds the newest copy; malformed hashes are rejected
+    let response = client
+        .get(&format!("{}/drop/by-hash/{}", base_url, digest.to_uppercase()))
+        .send()
+        .await
+        .expect("Lookup request failed");
+    assert_eq!(response.status(), 200);
+    let info: Value = response.json().await.expect("Failed to parse info response");
+    assert_eq!(info["id"], files[1]["id"]);
+    assert_eq!(info["sha256"].as_str(), Some(digest.as_str()));
+    let response = client
+        .get(&format!("{}/drop/by-hash/not-a-hash", base_url))
+        .send()
+        .await
+        .expect("Lookup request failed");
+    assert_eq!(response.status(), 400);
+
+    // Deleting one file leaves the contents for the other
+    let delete = |file: &Value| {
+        client
+            .delete(&format!("{}/drop/{}", base_url, file["id"].as_str().unwrap()))
+            .header("X-Delete-Token", file["delete_token"].as_str().unwrap())
+            .send()
+    };
+    assert_eq!(delete(&files[0]).await.expect("Request failed").status(), 204);
+    let response = client
+        .get(files[1]["full_url"].as_str().unwrap())
+        .send()
+        .await
+        .expect("Download request failed");
+    assert!(response.status().is_success(), "The other file should still download");
+    assert_eq!(response.text().await.expect("No body"), test_content);
+    assert_eq!(stored_files(dir.path()).len(), 1);
+
+    // The last reference takes the contents with it
+    assert_eq!(delete(&files[1]).await.expect("Request failed").status(), 204);
+    assert!(stored_files(dir.path()).is_empty(), "Contents should be removed with the last file");
+    let response = client
+        .get(&format!("{}/drop/by-hash/{}", base_url, digest))
+        .send()
+        .await
+        .expect("Lookup request failed");
+    assert_eq!(response.status(), 404);
+
+    println!("✅ Identical uploads test passed");
+}
+
+#[tokio::test]
+async fn test_encryption_at_rest() {
+    use drop::{encryption::EncryptionKey, storage::FileStore};
+
+    const KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const NEW_KEY: &str = "ZGVmZ2hpamtsbW5vcHFyc3R1dnd4eXp7fH1+f4CBgoM=";
+
+    // A server over the same directory with the given keys, after recovering what is on disk
+    async fn start(dir: &std::path::Path, key: Option<&str>, old_keys: &[&str]) -> String {
+        let mut app_state = test_app_state(dir, None);
+        app_state.config.stream_threshold = 0; // Keep everything on disk
+        app_state.config.encryption_key = key.map(|key| EncryptionKey::parse(key).unwrap());
+        app_state.config.encryption_old_keys = old_keys.iter().map(|key| EncryptionKey::parse(key).unwrap()).collect();
+        app_state.storage = FileStore::from_config(&app_state.config);
+        drop::recovery::recover_disk_files(&app_state).await;
+        spawn_server(app_state).await
+    }
+
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let client = create_test_client();
+    let upload = |base_url: String, name: &'static str, body: Vec<u8>| {
+        let client = client.clone();
+        async move { upload_raw(&client, &base_url, name, body).await["id"].as_str().unwrap().to_string() }
+    };
+
+    // Stored before encryption was turned on
+    let plain = b"written in the clear".to_vec();
+    let plain_id = upload(start(dir.path(), None, &[]).await, "plain.txt", plain.clone()).await;
+
+    let base_url = start(dir.path(), Some(KEY), &[]).await;
+    // Several chunks, the last one partial
+    let secret: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let secret_id = upload(base_url.clone(), "secret.bin", secret.clone()).await;
+
+    let raw = std::fs::read(stored_path(dir.path(), &secret_id)).unwrap();
+    assert!(raw.len() > secret.len(), "Encrypted file should carry a header and tags");
+    assert!(
+        !raw.windows(1024).any(|window| window == &secret[1000..2024]),
+        "Contents should not be on disk in the clear"
+    );
+
+    let response = client.get(&format!("{}/drop/{}", base_url, secret_id)).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-length"], secret.len().to_string().as_str());
+    assert_eq!(response.bytes().await.unwrap(), secret);
+
+    // Ranges across a chunk boundary decrypt just the chunks they need
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, secret_id))
+        .header("Range", "bytes=65530-65545")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await.unwrap(), &secret[65530..=65545]);
+
+    let info: Value = client
+        .get(&format!("{}/drop/{}/info", base_url, secret_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["encrypted"], true);
+    assert_eq!(info["size"], secret.len());
+
+    // Files from before are still served as they are
+    let response = client.get(&format!("{}/drop/{}", base_url, plain_id)).send().await.unwrap();
+    assert_eq!(response.bytes().await.unwrap(), plain);
+    let info: Value = client
+        .get(&format!("{}/drop/{}/info", base_url, plain_id))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["encrypted"], false);
+
+    // After rotation the old key still decrypts...
+    let base_url = start(dir.path(), Some(NEW_KEY), &[KEY]).await;
+    let response = client.get(&format!("{}/drop/{}", base_url, secret_id)).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await.unwrap(), secret);
+
+    // ...and without it the file can't be read at all
+    let base_url = start(dir.path(), Some(NEW_KEY), &[]).await;
+    let response = client.get(&format!("{}/drop/{}", base_url, secret_id)).send().await.unwrap();
+    assert!(!response.status().is_success(), "Got {}", response.status());
+
+    println!("✅ Encryption at rest test passed");
+}
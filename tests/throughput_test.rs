@@ -0,0 +1,78 @@
+//! Disk writes of uploads: throughput at different I/O buffer sizes, and
+//! cleanup of partial files. Throughput is printed in MB/s; for meaningful
+//! numbers run `cargo test --release --test throughput_test -- --nocapture`.
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use drop::storage::{DEFAULT_IO_BUFFER_SIZE, LocalDiskBackend, StorageBackend};
+use std::time::Instant;
+use uuid::Uuid;
+
+const TOTAL_SIZE: usize = 64 * 1024 * 1024;
+const CHUNK_SIZE: usize = 16 * 1024; // Roughly what a multipart field yields at a time
+
+fn chunks(count: usize) -> impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> {
+    let chunk = Bytes::from(vec![0xa5u8; CHUNK_SIZE]);
+    futures_util::stream::iter((0..count).map(move |_| Ok(chunk.clone())))
+}
+
+// Write TOTAL_SIZE bytes through `buffer_size` and return the rate in MB/s
+async fn write_throughput(dir: &std::path::Path, buffer_size: usize) -> f64 {
+    let disk = LocalDiskBackend::new(dir.to_path_buf()).with_io_buffer_size(buffer_size);
+    let started = Instant::now();
+    let (storage_ref, size) = disk
+        .put(Uuid::new_v4(), chunks(TOTAL_SIZE / CHUNK_SIZE), TOTAL_SIZE)
+        .await
+        .expect("Write failed");
+    let elapsed = started.elapsed();
+
+    assert_eq!(size, TOTAL_SIZE);
+    assert_eq!(disk.size(&storage_ref).await.unwrap(), TOTAL_SIZE as u64);
+    disk.delete(&storage_ref).await.unwrap();
+    TOTAL_SIZE as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
+
+#[tokio::test]
+async fn test_upload_write_throughput() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+
+    // 8KB is what uploads were flushed at before the buffer was configurable
+    for buffer_size in [8 * 1024, DEFAULT_IO_BUFFER_SIZE, 1024 * 1024] {
+        let rate = write_throughput(dir.path(), buffer_size).await;
+        println!("{:>5} KB buffer: {:.0} MB/s", buffer_size / 1024, rate);
+    }
+}
+
+#[tokio::test]
+async fn test_size_limit_fires_before_the_chunk_is_written() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let disk = LocalDiskBackend::new(dir.path().to_path_buf());
+    let id = Uuid::new_v4();
+
+    // The third chunk would pass the limit
+    let result = disk.put(id, chunks(3), 2 * CHUNK_SIZE + 1).await;
+    assert_eq!(result.unwrap_err(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(!disk.path_for(id).exists(), "The partial file should be removed");
+    assert!(!disk.part_path_for(id).exists(), "The partial file should be removed");
+    assert_eq!(disk.bytes_stored(), 0);
+}
+
+#[tokio::test]
+async fn test_abandoned_upload_is_never_visible() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let disk = LocalDiskBackend::new(dir.path().to_path_buf()).with_io_buffer_size(CHUNK_SIZE);
+    let id = Uuid::new_v4();
+
+    // A client that sends a few chunks and then goes quiet until it is dropped
+    let stalled = chunks(4).chain(futures_util::stream::pending());
+    let mut write = Box::pin(disk.put(id, stalled, TOTAL_SIZE));
+    assert!(tokio::time::timeout(std::time::Duration::from_millis(200), &mut write).await.is_err());
+    assert!(disk.part_path_for(id).exists(), "The upload is written under its temporary name");
+    assert!(!disk.path_for(id).exists(), "An unfinished upload must not be visible");
+
+    // Dropping the handler future, as on disconnect, removes the partial file
+    std::mem::drop(write);
+    assert!(!disk.part_path_for(id).exists(), "The partial file should be removed");
+    assert!(!disk.path_for(id).exists());
+    assert_eq!(disk.bytes_stored(), 0);
+}
@@ -0,0 +1,105 @@
+//! Parsing upload options from form fields and JSON, apart from any request.
+
+use drop::upload_options::{Disposition, OptionsParser, RequestedOptions, parse_json};
+
+fn invalid_fields(parser: OptionsParser) -> Vec<String> {
+    parser
+        .finish()
+        .expect_err("Options should be invalid")
+        .into_iter()
+        .map(|invalid| invalid.field)
+        .collect()
+}
+
+#[test]
+fn test_form_fields_set_options() {
+    let mut parser = OptionsParser::default();
+    parser.field("expires_in", " 3600 ");
+    parser.field("max_downloads", "3");
+    parser.field("alias", "q3-report");
+    parser.field("password", "hunter2");
+    parser.field("disposition", "Inline");
+    parser.field("pinned", "TRUE");
+    parser.field("embeddable", "true");
+
+    let options = parser.finish().expect("Options should be valid");
+    assert_eq!(
+        options,
+        RequestedOptions {
+            expires_in: Some(3600),
+            max_downloads: Some(3),
+            alias: Some("q3-report".to_string()),
+            password: Some("hunter2".to_string()),
+            disposition: Some(Disposition::Inline),
+            pinned: Some(true),
+            embeddable: Some(true),
+        }
+    );
+}
+
+#[test]
+fn test_options_field_takes_json() {
+    let mut parser = OptionsParser::default();
+    parser.field("options", r#"{"expires_in": 60, "max_downloads": "2", "disposition": "attachment"}"#);
+
+    let options = parser.finish().expect("Options should be valid");
+    assert_eq!(options.expires_in, Some(60));
+    assert_eq!(options.max_downloads, Some(2));
+    assert_eq!(options.disposition, Some(Disposition::Attachment));
+    assert_eq!(options.alias, None);
+}
+
+#[test]
+fn test_every_invalid_field_is_reported() {
+    let mut parser = OptionsParser::default();
+    parser.field("expires_in", "0");
+    parser.field("max_downloads", "-1");
+    parser.field("alias", "Has-Caps");
+    parser.field("password", "");
+    parser.field("disposition", "sideways");
+    parser.field("pinned", "forever");
+    assert_eq!(
+        invalid_fields(parser),
+        ["expires_in", "max_downloads", "alias", "password", "disposition", "pinned"]
+    );
+
+    let mut parser = OptionsParser::default();
+    parser.field("expires_in", &u64::MAX.to_string());
+    assert_eq!(invalid_fields(parser), ["expires_in"], "Expiry must be a representable time");
+}
+
+#[test]
+fn test_json_is_strict() {
+    let errors = parse_json(r#"{"expires_in": 60, "colour": "blue", "max_downloads": true}"#)
+        .expect_err("Unknown keys and wrong types should be refused");
+    let fields: Vec<&str> = errors.iter().map(|invalid| invalid.field.as_str()).collect();
+    assert_eq!(fields, ["colour", "max_downloads"]);
+
+    for value in ["", "[]", "\"expires_in\"", "{not json"] {
+        let errors = parse_json(value).expect_err("Only a JSON object should be accepted");
+        assert_eq!(errors[0].field, "options", "{:?} should be refused", value);
+    }
+}
+
+#[test]
+fn test_overrides_replace_only_what_they_set() {
+    let request = RequestedOptions {
+        expires_in: Some(3600),
+        max_downloads: Some(5),
+        ..Default::default()
+    };
+    let part = parse_json(r#"{"max_downloads": 1}"#).expect("Options should be valid");
+
+    let options = request.overridden_by(&part);
+    assert_eq!(options.expires_in, Some(3600));
+    assert_eq!(options.max_downloads, Some(1));
+}
+
+#[test]
+fn test_recognised_field_names() {
+    for name in ["expires_in", "max_downloads", "alias", "password", "disposition", "pinned", "embeddable", "options"] {
+        assert!(OptionsParser::is_option(name), "{} should be an option", name);
+    }
+    assert!(!OptionsParser::is_option("description"));
+    assert!(!OptionsParser::is_option("file"));
+}
@@ -0,0 +1,532 @@
+//! Upload endpoints and responses, idempotency keys and upload options.
+
+use reqwest::{Client, multipart};
+use serde_json::Value;
+use std::time::Duration;
+
+mod common;
+use common::{
+    create_test_client, file_form, open_sqlite, post_form, put_file, spawn_server, sqlite_url, test_app_state,
+    upload_raw,
+};
+
+#[tokio::test]
+async fn test_tls_links_use_https() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let mut app_state = test_app_state(dir.path(), None);
+    // Only the links are under test; the test server itself speaks plain HTTP
+    app_state.config.tls_cert_path = Some(dir.path().join("cert.pem"));
+    app_state.config.tls_key_path = Some(dir.path().join("key.pem"));
+    let base_url = spawn_server(app_state).await;
+
+    let response = create_test_client()
+        .put(&format!("{}/drop/tls.txt", base_url))
+        .header("X-Forwarded-Proto", "http")
+        .body("secure")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    let file = &upload["files"][0];
+    for field in ["short_url", "full_url"] {
+        let url = file[field].as_str().expect("No URL in response");
+        assert!(url.starts_with("https://"), "{} should use https: {}", field, url);
+    }
+}
+
+#[tokio::test]
+async fn test_uploads_larger_than_axum_body_limit() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+    // axum refuses bodies over 2 MB unless the upload routes lift its limit
+    let big = vec![b'x'; 3 * 1024 * 1024];
+
+    let second = multipart::Part::bytes(big.clone()).file_name("second.bin");
+    let form = file_form("first.bin", big.clone()).part("file", second);
+    let response = post_form(&client, &base_url, "", form).send().await.expect("Upload request failed");
+    assert_eq!(response.status(), 201, "A multipart upload over 2 MB should be accepted");
+    let uploaded: Value = response.json().await.expect("Failed to parse upload response");
+    let file = upload_raw(&client, &base_url, "third.bin", big.clone()).await;
+
+    for id in [&uploaded["files"][0]["id"], &uploaded["files"][1]["id"], &file["id"]] {
+        let response = client
+            .get(format!("{}/drop/{}", base_url, id.as_str().expect("No file ID")))
+            .send()
+            .await
+            .expect("Download request failed");
+        assert_eq!(response.bytes().await.expect("Failed to read body").len(), big.len());
+    }
+}
+
+#[tokio::test]
+async fn test_upload_page() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let response = client.get(&format!("{}/", base_url)).send().await.expect("Request failed");
+    assert_eq!(response.status(), 200);
+    assert!(
+        response.headers()[reqwest::header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"),
+        "Upload page should be HTML"
+    );
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"request.open("POST", "drop")"#), "Upload page should post to a relative URL");
+
+    // API-only deployments don't serve it
+    let mut app_state = test_app_state(dir.path(), None);
+    app_state.config.disable_ui = true;
+    let base_url = spawn_server(app_state).await;
+    let response = client.get(&format!("{}/", base_url)).send().await.expect("Request failed");
+    assert_eq!(response.status(), 404);
+
+    println!("✅ Upload page test passed");
+}
+
+#[tokio::test]
+async fn test_plain_text_upload_response() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    let form = multipart::Form::new()
+        .part("file", multipart::Part::text("one").file_name("one.txt"))
+        .part("file", multipart::Part::text("two").file_name("two.txt"));
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .header("Accept", "text/plain")
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 201);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines.len(), 2, "One short URL per file");
+    for line in lines {
+        assert!(line.starts_with(&format!("{}/drop/", base_url)), "Unexpected line: {}", line);
+        let download = client.get(line).send().await.expect("Download request failed");
+        assert_eq!(download.status(), 200);
+    }
+
+    // JSON stays the default, including for */*
+    let response = client
+        .put(&format!("{}/drop/three.txt", base_url))
+        .header("Accept", "*/*")
+        .body("three")
+        .send()
+        .await
+        .expect("Upload request failed");
+    let upload: Value = response.json().await.expect("Upload response should be JSON");
+    assert!(upload["files"][0]["short_url"].is_string());
+    let response = client
+        .put(&format!("{}/drop/three.txt", base_url))
+        .header("Accept", "application/json, text/plain;q=0.5")
+        .body("three")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("application/json"));
+
+    // Errors are negotiated too
+    let response = client
+        .put(&format!("{}/drop/four.txt?expires_in=soon", base_url))
+        .header("Accept", "text/plain")
+        .body("four")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 400);
+    assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+    assert_eq!(response.text().await.unwrap(), "Bad Request\n");
+    let response = client
+        .put(&format!("{}/drop/four.txt?expires_in=soon", base_url))
+        .body("four")
+        .send()
+        .await
+        .expect("Upload request failed");
+    let error: Value = response.json().await.expect("Error response should be JSON");
+    assert_eq!(error["error"], "Bad Request");
+    assert!(error["request_id"].is_string());
+
+    println!("✅ Plain text upload response test passed");
+}
+
+#[tokio::test]
+async fn test_uploads_return_created_with_location() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    // Multipart: the first file's path, with every file in the body
+    let form = multipart::Form::new()
+        .part("file", multipart::Part::text("one").file_name("one.txt"))
+        .part("file", multipart::Part::text("two").file_name("two.txt"));
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 201);
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    assert_eq!(upload["files"].as_array().unwrap().len(), 2);
+    assert_eq!(location, format!("/drop/{}", upload["files"][0]["id"].as_str().unwrap()));
+    let download = client.get(&format!("{}{}", base_url, location)).send().await.expect("Download request failed");
+    assert_eq!(download.text().await.unwrap(), "one");
+
+    // Raw PUT: the full download URL
+    let response = client
+        .put(&format!("{}/drop/three.txt", base_url))
+        .body("three")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 201);
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    let upload: Value = response.json().await.expect("Failed to parse upload response");
+    assert_eq!(location, upload["files"][0]["full_url"].as_str().unwrap());
+    let download = client.get(&location).send().await.expect("Download request failed");
+    assert_eq!(download.text().await.unwrap(), "three");
+
+    println!("✅ Created upload response test passed");
+}
+
+/// POST `contents` with `Idempotency-Key: key`
+async fn upload_with_key(client: &Client, base_url: &str, key: &str, contents: &str) -> reqwest::Response {
+    post_form(client, base_url, "", file_form("artifact.txt", contents))
+        .header("Idempotency-Key", key)
+        .send()
+        .await
+        .expect("Upload request failed")
+}
+
+#[tokio::test]
+async fn test_idempotency_key_replays_upload() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let app_state = test_app_state(dir.path(), None);
+    let file_storage = app_state.file_storage.clone();
+    let idempotency_keys = app_state.idempotency_keys.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let response = upload_with_key(&client, &base_url, "build-4711", "first").await;
+    assert_eq!(response.status(), 201);
+    assert!(response.headers().get("idempotent-replayed").is_none());
+    let first: Value = response.json().await.unwrap();
+
+    // The retry gets the same file back and stores nothing
+    let response = upload_with_key(&client, &base_url, "build-4711", "second").await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["idempotent-replayed"], "true");
+    let replayed: Value = response.json().await.unwrap();
+    assert_eq!(replayed, first);
+    assert_eq!(file_storage.len(), 1, "A replay should store nothing");
+
+    // Other keys, and uploads without one, are unaffected
+    let response = upload_with_key(&client, &base_url, "build-4712", "third").await;
+    assert_eq!(response.status(), 201);
+    assert_eq!(file_storage.len(), 2);
+
+    // A failed upload frees its key for the retry
+    let response = client
+        .post(&format!("{}/drop", base_url))
+        .header("Idempotency-Key", "build-4713")
+        .multipart(multipart::Form::new().text("expires_in", "60"))
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 422);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = upload_with_key(&client, &base_url, "build-4713", "retried").await;
+    assert_eq!(response.status(), 201);
+
+    // Still running, and past its TTL
+    let scope = "ip:127.0.0.1".to_string();
+    let pending = drop::idempotency::IdempotencyRecord {
+        response: None,
+        expires_at: chrono::Utc::now() + chrono::Duration::minutes(5),
+    };
+    idempotency_keys.insert((scope.clone(), "build-4714".to_string()), pending);
+    idempotency_keys
+        .get_mut(&(scope, "build-4711".to_string()))
+        .unwrap()
+        .expires_at = chrono::Utc::now();
+    let response = upload_with_key(&client, &base_url, "build-4714", "concurrent").await;
+    assert_eq!(response.status(), 409);
+    let response = upload_with_key(&client, &base_url, "build-4711", "again").await;
+    assert_eq!(response.status(), 201, "An expired key should count as new");
+    let renewed: Value = response.json().await.unwrap();
+    assert_ne!(renewed["files"][0]["id"], first["files"][0]["id"]);
+
+    let response = upload_with_key(&client, &base_url, &"k".repeat(256), "too long").await;
+    assert_eq!(response.status(), 400);
+
+    println!("✅ Idempotency key replay test passed");
+}
+
+#[tokio::test]
+async fn test_idempotency_key_in_database() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+    let mut app_state = test_app_state(dir.path(), Some(database.clone()));
+    app_state.config.admin_token = Some("test-admin-token".to_string());
+    let idempotency_keys = app_state.idempotency_keys.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let response = client
+        .put(&format!("{}/drop/artifact.bin", base_url))
+        .header("Idempotency-Key", "nightly")
+        .body("built once")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 201);
+    let first: Value = response.json().await.unwrap();
+    let response = client
+        .put(&format!("{}/drop/artifact.bin", base_url))
+        .header("Idempotency-Key", "nightly")
+        .body("built twice")
+        .send()
+        .await
+        .expect("Upload request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["idempotent-replayed"], "true");
+    assert_eq!(response.json::<Value>().await.unwrap(), first);
+    assert!(idempotency_keys.is_empty(), "Keys should be kept in the database");
+
+    // A claim still open in the database, from another instance say
+    let now = chrono::Utc::now();
+    let claim = database
+        .claim_idempotency_key("ip:127.0.0.1", "in-flight", now, now + chrono::Duration::minutes(5))
+        .await
+        .expect("Claim failed");
+    assert_eq!(claim, drop::database::KeyClaim::Claimed);
+    let response = upload_with_key(&client, &base_url, "in-flight", "duplicate").await;
+    assert_eq!(response.status(), 409);
+
+    // Keys are per client IP
+    let claim = database
+        .claim_idempotency_key("ip:203.0.113.7", "nightly", now, now + chrono::Duration::minutes(5))
+        .await
+        .expect("Claim failed");
+    assert_eq!(claim, drop::database::KeyClaim::Claimed);
+
+    // ...or per API key, whatever address its uploads come from
+    let response = client
+        .post(&format!("{}/admin/api-keys", base_url))
+        .header("X-Admin-Token", "test-admin-token")
+        .json(&serde_json::json!({ "name": "ci" }))
+        .send()
+        .await
+        .expect("Create request failed");
+    let api_key = response.json::<Value>().await.unwrap()["key"].as_str().expect("No key secret").to_string();
+    let keyed_upload = || {
+        put_file(&client, &base_url, "artifact.bin", "built by ci")
+            .header("Idempotency-Key", "nightly")
+            .bearer_auth(&api_key)
+            .send()
+    };
+    let response = keyed_upload().await.expect("Upload request failed");
+    assert_eq!(response.status(), 201, "The API key hasn't used this key yet");
+    let response = keyed_upload().await.expect("Upload request failed");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["idempotent-replayed"], "true");
+
+    println!("✅ Idempotency key database test passed");
+}
+
+#[tokio::test]
+async fn test_idempotency_key_released_when_database_write_fails() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let database = open_sqlite(dir.path()).await;
+
+    // Claims go through, recording their response does not
+    let pool = sqlx::SqlitePool::connect(&sqlite_url(dir.path())).await.expect("Failed to open SQLite pool");
+    sqlx::query(
+        "CREATE TRIGGER fail_idempotency_update BEFORE UPDATE ON idempotency_keys \
+         BEGIN SELECT RAISE(ABORT, 'injected failure'); END",
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create trigger");
+
+    let app_state = test_app_state(dir.path(), Some(database));
+    let idempotency_keys = app_state.idempotency_keys.clone();
+    let base_url = spawn_server(app_state).await;
+    let client = create_test_client();
+
+    let response = upload_with_key(&client, &base_url, "release-9", "recorded in memory").await;
+    assert_eq!(response.status(), 201);
+    assert_eq!(idempotency_keys.len(), 1, "The response should be kept in memory instead");
+
+    // No claim is left behind to turn retries away once the database is back
+    let (claims,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM idempotency_keys")
+        .fetch_one(&pool)
+        .await
+        .expect("Count failed");
+    assert_eq!(claims, 0, "The unfinished claim should be released");
+
+    println!("✅ Idempotency key release test passed");
+}
+
+#[tokio::test]
+async fn test_session_upload_is_sniffed_and_hashed_while_streaming() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+
+    // The PNG signature is split across the two chunks
+    let png = [b"\x89PNG\r\n\x1a\n".as_slice(), &[0u8; 2048]].concat();
+    let chunks = [&png[..4], &png[4..]];
+
+    let response = client
+        .post(&format!("{}/drop/sessions", base_url))
+        .json(&serde_json::json!({ "filename": "pixel.bin" }))
+        .send()
+        .await
+        .expect("Failed to create session");
+    let session: Value = response.json().await.expect("Failed to parse session response");
+    let session_url = format!("{}/drop/sessions/{}", base_url, session["session_id"].as_str().unwrap());
+    let mut offset = 0;
+    for chunk in chunks {
+        let response = client
+            .patch(&session_url)
+            .header("Upload-Offset", offset.to_string())
+            .body(chunk.to_vec())
+            .send()
+            .await
+            .expect("Failed to append chunk");
+        assert_eq!(response.status(), 204);
+        offset += chunk.len();
+    }
+    let response = client
+        .post(&format!("{}/complete", session_url))
+        .send()
+        .await
+        .expect("Failed to complete session");
+    let completed: Value = response.json().await.expect("Failed to parse upload response");
+
+    // The same bytes in one request hash the same
+    let response = client
+        .put(&format!("{}/drop/pixel.bin", base_url))
+        .body(png.clone())
+        .send()
+        .await
+        .expect("Upload request failed");
+    let single: Value = response.json().await.expect("Failed to parse upload response");
+    assert_eq!(completed["files"][0]["hash"], single["files"][0]["hash"]);
+    assert_eq!(completed["files"][0]["sha256"], single["files"][0]["sha256"]);
+
+    let info: Value = client
+        .get(&format!("{}/drop/{}/info", base_url, completed["files"][0]["id"].as_str().unwrap()))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["detected_content_type"], "image/png");
+    assert_eq!(info["content_type"], "image/png");
+
+    println!("✅ Session sniffing and hashing test passed");
+}
+
+#[tokio::test]
+async fn test_metadata_fields_around_file_parts() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+    let upload = |form: multipart::Form| post_form(&client, &base_url, "", form).send();
+
+    // Options before and after the file, and an unrecognised field in between
+    let form = multipart::Form::new()
+        .text("expires_in", "3600")
+        .text("description", "hello")
+        .part("file", multipart::Part::text("real contents").file_name("real.txt"))
+        .text("max_downloads", "1");
+    let response = upload(form).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    let uploaded: Value = response.json().await.expect("Failed to parse upload response");
+    let files = uploaded["files"].as_array().expect("No files");
+    assert_eq!(files.len(), 1, "The description must not become a file");
+    assert_eq!(files[0]["filename"], "real.txt");
+    assert!(files[0]["expires_at"].is_string(), "expires_in before the file should apply");
+    assert_eq!(files[0]["max_downloads"], 1, "max_downloads after the file should apply");
+
+    let response = client
+        .get(&format!("{}/drop/{}", base_url, files[0]["id"].as_str().unwrap()))
+        .send()
+        .await
+        .expect("Download request failed");
+    assert_eq!(response.text().await.unwrap(), "real contents");
+
+    // Only metadata, no file
+    let form = multipart::Form::new().text("description", "hello").text("expires_in", "60");
+    let response = upload(form).await.expect("Upload request failed");
+    assert_eq!(response.status(), 422);
+    let error: Value = response.json().await.expect("Error should be JSON");
+    assert_eq!(error["error"], "No file part found");
+
+    println!("✅ Metadata field test passed");
+}
+
+#[tokio::test]
+async fn test_structured_upload_options() {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let base_url = spawn_server(test_app_state(dir.path(), None)).await;
+    let client = create_test_client();
+    let upload = |form: multipart::Form| post_form(&client, &base_url, "", form).send();
+    let part_with_options = |name: &str, options: &str| {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("upload-options", options.parse().unwrap());
+        multipart::Part::text("contents").file_name(name.to_string()).headers(headers)
+    };
+
+    // One JSON field applies to every file; a part's own options override it
+    let form = multipart::Form::new()
+        .text("options", r#"{"expires_in": 3600, "max_downloads": "5"}"#)
+        .part("file", multipart::Part::text("contents").file_name("a.txt"))
+        .part("file", part_with_options("b.txt", r#"{"max_downloads": 1}"#));
+    let response = upload(form).await.expect("Upload request failed");
+    assert!(response.status().is_success(), "Upload should succeed");
+    let uploaded: Value = response.json().await.expect("Failed to parse upload response");
+    let files = uploaded["files"].as_array().expect("No files");
+    assert_eq!(files[0]["max_downloads"], 5);
+    assert_eq!(files[1]["max_downloads"], 1, "The part's own option should win");
+    assert!(files[0]["expires_at"].is_string());
+    assert!(files[1]["expires_at"].is_string(), "Options the part doesn't set still apply");
+
+    // Every invalid option is listed, not just the first
+    let form = multipart::Form::new()
+        .text("expires_in", "soon")
+        .text("max_downloads", "0")
+        .part("file", part_with_options("c.txt", r#"{"colour": "blue"}"#));
+    let response = upload(form).await.expect("Upload request failed");
+    assert_eq!(response.status(), 422);
+    let error: Value = response.json().await.expect("Error should be JSON");
+    assert_eq!(error["error"], "Invalid upload options");
+    let fields: Vec<&str> = error["fields"]
+        .as_array()
+        .expect("No fields")
+        .iter()
+        .map(|invalid| invalid["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["expires_in", "max_downloads", "colour (c.txt)"]);
+
+    // Recognised but unsupported options are refused rather than ignored
+    let form = multipart::Form::new()
+        .text("password", "hunter2")
+        .part("file", multipart::Part::text("contents").file_name("d.txt"));
+    let response = upload(form).await.expect("Upload request failed");
+    assert_eq!(response.status(), 422);
+    let error: Value = response.json().await.expect("Error should be JSON");
+    assert_eq!(error["fields"][0]["field"], "password");
+
+    println!("✅ Structured upload options test passed");
+}